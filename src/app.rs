@@ -1,7 +1,7 @@
-use std::path::Path;
+use std::{collections::HashMap, sync::Arc};
 
-use cgmath::{Deg, Quaternion, Rotation3, Vector3, Zero};
-use wgpu::TextureView;
+use cgmath::Vector3;
+use wgpu::{SurfaceConfiguration, TextureView};
 use winit::{
     event::{DeviceEvent, ElementState, Event, KeyEvent, WindowEvent},
     event_loop::EventLoopWindowTarget,
@@ -10,201 +10,186 @@ use winit::{
 
 use crate::{
     camera::{Camera, CameraController},
-    entity::{Entity, Geometry, Mesh, Node},
+    entity::{Aabb, Entity},
+    file_watcher::FileWatcher,
     gpu_context::GpuContext,
     layouts::Layouts,
-    light::PointLight,
-    material::Material,
-    render_pass::{HdrPipeline, PbrPass, ShadowPass, SkyboxPass},
+    material::MaterialOverrides,
+    render_pass::{
+        ErrorOverlay, HdrPipeline, OitPass, OpaquePass, ShadowPass, SkyboxPass, SsrPass,
+        TransparentPass,
+    },
     render_world::RenderWorld,
-    resources::{Resources, SkyboxLoader},
+    resources::{
+        Asset, AssetId, AssetServer, LoadEvent, LoadProgress, LoadedScene, SceneFile, SkyboxLoader,
+        TextureLoader,
+    },
     scene::Scene,
+    settings::{RenderSettings, TransparencyMode},
+    shader_library::ShaderLibrary,
+    stats::{DrawStats, FrameStats},
     surface_context::SurfaceContext,
-    texture::Texture,
+    texture::MipGenerator,
     transform::Transform,
 };
 
+/// Where the instances of a model requested from the asset server go in the
+/// scene, and how their materials are changed.
+struct ModelPlacement {
+    materials: Option<MaterialOverrides>,
+    instances: Instances,
+}
+
+enum Instances {
+    /// The transforms of the instances, until the model is first loaded.
+    Pending(Vec<Transform>),
+    /// The indices of the instances in the scene entities, replaced when the
+    /// model is reloaded.
+    Placed(Vec<usize>),
+}
+
 pub struct App {
+    settings: RenderSettings,
     layouts: Layouts,
     camera_controller: CameraController,
     camera: Camera,
     scene: Scene,
-    model_pass: PbrPass,
+    texture_loader: TextureLoader,
+    asset_server: AssetServer,
+    models: HashMap<AssetId, ModelPlacement>,
+    /// The model the camera is framed on when first loaded.
+    framed: Option<AssetId>,
+    shaders: ShaderLibrary,
+    config: SurfaceConfiguration,
+    passes: Passes,
+    stats: FrameStats,
+    /// The last notice for the user, shown in the window title until replaced.
+    status: Option<String>,
+}
+
+/// The passes drawing a frame, rebuilt when the shaders change.
+struct Passes {
+    model_pass: OpaquePass,
+    ssr_pass: SsrPass,
+    transparent_pass: TransparentPass,
+    oit_pass: OitPass,
     skybox_pass: SkyboxPass,
     shadow_pass: ShadowPass,
     hdr_pipeline: HdrPipeline,
+    error_overlay: ErrorOverlay,
 }
 
-impl App {
-    pub fn new(context: &GpuContext, surface: &SurfaceContext) -> App {
-        let GpuContext { device, queue, .. } = context;
-
-        let layouts = Layouts::new(device);
-
-        // CAMERA
-        let camera_controller = CameraController::new(0.1, 0.1);
-        let camera = Camera::new(
-            (0.0, 2.0, 3.0),
-            Deg(-90.0),
-            Deg(0.0),
-            45.0,
-            surface.config().width as f32 / surface.config().height as f32,
-            0.01,
-            100.0,
-        );
-
-        // MODELS
-        let helmet_transform = Transform::new((0.0, 1.0, 0.0), Quaternion::zero(), (1.0, 1.0, 1.0));
-
-        let mut helmet = Resources::load_gltf(
+impl Passes {
+    fn new(
+        device: &wgpu::Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+        settings: &RenderSettings,
+    ) -> Passes {
+        let samples = settings.display.msaa;
+        let model_pass =
+            OpaquePass::new(device, shaders, config, layouts, settings.shading, samples);
+        let ssr_pass = SsrPass::new(
             device,
-            queue,
-            Path::new("./assets/models/damaged_helmet/DamagedHelmet.gltf"),
-        );
-
-        helmet.apply_transform(helmet_transform);
-
-        let flat_cube_transform = Transform::new(
-            (3.0, 1.5, -2.0),
-            Quaternion::from_angle_x(Deg(-90.0)),
-            (2.0, 2.0, 2.0),
+            shaders,
+            layouts,
+            model_pass.resolved_depth_view(),
+            config.width,
+            config.height,
+            samples,
         );
 
-        let flat_cube = Entity::new(
-            vec![Node {
-                mesh: Some(Mesh {
-                    primitives: vec![(Geometry::cube(), 0)],
-                }),
-                transform: Transform::zero(),
-                children: Vec::new(),
-            }],
-            vec![Material::new(
-                [1.0, 1.0, 1.0, 1.0],
-                Some(Resources::load_texture(
-                    device,
-                    queue,
-                    Path::new("./assets/textures/test.png"),
-                    Texture::SRGBA_UNORM,
-                )),
-                None,
-                0.0,
-                0.0,
-                None,
-                None,
-            )],
-            flat_cube_transform,
-        );
+        Passes {
+            model_pass,
+            ssr_pass,
+            transparent_pass: TransparentPass::new(device, shaders, layouts, samples),
+            oit_pass: OitPass::new(device, shaders, config, layouts, samples),
+            skybox_pass: SkyboxPass::new(device, shaders, layouts, samples),
+            shadow_pass: ShadowPass::new(device, shaders, layouts),
+            hdr_pipeline: HdrPipeline::new(device, shaders, config, layouts, samples),
+            error_overlay: ErrorOverlay::new(device, shaders, config, layouts),
+        }
+    }
 
-        let stone_cube_transform = Transform::new(
-            (-3.0, 1.5, 2.5),
-            Quaternion::zero(),
-            // (0.01, 0.01, 0.01),
-            (1.0, 1.0, 1.0),
-        );
+    /// Creates the pipelines the `world` needs, which the passes otherwise
+    /// create when they first draw it.
+    fn prepare(&mut self, device: &wgpu::Device, world: &RenderWorld, settings: &RenderSettings) {
+        self.model_pass.prepare(device, world, settings);
+        self.transparent_pass.prepare(device, world);
+        self.oit_pass.prepare(device, world);
+        self.shadow_pass.prepare(device, world);
+    }
+}
 
-        let mut stone_cube = Resources::load_gltf(
-            device,
-            queue,
-            Path::new("./assets/models/stone_cube/scene.gltf"),
-        );
+impl App {
+    pub fn new(
+        context: &GpuContext,
+        surface: &SurfaceContext,
+        settings: RenderSettings,
+        scene_file: SceneFile,
+    ) -> Result<App, String> {
+        let GpuContext { device, queue, .. } = context;
 
-        stone_cube.apply_transform(stone_cube_transform);
-
-        let shiba_transform =
-            Transform::new((-2.0, 1.0, -2.0), Quaternion::zero(), (1.0, 1.0, 1.0));
-
-        let mut shiba =
-            Resources::load_gltf(device, queue, Path::new("./assets/models/shiba/scene.gltf"));
-        shiba.apply_transform(shiba_transform);
-
-        let floor_transform =
-            Transform::new((0.0, 0.0, 0.0), Quaternion::zero(), (25.0, 25.0, 25.0));
-
-        let floor = Entity::new(
-            vec![Node {
-                mesh: Some(Mesh {
-                    primitives: vec![(Geometry::plane(), 0)],
-                }),
-                transform: Transform::zero(),
-                children: Vec::new(),
-            }],
-            vec![Material::new(
-                [1.0, 1.0, 1.0, 1.0],
-                Some(Resources::load_texture(
-                    device,
-                    queue,
-                    Path::new("./assets/textures/brick-wall/brick-wall_albedo.png"),
-                    Texture::SRGBA_UNORM,
-                )),
-                Some(Resources::load_texture(
-                    device,
-                    queue,
-                    Path::new("./assets/textures/brick-wall/brick-wall_normal-ogl.png"),
-                    Texture::RGBA_UNORM,
-                )),
-                1.0,
-                1.0,
-                Some(Resources::load_texture(
-                    device,
-                    queue,
-                    Path::new("./assets/textures/brick-wall/brick-wall_roughness.png"),
-                    Texture::SRGBA_UNORM,
-                )),
-                Some(Resources::load_texture(
-                    device,
-                    queue,
-                    Path::new("./assets/textures/brick-wall/brick-wall_ao.png"),
-                    Texture::SRGBA_UNORM,
-                )),
-            )],
-            floor_transform,
-        );
+        let layouts = Layouts::new(device, settings.bindless_materials);
 
-        // LIGHT
+        let shaders = ShaderLibrary::new(settings.dev_shaders);
+        shaders.capture_device_errors(device);
 
-        let light = PointLight::new((7.5, 5.0, -4.0), (150.0, 0.0, 0.0));
-        let second_light = PointLight::new((-5.0, 4.0, 10.0), (0.0, 0.0, 150.0));
-        let third_light = PointLight::new((-1.5, 5.0, 2.0), (150.0, 150.0, 150.0));
+        let camera_controller = CameraController::new(0.1, 0.1);
 
-        // NEW SKYBOX WITH HDR
+        let watcher = Arc::new(FileWatcher::new());
+        let texture_loader =
+            TextureLoader::new(device, &shaders, settings.anisotropy, watcher.clone());
+        let mut asset_server = AssetServer::new(device, &shaders, watcher);
 
-        let skybox_loader = SkyboxLoader::new(device);
+        // SCENE
 
-        let skybox = skybox_loader.load(
+        let LoadedScene {
+            scene,
+            camera,
+            models,
+            framed,
+        } = scene_file.load(
             device,
             queue,
-            Path::new("./assets/skybox/studio_2k.hdr"),
-            512,
-        );
-
-        // SCENE
-
-        let entities = vec![helmet, flat_cube, stone_cube, floor, shiba];
-
-        let lights = vec![light, second_light, third_light];
-
-        let scene = Scene {
-            entities,
-            lights,
-            skybox,
-        };
-
-        let model_pass = PbrPass::new(device, surface.config(), &layouts);
-        let skybox_pass = SkyboxPass::new(device, &layouts);
-        let shadow_pass = ShadowPass::new(device, &layouts);
-
-        let hdr_pipeline = HdrPipeline::new(device, surface.config(), &layouts);
-
-        App {
+            &texture_loader,
+            &mut asset_server,
+            surface.config().width as f32 / surface.config().height as f32,
+        )?;
+
+        // The models are added to the scene once loaded, at each of their
+        // transforms
+        let models = models
+            .into_iter()
+            .map(|model| {
+                let placement = ModelPlacement {
+                    materials: model.materials,
+                    instances: Instances::Pending(model.transforms),
+                };
+                (model.id, placement)
+            })
+            .collect();
+
+        let config = surface.config().clone();
+        let passes = Passes::new(device, &shaders, &config, &layouts, &settings);
+
+        Ok(App {
+            settings,
             layouts,
             camera_controller,
             camera,
             scene,
-            model_pass,
-            skybox_pass,
-            shadow_pass,
-            hdr_pipeline,
-        }
+            texture_loader,
+            asset_server,
+            models,
+            framed,
+            shaders,
+            config,
+            passes,
+            stats: FrameStats::default(),
+            status: None,
+        })
     }
 
     pub fn update(&mut self, event: Event<()>, elwt: &EventLoopWindowTarget<()>) {
@@ -246,6 +231,16 @@ impl App {
                     KeyCode::ShiftLeft => {
                         self.camera_controller.move_direction -= Vector3::unit_y()
                     }
+                    KeyCode::KeyT => self.settings.transparency = self.settings.transparency.next(),
+                    KeyCode::KeyG => self.settings.gpu_culling = !self.settings.gpu_culling,
+                    KeyCode::KeyP => self.settings.depth_prepass = !self.settings.depth_prepass,
+                    KeyCode::KeyO => self.settings.ssao.enabled = !self.settings.ssao.enabled,
+                    KeyCode::KeyR => self.settings.ssr.enabled = !self.settings.ssr.enabled,
+                    KeyCode::KeyL => self.settings.shadows = !self.settings.shadows,
+                    KeyCode::KeyM => {
+                        let memory = self.texture_loader.memory_usage();
+                        self.status = Some(format!("Textures: {memory}"));
+                    }
                     _ => {}
                 },
                 ElementState::Released => match keycode {
@@ -264,57 +259,258 @@ impl App {
     }
 
     pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, view: &TextureView) {
+        self.add_loaded_assets(device, queue);
+        self.check_material_table(device);
         self.camera_controller.update(&mut self.camera);
 
         let render_world = RenderWorld::extract(
             device,
             queue,
             &self.layouts,
-            &self.scene.entities,
+            &self.scene,
             &self.camera,
-            &self.scene.lights,
-            &self.scene.skybox,
+            &self.settings,
         );
+        self.reload_shaders(device, &render_world);
 
-        self.generate_shadow_maps(device, queue, &render_world);
-        self.skybox_pass.draw(
+        self.stats.shadow = match self.settings.shadows {
+            true => self.generate_shadow_maps(device, queue, &render_world),
+            false => DrawStats::default(),
+        };
+        self.passes.skybox_pass.draw(
             device,
             queue,
-            self.hdr_pipeline.view(),
+            self.passes.hdr_pipeline.target(),
             &render_world,
             &render_world.camera,
         );
-        self.model_pass.draw(
+        self.stats.opaque = self.passes.model_pass.draw(
             device,
             queue,
-            self.hdr_pipeline.view(),
+            self.passes.hdr_pipeline.target(),
             &render_world,
             &render_world.camera,
+            &self.settings,
         );
+        self.stats.gpu_cull = self.passes.model_pass.cull_stats();
+        self.passes.ssr_pass.draw(
+            device,
+            queue,
+            self.passes.hdr_pipeline.target(),
+            &render_world,
+            &render_world.camera,
+            self.passes.model_pass.surface(),
+            &self.settings.ssr,
+        );
+
+        self.stats.transparent = match self.settings.transparency {
+            TransparencyMode::Sorted => self.passes.transparent_pass.draw(
+                device,
+                queue,
+                self.passes.hdr_pipeline.target(),
+                self.passes.model_pass.depth_view(),
+                &render_world,
+                &render_world.camera,
+            ),
+            TransparencyMode::WeightedBlended => {
+                let stats = self.passes.oit_pass.draw(
+                    device,
+                    queue,
+                    self.passes.model_pass.depth_view(),
+                    &render_world,
+                    &render_world.camera,
+                );
+                self.passes
+                    .oit_pass
+                    .composite(device, queue, self.passes.hdr_pipeline.view());
+                stats
+            }
+        };
 
-        self.hdr_pipeline.process(device, queue, view);
+        self.passes.hdr_pipeline.process(device, queue, view);
+        if let Some(error) = self.shaders.error() {
+            self.passes.error_overlay.draw(
+                device,
+                queue,
+                view,
+                &error,
+                self.config.width,
+                self.config.height,
+            );
+        }
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
         self.camera.update_aspect(width as f32 / height as f32);
+        self.config.width = width;
+        self.config.height = height;
+
+        self.passes
+            .model_pass
+            .resize(device, &self.layouts, width, height);
+        self.passes.ssr_pass.resize(
+            device,
+            &self.layouts,
+            self.passes.model_pass.resolved_depth_view(),
+            width,
+            height,
+        );
+        self.passes.oit_pass.resize(device, width, height);
+        self.passes.hdr_pipeline.resize(device, width, height);
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
 
-        self.model_pass.resize(device, width, height);
-        self.hdr_pipeline.resize(device, width, height);
+    pub fn load_progress(&self) -> LoadProgress {
+        self.asset_server.progress()
+    }
+
+    /// Binds each material on its own once the textures of the scene do not
+    /// fit in the bindless material table anymore.
+    fn check_material_table(&mut self, device: &wgpu::Device) {
+        if !self.layouts.bindless || RenderWorld::material_table_fits(&self.scene, &self.layouts) {
+            return;
+        }
+
+        let message = format!(
+            "The scene has more textures than the {} of the material table, binding each \
+             material on its own",
+            self.layouts.material_texture_capacity
+        );
+        eprintln!("{message}");
+        self.status = Some(message);
+
+        self.layouts = Layouts::new(device, false);
+        self.passes = Passes::new(
+            device,
+            &self.shaders,
+            &self.config,
+            &self.layouts,
+            &self.settings,
+        );
+    }
+
+    /// The errors of the shaders in development mode.
+    pub fn shader_error(&self) -> Option<String> {
+        self.shaders.error()
+    }
+
+    /// Rebuilds the pipelines when a shader changed in development mode. The
+    /// previous ones are kept if wgpu rejects the new ones, so the pipelines
+    /// of the `world` are created right away to be checked too.
+    fn reload_shaders(&mut self, device: &wgpu::Device, world: &RenderWorld) {
+        if !self.shaders.changed() {
+            return;
+        }
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let mut passes = Passes::new(
+            device,
+            &self.shaders,
+            &self.config,
+            &self.layouts,
+            &self.settings,
+        );
+        passes.prepare(device, world, &self.settings);
+        let mip_generator = MipGenerator::new(device, &self.shaders);
+        let skybox_loader = SkyboxLoader::new(device, &self.shaders);
+
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            self.shaders.report_error(error.to_string());
+            return;
+        }
+
+        self.passes = passes;
+        self.texture_loader.mip_generator = mip_generator;
+        self.asset_server.reload_skyboxes(skybox_loader);
+
+        if self.shaders.error().is_none() {
+            self.status = Some("Reloaded the shaders".to_string());
+        }
+    }
+
+    fn add_loaded_assets(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        for event in self
+            .asset_server
+            .update(device, queue, &self.texture_loader)
+        {
+            match event {
+                LoadEvent::Loaded(id, Asset::Model(entity)) => {
+                    let Some(placement) = self.models.get_mut(&id) else {
+                        continue;
+                    };
+                    let entities = &mut self.scene.entities;
+                    let entity = match &placement.materials {
+                        Some(overrides) => entity.with_material_overrides(overrides),
+                        None => entity,
+                    };
+
+                    // Reloaded instances keep the transforms of the ones they replace
+                    match &mut placement.instances {
+                        Instances::Pending(transforms) => {
+                            let indices = entities.len()..entities.len() + transforms.len();
+                            entities.extend(
+                                transforms
+                                    .iter()
+                                    .map(|transform| entity.instantiate(*transform)),
+                            );
+                            if self.framed == Some(id) {
+                                let bounds = entities[indices.clone()]
+                                    .iter()
+                                    .filter_map(Entity::bounds)
+                                    .flat_map(|aabb| [aabb.min, aabb.max]);
+                                self.camera.frame(&Aabb::from_points(bounds));
+                            }
+                            placement.instances = Instances::Placed(indices.collect());
+                        }
+                        Instances::Placed(indices) => {
+                            for index in indices {
+                                entities[*index] = entity.instantiate(entities[*index].transform);
+                            }
+                        }
+                    }
+                }
+                LoadEvent::Loaded(_, Asset::Skybox(skybox)) => self.scene.skybox = *skybox,
+                LoadEvent::TextureReloaded(path) => {
+                    self.status = Some(format!("Reloaded {}", path.display()));
+                }
+                LoadEvent::Failed { path, error, .. } => {
+                    let error = format!("Failed to load {}: {error}", path.display());
+                    eprintln!("{error}");
+                    self.status = Some(error);
+                }
+            }
+        }
     }
 
     fn generate_shadow_maps(
-        &self,
+        &mut self,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         render_world: &RenderWorld,
-    ) {
+    ) -> DrawStats {
+        let mut stats = DrawStats::default();
+
         for light in render_world.lights.iter() {
             let shadow_map = &light.shadow_map;
             for (camera_index, camera) in light.shadow_cameras.iter().enumerate() {
                 let shadow_map_view = &shadow_map.create_face_view(camera_index);
-                self.shadow_pass
-                    .draw(device, queue, shadow_map_view, render_world, camera);
+                stats += self.passes.shadow_pass.draw(
+                    device,
+                    queue,
+                    shadow_map_view,
+                    render_world,
+                    camera,
+                );
             }
         }
+
+        stats
     }
 }
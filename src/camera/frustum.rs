@@ -0,0 +1,138 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, Vector4};
+
+use crate::entity::{Aabb, BoundingSphere};
+
+/// The six planes of a view projection, pointing inwards. Planes are stored as
+/// (normal, distance) so a point p is inside when `normal · p + distance >= 0`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix (Gribb and Hartmann, 2001).
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Frustum {
+        let row = |i| view_proj.row(i);
+
+        let planes = [
+            row(3) + row(0), // left
+            row(3) - row(0), // right
+            row(3) + row(1), // bottom
+            row(3) - row(1), // top
+            row(3) + row(2), // near
+            row(3) - row(2), // far
+        ]
+        .map(|plane| plane / plane.truncate().magnitude());
+
+        Frustum { planes }
+    }
+
+    pub fn planes(&self) -> [Vector4<f32>; 6] {
+        self.planes
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center.to_vec()) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // Corner of the box that is furthest along the plane normal
+            let x = if plane.x >= 0.0 {
+                aabb.max.x
+            } else {
+                aabb.min.x
+            };
+            let y = if plane.y >= 0.0 {
+                aabb.max.y
+            } else {
+                aabb.min.y
+            };
+            let z = if plane.z >= 0.0 {
+                aabb.max.z
+            } else {
+                aabb.min.z
+            };
+
+            plane.x * x + plane.y * y + plane.z * z + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Point3};
+
+    use crate::camera::Camera;
+
+    use super::*;
+
+    /// Looking towards -z from the origin, 90 degrees wide and high, from 0.1
+    /// to 100 units away. At z = -10 it spans from -10 to 10 in x and y.
+    fn frustum() -> Frustum {
+        Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0), 90.0, 1.0, 0.1, 100.0).get_frustum()
+    }
+
+    fn cube(center: (f32, f32, f32), half_size: f32) -> Aabb {
+        let (x, y, z) = center;
+        Aabb::new(
+            (x - half_size, y - half_size, z - half_size),
+            (x + half_size, y + half_size, z + half_size),
+        )
+    }
+
+    #[test]
+    fn planes_point_inwards() {
+        let inside = Point3::new(0.0, 0.0, -10.0);
+
+        for plane in frustum().planes() {
+            assert!(plane.truncate().dot(inside.to_vec()) + plane.w > 0.0);
+        }
+    }
+
+    #[test]
+    fn box_inside() {
+        assert!(frustum().intersects_aabb(&cube((0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum().intersects_aabb(&cube((5.0, -5.0, -50.0), 2.0)));
+    }
+
+    #[test]
+    fn box_outside() {
+        let frustum = frustum();
+
+        // Behind, past the far plane, and beside each side plane
+        assert!(!frustum.intersects_aabb(&cube((0.0, 0.0, 10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube((0.0, 0.0, -150.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube((-15.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube((15.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube((0.0, -15.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_aabb(&cube((0.0, 15.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn box_straddling_a_plane() {
+        let frustum = frustum();
+
+        // Across the right, the top, the near and the far planes
+        assert!(frustum.intersects_aabb(&cube((10.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_aabb(&cube((0.0, 10.0, -10.0), 1.0)));
+        assert!(frustum.intersects_aabb(&cube((0.0, 0.0, 0.0), 1.0)));
+        assert!(frustum.intersects_aabb(&cube((0.0, 0.0, -100.0), 1.0)));
+    }
+
+    #[test]
+    fn spheres() {
+        let frustum = frustum();
+        let sphere = |center, radius| BoundingSphere {
+            center: Point3::from(center),
+            radius,
+        };
+
+        assert!(frustum.intersects_sphere(&sphere((0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_sphere(&sphere((10.5, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere((0.0, 0.0, 5.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere((20.0, 0.0, -10.0), 1.0)));
+    }
+}
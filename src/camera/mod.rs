@@ -1,7 +1,12 @@
 mod camera_controller;
+mod frustum;
+
 use cgmath::{perspective, Angle, Deg, InnerSpace, Matrix4, Point3, Rad, Vector3};
 
 pub use camera_controller::CameraController;
+pub use frustum::Frustum;
+
+use crate::entity::Aabb;
 
 pub struct Camera {
     pub position: Point3<f32>,
@@ -89,10 +94,36 @@ impl Camera {
         perspective(Deg(self.fovy), self.aspect, self.near, self.far)
     }
 
+    pub fn get_frustum(&self) -> Frustum {
+        Frustum::from_matrix(self.get_projection() * self.get_view())
+    }
+
     pub fn update_aspect(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
 
+    /// Nearest near plane [Camera::frame] moves to, keeping the depth precision.
+    const MIN_NEAR: f32 = 0.001;
+
+    /// Moves the camera in front of the box, looking towards -z at its
+    /// center, close enough to see all of it. An empty box leaves it as is.
+    pub fn frame(&mut self, aabb: &Aabb) {
+        let sphere = aabb.bounding_sphere();
+        if !(sphere.radius > 0.0 && sphere.radius.is_finite()) {
+            return;
+        }
+
+        let distance = sphere.radius / (Deg(self.fovy) / 2.0).sin();
+
+        self.position = sphere.center + Vector3::new(0.0, 0.0, distance);
+        self.yaw = Deg(-90.0).into();
+        self.pitch = Rad(0.0);
+        self.update_directions();
+
+        self.near = self.near.min(distance * 0.1).max(Self::MIN_NEAR);
+        self.far = self.far.max(2.0 * (distance + sphere.radius));
+    }
+
     pub(self) fn update_directions(&mut self) {
         let (look_dir, right, forward) = calculate_local_directions(self.yaw, self.pitch, self.up);
 
@@ -117,3 +148,45 @@ fn calculate_local_directions(
 
     (look_dir, right, forward)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::new(
+            (0.0, 2.0, 3.0),
+            Deg(-90.0),
+            Deg(0.0),
+            45.0,
+            1.0,
+            0.01,
+            100.0,
+        )
+    }
+
+    #[test]
+    fn frames_the_whole_box() {
+        let mut camera = camera();
+        camera.frame(&Aabb::new((-1.0, -1.0, -1.0), (1.0, 1.0, 1.0)));
+
+        let distance = 3.0f32.sqrt() / Deg(22.5).sin();
+        assert!((camera.position.z - distance).abs() < 1e-4);
+        assert!(camera.near > 0.0 && camera.near <= 0.01);
+        assert!(camera.far >= 2.0 * distance);
+
+        // A tiny model keeps a near plane in front of the camera
+        camera.frame(&Aabb::new((0.0, 0.0, 0.0), (1e-6, 1e-6, 1e-6)));
+        assert_eq!(camera.near, Camera::MIN_NEAR);
+    }
+
+    #[test]
+    fn keeps_the_camera_for_empty_bounds() {
+        let mut camera = camera();
+        camera.frame(&Aabb::from_points(Vec::<Point3<f32>>::new()));
+
+        assert_eq!(camera.position, Point3::new(0.0, 2.0, 3.0));
+        assert_eq!(camera.near, 0.01);
+        assert_eq!(camera.far, 100.0);
+    }
+}
@@ -0,0 +1,100 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform, Vector3};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new<T: Into<Point3<f32>>>(min: T, max: T) -> Aabb {
+        Aabb {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    /// Smallest box containing all the points, or an empty box at the origin if there are none.
+    pub fn from_points<T: Into<Point3<f32>>>(points: impl IntoIterator<Item = T>) -> Aabb {
+        let mut points = points.into_iter().map(Into::into);
+
+        let Some(first) = points.next() else {
+            return Aabb::new(Point3::origin(), Point3::origin());
+        };
+
+        points.fold(Aabb::new(first, first), |aabb, point| Aabb {
+            min: Point3::new(
+                aabb.min.x.min(point.x),
+                aabb.min.y.min(point.y),
+                aabb.min.z.min(point.z),
+            ),
+            max: Point3::new(
+                aabb.max.x.max(point.x),
+                aabb.max.y.max(point.y),
+                aabb.max.z.max(point.z),
+            ),
+        })
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box enclosing this one after being transformed by the matrix (Arvo, 1990).
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Aabb {
+        let center = matrix.transform_point(self.center());
+        let extents = self.half_extents();
+
+        let half_extents = Vector3::new(
+            matrix.x.x.abs() * extents.x
+                + matrix.y.x.abs() * extents.y
+                + matrix.z.x.abs() * extents.z,
+            matrix.x.y.abs() * extents.x
+                + matrix.y.y.abs() * extents.y
+                + matrix.z.y.abs() * extents.z,
+            matrix.x.z.abs() * extents.x
+                + matrix.y.z.abs() * extents.y
+                + matrix.z.z.abs() * extents.z,
+        );
+
+        Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.center(),
+            radius: self.half_extents().magnitude(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere enclosing this one after being transformed by the matrix. Non uniform
+    /// scales grow the radius by the largest axis scale.
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> BoundingSphere {
+        let scale = Vector3::new(
+            matrix.x.truncate().magnitude(),
+            matrix.y.truncate().magnitude(),
+            matrix.z.truncate().magnitude(),
+        );
+
+        BoundingSphere {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale.x.max(scale.y).max(scale.z),
+        }
+    }
+}
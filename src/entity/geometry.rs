@@ -1,13 +1,26 @@
-use super::Vertex;
+use super::{Aabb, BoundingSphere, Vertex};
 
 pub struct Geometry {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u16>,
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
 }
 
 impl Geometry {
     pub fn new(vertices: Vec<Vertex>, indices: Vec<u16>) -> Geometry {
-        Geometry { vertices, indices }
+        let aabb = Aabb::from_points(vertices.iter().map(|vertex| vertex.position));
+        Geometry::with_aabb(vertices, indices, aabb)
+    }
+
+    /// Uses precomputed bounds, e.g. the min/max of a glTF position accessor.
+    pub fn with_aabb(vertices: Vec<Vertex>, indices: Vec<u16>, aabb: Aabb) -> Geometry {
+        Geometry {
+            vertices,
+            indices,
+            aabb,
+            bounding_sphere: aabb.bounding_sphere(),
+        }
     }
 
     pub fn plane() -> Geometry {
@@ -1,17 +1,23 @@
+mod bounds;
 mod geometry;
 mod mesh;
 mod node;
 mod vertex;
 
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
-pub use self::{geometry::*, mesh::Mesh, node::*, vertex::Vertex};
+use cgmath::Matrix4;
 
-use crate::{material::Material, transform::Transform};
+pub use self::{bounds::*, geometry::*, mesh::Mesh, node::*, vertex::Vertex};
+
+use crate::{
+    material::{Material, MaterialOverrides},
+    transform::Transform,
+};
 
 pub struct Entity {
     nodes: Vec<Node>,
-    materials: Vec<Material>,
+    materials: Arc<Vec<Material>>,
     pub transform: Transform,
 }
 
@@ -28,12 +34,32 @@ impl Entity {
     pub fn new(nodes: Vec<Node>, materials: Vec<Material>, transform: Transform) -> Entity {
         Entity {
             nodes,
-            materials,
+            materials: Arc::new(materials),
             transform,
         }
     }
 
-    pub fn get_materials(&self) -> &Vec<Material> {
+    /// Creates another entity that shares the meshes and materials of this one,
+    /// placed with its own [Transform].
+    pub fn instantiate(&self, transform: Transform) -> Entity {
+        Entity {
+            nodes: self.nodes.clone(),
+            materials: self.materials.clone(),
+            transform,
+        }
+    }
+
+    /// Creates another entity with the same meshes and its materials changed
+    /// by the `overrides`.
+    pub fn with_material_overrides(&self, overrides: &MaterialOverrides) -> Entity {
+        Entity {
+            nodes: self.nodes.clone(),
+            materials: Arc::new(self.materials.iter().map(|m| overrides.apply(m)).collect()),
+            transform: self.transform,
+        }
+    }
+
+    pub fn get_materials(&self) -> &Arc<Vec<Material>> {
         &self.materials
     }
 
@@ -41,7 +67,27 @@ impl Entity {
         &self.nodes
     }
 
-    pub fn apply_transform(&mut self, transform: Transform) {
-        self.transform = transform;
+    /// World space box enclosing the meshes, if there are any.
+    pub fn bounds(&self) -> Option<Aabb> {
+        let mut boxes = Vec::new();
+        collect_bounds(&self.nodes, self.transform.model(), &mut boxes);
+
+        boxes
+            .into_iter()
+            .reduce(|a, b| Aabb::from_points([a.min, a.max, b.min, b.max]))
+    }
+}
+
+fn collect_bounds(nodes: &[Node], parent_model_matrix: Matrix4<f32>, boxes: &mut Vec<Aabb>) {
+    for node in nodes {
+        let model_matrix = parent_model_matrix * node.transform.model();
+
+        if let Some(mesh) = &node.mesh {
+            for (geometry, _) in &mesh.primitives {
+                boxes.push(geometry.aabb.transform(&model_matrix));
+            }
+        }
+
+        collect_bounds(&node.children, model_matrix, boxes);
     }
 }
@@ -1,13 +1,16 @@
 use crate::transform::Transform;
 
-use std::fmt::Debug;
+use std::{fmt::Debug, sync::Arc};
 
 use super::Mesh;
 
+/// Nodes can share their [Mesh], in which case the render world draws all of
+/// them with a single instanced draw call.
+#[derive(Clone)]
 pub struct Node {
     pub transform: Transform,
     pub children: Vec<Node>,
-    pub mesh: Option<Mesh>,
+    pub mesh: Option<Arc<Mesh>>,
 }
 
 impl Debug for Node {
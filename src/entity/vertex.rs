@@ -1,7 +1,11 @@
+use std::mem::{offset_of, size_of};
+
+use bytemuck::{Pod, Zeroable};
 use wgpu::{VertexAttribute, VertexBufferLayout};
 
+/// Packed tightly, so the vertices are uploaded as they are.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub uv: [f32; 2],
@@ -21,7 +25,7 @@ impl<'a> Vertex {
 
     pub fn desc() -> VertexBufferLayout<'a> {
         VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as u64,
+            array_stride: size_of::<Vertex>() as u64,
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 VertexAttribute {
@@ -31,20 +35,69 @@ impl<'a> Vertex {
                 },
                 VertexAttribute {
                     format: wgpu::VertexFormat::Float32x2,
-                    offset: (std::mem::size_of::<f32>() * 3) as u64,
+                    offset: offset_of!(Vertex, uv) as u64,
                     shader_location: 1,
                 },
                 VertexAttribute {
                     format: wgpu::VertexFormat::Float32x3,
-                    offset: (std::mem::size_of::<f32>() * 5) as u64,
+                    offset: offset_of!(Vertex, normal) as u64,
                     shader_location: 2,
                 },
                 VertexAttribute {
                     format: wgpu::VertexFormat::Float32x4,
-                    offset: (std::mem::size_of::<f32>() * 8) as u64,
+                    offset: offset_of!(Vertex, tangent) as u64,
                     shader_location: 3,
                 },
             ],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use naga::{Binding, Scalar, TypeInner};
+
+    use crate::shader_library::ShaderLibrary;
+
+    use super::*;
+
+    /// Vertex inputs are not laid out like buffers, so the attributes are
+    /// checked against the locations and types of the WGSL struct instead.
+    #[test]
+    fn vertex_matches_wgsl() {
+        let file = "shaders/include/vertex.wgsl";
+        let module = ShaderLibrary::reflect(file, &[]);
+        let (_, ty) = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some("Vertex"))
+            .unwrap_or_else(|| panic!("{file} has no struct Vertex"));
+        let TypeInner::Struct { members, .. } = &ty.inner else {
+            panic!("Vertex of {file} is not a struct");
+        };
+
+        let desc = Vertex::desc();
+        assert_eq!(members.len(), desc.attributes.len());
+
+        let mut offset = 0;
+        for (member, attribute) in members.iter().zip(desc.attributes) {
+            let name = member.name.as_deref().unwrap_or_default();
+
+            let Some(Binding::Location { location, .. }) = member.binding else {
+                panic!("Vertex.{name} of {file} has no location");
+            };
+            assert_eq!(location, attribute.shader_location, "Vertex.{name}");
+
+            let TypeInner::Vector { size, scalar } = module.types[member.ty].inner else {
+                panic!("Vertex.{name} of {file} is not a vector");
+            };
+            assert_eq!(scalar, Scalar::F32, "Vertex.{name}");
+            assert_eq!(size as u64 * 4, attribute.format.size(), "Vertex.{name}");
+
+            // Packed tightly, in the order of the WGSL struct
+            assert_eq!(attribute.offset, offset, "Vertex.{name}");
+            offset += attribute.format.size();
+        }
+        assert_eq!(offset, desc.array_stride);
+    }
+}
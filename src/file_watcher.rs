@@ -0,0 +1,109 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Reports the files that changed on disk among the watched ones. Shared
+/// between the loaders, which watch the files they read.
+pub struct FileWatcher {
+    /// None when the platform watcher can not be created, nothing is
+    /// reported then.
+    watcher: Option<Mutex<RecommendedWatcher>>,
+    events: Mutex<Receiver<notify::Result<Event>>>,
+    state: Mutex<WatchState>,
+}
+
+#[derive(Default)]
+struct WatchState {
+    files: HashSet<PathBuf>,
+    directories: HashSet<PathBuf>,
+    /// Changed files with the time of their last event.
+    changes: HashMap<PathBuf, Instant>,
+}
+
+impl FileWatcher {
+    /// Editors save in several steps, so a file is only reported once it was
+    /// left untouched for this long.
+    const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+    pub fn new() -> FileWatcher {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)
+            .map_err(|error| eprintln!("Can not watch the files for changes: {error}"))
+            .ok();
+
+        FileWatcher {
+            watcher: watcher.map(Mutex::new),
+            events: Mutex::new(events),
+            state: Mutex::new(WatchState::default()),
+        }
+    }
+
+    /// Returns the canonical path the changes are reported with.
+    pub fn watch(&self, path: &Path) -> PathBuf {
+        let path = canonicalize(path);
+        let mut state = self.state.lock().unwrap();
+
+        // The directory is watched instead of the file, since saving often
+        // replaces the file with a new one
+        if let (Some(directory), Some(watcher)) = (path.parent(), &self.watcher) {
+            if state.directories.insert(directory.to_path_buf()) {
+                let result = watcher
+                    .lock()
+                    .unwrap()
+                    .watch(directory, RecursiveMode::NonRecursive);
+                if let Err(error) = result {
+                    eprintln!("Can not watch {}: {error}", directory.display());
+                }
+            }
+        }
+
+        state.files.insert(path.clone());
+        path
+    }
+
+    /// The watched files that changed since the last call.
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut state = self.state.lock().unwrap();
+
+        for event in self.events.lock().unwrap().try_iter() {
+            let Ok(event) = event else {
+                continue;
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                continue;
+            }
+
+            for path in event.paths {
+                let path = canonicalize(&path);
+                if state.files.contains(&path) {
+                    state.changes.insert(path, Instant::now());
+                }
+            }
+        }
+
+        let settled = state
+            .changes
+            .iter()
+            .filter(|(_, time)| time.elapsed() >= Self::SETTLE_TIME)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+
+        for path in &settled {
+            state.changes.remove(path);
+        }
+
+        settled
+    }
+}
+
+pub fn canonicalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -1,59 +1,242 @@
+use std::sync::Arc;
+
 use wgpu::{
-    DeviceDescriptor, Features, Instance, InstanceDescriptor, Limits, MemoryHints,
+    Backends, DeviceDescriptor, Features, Instance, InstanceDescriptor, Limits, MemoryHints,
     RequestAdapterOptions,
 };
 
-use crate::surface_context::SurfaceContext;
+use winit::window::Window;
+
+use crate::{
+    layouts::Layouts, settings::DisplaySettings, surface_context::SurfaceContext, texture::Texture,
+};
 
 pub struct GpuContext {
-    pub instance: wgpu::Instance,
-    _adapter: wgpu::Adapter,
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
 }
 
 impl GpuContext {
-    pub fn new(surface: &SurfaceContext) -> GpuContext {
-        let instance = Instance::new(InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
-
-        let adapter = pollster::block_on(async {
-            instance
-                .request_adapter(&RequestAdapterOptions {
-                    power_preference: wgpu::PowerPreference::HighPerformance,
-                    compatible_surface: surface.get(),
-                    force_fallback_adapter: false,
-                })
-                .await
-        })
-        .unwrap();
+    /// Creates the surface of the `window` first, so the adapter picked can
+    /// present to it. Fails when there is no such adapter for the backends,
+    /// the one at the index of the settings can not present to it, or it can
+    /// not draw the samples per pixel of the MSAA.
+    pub fn new(
+        surface: &mut SurfaceContext,
+        window: Arc<Window>,
+        settings: &DisplaySettings,
+    ) -> Result<GpuContext, String> {
+        let instance = create_instance(settings.backends);
+        surface.create(&instance, window)?;
+
+        let adapter = match settings.adapter {
+            Some(index) => {
+                let adapter = instance
+                    .enumerate_adapters(settings.backends)
+                    .into_iter()
+                    .nth(index)
+                    .ok_or_else(|| format!("There is no adapter {index}, see --list-adapters"))?;
+
+                if surface
+                    .get()
+                    .is_some_and(|surface| !adapter.is_surface_supported(surface))
+                {
+                    let name = adapter.get_info().name;
+                    return Err(format!(
+                        "Adapter {index} ({name}) can not present to the window, see \
+                         --list-adapters"
+                    ));
+                }
+
+                adapter
+            }
+            None => pollster::block_on(async {
+                instance
+                    .request_adapter(&RequestAdapterOptions {
+                        power_preference: wgpu::PowerPreference::HighPerformance,
+                        compatible_surface: surface.get(),
+                        force_fallback_adapter: false,
+                    })
+                    .await
+            })
+            .ok_or_else(|| {
+                format!(
+                    "There is no adapter for the {:?} backends presenting to the window",
+                    settings.backends
+                )
+            })?,
+        };
+
+        check_msaa(&adapter, settings.msaa)?;
+
+        let (required_features, required_limits) =
+            device_requirements(adapter.features(), &adapter.limits());
 
         let (device, queue) = pollster::block_on(async {
             adapter
                 .request_device(
                     &DeviceDescriptor {
                         label: Some("Device"),
-                        required_features: Features::TEXTURE_BINDING_ARRAY | Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING | Features::DEPTH_CLIP_CONTROL | Features::PUSH_CONSTANTS,
-                        required_limits: Limits{
-                            max_push_constant_size: 4,
-                            max_bind_groups: 5,
-                            ..Default::default()
-                        },
-                        memory_hints: MemoryHints::default()
+                        required_features,
+                        required_limits,
+                        memory_hints: MemoryHints::default(),
                     },
                     None,
                 )
                 .await
         })
-        .unwrap();
+        .map_err(|error| {
+            let name = adapter.get_info().name;
+            format!("Can not create a device on {name}: {error}")
+        })?;
 
-        GpuContext {
-            instance,
-            _adapter: adapter,
+        Ok(GpuContext {
+            adapter,
             device,
             queue,
+        })
+    }
+
+    /// Prints the adapters of the backends, with the indices `--adapter`
+    /// takes.
+    pub fn list_adapters(backends: Backends) {
+        let adapters = create_instance(backends).enumerate_adapters(backends);
+        if adapters.is_empty() {
+            println!("There is no adapter for the {backends:?} backends");
+        }
+
+        for (index, adapter) in adapters.iter().enumerate() {
+            let info = adapter.get_info();
+            let driver = format!("{} {}", info.driver, info.driver_info);
+            println!(
+                "{index}: {} ({:?}, {:?}, {})",
+                info.name,
+                info.backend,
+                info.device_type,
+                driver.trim()
+            );
         }
     }
 }
+
+/// The features and limits to request from an adapter supporting the
+/// `features` and `limits`. The optional features are only requested when
+/// supported, the paths using them fall back otherwise.
+fn device_requirements(features: Features, limits: &Limits) -> (Features, Limits) {
+    // Used by the GPU driven path, the compressed textures, the bindless
+    // material table and the MSAA sample counts when available
+    let optional_features = features
+        & (Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            | Features::INDIRECT_FIRST_INSTANCE
+            | Features::MULTI_DRAW_INDIRECT
+            | Features::TEXTURE_COMPRESSION_BC
+            | Features::TEXTURE_COMPRESSION_ETC2
+            | Features::TEXTURE_COMPRESSION_ASTC
+            | Layouts::BINDLESS_FEATURES);
+
+    let mut required_limits = Limits {
+        max_push_constant_size: 4,
+        max_bind_groups: 6,
+        ..Default::default()
+    };
+
+    // Sized by the bindless material table
+    if optional_features.contains(Layouts::BINDLESS_FEATURES) {
+        required_limits.max_sampled_textures_per_shader_stage =
+            limits.max_sampled_textures_per_shader_stage;
+        required_limits.max_samplers_per_shader_stage = limits.max_samplers_per_shader_stage;
+    }
+
+    (
+        Features::DEPTH_CLIP_CONTROL | Features::PUSH_CONSTANTS | optional_features,
+        required_limits,
+    )
+}
+
+/// Checks the targets drawn with MSAA can have the `samples`. Without the
+/// adapter specific format features only 4 samples are guaranteed.
+fn check_msaa(adapter: &wgpu::Adapter, samples: u32) -> Result<(), String> {
+    if samples == 1 {
+        return Ok(());
+    }
+
+    let specific = adapter
+        .features()
+        .contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let formats = [
+        Texture::RGBA_16_FLOAT,
+        Texture::R_16_FLOAT,
+        Texture::SRGBA_UNORM,
+        Texture::DEPTH_32_FLOAT,
+    ];
+
+    for format in formats {
+        let flags = adapter.get_texture_format_features(format).flags;
+        let supported = match specific {
+            true => flags.sample_count_supported(samples),
+            false => samples == 4,
+        };
+        if !supported {
+            let name = adapter.get_info().name;
+            return Err(format!(
+                "--msaa: {name} can not draw {samples} samples per pixel of {format:?}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn create_instance(backends: Backends) -> Instance {
+    Instance::new(InstanceDescriptor {
+        backends,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A device of the default adapter without any optional feature, or None
+    /// when there is no adapter to run the test on.
+    pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = Instance::new(InstanceDescriptor::default());
+        let Some(adapter) = pollster::block_on(instance.request_adapter(&Default::default()))
+        else {
+            eprintln!("There is no adapter, skipping the test");
+            return None;
+        };
+
+        let descriptor = DeviceDescriptor {
+            required_limits: adapter.limits(),
+            ..Default::default()
+        };
+        Some(pollster::block_on(adapter.request_device(&descriptor, None)).unwrap())
+    }
+
+    #[test]
+    fn bindless_features_are_optional() {
+        let limits = Limits {
+            max_sampled_textures_per_shader_stage: 500_000,
+            max_samplers_per_shader_stage: 1_000,
+            ..Limits::default()
+        };
+
+        let (features, required_limits) = device_requirements(Features::empty(), &limits);
+        assert!(!features.intersects(Layouts::BINDLESS_FEATURES));
+        assert_eq!(
+            required_limits.max_sampled_textures_per_shader_stage,
+            Limits::default().max_sampled_textures_per_shader_stage
+        );
+
+        let (features, required_limits) = device_requirements(Layouts::BINDLESS_FEATURES, &limits);
+        assert!(features.contains(Layouts::BINDLESS_FEATURES));
+        assert_eq!(
+            required_limits.max_sampled_textures_per_shader_stage,
+            500_000
+        );
+        assert_eq!(required_limits.max_samplers_per_shader_stage, 1_000);
+    }
+}
@@ -2,55 +2,201 @@ use std::num::NonZeroU32;
 
 use wgpu::{
     BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
-    BufferBindingType, Device, SamplerBindingType, ShaderStages,
+    BufferBindingType, Device, Features, Limits, SamplerBindingType, ShaderStages,
 };
 
 pub struct Layouts {
     pub camera: BindGroupLayout,
     pub transform: BindGroupLayout,
+    /// Either the bind group of a single material or the bindless material
+    /// table, depending on [Layouts::bindless].
     pub material: BindGroupLayout,
+    pub bindless: bool,
+    /// Size of the texture and sampler arrays of the material table.
+    pub material_texture_capacity: u32,
     pub light: BindGroupLayout,
     pub cube_map: BindGroupLayout,
     pub texture: BindGroupLayout,
+    pub cull: BindGroupLayout,
+    pub depth_pyramid: BindGroupLayout,
+    pub gbuffer: BindGroupLayout,
+    /// A depth texture read by the screen space passes.
+    pub depth: BindGroupLayout,
+    /// The normal and roughness, the albedo and metallic of the opaque surfaces,
+    /// written by both shading paths for the screen space reflections.
+    pub surface: BindGroupLayout,
 }
 
 impl Layouts {
-    pub fn new(device: &Device) -> Layouts {
-        let camera = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Camera bind group layout"),
-            entries: &[BindGroupLayoutEntry {
+    /// The most point lights of a scene, each with its shadow map.
+    pub const LIGHTS: u32 = 3;
+    /// Needed by the bindless material table, which is optional.
+    pub const BINDLESS_FEATURES: Features = Features::TEXTURE_BINDING_ARRAY
+        .union(Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
+    /// Textures and samplers left for the other groups of the fragment stage.
+    const RESERVED_TEXTURES: u32 = 16;
+    const MAX_MATERIAL_TEXTURES: u32 = 1024;
+    const MIN_MATERIAL_TEXTURES: u32 = 64;
+
+    /// The bindless material table is used when requested and the device can
+    /// index arrays of textures and samplers with a non uniform material id.
+    pub fn new(device: &Device, bindless: bool) -> Layouts {
+        let (bindless, material_texture_capacity) =
+            Self::bindless_support(bindless, device.features(), &device.limits());
+
+        let entries = LayoutEntries::new(bindless, material_texture_capacity);
+        let create = |label, entries: &[BindGroupLayoutEntry]| {
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries,
+            })
+        };
+
+        // Named after what the material group holds
+        let material_label = match bindless {
+            true => "Material table bind group layout",
+            false => "Material bind group layout",
+        };
+
+        Layouts {
+            camera: create("Camera bind group layout", &entries.camera),
+            transform: create("Transform bind group layout", &entries.transform),
+            material: create(material_label, &entries.material),
+            bindless,
+            material_texture_capacity,
+            light: create("Light bind group layout", &entries.light),
+            cube_map: create("Cube map bind group layout", &entries.cube_map),
+            texture: create("Texture bind group layout", &entries.texture),
+            cull: create("Cull bind group layout", &entries.cull),
+            depth_pyramid: create("Depth pyramid bind group layout", &entries.depth_pyramid),
+            gbuffer: create("G-buffer bind group layout", &entries.gbuffer),
+            depth: create("Depth bind group layout", &entries.depth),
+            surface: create("Surface bind group layout", &entries.surface),
+        }
+    }
+
+    /// Whether the material table is used, and the size of its texture and
+    /// sampler arrays on a device with the `features` and `limits`.
+    fn bindless_support(requested: bool, features: Features, limits: &Limits) -> (bool, u32) {
+        let capacity = limits
+            .max_sampled_textures_per_shader_stage
+            .min(limits.max_samplers_per_shader_stage)
+            .saturating_sub(Self::RESERVED_TEXTURES)
+            .min(Self::MAX_MATERIAL_TEXTURES);
+
+        let bindless = requested
+            && features.contains(Layouts::BINDLESS_FEATURES)
+            && capacity >= Self::MIN_MATERIAL_TEXTURES;
+
+        (bindless, capacity)
+    }
+
+    /// The layouts of a pipeline, `own` standing for [Group::Own].
+    pub fn groups<'a>(
+        &'a self,
+        groups: &[Group],
+        own: &[&'a BindGroupLayout],
+    ) -> Vec<&'a BindGroupLayout> {
+        let mut own = own.iter();
+        groups
+            .iter()
+            .map(|group| match group {
+                Group::Camera => &self.camera,
+                Group::Transform => &self.transform,
+                Group::Material => &self.material,
+                Group::Light => &self.light,
+                Group::CubeMap => &self.cube_map,
+                Group::Texture => &self.texture,
+                Group::Cull => &self.cull,
+                Group::DepthPyramid => &self.depth_pyramid,
+                Group::GBuffer => &self.gbuffer,
+                Group::Depth => &self.depth,
+                Group::Surface => &self.surface,
+                Group::Own => own.next().expect("A layout for each own group"),
+            })
+            .collect()
+    }
+}
+
+/// A group of a pipeline layout. Each pass lists its groups once, for its
+/// pipeline layout and for the tests checking its shader against them.
+#[derive(Clone, Copy, Debug)]
+pub enum Group {
+    Camera,
+    Transform,
+    Material,
+    Light,
+    CubeMap,
+    Texture,
+    Cull,
+    DepthPyramid,
+    GBuffer,
+    Depth,
+    Surface,
+    /// A layout the pass creates itself.
+    Own,
+}
+
+/// The entries of the layouts, known without a device so the shaders can be
+/// checked against them.
+pub struct LayoutEntries {
+    pub camera: Vec<BindGroupLayoutEntry>,
+    pub transform: Vec<BindGroupLayoutEntry>,
+    pub material: Vec<BindGroupLayoutEntry>,
+    pub light: Vec<BindGroupLayoutEntry>,
+    pub cube_map: Vec<BindGroupLayoutEntry>,
+    pub texture: Vec<BindGroupLayoutEntry>,
+    pub cull: Vec<BindGroupLayoutEntry>,
+    pub depth_pyramid: Vec<BindGroupLayoutEntry>,
+    pub gbuffer: Vec<BindGroupLayoutEntry>,
+    pub depth: Vec<BindGroupLayoutEntry>,
+    pub surface: Vec<BindGroupLayoutEntry>,
+}
+
+impl LayoutEntries {
+    pub fn new(bindless: bool, material_texture_capacity: u32) -> LayoutEntries {
+        let camera = vec![BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::all(),
+            ty: BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+
+        let transform = vec![
+            BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::all(),
+                visibility: ShaderStages::VERTEX,
                 ty: BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
-            }],
-        });
-        let transform = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Transform bind group layout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
+            },
+            // Material id of every instance
+            BindGroupLayoutEntry {
+                binding: 1,
                 visibility: ShaderStages::VERTEX,
                 ty: BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
-            }],
-        });
+            },
+        ];
 
-        let material = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Material bind group layout"),
-            entries: &[
+        let material = if bindless {
+            vec![
                 BindGroupLayoutEntry {
                     binding: 0,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
+                        ty: BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -60,7 +206,7 @@ impl Layouts {
                     binding: 1,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                    count: None,
+                    count: NonZeroU32::new(material_texture_capacity),
                 },
                 BindGroupLayoutEntry {
                     binding: 2,
@@ -70,16 +216,29 @@ impl Layouts {
                         view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false,
                     },
+                    count: NonZeroU32::new(material_texture_capacity),
+                },
+            ]
+        } else {
+            vec![
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 3,
+                    binding: 1,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 4,
+                    binding: 2,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
@@ -89,13 +248,13 @@ impl Layouts {
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 5,
+                    binding: 3,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 6,
+                    binding: 4,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
@@ -105,13 +264,13 @@ impl Layouts {
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 7,
+                    binding: 5,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 8,
+                    binding: 6,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
@@ -120,93 +279,334 @@ impl Layouts {
                     },
                     count: None,
                 },
-            ],
-        });
-
-        let light = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Light bind group layout"),
-            entries: &[
                 BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: 7,
                     visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 8,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
                         sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false,
                     },
-                    count: NonZeroU32::new(3),
+                    count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 2,
+                    binding: 9,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                    count: NonZeroU32::new(3),
-                },
-            ],
-        });
-
-        // TODO: Put view before sampler for consistency
-        let cube_map = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Cube map bind group layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: 10,
                     visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false,
                     },
                     count: None,
                 },
-            ],
+            ]
+        };
+
+        // The shadow maps of the lights share a sampler
+        let shadow_maps = (0..Layouts::LIGHTS).map(|light| BindGroupLayoutEntry {
+            binding: 2 + light,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
         });
 
-        let texture = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Texture bind group layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
+        let light = [
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                    count: None,
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+        .into_iter()
+        .chain(shadow_maps)
+        .collect();
+
+        // TODO: Put view before sampler for consistency
+        let cube_map = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
                 },
-            ],
-        });
+                count: None,
+            },
+        ];
 
-        Layouts {
+        let texture = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+
+        let cull = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+
+        let depth_pyramid = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ];
+
+        // Albedo, normal, metallic roughness and ambient occlussion, emissive
+        let gbuffer = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ];
+
+        let depth = vec![BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }];
+
+        // Normal and roughness, albedo and metallic
+        let surface = vec![
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ];
+
+        LayoutEntries {
             camera,
             transform,
             material,
             light,
             cube_map,
             texture,
+            cull,
+            depth_pyramid,
+            gbuffer,
+            depth,
+            surface,
         }
     }
+
+    /// The entries of a pipeline's groups, `None` for [Group::Own].
+    #[cfg(test)]
+    pub fn groups(&self, groups: &[Group]) -> Vec<Option<&[BindGroupLayoutEntry]>> {
+        groups
+            .iter()
+            .map(|group| match group {
+                Group::Camera => Some(self.camera.as_slice()),
+                Group::Transform => Some(self.transform.as_slice()),
+                Group::Material => Some(self.material.as_slice()),
+                Group::Light => Some(self.light.as_slice()),
+                Group::CubeMap => Some(self.cube_map.as_slice()),
+                Group::Texture => Some(self.texture.as_slice()),
+                Group::Cull => Some(self.cull.as_slice()),
+                Group::DepthPyramid => Some(self.depth_pyramid.as_slice()),
+                Group::GBuffer => Some(self.gbuffer.as_slice()),
+                Group::Depth => Some(self.depth.as_slice()),
+                Group::Surface => Some(self.surface.as_slice()),
+                Group::Own => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_context::tests::test_device;
+
+    use super::*;
+
+    #[test]
+    fn uses_the_material_table_when_supported() {
+        let limits = |textures| Limits {
+            max_sampled_textures_per_shader_stage: textures,
+            max_samplers_per_shader_stage: textures,
+            ..Limits::default()
+        };
+        let features = Layouts::BINDLESS_FEATURES;
+
+        assert_eq!(
+            Layouts::bindless_support(true, features, &limits(500_000)),
+            (true, 1024)
+        );
+        assert_eq!(
+            Layouts::bindless_support(true, features, &limits(96)),
+            (true, 80)
+        );
+        assert!(!Layouts::bindless_support(false, features, &limits(500_000)).0);
+        assert!(!Layouts::bindless_support(true, Features::TEXTURE_BINDING_ARRAY, &limits(96)).0);
+        // The default limits leave too few textures for the table
+        assert!(!Layouts::bindless_support(true, features, &limits(16)).0);
+    }
+
+    #[test]
+    fn falls_back_without_bindless_features() {
+        let Some((device, _queue)) = test_device() else {
+            return;
+        };
+
+        // Any invalid layout panics in the uncaptured error handler
+        let layouts = Layouts::new(&device, true);
+        assert!(!layouts.bindless);
+    }
 }
@@ -1,6 +1,7 @@
 mod app;
 mod camera;
 mod entity;
+mod file_watcher;
 mod gpu_context;
 mod layouts;
 mod light;
@@ -9,25 +10,61 @@ mod render_pass;
 mod render_world;
 mod resources;
 mod scene;
+mod settings;
+#[cfg(test)]
+mod shader_bindings;
+mod shader_library;
+mod shader_types;
 mod skybox;
+mod stats;
 mod surface_context;
 mod texture;
 mod transform;
 mod window_context;
 
+use std::{
+    process::exit,
+    time::{Duration, Instant},
+};
+
 use app::App;
 use gpu_context::GpuContext;
+use resources::Resources;
+use settings::Command;
 use surface_context::SurfaceContext;
 use window_context::WindowContext;
 use winit::event::{Event, WindowEvent};
 
 fn main() {
-    let window_loop = WindowContext::new();
+    let settings = match Command::from_args(std::env::args().skip(1)) {
+        Ok(Command::View(settings)) => settings,
+        Ok(Command::ListAdapters(backends)) => {
+            GpuContext::list_adapters(backends);
+            return;
+        }
+        Ok(Command::Help) => {
+            println!("{}", Command::USAGE);
+            return;
+        }
+        Err(error) => fail(error),
+    };
+
+    let scene_file = Resources::read_scene(&settings.scene, settings.environment.as_deref())
+        .unwrap_or_else(|error| fail(error));
+
+    let window_loop = WindowContext::new(&settings.display);
     let mut surface = SurfaceContext::new();
-    let context = GpuContext::new(&surface);
-    surface.init(&context, window_loop.window.clone());
+    let context = GpuContext::new(&mut surface, window_loop.window.clone(), &settings.display)
+        .unwrap_or_else(|error| fail(error));
+    surface.init(
+        &context,
+        window_loop.window.inner_size(),
+        settings.display.present_mode,
+    );
 
-    let mut app = App::new(&context, &surface);
+    let mut app =
+        App::new(&context, &surface, settings, scene_file).unwrap_or_else(|error| fail(error));
+    let mut last_stats_update = Instant::now();
 
     window_loop
         .event_loop
@@ -48,6 +85,26 @@ fn main() {
                 app.render(&context.device, &context.queue, &view);
 
                 output.present();
+
+                if last_stats_update.elapsed() >= Duration::from_secs(1) {
+                    let progress = app.load_progress();
+                    let mut title = match progress.is_done() {
+                        true => format!("{} - {}", WindowContext::TITLE, app.stats()),
+                        false => {
+                            format!("{} - {} - {}", WindowContext::TITLE, progress, app.stats())
+                        }
+                    };
+                    if let Some(status) = app.status() {
+                        title = format!("{title} - {status}");
+                    }
+                    // The whole diagnostic is drawn over the frame
+                    if let Some(error) = app.shader_error() {
+                        let line = error.lines().find(|line| !line.trim().is_empty());
+                        title = format!("{title} - Shader error: {}", line.unwrap_or_default());
+                    }
+                    window_loop.window.set_title(&title);
+                    last_stats_update = Instant::now();
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
@@ -66,3 +123,8 @@ fn main() {
         })
         .unwrap();
 }
+
+fn fail(error: String) -> ! {
+    eprintln!("{error}");
+    exit(1)
+}
@@ -1,25 +1,58 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
 use crate::texture::Texture;
 
+/// How the alpha channel of the base color is interpreted, mirroring glTF's `alphaMode`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fragments with an alpha below the cutoff are discarded.
+    Mask(f32),
+    Blend,
+}
+
+impl AlphaMode {
+    pub fn cutoff(&self) -> f32 {
+        match self {
+            AlphaMode::Mask(cutoff) => *cutoff,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The textures are shared with the other materials using them, see
+/// [crate::resources::TextureLoader].
 pub struct Material {
     pub base_color: [f32; 4],
-    pub base_texture: Option<Texture>,
-    pub normal_texture: Option<Texture>,
+    pub base_texture: Option<Arc<Texture>>,
+    pub normal_texture: Option<Arc<Texture>>,
     pub metallic_factor: f32,
     pub roughness_factor: f32,
-    pub metallic_roughness_texture: Option<Texture>,
-    pub ambient_occlussion_texture: Option<Texture>,
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    pub ambient_occlussion_texture: Option<Arc<Texture>>,
+    /// Linear color emitted, multiplied by the emissive texture.
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<Arc<Texture>>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
 }
 
 impl Material {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         base_color: [f32; 4],
-        base_texture: Option<Texture>,
-        normal_texture: Option<Texture>,
+        base_texture: Option<Arc<Texture>>,
+        normal_texture: Option<Arc<Texture>>,
         metallic_factor: f32,
         roughness_factor: f32,
-        metallic_roughness_texture: Option<Texture>,
-        ambient_occlussion_texture: Option<Texture>,
+        metallic_roughness_texture: Option<Arc<Texture>>,
+        ambient_occlussion_texture: Option<Arc<Texture>>,
+        emissive_factor: [f32; 3],
+        emissive_texture: Option<Arc<Texture>>,
+        alpha_mode: AlphaMode,
+        double_sided: bool,
     ) -> Material {
         Material {
             base_color,
@@ -29,6 +62,54 @@ impl Material {
             roughness_factor,
             metallic_roughness_texture,
             ambient_occlussion_texture,
+            emissive_factor,
+            emissive_texture,
+            alpha_mode,
+            double_sided,
+        }
+    }
+}
+
+/// Parameters replacing the ones of a material, the others are kept.
+#[derive(Default)]
+pub struct MaterialOverrides {
+    pub base_color: Option<[f32; 4]>,
+    pub base_texture: Option<Arc<Texture>>,
+    pub normal_texture: Option<Arc<Texture>>,
+    pub metallic_factor: Option<f32>,
+    pub roughness_factor: Option<f32>,
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    pub ambient_occlussion_texture: Option<Arc<Texture>>,
+    pub emissive_factor: Option<[f32; 3]>,
+    pub emissive_texture: Option<Arc<Texture>>,
+    pub alpha_mode: Option<AlphaMode>,
+    pub double_sided: Option<bool>,
+}
+
+impl MaterialOverrides {
+    pub fn apply(&self, material: &Material) -> Material {
+        let texture = |replacement: &Option<Arc<Texture>>, texture: &Option<Arc<Texture>>| {
+            replacement.as_ref().or(texture.as_ref()).cloned()
+        };
+
+        Material {
+            base_color: self.base_color.unwrap_or(material.base_color),
+            base_texture: texture(&self.base_texture, &material.base_texture),
+            normal_texture: texture(&self.normal_texture, &material.normal_texture),
+            metallic_factor: self.metallic_factor.unwrap_or(material.metallic_factor),
+            roughness_factor: self.roughness_factor.unwrap_or(material.roughness_factor),
+            metallic_roughness_texture: texture(
+                &self.metallic_roughness_texture,
+                &material.metallic_roughness_texture,
+            ),
+            ambient_occlussion_texture: texture(
+                &self.ambient_occlussion_texture,
+                &material.ambient_occlussion_texture,
+            ),
+            emissive_factor: self.emissive_factor.unwrap_or(material.emissive_factor),
+            emissive_texture: texture(&self.emissive_texture, &material.emissive_texture),
+            alpha_mode: self.alpha_mode.unwrap_or(material.alpha_mode),
+            double_sided: self.double_sided.unwrap_or(material.double_sided),
         }
     }
 }
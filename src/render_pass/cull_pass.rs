@@ -0,0 +1,276 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use encase::{ShaderType, StorageBuffer};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, Maintain, MapMode, PipelineLayoutDescriptor, PushConstantRange, Queue, ShaderStages,
+};
+
+use crate::{
+    layouts::{Group, Layouts},
+    render_world::IndirectDraws,
+    shader_library::ShaderLibrary,
+    stats::{CullCounts, CullStats},
+};
+
+use super::depth_pyramid::DepthPyramid;
+
+/// Occlusion culling runs in two phases, so objects that become visible this
+/// frame are never missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullPhase {
+    /// Culls against the frustum and the depth pyramid of the previous frame.
+    First,
+    /// Re-tests the objects the first phase found occluded against the depth
+    /// pyramid built from what the first phase drew.
+    Second,
+    /// Culls every object against the frustum and the pyramid of the complete
+    /// depth prepass, for the shading pass that follows it.
+    Final,
+}
+
+/// Culls the opaque objects on the GPU, writing the indirect draw commands the
+/// [PbrPass](super::PbrPass) draws with.
+pub struct CullPass {
+    pipeline: ComputePipeline,
+    counts: CountsReadback,
+}
+
+/// The objects each phase counts, copied to a buffer that is mapped while the
+/// next frames render, so reading them never waits for the GPU.
+struct CountsReadback {
+    counts: Buffer,
+    bind_group: BindGroup,
+    readback: Buffer,
+    /// Set by the map callback once the readback buffer can be read.
+    mapped: Arc<AtomicBool>,
+    /// The object count of the frame being mapped, if one is.
+    pending: Option<u32>,
+    stats: Option<CullStats>,
+}
+
+impl CullPass {
+    /// The groups of the culling pipeline.
+    pub const BIND_GROUPS: [Group; 3] = [Group::Cull, Group::DepthPyramid, Group::Own];
+
+    pub fn new(device: &Device, shaders: &ShaderLibrary, layouts: &Layouts) -> CullPass {
+        let shader = shaders.module(device, "shaders/cull.wgsl", &[]);
+
+        let counts_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Cull counts bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Cull pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[&counts_layout]),
+            push_constant_ranges: &[PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..4,
+            }],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Cull pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let counts = device.create_buffer(&BufferDescriptor {
+            label: Some("Cull counts buffer"),
+            size: CountsReadback::SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Cull counts bind group"),
+            layout: &counts_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: counts.as_entire_binding(),
+            }],
+        });
+
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Cull counts readback buffer"),
+            size: CountsReadback::SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        CullPass {
+            pipeline,
+            counts: CountsReadback {
+                counts,
+                bind_group,
+                readback,
+                mapped: Arc::new(AtomicBool::new(false)),
+                pending: None,
+                stats: None,
+            },
+        }
+    }
+
+    pub fn cull(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        draws: &IndirectDraws,
+        depth_pyramid: &DepthPyramid,
+        phase: CullPhase,
+    ) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Cull Encoder"),
+        });
+
+        // The phases of a frame add to the counts
+        if phase == CullPhase::First {
+            encoder.clear_buffer(&self.counts.counts, 0, None);
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+
+        let phase = match phase {
+            CullPhase::First => 0u32,
+            CullPhase::Second => 1u32,
+            CullPhase::Final => 2u32,
+        };
+
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, draws, &[]);
+        compute_pass.set_bind_group(1, depth_pyramid, &[]);
+        compute_pass.set_bind_group(2, &self.counts.bind_group, &[]);
+        compute_pass.set_push_constants(0, &phase.to_ne_bytes());
+        compute_pass.dispatch_workgroups(draws.workgroup_count(), 1, 1);
+
+        drop(compute_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+    }
+
+    /// Starts reading back the counts of the frame the `draws` were culled
+    /// in, unless an earlier frame is still being read, and returns the last
+    /// counts read.
+    pub fn read_back(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        draws: &IndirectDraws,
+    ) -> Option<CullStats> {
+        let counts = &mut self.counts;
+        device.poll(Maintain::Poll);
+
+        if let Some(objects) = counts.pending {
+            if !counts.mapped.swap(false, Ordering::Acquire) {
+                return counts.stats;
+            }
+
+            let bytes = counts.readback.slice(..).get_mapped_range();
+            counts.stats = Some(CountsReadback::stats(objects, &bytes));
+            drop(bytes);
+            counts.readback.unmap();
+            counts.pending = None;
+        }
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Cull counts readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&counts.counts, 0, &counts.readback, 0, CountsReadback::SIZE);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let mapped = counts.mapped.clone();
+        counts
+            .readback
+            .slice(..)
+            .map_async(MapMode::Read, move |result| match result {
+                Ok(()) => mapped.store(true, Ordering::Release),
+                Err(error) => eprintln!("Can not read back the cull counts: {error}"),
+            });
+        counts.pending = Some(draws.object_count);
+
+        counts.stats
+    }
+}
+
+impl CountsReadback {
+    /// A visible and an occluded count for each phase.
+    const SIZE: u64 = 3 * 2 * std::mem::size_of::<u32>() as u64;
+
+    fn stats(objects: u32, bytes: &[u8]) -> CullStats {
+        let [first, second, last]: [PhaseCounts; 3] = StorageBuffer::new(bytes).create().unwrap();
+        let counts = |phase: PhaseCounts| CullCounts {
+            visible: phase.visible,
+            occluded: phase.occluded,
+        };
+
+        CullStats {
+            objects,
+            first: counts(first),
+            second: counts(second),
+            last: counts(last),
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct PhaseCounts {
+    visible: u32,
+    occluded: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn phase_counts_match_wgsl() {
+        assert_layout::<PhaseCounts>("shaders/cull.wgsl", &[], "PhaseCounts");
+    }
+
+    #[test]
+    fn reads_the_counts_of_each_phase() {
+        let bytes = [10u32, 4, 3, 1, 12, 2]
+            .iter()
+            .flat_map(|count| count.to_le_bytes())
+            .collect::<Vec<_>>();
+        let stats = CountsReadback::stats(20, &bytes);
+
+        assert_eq!(
+            stats.first,
+            CullCounts {
+                visible: 10,
+                occluded: 4
+            }
+        );
+        assert_eq!(stats.drawn(), 13);
+        assert_eq!(stats.culled(), 7);
+        assert_eq!(
+            stats.to_string(),
+            "20 objects, 6 outside, 10/4 visible/occluded then 3/1, 12/2 on the prepass"
+        );
+    }
+}
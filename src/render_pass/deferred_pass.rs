@@ -0,0 +1,337 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, Color, ColorTargetState,
+    ColorWrites, CommandEncoderDescriptor, CompareFunction, Device, FrontFace, LoadOp, Operations,
+    PipelineLayoutDescriptor, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, StoreOp, SurfaceConfiguration, TextureUsages,
+    TextureView,
+};
+
+use crate::{
+    entity::Vertex,
+    layouts::{Group, Layouts},
+    render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    settings::RenderSettings,
+    shader_library::ShaderLibrary,
+    stats::DrawStats,
+    texture::Texture,
+};
+
+use super::{
+    pipeline::{create_pipeline, PipelineCache, PipelineTemplate},
+    ssao_pass::SsaoPass,
+    ssr_pass::create_surface_bind_group,
+};
+
+/// Deferred alternative to the [PbrPass](super::PbrPass). The opaque objects
+/// write their surfaces into the G-buffer, which a fullscreen pass then lights
+/// with the same BRDF and shadows as the forward path, so the cost of the
+/// lights only depends on the covered pixels. The G-buffer is drawn with the
+/// CPU frustum culling.
+pub struct DeferredPass {
+    pipelines: PipelineCache,
+    /// Without and with shadows.
+    lighting_pipelines: [RenderPipeline; 2],
+    gbuffer: GBuffer,
+    ssao: SsaoPass,
+}
+
+/// The surface of the nearest opaque object at every pixel.
+struct GBuffer {
+    /// Albedo and metallic.
+    albedo: Texture,
+    /// Normal and roughness.
+    normal: Texture,
+    /// Metallic, roughness and ambient occlussion.
+    material: Texture,
+    emissive: Texture,
+    depth_texture: Texture,
+    bind_group: BindGroup,
+    depth_bind_group: BindGroup,
+    /// The albedo and normal, for the screen space reflections.
+    surface_bind_group: BindGroup,
+}
+
+impl DeferredPass {
+    /// The groups of the g-buffer pipelines.
+    pub const GBUFFER_GROUPS: [Group; 3] = [Group::Camera, Group::Transform, Group::Material];
+    /// The groups of the lighting pipeline.
+    pub const LIGHTING_GROUPS: [Group; 6] = [
+        Group::Camera,
+        Group::GBuffer,
+        Group::Depth,
+        Group::Light,
+        Group::CubeMap,
+        Group::Texture,
+    ];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+    ) -> DeferredPass {
+        // G-BUFFER
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("G-buffer pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::GBUFFER_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let target = |format| ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        };
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            PipelineTemplate {
+                label: "G-buffer pipeline",
+                layout,
+                fragment_entry_point: "fs_gbuffer",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![
+                    target(Texture::SRGBA_UNORM),
+                    target(Texture::RGBA_16_FLOAT),
+                    target(Texture::RGBA_UNORM),
+                    target(Texture::RGBA_16_FLOAT),
+                ],
+                material_blend: false,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::LessEqual,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples: 1,
+            },
+        );
+
+        // LIGHTING
+        let lighting_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Deferred lighting pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::LIGHTING_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let lighting_pipeline = |defines: &[&str]| {
+            create_pipeline(
+                device,
+                &lighting_layout,
+                &[],
+                Texture::RGBA_16_FLOAT,
+                None,
+                None,
+                1,
+                &shaders.module(device, "shaders/deferred.wgsl", defines),
+            )
+        };
+        let lighting_pipelines = [lighting_pipeline(&[]), lighting_pipeline(&["SHADOWS"])];
+
+        let gbuffer = GBuffer::new(device, layouts, config.width, config.height);
+
+        // SSAO
+        let ssao = SsaoPass::new(
+            device,
+            shaders,
+            layouts,
+            &gbuffer.depth_texture.view,
+            config.width,
+            config.height,
+        );
+
+        DeferredPass {
+            pipelines,
+            lighting_pipelines,
+            gbuffer,
+            ssao,
+        }
+    }
+
+    /// Creates the pipelines the `world` needs, see [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld) {
+        self.pipelines.prepare(device, world.pipeline_keys());
+    }
+
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        view: &TextureView,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        settings: &RenderSettings,
+    ) -> DrawStats {
+        self.prepare(device, world);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Deferred render Encoder"),
+        });
+
+        let gbuffer = &self.gbuffer;
+        let clear = |view| {
+            Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("G-buffer Pass"),
+            color_attachments: &[
+                clear(&gbuffer.albedo.view),
+                clear(&gbuffer.normal.view),
+                clear(&gbuffer.material.view),
+                clear(&gbuffer.emissive.view),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &gbuffer.depth_texture.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(0, camera, &[]);
+        let mut stats = render_pass.draw_world(world, camera, &self.pipelines);
+
+        drop(render_pass);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.ssao.draw(device, queue, camera, &settings.ssao);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Deferred lighting Encoder"),
+        });
+
+        // Shades every covered pixel on top of the skybox
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Deferred lighting Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.lighting_pipelines[settings.shadows as usize]);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &gbuffer.bind_group, &[]);
+        render_pass.set_bind_group(2, &gbuffer.depth_bind_group, &[]);
+        render_pass.set_bind_group(3, &world.lights_bind_group, &[]);
+        render_pass.set_bind_group(4, &world.skybox.irr_map_bind_group, &[]);
+        render_pass.set_bind_group(5, &self.ssao, &[]);
+        render_pass.draw(0..3, 0..1);
+        stats.draw_calls += 1;
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+
+        stats
+    }
+
+    /// Exposes the depth texture of the opaque geometry
+    pub fn depth_view(&self) -> &TextureView {
+        &self.gbuffer.depth_texture.view
+    }
+
+    /// Binds the surface of the opaque geometry stored in the G-buffer.
+    pub fn surface(&self) -> &BindGroup {
+        &self.gbuffer.surface_bind_group
+    }
+
+    pub fn resize(&mut self, device: &Device, layouts: &Layouts, width: u32, height: u32) {
+        self.gbuffer = GBuffer::new(device, layouts, width, height);
+        self.ssao.resize(
+            device,
+            layouts,
+            &self.gbuffer.depth_texture.view,
+            width,
+            height,
+        );
+    }
+}
+
+impl GBuffer {
+    fn new(device: &Device, layouts: &Layouts, width: u32, height: u32) -> GBuffer {
+        let target = |label, format| {
+            Texture::new(
+                device,
+                width,
+                height,
+                Some(label),
+                format,
+                TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            )
+        };
+
+        let albedo = target("G-buffer albedo texture", Texture::SRGBA_UNORM);
+        let normal = target("G-buffer normal texture", Texture::RGBA_16_FLOAT);
+        let material = target("G-buffer material texture", Texture::RGBA_UNORM);
+        let emissive = target("G-buffer emissive texture", Texture::RGBA_16_FLOAT);
+        let depth_texture = target("Depth texture", Texture::DEPTH_32_FLOAT);
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("G-buffer bind group"),
+            layout: &layouts.gbuffer,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&albedo.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&normal.view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&material.view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&emissive.view),
+                },
+            ],
+        });
+
+        let depth_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("G-buffer depth bind group"),
+            layout: &layouts.depth,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&depth_texture.view),
+            }],
+        });
+
+        let surface_bind_group = create_surface_bind_group(device, layouts, &normal, &albedo);
+
+        GBuffer {
+            albedo,
+            normal,
+            material,
+            emissive,
+            depth_texture,
+            bind_group,
+            depth_bind_group,
+            surface_bind_group,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use wgpu::{
+    CommandEncoderDescriptor, CompareFunction, Device, FrontFace, LoadOp, Operations,
+    PipelineLayoutDescriptor, Queue, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    StoreOp, TextureView,
+};
+
+use crate::{
+    entity::Vertex,
+    layouts::{Group, Layouts},
+    render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    shader_library::ShaderLibrary,
+    stats::DrawStats,
+    texture::Texture,
+};
+
+use super::{
+    cull_pass::CullPhase,
+    pipeline::{PipelineCache, PipelineTemplate},
+};
+
+/// Writes the depth of the opaque and alpha masked objects before they are
+/// shaded, so the [PbrPass](super::PbrPass) only shades the visible fragments.
+/// Only the alpha masked materials run a fragment shader, the others keep the
+/// early depth test.
+pub struct DepthPrepass {
+    pipelines: PipelineCache,
+}
+
+impl DepthPrepass {
+    /// The groups of the depth only pipelines.
+    pub const BIND_GROUPS: [Group; 3] = [Group::Camera, Group::Transform, Group::Material];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        samples: u32,
+    ) -> DepthPrepass {
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Depth prepass pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            PipelineTemplate {
+                label: "Depth prepass pipeline",
+                layout,
+                fragment_entry_point: "fs_prepass",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![],
+                material_blend: false,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::LessEqual,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples,
+            },
+        );
+
+        DepthPrepass { pipelines }
+    }
+
+    /// Creates the pipelines the `world` needs, see [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld) {
+        self.pipelines.prepare(device, world.pipeline_keys());
+    }
+
+    /// Draws with the CPU path when no phase is given, or with the indirect
+    /// commands of the given cull phase. Only the second phase keeps the depth
+    /// that is already there.
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        depth_view: &TextureView,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        phase: Option<CullPhase>,
+    ) -> DrawStats {
+        self.prepare(device, world);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Depth prepass Encoder"),
+        });
+
+        let depth_load = match phase {
+            Some(CullPhase::Second) => LoadOp::Load,
+            _ => LoadOp::Clear(1.0),
+        };
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Depth prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(Operations {
+                    load: depth_load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(0, camera, &[]);
+
+        let stats = match world.indirect() {
+            Some(draws) if phase.is_some() => {
+                render_pass.draw_world_indirect(world, draws, &self.pipelines)
+            }
+            _ => render_pass.draw_world(world, camera, &self.pipelines),
+        };
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+
+        stats
+    }
+}
@@ -0,0 +1,363 @@
+use std::ops::Deref;
+
+use cgmath::{Matrix4, SquareMatrix, Vector2};
+use encase::ShaderType;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, Extent3d, PipelineLayoutDescriptor, Queue, ShaderStages, StorageTextureAccess,
+    TextureDescriptor, TextureDimension, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::{
+    layouts::Layouts, shader_library::ShaderLibrary, shader_types::uniform_bytes, texture::Texture,
+};
+
+/// Hierarchical depth buffer of the opaque geometry, each mip level keeping the
+/// farthest or nearest depth of the level below. The [CullPass](super::CullPass)
+/// tests the object bounds against the farthest depth to skip the ones hidden
+/// behind other geometry.
+pub struct DepthPyramid {
+    copy_pipeline: ComputePipeline,
+    downsample_pipeline: ComputePipeline,
+    copy_layout: BindGroupLayout,
+    downsample_layout: BindGroupLayout,
+    targets: PyramidTargets,
+}
+
+/// Everything that depends on the size of the depth texture.
+struct PyramidTargets {
+    size: (u32, u32),
+    mip_count: u32,
+    copy_bind_group: BindGroup,
+    /// Reads a level and writes the next one, starting at the second level.
+    downsample_bind_groups: Vec<BindGroup>,
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+/// Which depth of the level below each level of a [DepthPyramid] keeps.
+#[derive(Clone, Copy, Debug)]
+pub enum DepthReduction {
+    /// Nothing is in front of it, for occlusion culling.
+    Farthest,
+    /// Nothing is behind it, for tracing rays against the depth.
+    Nearest,
+}
+
+impl DepthPyramid {
+    const WORKGROUP_SIZE: u32 = 8;
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        reduction: DepthReduction,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> DepthPyramid {
+        let shader = shaders.module(device, "shaders/depth_pyramid.wgsl", &[]);
+
+        let destination = BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: Texture::R_32_FLOAT,
+                view_dimension: TextureViewDimension::D2,
+            },
+            count: None,
+        };
+
+        let copy_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Depth pyramid copy layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                destination,
+            ],
+        });
+
+        let downsample_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Depth pyramid downsample layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                destination,
+            ],
+        });
+
+        let create_pipeline = |layout: &BindGroupLayout, entry_point: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Depth pyramid pipeline layout"),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("Depth pyramid pipeline"),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+
+        let copy_pipeline = create_pipeline(&copy_layout, "cs_copy");
+        let downsample_entry_point = match reduction {
+            DepthReduction::Farthest => "cs_downsample",
+            DepthReduction::Nearest => "cs_downsample_nearest",
+        };
+        let downsample_pipeline = create_pipeline(&downsample_layout, downsample_entry_point);
+
+        let targets = PyramidTargets::new(
+            device,
+            layouts,
+            &copy_layout,
+            &downsample_layout,
+            depth_view,
+            width,
+            height,
+        );
+
+        DepthPyramid {
+            copy_pipeline,
+            downsample_pipeline,
+            copy_layout,
+            downsample_layout,
+            targets,
+        }
+    }
+
+    /// Rebuilds the pyramid from the current content of the depth texture,
+    /// which was rendered with the `view_proj` camera.
+    pub fn build(&self, device: &Device, queue: &Queue, view_proj: Matrix4<f32>) {
+        let targets = &self.targets;
+        targets.write_uniform(queue, view_proj);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Depth pyramid Encoder"),
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Depth pyramid Pass"),
+            timestamp_writes: None,
+        });
+
+        let workgroups = |level: u32| {
+            let width = (targets.size.0 >> level).max(1);
+            let height = (targets.size.1 >> level).max(1);
+            (
+                width.div_ceil(Self::WORKGROUP_SIZE),
+                height.div_ceil(Self::WORKGROUP_SIZE),
+            )
+        };
+
+        compute_pass.set_pipeline(&self.copy_pipeline);
+        compute_pass.set_bind_group(0, &targets.copy_bind_group, &[]);
+        let (x, y) = workgroups(0);
+        compute_pass.dispatch_workgroups(x, y, 1);
+
+        compute_pass.set_pipeline(&self.downsample_pipeline);
+        for (level, bind_group) in (1..targets.mip_count).zip(&targets.downsample_bind_groups) {
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            let (x, y) = workgroups(level);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+
+        drop(compute_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+    }
+
+    /// The pyramid needs to be built again before it is used for culling.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.targets = PyramidTargets::new(
+            device,
+            layouts,
+            &self.copy_layout,
+            &self.downsample_layout,
+            depth_view,
+            width,
+            height,
+        );
+    }
+}
+
+impl Deref for DepthPyramid {
+    type Target = BindGroup;
+
+    fn deref(&self) -> &Self::Target {
+        &self.targets.bind_group
+    }
+}
+
+impl PyramidTargets {
+    fn new(
+        device: &Device,
+        layouts: &Layouts,
+        copy_layout: &BindGroupLayout,
+        downsample_layout: &BindGroupLayout,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> PyramidTargets {
+        let mip_count = u32::BITS - width.max(height).leading_zeros();
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Depth pyramid texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mip_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Texture::R_32_FLOAT,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+
+        let mip_views = (0..mip_count)
+            .map(|level| {
+                texture.create_view(&TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let copy_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Depth pyramid copy bind group"),
+            layout: copy_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(depth_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&mip_views[0]),
+                },
+            ],
+        });
+
+        let downsample_bind_groups = mip_views
+            .windows(2)
+            .map(|views| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Depth pyramid downsample bind group"),
+                    layout: downsample_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&views[0]),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::TextureView(&views[1]),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        // Not valid until built, so the first cull phase skips the occlusion test
+        let uniform = DepthPyramidUniform::new(Matrix4::identity(), (width, height), false);
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Depth pyramid buffer"),
+            contents: &uniform_bytes(&uniform),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Depth pyramid bind group"),
+            layout: &layouts.depth_pyramid,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        PyramidTargets {
+            size: (width, height),
+            mip_count,
+            copy_bind_group,
+            downsample_bind_groups,
+            buffer,
+            bind_group,
+        }
+    }
+
+    fn write_uniform(&self, queue: &Queue, view_proj: Matrix4<f32>) {
+        let uniform = DepthPyramidUniform::new(view_proj, self.size, true);
+        queue.write_buffer(&self.buffer, 0, &uniform_bytes(&uniform));
+    }
+}
+
+#[derive(ShaderType)]
+struct DepthPyramidUniform {
+    view_proj: Matrix4<f32>,
+    size: Vector2<f32>,
+    valid: u32,
+}
+
+impl DepthPyramidUniform {
+    fn new(view_proj: Matrix4<f32>, size: (u32, u32), valid: bool) -> DepthPyramidUniform {
+        DepthPyramidUniform {
+            view_proj,
+            size: Vector2::new(size.0 as f32, size.1 as f32),
+            valid: valid as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn depth_pyramid_matches_wgsl() {
+        assert_layout::<DepthPyramidUniform>("shaders/cull.wgsl", &[], "DepthPyramid");
+    }
+}
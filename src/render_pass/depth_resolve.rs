@@ -0,0 +1,202 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, CommandEncoderDescriptor, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, FragmentState, LoadOp, MultisampleState, Operations,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderStages, StencilState,
+    StoreOp, TextureSampleType, TextureUsages, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    layouts::{Group, Layouts},
+    shader_library::ShaderLibrary,
+    texture::Texture,
+};
+
+/// Copies the farthest sample of a multisampled depth texture into a single
+/// sampled one, which the depth pyramid and the screen space passes can bind.
+pub struct DepthResolve {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pub texture: Texture,
+}
+
+impl DepthResolve {
+    /// The groups of the resolve pipeline.
+    pub const BIND_GROUPS: [Group; 1] = [Group::Own];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        multisampled: &Texture,
+        width: u32,
+        height: u32,
+    ) -> DepthResolve {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Depth resolve bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: true,
+                },
+                count: None,
+            }],
+        });
+
+        let shader = shaders.module(device, "shaders/depth_resolve.wgsl", &[]);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Depth resolve pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[&layout]),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Depth resolve pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                compilation_options: Default::default(),
+                targets: &[],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: Texture::DEPTH_32_FLOAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (texture, bind_group) = create_target(device, &layout, multisampled, width, height);
+
+        DepthResolve {
+            pipeline,
+            layout,
+            bind_group,
+            texture,
+        }
+    }
+
+    pub fn resolve(&self, device: &Device, queue: &Queue) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Depth resolve Encoder"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Depth resolve Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    pub fn resize(&mut self, device: &Device, multisampled: &Texture, width: u32, height: u32) {
+        (self.texture, self.bind_group) =
+            create_target(device, &self.layout, multisampled, width, height);
+    }
+}
+
+fn create_target(
+    device: &Device,
+    layout: &BindGroupLayout,
+    multisampled: &Texture,
+    width: u32,
+    height: u32,
+) -> (Texture, BindGroup) {
+    let texture = Texture::new(
+        device,
+        width,
+        height,
+        Some("Resolved depth texture"),
+        Texture::DEPTH_32_FLOAT,
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+    );
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Depth resolve bind group"),
+        layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: BindingResource::TextureView(&multisampled.view),
+        }],
+    });
+
+    (texture, bind_group)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu_context::tests::test_device;
+
+    use super::*;
+
+    #[test]
+    fn resolves_a_multisampled_depth() {
+        let Some((device, queue)) = test_device() else {
+            return;
+        };
+
+        let layouts = Layouts::new(&device, false);
+        let multisampled = |size| {
+            Texture::multisampled(
+                &device,
+                size,
+                size,
+                None,
+                Texture::DEPTH_32_FLOAT,
+                TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                4,
+            )
+        };
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let mut depth_resolve = DepthResolve::new(
+            &device,
+            &ShaderLibrary::new(false),
+            &layouts,
+            &multisampled(64),
+            64,
+            64,
+        );
+        depth_resolve.resolve(&device, &queue);
+        depth_resolve.resize(&device, &multisampled(32), 32, 32);
+        depth_resolve.resolve(&device, &queue);
+
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            panic!("{error}");
+        }
+        assert_eq!(depth_resolve.texture.size().width, 32);
+    }
+}
@@ -0,0 +1,292 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, CommandEncoderDescriptor,
+    Device, LoadOp, Operations, PipelineLayoutDescriptor, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, ShaderStages, StoreOp, SurfaceConfiguration,
+    TextureUsages, TextureView,
+};
+
+use crate::{
+    layouts::{Group, Layouts},
+    shader_library::ShaderLibrary,
+    texture::Texture,
+};
+
+use super::pipeline::create_pipeline;
+
+/// Draws the shader errors of development mode over the tone mapped frame, on
+/// a red banner along the top. The message is rasterized on the CPU when it
+/// or the width of the frame changes.
+pub struct ErrorOverlay {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    font: FontRef<'static>,
+    banner: Option<Banner>,
+}
+
+/// A message rasterized for a frame size.
+struct Banner {
+    message: String,
+    size: (u32, u32),
+    bind_group: BindGroup,
+}
+
+impl ErrorOverlay {
+    /// The groups of the overlay pipeline.
+    pub const BIND_GROUPS: [Group; 1] = [Group::Own];
+
+    const FONT: &'static [u8] = include_bytes!("../../assets/fonts/DejaVuSansMono.ttf");
+    const FONT_SIZE: f32 = 16.0;
+    /// Around the text, in pixels.
+    const MARGIN: u32 = 8;
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+    ) -> ErrorOverlay {
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Error overlay bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let shader = shaders.module(device, "shaders/error_overlay.wgsl", &[]);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Error overlay pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[&layout]),
+            push_constant_ranges: &[],
+        });
+
+        // Drawn on the same view as the tone mapping
+        let pipeline = create_pipeline(
+            device,
+            &pipeline_layout,
+            &[],
+            config.format.add_srgb_suffix(),
+            None,
+            Some(BlendState::ALPHA_BLENDING),
+            1,
+            &shader,
+        );
+
+        ErrorOverlay {
+            pipeline,
+            layout,
+            font: FontRef::try_from_slice(Self::FONT).expect("The built in font is valid"),
+            banner: None,
+        }
+    }
+
+    /// Draws the `message` over the `output` of the `width` and `height`.
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        output: &TextureView,
+        message: &str,
+        width: u32,
+        height: u32,
+    ) {
+        let current = self
+            .banner
+            .as_ref()
+            .is_some_and(|banner| banner.message == message && banner.size == (width, height));
+        if !current {
+            self.banner = Some(self.create_banner(device, queue, message, width, height));
+        }
+        let Some(banner) = &self.banner else {
+            return;
+        };
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Error overlay Encoder"),
+        });
+
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Error overlay pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &banner.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+
+        drop(pass);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn create_banner(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        message: &str,
+        width: u32,
+        height: u32,
+    ) -> Banner {
+        let (coverage, banner_height) = rasterize(&self.font, message, width, height);
+
+        let texture = Texture::new(
+            device,
+            width,
+            banner_height,
+            Some("Error overlay texture"),
+            Texture::RGBA_UNORM,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        );
+        let texels = coverage
+            .iter()
+            .flat_map(|coverage| [*coverage; 4])
+            .collect::<Vec<_>>();
+        texture.write(queue, &texels);
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Error overlay bind group"),
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&texture.view),
+            }],
+        });
+
+        Banner {
+            message: message.to_string(),
+            size: (width, height),
+            bind_group,
+        }
+    }
+}
+
+/// The coverage of the `message` in a banner of the `width`, wrapped and cut
+/// to fit in the `max_height`, and the height of the banner.
+fn rasterize(font: &FontRef, message: &str, width: u32, max_height: u32) -> (Vec<u8>, u32) {
+    let font = font.as_scaled(PxScale::from(ErrorOverlay::FONT_SIZE));
+    let margin = ErrorOverlay::MARGIN;
+
+    // The font is monospaced
+    let advance = font.h_advance(font.glyph_id('0'));
+    let line_height = (font.height() + font.line_gap()).ceil() as u32;
+
+    let columns = (width.saturating_sub(2 * margin) as f32 / advance) as usize;
+    let rows = (max_height.saturating_sub(2 * margin) / line_height) as usize;
+    let lines = wrap(message, columns.max(1), rows.max(1));
+
+    let height = (lines.len() as u32 * line_height + 2 * margin).min(max_height);
+    let mut coverage = vec![0u8; (width * height) as usize];
+
+    for (row, line) in lines.iter().enumerate() {
+        let baseline = (margin + row as u32 * line_height) as f32 + font.ascent();
+
+        for (column, character) in line.chars().enumerate() {
+            let mut glyph = font.scaled_glyph(character);
+            glyph.position = ab_glyph::point(margin as f32 + column as f32 * advance, baseline);
+
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outline.px_bounds();
+            outline.draw(|x, y, glyph_coverage| {
+                let x = bounds.min.x as i64 + x as i64;
+                let y = bounds.min.y as i64 + y as i64;
+                if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+                    let texel = &mut coverage[(y * width as i64 + x) as usize];
+                    *texel = (*texel).max((glyph_coverage * 255.0) as u8);
+                }
+            });
+        }
+    }
+
+    (coverage, height)
+}
+
+/// Splits the `message` in lines of at most `columns` characters, keeping at
+/// most `rows` of them. The last row tells how many were left out.
+fn wrap(message: &str, columns: usize, rows: usize) -> Vec<String> {
+    let mut lines = message
+        .replace('\t', "    ")
+        .lines()
+        .flat_map(|line| {
+            let characters = line.trim_end().chars().collect::<Vec<_>>();
+            match characters.is_empty() {
+                true => vec![String::new()],
+                false => characters
+                    .chunks(columns)
+                    .map(|chunk| chunk.iter().collect())
+                    .collect(),
+            }
+        })
+        .collect::<Vec<String>>();
+
+    if lines.len() > rows {
+        let hidden = lines.len() - rows + 1;
+        lines.truncate(rows - 1);
+        lines.push(format!("({hidden} more lines)"));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_the_message_in_the_banner() {
+        let message = "error: expected ';'\n\t┌─ shaders/pbr.wgsl:12:5\n\n= a long note";
+
+        assert_eq!(
+            wrap(message, 12, 10),
+            [
+                "error: expec",
+                "ted ';'",
+                "    ┌─ shade",
+                "rs/pbr.wgsl:",
+                "12:5",
+                "",
+                "= a long not",
+                "e",
+            ]
+        );
+        assert_eq!(
+            wrap(message, 12, 4),
+            ["error: expec", "ted ';'", "    ┌─ shade", "(5 more lines)"]
+        );
+    }
+
+    #[test]
+    fn rasterizes_every_line() {
+        let font = FontRef::try_from_slice(ErrorOverlay::FONT).unwrap();
+
+        let (coverage, one_line) = rasterize(&font, "error", 200, 600);
+        assert_eq!(coverage.len(), 200 * one_line as usize);
+        assert!(coverage.iter().any(|coverage| *coverage > 128));
+
+        let (_, two_lines) = rasterize(&font, "error\nnote", 200, 600);
+        assert!(two_lines > one_line);
+
+        // Cut to the height of the frame
+        let (coverage, height) = rasterize(&font, &"error\n".repeat(100), 200, 300);
+        assert!(height <= 300);
+        assert_eq!(coverage.len(), 200 * height as usize);
+    }
+}
@@ -1,35 +1,39 @@
-use wgpu::{Device, Operations, Queue, ShaderModuleDescriptor, ShaderSource, TextureUsages};
+use wgpu::{Device, Operations, Queue, TextureUsages};
 
-use crate::{layouts::Layouts, texture::Texture};
+use crate::{
+    layouts::{Group, Layouts},
+    shader_library::ShaderLibrary,
+    texture::Texture,
+};
 
-use super::pipeline::create_pipeline;
+use super::{pipeline::create_pipeline, render_target::RenderTarget};
 
 pub struct HdrPipeline {
     pipeline: wgpu::RenderPipeline,
     bind_group: wgpu::BindGroup,
-    texture: Texture,
+    target: RenderTarget,
+    /// Per pixel of the scene drawn into the target.
+    samples: u32,
     width: u32,
     height: u32,
     layout: wgpu::BindGroupLayout,
 }
 
 impl HdrPipeline {
+    /// The groups of the tone mapping pipeline.
+    pub const BIND_GROUPS: [Group; 1] = [Group::Texture];
+
     pub fn new(
         device: &wgpu::Device,
+        shaders: &ShaderLibrary,
         config: &wgpu::SurfaceConfiguration,
         layouts: &Layouts,
+        samples: u32,
     ) -> Self {
         let width = config.width;
         let height = config.height;
 
-        let texture = Texture::new(
-            device,
-            width,
-            height,
-            Some("HDR texture"),
-            Texture::RGBA_16_FLOAT,
-            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-        );
+        let target = create_target(device, width, height, samples);
 
         let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Hdr layout"),
@@ -60,23 +64,20 @@ impl HdrPipeline {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                    resource: wgpu::BindingResource::TextureView(&target.texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&target.texture.sampler),
                 },
             ],
         });
 
-        let shader = ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: ShaderSource::Wgsl(include_str!("../shaders/hdr.wgsl").into()),
-        };
+        let shader = shaders.module(device, "shaders/hdr.wgsl", &[]);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&layouts.texture],
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
             push_constant_ranges: &[],
         });
 
@@ -86,13 +87,16 @@ impl HdrPipeline {
             &[],
             config.format.add_srgb_suffix(),
             None,
-            shader,
+            None,
+            1,
+            &shader,
         );
 
         Self {
             pipeline,
             bind_group,
-            texture,
+            target,
+            samples,
             width,
             height,
             layout,
@@ -101,14 +105,7 @@ impl HdrPipeline {
 
     /// Resize the HDR texture
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
-        self.texture = Texture::new(
-            device,
-            width,
-            height,
-            Some("HDR texture"),
-            Texture::RGBA_16_FLOAT,
-            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-        );
+        self.target = create_target(device, width, height, self.samples);
 
         self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Hdr bind group"),
@@ -116,11 +113,11 @@ impl HdrPipeline {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&self.texture.view),
+                    resource: wgpu::BindingResource::TextureView(&self.target.texture.view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.texture.sampler),
+                    resource: wgpu::BindingResource::Sampler(&self.target.texture.sampler),
                 },
             ],
         });
@@ -130,7 +127,12 @@ impl HdrPipeline {
 
     /// Exposes the HDR texture
     pub fn view(&self) -> &wgpu::TextureView {
-        &self.texture.view
+        &self.target.texture.view
+    }
+
+    /// The target the scene is drawn on, multisampled with MSAA.
+    pub fn target(&self) -> &RenderTarget {
+        &self.target
     }
 
     /// This renders the internal HDR texture to the [TextureView]
@@ -164,3 +166,15 @@ impl HdrPipeline {
         queue.submit(std::iter::once(encoder));
     }
 }
+
+fn create_target(device: &Device, width: u32, height: u32, samples: u32) -> RenderTarget {
+    RenderTarget::new(
+        device,
+        width,
+        height,
+        "HDR texture",
+        Texture::RGBA_16_FLOAT,
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        samples,
+    )
+}
@@ -1,9 +1,31 @@
+mod cull_pass;
+mod deferred_pass;
+mod depth_prepass;
+mod depth_pyramid;
+mod depth_resolve;
+mod error_overlay;
 mod hdr;
+mod oit_pass;
+mod opaque_pass;
 mod pbr_pass;
 mod pipeline;
+mod render_target;
 mod shadow_pass;
 mod skybox_pass;
+mod ssao_pass;
+mod ssr_pass;
+mod transparent_pass;
 
 pub use self::{
-    hdr::HdrPipeline, pbr_pass::PbrPass, shadow_pass::ShadowPass, skybox_pass::SkyboxPass,
+    error_overlay::ErrorOverlay, hdr::HdrPipeline, oit_pass::OitPass, opaque_pass::OpaquePass,
+    shadow_pass::ShadowPass, skybox_pass::SkyboxPass, ssr_pass::SsrPass,
+    transparent_pass::TransparentPass,
+};
+
+pub use pipeline::{PipelineCache, PipelineKey};
+
+#[cfg(test)]
+pub use self::{
+    cull_pass::CullPass, deferred_pass::DeferredPass, depth_prepass::DepthPrepass,
+    depth_resolve::DepthResolve, pbr_pass::PbrPass, ssao_pass::SsaoPass,
 };
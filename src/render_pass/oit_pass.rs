@@ -0,0 +1,329 @@
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendFactor,
+    BlendOperation, BlendState, Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor,
+    CompareFunction, Device, FrontFace, LoadOp, Operations, PipelineLayoutDescriptor, Queue,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    RenderPipeline, ShaderStages, StoreOp, SurfaceConfiguration, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDimension,
+};
+
+use crate::{
+    entity::Vertex,
+    layouts::{Group, Layouts},
+    render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    shader_library::ShaderLibrary,
+    stats::DrawStats,
+    texture::Texture,
+};
+
+use super::{
+    pipeline::{create_pipeline, PipelineCache, PipelineTemplate},
+    render_target::RenderTarget,
+};
+
+/// Weighted blended order independent transparency. The blended objects are
+/// accumulated, in any order, into an accumulation and a revealage target which
+/// are then composited over the HDR texture.
+pub struct OitPass {
+    pipelines: PipelineCache,
+    composite_pipeline: RenderPipeline,
+    composite_layout: BindGroupLayout,
+    composite_bind_group: BindGroup,
+    accum_texture: RenderTarget,
+    revealage_texture: RenderTarget,
+    /// Per pixel of the accumulation targets.
+    samples: u32,
+}
+
+impl OitPass {
+    /// The groups of the accumulation pipelines.
+    pub const BIND_GROUPS: [Group; 5] = [
+        Group::Camera,
+        Group::Transform,
+        Group::Material,
+        Group::Light,
+        Group::CubeMap,
+    ];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+        samples: u32,
+    ) -> OitPass {
+        let (accum_texture, revealage_texture) =
+            create_targets(device, config.width, config.height, samples);
+
+        // ACCUMULATION PIPELINES
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Oit pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+
+        let revealage = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::OneMinusSrc,
+            operation: BlendOperation::Add,
+        };
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            PipelineTemplate {
+                label: "Oit accumulation pipeline",
+                layout,
+                fragment_entry_point: "fs_oit",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![
+                    ColorTargetState {
+                        format: Texture::RGBA_16_FLOAT,
+                        blend: Some(BlendState {
+                            color: additive,
+                            alpha: additive,
+                        }),
+                        write_mask: ColorWrites::ALL,
+                    },
+                    ColorTargetState {
+                        format: Texture::R_16_FLOAT,
+                        blend: Some(BlendState {
+                            color: revealage,
+                            alpha: revealage,
+                        }),
+                        write_mask: ColorWrites::ALL,
+                    },
+                ],
+                material_blend: false,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::LessEqual,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples,
+            },
+        );
+
+        // COMPOSITE PIPELINE
+        let composite_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Oit composite layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let composite_bind_group = create_composite_bind_group(
+            device,
+            &composite_layout,
+            &accum_texture,
+            &revealage_texture,
+        );
+
+        let composite_shader = shaders.module(device, "shaders/oit_composite.wgsl", &[]);
+
+        let composite_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Oit composite pipeline layout"),
+            bind_group_layouts: &[&composite_layout],
+            push_constant_ranges: &[],
+        });
+
+        let composite_pipeline = create_pipeline(
+            device,
+            &composite_pipeline_layout,
+            &[],
+            Texture::RGBA_16_FLOAT,
+            None,
+            Some(BlendState::ALPHA_BLENDING),
+            1,
+            &composite_shader,
+        );
+
+        OitPass {
+            pipelines,
+            composite_pipeline,
+            composite_layout,
+            composite_bind_group,
+            accum_texture,
+            revealage_texture,
+            samples,
+        }
+    }
+
+    /// Creates the pipelines the `world` needs, see [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld) {
+        self.pipelines
+            .prepare(device, world.transparent_pipeline_keys());
+    }
+
+    /// Accumulates the blended objects, testing against the opaque depth.
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        depth_view: &TextureView,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+    ) -> DrawStats {
+        self.prepare(device, world);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Oit accumulation Encoder"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Oit accumulation Pass"),
+            color_attachments: &[
+                self.accum_texture
+                    .attachment(LoadOp::Clear(Color::TRANSPARENT)),
+                self.revealage_texture
+                    .attachment(LoadOp::Clear(Color::WHITE)),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(3, &world.lights_bind_group, &[]);
+
+        let stats = render_pass.draw_transparent(world, camera, &self.pipelines);
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+
+        stats
+    }
+
+    /// Blends the accumulated transparency over the [TextureView] supplied as parameter.
+    pub fn composite(&self, device: &Device, queue: &Queue, view: &TextureView) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Oit composite Encoder"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Oit composite Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        let (accum_texture, revealage_texture) =
+            create_targets(device, width, height, self.samples);
+
+        self.composite_bind_group = create_composite_bind_group(
+            device,
+            &self.composite_layout,
+            &accum_texture,
+            &revealage_texture,
+        );
+        self.accum_texture = accum_texture;
+        self.revealage_texture = revealage_texture;
+    }
+}
+
+fn create_targets(
+    device: &Device,
+    width: u32,
+    height: u32,
+    samples: u32,
+) -> (RenderTarget, RenderTarget) {
+    let accum_texture = RenderTarget::new(
+        device,
+        width,
+        height,
+        "Oit accumulation texture",
+        Texture::RGBA_16_FLOAT,
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        samples,
+    );
+
+    let revealage_texture = RenderTarget::new(
+        device,
+        width,
+        height,
+        "Oit revealage texture",
+        Texture::R_16_FLOAT,
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        samples,
+    );
+
+    (accum_texture, revealage_texture)
+}
+
+fn create_composite_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    accum_texture: &RenderTarget,
+    revealage_texture: &RenderTarget,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Oit composite bind group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&accum_texture.texture.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&revealage_texture.texture.view),
+            },
+        ],
+    })
+}
@@ -0,0 +1,106 @@
+use wgpu::{BindGroup, Device, Queue, SurfaceConfiguration, TextureView};
+
+use crate::{
+    layouts::Layouts,
+    render_world::{ExtractedCamera, RenderWorld},
+    settings::{RenderSettings, ShadingPath},
+    shader_library::ShaderLibrary,
+    stats::{CullStats, DrawStats},
+};
+
+use super::{deferred_pass::DeferredPass, pbr_pass::PbrPass, render_target::RenderTarget};
+
+/// Draws the opaque and alpha masked objects with the shading path chosen at
+/// startup. Only the forward path draws with MSAA.
+#[allow(clippy::large_enum_variant)] // Only one is ever created
+pub enum OpaquePass {
+    Forward(PbrPass),
+    Deferred(DeferredPass),
+}
+
+impl OpaquePass {
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+        shading: ShadingPath,
+        samples: u32,
+    ) -> OpaquePass {
+        match shading {
+            ShadingPath::Forward => {
+                OpaquePass::Forward(PbrPass::new(device, shaders, config, layouts, samples))
+            }
+            ShadingPath::Deferred => {
+                OpaquePass::Deferred(DeferredPass::new(device, shaders, config, layouts))
+            }
+        }
+    }
+
+    /// Creates the pipelines the `world` needs with the `settings`, see
+    /// [PipelineCache::prepare](super::PipelineCache::prepare).
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld, settings: &RenderSettings) {
+        match self {
+            OpaquePass::Forward(pass) => pass.prepare(device, world, settings),
+            OpaquePass::Deferred(pass) => pass.prepare(device, world),
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &RenderTarget,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        settings: &RenderSettings,
+    ) -> DrawStats {
+        match self {
+            OpaquePass::Forward(pass) => pass.draw(device, queue, target, world, camera, settings),
+            OpaquePass::Deferred(pass) => {
+                let view = &target.texture.view;
+                pass.draw(device, queue, view, world, camera, settings)
+            }
+        }
+    }
+
+    /// The counts of the GPU cull pass, which only the forward path uses.
+    pub fn cull_stats(&self) -> Option<CullStats> {
+        match self {
+            OpaquePass::Forward(pass) => pass.cull_stats(),
+            OpaquePass::Deferred(_) => None,
+        }
+    }
+
+    /// Exposes the depth texture of the opaque geometry
+    pub fn depth_view(&self) -> &TextureView {
+        match self {
+            OpaquePass::Forward(pass) => pass.depth_view(),
+            OpaquePass::Deferred(pass) => pass.depth_view(),
+        }
+    }
+
+    /// The depth of the opaque geometry with a single sample per pixel, for
+    /// the screen space passes.
+    pub fn resolved_depth_view(&self) -> &TextureView {
+        match self {
+            OpaquePass::Forward(pass) => pass.resolved_depth_view(),
+            OpaquePass::Deferred(pass) => pass.depth_view(),
+        }
+    }
+
+    /// Binds the surface of the opaque geometry for the screen space passes.
+    pub fn surface(&self) -> &BindGroup {
+        match self {
+            OpaquePass::Forward(pass) => pass.surface(),
+            OpaquePass::Deferred(pass) => pass.surface(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &Device, layouts: &Layouts, width: u32, height: u32) {
+        match self {
+            OpaquePass::Forward(pass) => pass.resize(device, layouts, width, height),
+            OpaquePass::Deferred(pass) => pass.resize(device, layouts, width, height),
+        }
+    }
+}
@@ -1,95 +1,388 @@
 use wgpu::{
-    CommandEncoderDescriptor, Device, LoadOp, Operations, PipelineLayoutDescriptor, Queue,
-    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
-    RenderPipeline, ShaderModuleDescriptor, ShaderSource, StoreOp, SurfaceConfiguration,
+    BindGroup, Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompareFunction,
+    Device, FrontFace, LoadOp, Operations, PipelineLayoutDescriptor, Queue,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp, SurfaceConfiguration,
     TextureUsages, TextureView,
 };
 
 use crate::{
     entity::Vertex,
-    layouts::Layouts,
-    render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    layouts::{Group, Layouts},
+    render_world::{DrawWorld, ExtractedCamera, IndirectDraws, RenderWorld},
+    settings::RenderSettings,
+    shader_library::ShaderLibrary,
+    stats::{CullStats, DrawStats},
     texture::Texture,
 };
 
-use super::pipeline::create_pipeline;
+use super::{
+    cull_pass::{CullPass, CullPhase},
+    depth_prepass::DepthPrepass,
+    depth_pyramid::{DepthPyramid, DepthReduction},
+    depth_resolve::DepthResolve,
+    pipeline::{PipelineCache, PipelineTemplate},
+    render_target::RenderTarget,
+    ssao_pass::SsaoPass,
+    ssr_pass::create_surface_bind_group,
+};
 
 pub struct PbrPass {
-    pipeline: RenderPipeline,
+    pipelines: PipelineCache,
+    /// Shades on top of the depth prepass, only where the depth is equal.
+    prepass_pipelines: PipelineCache,
+    depth_prepass: DepthPrepass,
+    /// Multisampled with MSAA, then resolved for the passes that read it.
     depth_texture: Texture,
+    depth_resolve: Option<DepthResolve>,
+    surface: SurfaceTargets,
+    samples: u32,
+    cull_pass: CullPass,
+    depth_pyramid: DepthPyramid,
+    ssao: SsaoPass,
+    /// The counts of the GPU cull pass, when it culled the last frame.
+    cull_stats: Option<CullStats>,
+}
+
+/// The surface of the shaded opaque geometry, for the screen space reflections.
+struct SurfaceTargets {
+    /// Normal and roughness.
+    normal: RenderTarget,
+    /// Albedo and metallic.
+    albedo: RenderTarget,
+    bind_group: BindGroup,
 }
 
 impl PbrPass {
-    pub fn new(device: &Device, config: &SurfaceConfiguration, layouts: &Layouts) -> PbrPass {
-        let shader = ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: ShaderSource::Wgsl(include_str!("../shaders/pbr.wgsl").into()),
-        };
+    /// The groups of the forward shading pipelines.
+    pub const BIND_GROUPS: [Group; 6] = [
+        Group::Camera,
+        Group::Transform,
+        Group::Material,
+        Group::Light,
+        Group::CubeMap,
+        Group::Texture,
+    ];
 
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        config: &SurfaceConfiguration,
+        layouts: &Layouts,
+        samples: u32,
+    ) -> PbrPass {
         // DEPTH TEXTURE
-        let depth_texture = Texture::new(
+        let depth_texture = create_depth_texture(device, config.width, config.height, samples);
+        let depth_resolve = (samples > 1).then(|| {
+            DepthResolve::new(
+                device,
+                shaders,
+                layouts,
+                &depth_texture,
+                config.width,
+                config.height,
+            )
+        });
+        let resolved_depth = depth_resolve
+            .as_ref()
+            .map_or(&depth_texture, |resolve| &resolve.texture);
+
+        let surface = SurfaceTargets::new(device, layouts, config.width, config.height, samples);
+
+        // PIPELINE
+        let target = |format| ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        };
+
+        let template = |label, depth_compare, depth_write| {
+            let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Pipeline layout"),
+                bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
+                push_constant_ranges: &[],
+            });
+
+            PipelineTemplate {
+                label,
+                layout,
+                fragment_entry_point: "fs_main",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![
+                    target(Texture::RGBA_16_FLOAT),
+                    target(Texture::RGBA_16_FLOAT),
+                    target(Texture::SRGBA_UNORM),
+                ],
+                material_blend: true,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare,
+                depth_write,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples,
+            }
+        };
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            template("Pbr pipeline", CompareFunction::LessEqual, true),
+        );
+        let prepass_pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            template("Pbr prepass pipeline", CompareFunction::Equal, false),
+        );
+
+        // DEPTH PREPASS
+        let depth_prepass = DepthPrepass::new(device, shaders, layouts, samples);
+
+        // GPU CULLING
+        let cull_pass = CullPass::new(device, shaders, layouts);
+        let depth_pyramid = DepthPyramid::new(
             device,
+            shaders,
+            layouts,
+            DepthReduction::Farthest,
+            &resolved_depth.view,
             config.width,
             config.height,
-            Some("Depth texture"),
-            Texture::DEPTH_32_FLOAT,
-            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
         );
 
-        // PIPELINE
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Pipeline layout"),
-            bind_group_layouts: &[
-                &layouts.camera,
-                &layouts.transform,
-                &layouts.material,
-                &layouts.light,
-                &layouts.cube_map,
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let pipeline = create_pipeline(
+        // SSAO
+        let ssao = SsaoPass::new(
             device,
-            &pipeline_layout,
-            &[Vertex::desc()],
-            Texture::RGBA_16_FLOAT,
-            Some(Texture::DEPTH_32_FLOAT),
-            shader,
+            shaders,
+            layouts,
+            &resolved_depth.view,
+            config.width,
+            config.height,
         );
 
         PbrPass {
-            pipeline,
+            pipelines,
+            prepass_pipelines,
+            depth_prepass,
             depth_texture,
+            depth_resolve,
+            surface,
+            samples,
+            cull_pass,
+            depth_pyramid,
+            ssao,
+            cull_stats: None,
         }
     }
 
+    /// Creates the pipelines the `world` needs with the `settings`, see
+    /// [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld, settings: &RenderSettings) {
+        match settings.depth_prepass || settings.ssao.enabled {
+            true => {
+                self.depth_prepass.prepare(device, world);
+                self.prepass_pipelines
+                    .prepare(device, world.pipeline_keys());
+            }
+            false => self.pipelines.prepare(device, world.pipeline_keys()),
+        }
+    }
+
+    /// With the depth prepass the depth of every visible object is written
+    /// first, and then only the fragments matching it are shaded. The SSAO
+    /// needs that depth, so it always draws the prepass. Without it the SSAO
+    /// is disabled, and only clears the occlusion.
     pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &RenderTarget,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        settings: &RenderSettings,
+    ) -> DrawStats {
+        let depth_prepass = settings.depth_prepass || settings.ssao.enabled;
+        self.prepare(device, world, settings);
+        self.cull_stats = None;
+
+        let Some(draws) = world.indirect() else {
+            if !depth_prepass {
+                self.ssao.draw(device, queue, camera, &settings.ssao);
+                let stats = self.draw_phase(device, queue, target, world, camera, None, false);
+                self.resolve_depth(device, queue);
+                return stats;
+            }
+
+            let prepass = self.depth_prepass.draw(
+                device,
+                queue,
+                &self.depth_texture.view,
+                world,
+                camera,
+                None,
+            );
+            self.resolve_depth(device, queue);
+            self.ssao.draw(device, queue, camera, &settings.ssao);
+            let stats = self.draw_phase(device, queue, target, world, camera, None, true);
+            return stats.with_pass(prepass);
+        };
+
+        if !depth_prepass {
+            self.ssao.draw(device, queue, camera, &settings.ssao);
+        }
+
+        // Draws what the previous frame's depth does not hide, then re-tests
+        // the hidden objects against the depth that was just drawn
+        self.cull_pass
+            .cull(device, queue, draws, &self.depth_pyramid, CullPhase::First);
+        let stats = self.draw_depth_phase(
+            device,
+            queue,
+            target,
+            world,
+            camera,
+            CullPhase::First,
+            depth_prepass,
+        );
+
+        self.resolve_depth(device, queue);
+        self.depth_pyramid.build(device, queue, camera.view_proj);
+
+        self.cull_pass
+            .cull(device, queue, draws, &self.depth_pyramid, CullPhase::Second);
+        let second_phase = self.draw_depth_phase(
+            device,
+            queue,
+            target,
+            world,
+            camera,
+            CullPhase::Second,
+            depth_prepass,
+        );
+
+        // Every object is already counted by the first phase
+        let stats = stats.with_pass(second_phase);
+        self.resolve_depth(device, queue);
+        if !depth_prepass {
+            return self.read_cull_stats(device, queue, draws, stats);
+        }
+
+        // The depth is complete, only what it shows is shaded
+        self.ssao.draw(device, queue, camera, &settings.ssao);
+        self.depth_pyramid.build(device, queue, camera.view_proj);
+        self.cull_pass
+            .cull(device, queue, draws, &self.depth_pyramid, CullPhase::Final);
+        let shading = self.draw_phase(
+            device,
+            queue,
+            target,
+            world,
+            camera,
+            Some(CullPhase::Final),
+            true,
+        );
+
+        self.read_cull_stats(device, queue, draws, stats.with_pass(shading))
+    }
+
+    /// Counts the objects of the GPU driven path the cull pass drew and culled
+    /// in an earlier frame, once they are read back.
+    fn read_cull_stats(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        draws: &IndirectDraws,
+        stats: DrawStats,
+    ) -> DrawStats {
+        self.cull_stats = self.cull_pass.read_back(device, queue, draws);
+
+        match self.cull_stats {
+            Some(cull_stats) => DrawStats {
+                submitted: cull_stats.drawn(),
+                culled: cull_stats.culled(),
+                ..stats
+            },
+            None => stats,
+        }
+    }
+
+    /// The counts of the GPU cull pass, when it culled the last frame.
+    pub fn cull_stats(&self) -> Option<CullStats> {
+        self.cull_stats
+    }
+
+    /// Draws a cull phase into the depth prepass or, without it, shades it.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_depth_phase(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &RenderTarget,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        phase: CullPhase,
+        depth_prepass: bool,
+    ) -> DrawStats {
+        match depth_prepass {
+            true => self.depth_prepass.draw(
+                device,
+                queue,
+                &self.depth_texture.view,
+                world,
+                camera,
+                Some(phase),
+            ),
+            false => self.draw_phase(device, queue, target, world, camera, Some(phase), false),
+        }
+    }
+
+    /// Records a render pass with the CPU path when no phase is given, or with
+    /// the indirect commands of the given cull phase. On top of the depth
+    /// prepass the depth is kept and only tested for equality.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_phase(
         &self,
         device: &Device,
         queue: &Queue,
-        view: &TextureView,
+        target: &RenderTarget,
         world: &RenderWorld,
         camera: &ExtractedCamera,
-    ) {
+        phase: Option<CullPhase>,
+        depth_prepass: bool,
+    ) -> DrawStats {
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("Model render Encoder"),
         });
 
+        let depth_load = match phase {
+            Some(CullPhase::Second) => LoadOp::Load,
+            _ if depth_prepass => LoadOp::Load,
+            _ => LoadOp::Clear(1.0),
+        };
+
+        let pipelines = match depth_prepass {
+            true => &self.prepass_pipelines,
+            false => &self.pipelines,
+        };
+
+        // The surface is drawn from scratch, unless it is the second phase
+        let surface_load = match phase {
+            Some(CullPhase::Second) => LoadOp::Load,
+            _ => LoadOp::Clear(Color::TRANSPARENT),
+        };
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             label: Some("Model render Pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Load,
-                    store: StoreOp::Store,
-                },
-            })],
+            color_attachments: &[
+                target.attachment(LoadOp::Load),
+                self.surface.normal.attachment(surface_load),
+                self.surface.albedo.attachment(surface_load),
+            ],
             depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                 view: &self.depth_texture.view,
                 depth_ops: Some(Operations {
-                    load: LoadOp::Clear(1.0),
+                    load: depth_load,
                     store: StoreOp::Store,
                 }),
                 stencil_ops: None,
@@ -98,26 +391,113 @@ impl PbrPass {
             timestamp_writes: None,
         });
 
-        render_pass.set_pipeline(&self.pipeline);
         render_pass.set_bind_group(0, camera, &[]);
         render_pass.set_bind_group(3, &world.lights_bind_group, &[]);
+        render_pass.set_bind_group(5, &self.ssao, &[]);
 
-        render_pass.draw_world(world);
+        let stats = match world.indirect() {
+            Some(draws) if phase.is_some() => {
+                render_pass.draw_world_indirect(world, draws, pipelines)
+            }
+            _ => render_pass.draw_world(world, camera, pipelines),
+        };
 
         drop(render_pass);
         let encoder = encoder.finish();
 
         queue.submit(std::iter::once(encoder));
+
+        stats
     }
 
-    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
-        self.depth_texture = Texture::new(
-            device,
-            width,
-            height,
-            Some("Depth texture"),
-            Texture::DEPTH_32_FLOAT,
-            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
-        );
+    /// Resolves the multisampled depth for the passes that read it, once it
+    /// changed.
+    fn resolve_depth(&self, device: &Device, queue: &Queue) {
+        if let Some(depth_resolve) = &self.depth_resolve {
+            depth_resolve.resolve(device, queue);
+        }
     }
+
+    /// Exposes the depth texture of the opaque geometry, for the passes that
+    /// draw on top of it.
+    pub fn depth_view(&self) -> &TextureView {
+        &self.depth_texture.view
+    }
+
+    /// The depth of the opaque geometry with a single sample per pixel. With
+    /// the depth prepass it is complete before the opaque geometry is shaded,
+    /// which screen space effects can rely on.
+    pub fn resolved_depth_view(&self) -> &TextureView {
+        match &self.depth_resolve {
+            Some(depth_resolve) => &depth_resolve.texture.view,
+            None => &self.depth_texture.view,
+        }
+    }
+
+    /// Binds the surface of the shaded opaque geometry.
+    pub fn surface(&self) -> &BindGroup {
+        &self.surface.bind_group
+    }
+
+    pub fn resize(&mut self, device: &Device, layouts: &Layouts, width: u32, height: u32) {
+        self.surface = SurfaceTargets::new(device, layouts, width, height, self.samples);
+        self.depth_texture = create_depth_texture(device, width, height, self.samples);
+        if let Some(depth_resolve) = &mut self.depth_resolve {
+            depth_resolve.resize(device, &self.depth_texture, width, height);
+        }
+
+        let resolved_depth = match &self.depth_resolve {
+            Some(depth_resolve) => &depth_resolve.texture.view,
+            None => &self.depth_texture.view,
+        };
+        self.depth_pyramid
+            .resize(device, layouts, resolved_depth, width, height);
+        self.ssao
+            .resize(device, layouts, resolved_depth, width, height);
+    }
+}
+
+impl SurfaceTargets {
+    fn new(
+        device: &Device,
+        layouts: &Layouts,
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> SurfaceTargets {
+        let target = |label, format| {
+            RenderTarget::new(
+                device,
+                width,
+                height,
+                label,
+                format,
+                TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                samples,
+            )
+        };
+
+        let normal = target("Surface normal texture", Texture::RGBA_16_FLOAT);
+        let albedo = target("Surface albedo texture", Texture::SRGBA_UNORM);
+        let bind_group =
+            create_surface_bind_group(device, layouts, &normal.texture, &albedo.texture);
+
+        SurfaceTargets {
+            normal,
+            albedo,
+            bind_group,
+        }
+    }
+}
+
+fn create_depth_texture(device: &Device, width: u32, height: u32, samples: u32) -> Texture {
+    Texture::multisampled(
+        device,
+        width,
+        height,
+        Some("Depth texture"),
+        Texture::DEPTH_32_FLOAT,
+        TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        samples,
+    )
 }
@@ -1,36 +1,56 @@
+use std::{collections::HashMap, sync::Arc};
+
 use wgpu::{
-    ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState, Device,
-    Face, FragmentState, FrontFace, MultisampleState, PipelineLayout, PolygonMode, PrimitiveState,
-    PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    BlendState, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState, DepthStencilState,
+    Device, Face, FragmentState, FrontFace, MultisampleState, PipelineLayout, PolygonMode,
+    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor, ShaderModule,
     StencilState, TextureFormat, VertexBufferLayout, VertexState,
 };
 
+use crate::{layouts::Layouts, shader_library::ShaderLibrary};
+
+/// The defines of a shader variant shading materials through the `get_*`
+/// material functions.
+fn material_defines(layouts: &Layouts, normal_map: bool, shadows: bool) -> Vec<&'static str> {
+    [
+        (layouts.bindless, "BINDLESS"),
+        (normal_map, "NORMAL_MAP"),
+        (shadows, "SHADOWS"),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, define)| define)
+    .collect()
+}
+
+/// The `samples` per pixel are those of the color target.
+#[allow(clippy::too_many_arguments)]
 pub fn create_pipeline(
     device: &Device,
     layout: &PipelineLayout,
     vertex_layout: &[VertexBufferLayout],
     color_format: TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
-    shader: ShaderModuleDescriptor,
+    blend: Option<BlendState>,
+    samples: u32,
+    shader: &ShaderModule,
 ) -> RenderPipeline {
-    let shader = device.create_shader_module(shader);
-
     device.create_render_pipeline(&RenderPipelineDescriptor {
         label: Some("Pipeline"),
         layout: Some(layout),
         vertex: VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             compilation_options: Default::default(),
             buffers: vertex_layout,
         },
         fragment: Some(FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fs_main",
             compilation_options: Default::default(),
             targets: &[Some(ColorTargetState {
                 format: color_format,
-                blend: None,
+                blend,
                 write_mask: ColorWrites::ALL,
             })],
         }),
@@ -51,7 +71,7 @@ pub fn create_pipeline(
             bias: DepthBiasState::default(),
         }),
         multisample: MultisampleState {
-            count: 1,
+            count: samples,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -59,3 +79,337 @@ pub fn create_pipeline(
         cache: None,
     })
 }
+
+/// The part of the pipeline state that is selected by each material, and the
+/// shader variant selected by the material and the settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub cull_mode: Option<Face>,
+    pub blend: Option<BlendState>,
+    pub depth_write: bool,
+    /// Whether fragments can be discarded by the alpha cutoff.
+    pub alpha_mask: bool,
+    pub normal_map: bool,
+    pub shadows: bool,
+}
+
+impl PipelineKey {
+    /// Drops the parts of the key a pass does not take from the material: the
+    /// depth write when the pass writes no depth, the alpha mask when the pass
+    /// is not `depth_only` and runs the fragment shader anyway, and the blend
+    /// when the pass does not blend as the material asks.
+    fn variant(self, depth_write: bool, depth_only: bool, material_blend: bool) -> PipelineKey {
+        PipelineKey {
+            depth_write: self.depth_write && depth_write,
+            alpha_mask: self.alpha_mask && depth_only,
+            blend: self.blend.filter(|_| material_blend),
+            ..self
+        }
+    }
+}
+
+/// The part of the pipeline state that is fixed by the pass.
+pub struct PipelineTemplate {
+    pub label: &'static str,
+    pub layout: PipelineLayout,
+    pub fragment_entry_point: &'static str,
+    pub vertex_layout: Vec<VertexBufferLayout<'static>>,
+    /// Without color targets the fragment shader only runs for the alpha
+    /// masked materials, the others keep the early depth test.
+    pub color_targets: Vec<ColorTargetState>,
+    /// Whether the first color target blends as the material asks, otherwise
+    /// the blend states of the targets are used as they are.
+    pub material_blend: bool,
+    pub depth_format: TextureFormat,
+    pub depth_compare: CompareFunction,
+    /// Whether depth is written for the materials that ask for it.
+    pub depth_write: bool,
+    pub front_face: FrontFace,
+    pub unclipped_depth: bool,
+    /// Samples per pixel of the targets.
+    pub samples: u32,
+}
+
+/// Creates the pipeline variants used by a pass on demand and keeps them
+/// around, so each [PipelineKey] is only compiled once.
+pub struct PipelineCache {
+    template: PipelineTemplate,
+    /// The variants of the material shader, by their normal map and shadows.
+    shaders: HashMap<(bool, bool), Arc<ShaderModule>>,
+    pipelines: HashMap<PipelineKey, RenderPipeline>,
+}
+
+impl PipelineCache {
+    /// The `file` is a material shader, see [ShaderLibrary::module].
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        file: &str,
+        template: PipelineTemplate,
+    ) -> PipelineCache {
+        let shaders = [(false, false), (false, true), (true, false), (true, true)]
+            .into_iter()
+            .map(|(normal_map, shadows)| {
+                let defines = material_defines(layouts, normal_map, shadows);
+                (
+                    (normal_map, shadows),
+                    shaders.module(device, file, &defines),
+                )
+            })
+            .collect();
+
+        PipelineCache {
+            template,
+            shaders,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Creates the pipelines for the keys that are not cached yet. Needs to be
+    /// called before recording a render pass that uses them.
+    pub fn prepare(&mut self, device: &Device, keys: impl IntoIterator<Item = PipelineKey>) {
+        for key in keys {
+            let key = self.variant(key);
+            if !self.pipelines.contains_key(&key) {
+                let pipeline = self.create(device, key);
+                self.pipelines.insert(key, pipeline);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &PipelineKey) -> &RenderPipeline {
+        self.pipelines
+            .get(&self.variant(*key))
+            .expect("Pipeline variant has not been prepared")
+    }
+
+    fn variant(&self, key: PipelineKey) -> PipelineKey {
+        let template = &self.template;
+        key.variant(
+            template.depth_write,
+            template.color_targets.is_empty(),
+            template.material_blend,
+        )
+    }
+
+    fn create(&self, device: &Device, key: PipelineKey) -> RenderPipeline {
+        let template = &self.template;
+        let shader = &self.shaders[&(key.normal_map, key.shadows)];
+
+        let mut targets = template
+            .color_targets
+            .iter()
+            .cloned()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        if let (true, Some(Some(target))) = (template.material_blend, targets.first_mut()) {
+            target.blend = key.blend;
+        }
+
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(template.label),
+            layout: Some(&template.layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                compilation_options: Default::default(),
+                buffers: &template.vertex_layout,
+            },
+            fragment: (key.alpha_mask || !targets.is_empty()).then(|| FragmentState {
+                module: shader,
+                entry_point: template.fragment_entry_point,
+                compilation_options: Default::default(),
+                targets: &targets,
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: template.front_face,
+                cull_mode: key.cull_mode,
+                unclipped_depth: template.unclipped_depth,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: template.depth_format,
+                depth_write_enabled: key.depth_write,
+                depth_compare: template.depth_compare,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: template.samples,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wgpu::{ColorWrites, PipelineLayoutDescriptor};
+
+    use crate::{
+        entity::Vertex,
+        gpu_context::tests::test_device,
+        render_pass::{DepthPrepass, ShadowPass},
+        texture::Texture,
+    };
+
+    use super::*;
+
+    fn key(cull_mode: Option<Face>, blend: Option<BlendState>) -> PipelineKey {
+        PipelineKey {
+            cull_mode,
+            blend,
+            depth_write: true,
+            alpha_mask: false,
+            normal_map: false,
+            shadows: false,
+        }
+    }
+
+    #[test]
+    fn variants_drop_what_the_pass_does_not_take() {
+        let material = PipelineKey {
+            alpha_mask: true,
+            ..key(Some(Face::Back), Some(BlendState::ALPHA_BLENDING))
+        };
+
+        // A depth only pass writing depth and blending as the material asks
+        assert_eq!(material.variant(true, true, true), material);
+
+        let variant = material.variant(false, false, false);
+        assert!(!variant.depth_write);
+        assert!(!variant.alpha_mask);
+        assert_eq!(variant.blend, None);
+        assert_eq!(variant.cull_mode, Some(Face::Back));
+
+        // Opaque materials share the variant of a depth only pass
+        let opaque = key(Some(Face::Back), None);
+        assert_ne!(material.variant(true, true, false), opaque);
+        assert_eq!(
+            PipelineKey {
+                alpha_mask: false,
+                ..material
+            }
+            .variant(true, true, false),
+            opaque
+        );
+    }
+
+    #[test]
+    fn compiles_each_variant_once() {
+        let Some((device, _queue)) = test_device() else {
+            return;
+        };
+
+        let layouts = Layouts::new(&device, false);
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &layouts.groups(&ShadowPass::BIND_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let mut pipelines = PipelineCache::new(
+            &device,
+            &ShaderLibrary::new(false),
+            &layouts,
+            "shaders/shadow.wgsl",
+            PipelineTemplate {
+                label: "Test pipeline",
+                layout,
+                fragment_entry_point: "fs_main",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![ColorTargetState {
+                    format: Texture::SRGBA_UNORM,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+                material_blend: false,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::Less,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples: 1,
+            },
+        );
+
+        let back = key(Some(Face::Back), None);
+        // The pass does not blend, so this is the same variant
+        let back_blended = key(Some(Face::Back), Some(BlendState::ALPHA_BLENDING));
+        // The pass has color targets, so every material runs the fragment shader
+        let back_masked = PipelineKey {
+            alpha_mask: true,
+            ..back
+        };
+        let double_sided = key(None, None);
+
+        pipelines.prepare(&device, [back, back, back_blended, back_masked]);
+        assert_eq!(pipelines.pipelines.len(), 1);
+
+        let first = pipelines.get(&back).global_id();
+        assert_eq!(pipelines.get(&back_blended).global_id(), first);
+        assert_eq!(pipelines.get(&back_masked).global_id(), first);
+
+        // Only the missing variant is compiled, the others are kept
+        pipelines.prepare(&device, [back, double_sided]);
+        assert_eq!(pipelines.pipelines.len(), 2);
+        assert_eq!(pipelines.get(&back).global_id(), first);
+        assert_ne!(pipelines.get(&double_sided).global_id(), first);
+    }
+
+    #[test]
+    fn depth_only_variants_by_alpha_mask() {
+        let Some((device, _queue)) = test_device() else {
+            return;
+        };
+
+        let layouts = Layouts::new(&device, false);
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &layouts.groups(&DepthPrepass::BIND_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let mut pipelines = PipelineCache::new(
+            &device,
+            &ShaderLibrary::new(false),
+            &layouts,
+            "shaders/pbr.wgsl",
+            PipelineTemplate {
+                label: "Test pipeline",
+                layout,
+                fragment_entry_point: "fs_prepass",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![],
+                material_blend: false,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::LessEqual,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples: 1,
+            },
+        );
+
+        // The opaque variant has no fragment shader, the masked one discards
+        let opaque = key(Some(Face::Back), None);
+        let masked = PipelineKey {
+            alpha_mask: true,
+            ..opaque
+        };
+
+        pipelines.prepare(&device, [opaque, masked]);
+        assert_eq!(pipelines.pipelines.len(), 2);
+        assert_ne!(
+            pipelines.get(&opaque).global_id(),
+            pipelines.get(&masked).global_id()
+        );
+    }
+}
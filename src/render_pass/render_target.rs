@@ -0,0 +1,56 @@
+use wgpu::{
+    Color, Device, LoadOp, Operations, RenderPassColorAttachment, StoreOp, TextureFormat,
+    TextureUsages,
+};
+
+use crate::texture::Texture;
+
+/// A color target that is drawn through a multisampled texture when there is
+/// more than one sample per pixel. Each render pass resolves it into the
+/// single sampled texture, which the following passes can read.
+pub struct RenderTarget {
+    pub texture: Texture,
+    multisampled: Option<Texture>,
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: &Device,
+        width: u32,
+        height: u32,
+        label: &str,
+        format: TextureFormat,
+        usage: TextureUsages,
+        samples: u32,
+    ) -> RenderTarget {
+        let texture = Texture::new(device, width, height, Some(label), format, usage);
+        let multisampled = (samples > 1).then(|| {
+            Texture::multisampled(
+                device,
+                width,
+                height,
+                Some(label),
+                format,
+                TextureUsages::RENDER_ATTACHMENT,
+                samples,
+            )
+        });
+
+        RenderTarget {
+            texture,
+            multisampled,
+        }
+    }
+
+    /// Keeps the samples for the next pass and resolves them into the texture.
+    pub fn attachment(&self, load: LoadOp<Color>) -> Option<RenderPassColorAttachment<'_>> {
+        Some(RenderPassColorAttachment {
+            view: &self.multisampled.as_ref().unwrap_or(&self.texture).view,
+            resolve_target: self.multisampled.as_ref().map(|_| &self.texture.view),
+            ops: Operations {
+                load,
+                store: StoreOp::Store,
+            },
+        })
+    }
+}
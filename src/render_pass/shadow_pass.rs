@@ -1,37 +1,40 @@
 use wgpu::{
-    BlendState, Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompareFunction,
-    DepthBiasState, DepthStencilState, Device, Face, FragmentState, FrontFace, LoadOp,
-    MultisampleState, Operations, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
-    PrimitiveTopology, Queue, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
-    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
-    ShaderSource, StencilState, StoreOp, TextureUsages, TextureView, VertexState,
+    Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompareFunction, Device,
+    FrontFace, LoadOp, Operations, PipelineLayoutDescriptor, Queue, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp, TextureUsages, TextureView,
 };
 
 use crate::{
     entity::Vertex,
-    layouts::Layouts,
+    layouts::{Group, Layouts},
     render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    shader_library::ShaderLibrary,
+    stats::DrawStats,
     texture::Texture,
 };
 
+use super::pipeline::{PipelineCache, PipelineTemplate};
+
+/// Renders the distance to a light into a face of its shadow map. Blended
+/// materials are masked at half their opacity, as shadows can not be partial.
 pub struct ShadowPass {
-    pipeline: RenderPipeline,
+    pipelines: PipelineCache,
+    blended_pipelines: PipelineCache,
     depth_texture: Texture,
 }
 
 impl ShadowPass {
-    pub fn new(device: &Device, layouts: &Layouts) -> ShadowPass {
-        let shader = ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: ShaderSource::Wgsl(include_str!("../shaders/shadow.wgsl").into()),
-        };
-        let shader = device.create_shader_module(shader);
+    /// The groups of the shadow map pipelines.
+    pub const BIND_GROUPS: [Group; 3] = [Group::Camera, Group::Transform, Group::Material];
 
-        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("Pipeline layout"),
-            bind_group_layouts: &[&layouts.camera, &layouts.transform],
-            push_constant_ranges: &[],
-        });
+    pub fn new(device: &Device, shaders: &ShaderLibrary, layouts: &Layouts) -> ShadowPass {
+        let layout = || {
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Pipeline layout"),
+                bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
+                push_constant_ranges: &[],
+            })
+        };
 
         let depth_texture = Texture::new(
             device,
@@ -42,61 +45,65 @@ impl ShadowPass {
             TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
         );
 
-        //TODO use custom create_pipeline to create this one too
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Shadow render pipeline"),
-            layout: Some(&layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                compilation_options: Default::default(),
-                buffers: &[Vertex::desc()],
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                compilation_options: Default::default(),
-                targets: &[Some(ColorTargetState {
-                    format: Texture::SRGBA_UNORM,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Cw,
-                cull_mode: Some(Face::Back),
-                unclipped_depth: true,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: Texture::DEPTH_32_FLOAT,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState::default(),
-            multiview: None,
-            cache: None,
-        });
+        // The shadow cameras mirror the x axis, which flips the winding order
+        let template = |label, fragment_entry_point, material_blend| PipelineTemplate {
+            label,
+            layout: layout(),
+            fragment_entry_point,
+            vertex_layout: vec![Vertex::desc()],
+            color_targets: vec![ColorTargetState {
+                format: Texture::SRGBA_UNORM,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            }],
+            material_blend,
+            depth_format: Texture::DEPTH_32_FLOAT,
+            depth_compare: CompareFunction::Less,
+            depth_write: true,
+            front_face: FrontFace::Cw,
+            unclipped_depth: true,
+            samples: 1,
+        };
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/shadow.wgsl",
+            template("Shadow render pipeline", "fs_main", true),
+        );
+        let blended_pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/shadow.wgsl",
+            template("Blended shadow render pipeline", "fs_blended", false),
+        );
 
         ShadowPass {
-            pipeline,
+            pipelines,
+            blended_pipelines,
             depth_texture,
         }
     }
 
+    /// Creates the pipelines the `world` needs, see [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld) {
+        self.pipelines.prepare(device, world.pipeline_keys());
+        self.blended_pipelines
+            .prepare(device, world.transparent_pipeline_keys());
+    }
+
     pub fn draw(
-        &self,
+        &mut self,
         device: &Device,
         queue: &Queue,
         view: &TextureView,
         world: &RenderWorld,
         camera: &ExtractedCamera,
-    ) {
+    ) -> DrawStats {
+        self.prepare(device, world);
+
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
             label: Some("Shadow pass encoder"),
         });
@@ -123,14 +130,15 @@ impl ShadowPass {
             timestamp_writes: None,
         });
 
-        render_pass.set_pipeline(&self.pipeline);
-
         render_pass.set_bind_group(0, camera, &[]);
-        render_pass.draw_world(world);
+        let mut stats = render_pass.draw_world(world, camera, &self.pipelines);
+        stats += render_pass.draw_transparent(world, camera, &self.blended_pipelines);
 
         drop(render_pass);
         let encoder = encoder.finish();
 
         queue.submit(std::iter::once(encoder));
+
+        stats
     }
 }
@@ -1,35 +1,49 @@
 use wgpu::{
-    Color, CommandEncoderDescriptor, Device, LoadOp, Operations, PipelineLayoutDescriptor, Queue,
-    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, ShaderModuleDescriptor,
-    ShaderSource, StoreOp, TextureView,
+    Color, CommandEncoderDescriptor, Device, LoadOp, PipelineLayoutDescriptor, Queue,
+    RenderPassDescriptor, RenderPipeline,
 };
 
 use crate::{
-    layouts::Layouts,
+    layouts::{Group, Layouts},
     render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    shader_library::ShaderLibrary,
     texture::Texture,
 };
 
-use super::pipeline::create_pipeline;
+use super::{pipeline::create_pipeline, render_target::RenderTarget};
 
 pub struct SkyboxPass {
     pipeline: RenderPipeline,
 }
 
 impl SkyboxPass {
-    pub fn new(device: &Device, layouts: &Layouts) -> SkyboxPass {
-        let shader = ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: ShaderSource::Wgsl(include_str!("../shaders/skybox.wgsl").into()),
-        };
+    /// The groups of the skybox pipeline.
+    pub const BIND_GROUPS: [Group; 2] = [Group::Camera, Group::CubeMap];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        samples: u32,
+    ) -> SkyboxPass {
+        let shader = shaders.module(device, "shaders/skybox.wgsl", &[]);
 
         let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Skybox pipeline layout"),
-            bind_group_layouts: &[&layouts.camera, &layouts.cube_map],
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
             push_constant_ranges: &[],
         });
 
-        let pipeline = create_pipeline(device, &layout, &[], Texture::RGBA_16_FLOAT, None, shader);
+        let pipeline = create_pipeline(
+            device,
+            &layout,
+            &[],
+            Texture::RGBA_16_FLOAT,
+            None,
+            None,
+            samples,
+            &shader,
+        );
 
         SkyboxPass { pipeline }
     }
@@ -38,7 +52,7 @@ impl SkyboxPass {
         &self,
         device: &Device,
         queue: &Queue,
-        view: &TextureView,
+        target: &RenderTarget,
         world: &RenderWorld,
         camera: &ExtractedCamera,
     ) {
@@ -48,14 +62,7 @@ impl SkyboxPass {
 
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
             label: Some("Skybox render Pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(Color::WHITE),
-                    store: StoreOp::Store,
-                },
-            })],
+            color_attachments: &[target.attachment(LoadOp::Clear(Color::WHITE))],
             depth_stencil_attachment: None,
             occlusion_query_set: None,
             timestamp_writes: None,
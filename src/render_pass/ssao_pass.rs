@@ -0,0 +1,369 @@
+use std::ops::Deref;
+
+use cgmath::{InnerSpace, Vector3, Vector4};
+use encase::ShaderType;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages,
+    Color, ColorTargetState, ColorWrites, CommandEncoderDescriptor, Device, FragmentState, LoadOp,
+    MultisampleState, Operations, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModule, ShaderStages, StoreOp, TextureUsages, TextureView, VertexState,
+};
+
+use crate::{
+    layouts::{Group, Layouts},
+    render_world::ExtractedCamera,
+    settings::SsaoSettings,
+    shader_library::ShaderLibrary,
+    shader_types::uniform_bytes,
+    texture::Texture,
+};
+
+/// Screen space ambient occlusion of the opaque geometry, computed from its
+/// depth once it is complete and before it is shaded. The shading passes
+/// multiply it into the ambient term only.
+pub struct SsaoPass {
+    ssao_pipeline: RenderPipeline,
+    blur_pipeline: RenderPipeline,
+    buffer: Buffer,
+    bind_group: BindGroup,
+    targets: SsaoTargets,
+    /// Whether the occlusion has been cleared since it was last computed.
+    cleared: bool,
+}
+
+/// Everything that depends on the size of the depth texture.
+struct SsaoTargets {
+    raw: Texture,
+    blurred: Texture,
+    depth_bind_group: BindGroup,
+    raw_bind_group: BindGroup,
+    /// The blurred occlusion, for the shading passes.
+    bind_group: BindGroup,
+}
+
+impl SsaoPass {
+    /// The groups of the occlusion pipeline.
+    pub const SSAO_GROUPS: [Group; 3] = [Group::Camera, Group::Depth, Group::Own];
+    /// The groups of the blur pipeline.
+    pub const BLUR_GROUPS: [Group; 4] = [Group::Camera, Group::Depth, Group::Own, Group::Texture];
+
+    const KERNEL_SIZE: usize = 64;
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> SsaoPass {
+        let shader = shaders.module(device, "shaders/ssao.wgsl", &[]);
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Ssao bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let ssao_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Ssao pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::SSAO_GROUPS, &[&layout]),
+            push_constant_ranges: &[],
+        });
+
+        let blur_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Ssao blur pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BLUR_GROUPS, &[&layout]),
+            push_constant_ranges: &[],
+        });
+
+        let ssao_pipeline = create_fullscreen_pipeline(device, &ssao_layout, &shader, "fs_ssao");
+        let blur_pipeline = create_fullscreen_pipeline(device, &blur_layout, &shader, "fs_blur");
+
+        let uniform = SsaoUniform::new(&SsaoSettings::default());
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Ssao buffer"),
+            contents: &uniform_bytes(&uniform),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Ssao bind group"),
+            layout: &layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let targets = SsaoTargets::new(device, layouts, depth_view, width, height);
+
+        SsaoPass {
+            ssao_pipeline,
+            blur_pipeline,
+            buffer,
+            bind_group,
+            targets,
+            cleared: false,
+        }
+    }
+
+    /// Computes the occlusion from the current content of the depth texture.
+    /// When disabled nothing is occluded, and the depth is not read.
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        camera: &ExtractedCamera,
+        settings: &SsaoSettings,
+    ) {
+        if !settings.enabled && self.cleared {
+            return;
+        }
+        self.cleared = !settings.enabled;
+
+        let targets = &self.targets;
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Ssao Encoder"),
+        });
+
+        if !settings.enabled {
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Ssao clear Pass"),
+                color_attachments: &[color_attachment(&targets.blurred.view, true)],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            queue.submit(std::iter::once(encoder.finish()));
+            return;
+        }
+
+        queue.write_buffer(&self.buffer, 0, &uniform_bytes(&SsaoUniform::new(settings)));
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Ssao Pass"),
+            color_attachments: &[color_attachment(&targets.raw.view, false)],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.ssao_pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &targets.depth_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Ssao blur Pass"),
+            color_attachments: &[color_attachment(&targets.blurred.view, false)],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.blur_pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, &targets.depth_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.set_bind_group(3, &targets.raw_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.targets = SsaoTargets::new(device, layouts, depth_view, width, height);
+        self.cleared = false;
+    }
+}
+
+impl Deref for SsaoPass {
+    type Target = BindGroup;
+
+    fn deref(&self) -> &Self::Target {
+        &self.targets.bind_group
+    }
+}
+
+fn create_fullscreen_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    fragment_entry_point: &str,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Ssao pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            compilation_options: Default::default(),
+            targets: &[Some(ColorTargetState {
+                format: Texture::R_16_FLOAT,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn color_attachment(view: &TextureView, clear: bool) -> Option<RenderPassColorAttachment<'_>> {
+    Some(RenderPassColorAttachment {
+        view,
+        resolve_target: None,
+        ops: Operations {
+            load: match clear {
+                true => LoadOp::Clear(Color::WHITE),
+                false => LoadOp::Load,
+            },
+            store: StoreOp::Store,
+        },
+    })
+}
+
+impl SsaoTargets {
+    fn new(
+        device: &Device,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> SsaoTargets {
+        let target = |label| {
+            Texture::new(
+                device,
+                width,
+                height,
+                Some(label),
+                Texture::R_16_FLOAT,
+                TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+            )
+        };
+
+        let raw = target("Ssao texture");
+        let blurred = target("Ssao blurred texture");
+
+        let depth_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Ssao depth bind group"),
+            layout: &layouts.depth,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(depth_view),
+            }],
+        });
+
+        let texture_bind_group = |label, texture: &Texture| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some(label),
+                layout: &layouts.texture,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&texture.view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&texture.sampler),
+                    },
+                ],
+            })
+        };
+
+        let raw_bind_group = texture_bind_group("Ssao raw bind group", &raw);
+        let bind_group = texture_bind_group("Ssao output bind group", &blurred);
+
+        SsaoTargets {
+            raw,
+            blurred,
+            depth_bind_group,
+            raw_bind_group,
+            bind_group,
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct SsaoUniform {
+    radius: f32,
+    intensity: f32,
+    sample_count: u32,
+    kernel: [Vector4<f32>; SsaoPass::KERNEL_SIZE],
+}
+
+impl SsaoUniform {
+    fn new(settings: &SsaoSettings) -> SsaoUniform {
+        // Same kernel every frame, so the noise does not flicker
+        let mut rng = StdRng::seed_from_u64(0);
+        let kernel = std::array::from_fn(|i| {
+            let direction = Vector3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+            )
+            .normalize();
+
+            // More samples close to the surface
+            let scale = i as f32 / SsaoPass::KERNEL_SIZE as f32;
+            let sample = direction * rng.gen_range(0.0..1.0) * (0.1 + 0.9 * scale * scale);
+            sample.extend(0.0)
+        });
+
+        SsaoUniform {
+            radius: settings.radius,
+            intensity: settings.intensity,
+            sample_count: settings.sample_count.min(SsaoPass::KERNEL_SIZE as u32),
+            kernel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn ssao_matches_wgsl() {
+        assert_layout::<SsaoUniform>("shaders/ssao.wgsl", &[], "Ssao");
+    }
+}
@@ -0,0 +1,480 @@
+use encase::ShaderType;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendComponent, BlendFactor,
+    BlendOperation, BlendState, Buffer, BufferBindingType, BufferUsages, Color, ColorTargetState,
+    ColorWrites, CommandEncoderDescriptor, Device, FragmentState, LoadOp, MultisampleState,
+    Operations, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
+    SamplerBindingType, ShaderModule, ShaderStages, StoreOp, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    layouts::{Group, Layouts},
+    render_world::{ExtractedCamera, RenderWorld},
+    settings::SsrSettings,
+    shader_library::ShaderLibrary,
+    shader_types::uniform_bytes,
+    texture::Texture,
+};
+
+use super::{
+    depth_pyramid::{DepthPyramid, DepthReduction},
+    render_target::RenderTarget,
+};
+
+/// Screen space reflections of the opaque geometry, once it is shaded and
+/// before the transparent objects are drawn. The reflected rays are traced
+/// through a pyramid of the nearest depth, and the environment cube map is
+/// reflected where they miss. The result is added to the HDR target.
+pub struct SsrPass {
+    trace_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+    trace_layout: BindGroupLayout,
+    composite_layout: BindGroupLayout,
+    buffer: Buffer,
+    depth_pyramid: DepthPyramid,
+    targets: SsrTargets,
+}
+
+/// Everything that depends on the size of the depth texture.
+struct SsrTargets {
+    /// The shaded opaque geometry the rays hit.
+    scene_color: Texture,
+    reflections: Texture,
+    depth_bind_group: BindGroup,
+    trace_bind_group: BindGroup,
+    composite_bind_group: BindGroup,
+}
+
+impl SsrPass {
+    /// The groups of the trace pipeline.
+    pub const TRACE_GROUPS: [Group; 5] = [
+        Group::Camera,
+        Group::Surface,
+        Group::Depth,
+        Group::DepthPyramid,
+        Group::Own,
+    ];
+    /// The groups of the composite pipeline.
+    pub const COMPOSITE_GROUPS: [Group; 5] = [
+        Group::Camera,
+        Group::Surface,
+        Group::Depth,
+        Group::Own,
+        Group::CubeMap,
+    ];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+        samples: u32,
+    ) -> SsrPass {
+        let shader = shaders.module(device, "shaders/ssr.wgsl", &[]);
+
+        let uniform = BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let texture = |binding, filterable| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        // Settings and the scene color
+        let trace_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Ssr trace bind group layout"),
+            entries: &[uniform, texture(1, false)],
+        });
+
+        // Settings and the traced reflections
+        let composite_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Ssr composite bind group layout"),
+            entries: &[
+                uniform,
+                texture(1, true),
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        // TRACE
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Ssr trace pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::TRACE_GROUPS, &[&trace_layout]),
+            push_constant_ranges: &[],
+        });
+
+        let trace_pipeline = create_fullscreen_pipeline(
+            device,
+            &layout,
+            &shader,
+            "fs_trace",
+            Texture::RGBA_16_FLOAT,
+            None,
+            1,
+        );
+
+        // COMPOSITE
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Ssr composite pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::COMPOSITE_GROUPS, &[&composite_layout]),
+            push_constant_ranges: &[],
+        });
+
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+
+        let composite_pipeline = create_fullscreen_pipeline(
+            device,
+            &layout,
+            &shader,
+            "fs_composite",
+            Texture::RGBA_16_FLOAT,
+            Some(BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+            samples,
+        );
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Ssr buffer"),
+            contents: &uniform_bytes(&SsrUniform::new(&SsrSettings::default())),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let depth_pyramid = DepthPyramid::new(
+            device,
+            shaders,
+            layouts,
+            DepthReduction::Nearest,
+            depth_view,
+            width,
+            height,
+        );
+
+        let targets = SsrTargets::new(
+            device,
+            layouts,
+            &trace_layout,
+            &composite_layout,
+            &buffer,
+            depth_view,
+            width,
+            height,
+        );
+
+        SsrPass {
+            trace_pipeline,
+            composite_pipeline,
+            trace_layout,
+            composite_layout,
+            buffer,
+            depth_pyramid,
+            targets,
+        }
+    }
+
+    /// Adds the reflections to the `target`, which holds the shaded opaque
+    /// geometry described by the `surface` bind group. With MSAA they are
+    /// added to every sample, the rays are traced from the resolved texture.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        target: &RenderTarget,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+        surface: &BindGroup,
+        settings: &SsrSettings,
+    ) {
+        if !settings.enabled {
+            return;
+        }
+
+        let targets = &self.targets;
+        queue.write_buffer(&self.buffer, 0, &uniform_bytes(&SsrUniform::new(settings)));
+        self.depth_pyramid.build(device, queue, camera.view_proj);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Ssr Encoder"),
+        });
+
+        target.texture.copy_to(&mut encoder, &targets.scene_color);
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Ssr trace Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &targets.reflections.view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.trace_pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, surface, &[]);
+        render_pass.set_bind_group(2, &targets.depth_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.depth_pyramid, &[]);
+        render_pass.set_bind_group(4, &targets.trace_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Ssr composite Pass"),
+            color_attachments: &[target.attachment(LoadOp::Load)],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(1, surface, &[]);
+        render_pass.set_bind_group(2, &targets.depth_bind_group, &[]);
+        render_pass.set_bind_group(3, &targets.composite_bind_group, &[]);
+        render_pass.set_bind_group(4, &world.skybox.env_map_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        layouts: &Layouts,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.depth_pyramid
+            .resize(device, layouts, depth_view, width, height);
+        self.targets = SsrTargets::new(
+            device,
+            layouts,
+            &self.trace_layout,
+            &self.composite_layout,
+            &self.buffer,
+            depth_view,
+            width,
+            height,
+        );
+    }
+}
+
+/// Binds the normal and roughness, albedo and metallic textures the shading
+/// passes write for the [SsrPass].
+pub fn create_surface_bind_group(
+    device: &Device,
+    layouts: &Layouts,
+    normal: &Texture,
+    albedo: &Texture,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Surface bind group"),
+        layout: &layouts.surface,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&normal.view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&albedo.view),
+            },
+        ],
+    })
+}
+
+fn create_fullscreen_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    shader: &ShaderModule,
+    fragment_entry_point: &str,
+    format: TextureFormat,
+    blend: Option<BlendState>,
+    samples: u32,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Ssr pipeline"),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            compilation_options: Default::default(),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: samples,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+impl SsrTargets {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        device: &Device,
+        layouts: &Layouts,
+        trace_layout: &BindGroupLayout,
+        composite_layout: &BindGroupLayout,
+        buffer: &Buffer,
+        depth_view: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> SsrTargets {
+        let scene_color = Texture::new(
+            device,
+            width,
+            height,
+            Some("Ssr scene color texture"),
+            Texture::RGBA_16_FLOAT,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        );
+
+        let reflections = Texture::new(
+            device,
+            width,
+            height,
+            Some("Ssr reflections texture"),
+            Texture::RGBA_16_FLOAT,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        );
+
+        let depth_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Ssr depth bind group"),
+            layout: &layouts.depth,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(depth_view),
+            }],
+        });
+
+        let trace_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Ssr trace bind group"),
+            layout: trace_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&scene_color.view),
+                },
+            ],
+        });
+
+        let composite_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Ssr composite bind group"),
+            layout: composite_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&reflections.view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(&reflections.sampler),
+                },
+            ],
+        });
+
+        SsrTargets {
+            scene_color,
+            reflections,
+            depth_bind_group,
+            trace_bind_group,
+            composite_bind_group,
+        }
+    }
+}
+
+#[derive(ShaderType)]
+struct SsrUniform {
+    max_steps: u32,
+    thickness: f32,
+    max_distance: f32,
+    max_roughness: f32,
+    blur_radius: f32,
+}
+
+impl SsrUniform {
+    fn new(settings: &SsrSettings) -> SsrUniform {
+        SsrUniform {
+            max_steps: settings.max_steps,
+            thickness: settings.thickness,
+            max_distance: settings.max_distance,
+            max_roughness: settings.max_roughness,
+            blur_radius: settings.blur_radius,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn ssr_matches_wgsl() {
+        assert_layout::<SsrUniform>("shaders/ssr.wgsl", &[], "Ssr");
+    }
+}
@@ -0,0 +1,125 @@
+use wgpu::{
+    ColorTargetState, ColorWrites, CommandEncoderDescriptor, CompareFunction, Device, FrontFace,
+    LoadOp, Operations, PipelineLayoutDescriptor, Queue, RenderPassDepthStencilAttachment,
+    RenderPassDescriptor, StoreOp, TextureView,
+};
+
+use crate::{
+    entity::Vertex,
+    layouts::{Group, Layouts},
+    render_world::{DrawWorld, ExtractedCamera, RenderWorld},
+    shader_library::ShaderLibrary,
+    stats::DrawStats,
+    texture::Texture,
+};
+
+use super::{
+    pipeline::{PipelineCache, PipelineTemplate},
+    render_target::RenderTarget,
+};
+
+/// Draws the blended materials on top of the opaque geometry. It tests against
+/// the depth written by the [PbrPass](super::PbrPass) but does not write to it.
+pub struct TransparentPass {
+    pipelines: PipelineCache,
+}
+
+impl TransparentPass {
+    /// The groups of the blended pipelines.
+    pub const BIND_GROUPS: [Group; 5] = [
+        Group::Camera,
+        Group::Transform,
+        Group::Material,
+        Group::Light,
+        Group::CubeMap,
+    ];
+
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        layouts: &Layouts,
+        samples: u32,
+    ) -> TransparentPass {
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Transparent pipeline layout"),
+            bind_group_layouts: &layouts.groups(&Self::BIND_GROUPS, &[]),
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = PipelineCache::new(
+            device,
+            shaders,
+            layouts,
+            "shaders/pbr.wgsl",
+            PipelineTemplate {
+                label: "Transparent render pipeline",
+                layout,
+                fragment_entry_point: "fs_transparent",
+                vertex_layout: vec![Vertex::desc()],
+                color_targets: vec![ColorTargetState {
+                    format: Texture::RGBA_16_FLOAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+                material_blend: true,
+                depth_format: Texture::DEPTH_32_FLOAT,
+                depth_compare: CompareFunction::LessEqual,
+                depth_write: true,
+                front_face: FrontFace::Ccw,
+                unclipped_depth: false,
+                samples,
+            },
+        );
+
+        TransparentPass { pipelines }
+    }
+
+    /// Creates the pipelines the `world` needs, see [PipelineCache::prepare].
+    pub fn prepare(&mut self, device: &Device, world: &RenderWorld) {
+        self.pipelines
+            .prepare(device, world.transparent_pipeline_keys());
+    }
+
+    pub fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &RenderTarget,
+        depth_view: &TextureView,
+        world: &RenderWorld,
+        camera: &ExtractedCamera,
+    ) -> DrawStats {
+        self.prepare(device, world);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Transparent render Encoder"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Transparent render Pass"),
+            color_attachments: &[target.attachment(LoadOp::Load)],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(0, camera, &[]);
+        render_pass.set_bind_group(3, &world.lights_bind_group, &[]);
+
+        let stats = render_pass.draw_transparent(world, camera, &self.pipelines);
+
+        drop(render_pass);
+        let encoder = encoder.finish();
+
+        queue.submit(std::iter::once(encoder));
+
+        stats
+    }
+}
@@ -0,0 +1,152 @@
+use cgmath::{MetricSpace, Point3};
+
+use crate::render_pass::PipelineKey;
+
+use super::{extracted::ExtractedMaterial, render_object::RenderObject};
+
+/// Orders the opaque objects so drawing them changes as little state as
+/// possible: grouped by pipeline, then by material when the materials have
+/// their own bind group, then by mesh, and front to back inside each group so
+/// early depth testing rejects the hidden fragments.
+pub fn build_draw_list(
+    objects: Vec<RenderObject>,
+    materials: &[ExtractedMaterial],
+    camera_position: Point3<f32>,
+) -> Vec<RenderObject> {
+    let materials = materials
+        .iter()
+        .map(|material| (material.pipeline_key, material.bind_group().is_some()))
+        .collect::<Vec<_>>();
+
+    sort_objects(objects, &materials, camera_position)
+}
+
+/// Sorts by the pipeline key of each material and whether it has its own
+/// bind group.
+fn sort_objects(
+    mut objects: Vec<RenderObject>,
+    materials: &[(PipelineKey, bool)],
+    camera_position: Point3<f32>,
+) -> Vec<RenderObject> {
+    // Pipelines in order of first use, looked up once per material
+    let mut pipeline_keys: Vec<PipelineKey> = vec![];
+    let mut pipelines = vec![0; materials.len()];
+    for object in &objects {
+        let (key, _) = materials[object.material_index()];
+        match pipeline_keys.iter().position(|used| *used == key) {
+            Some(pipeline) => pipelines[object.material_index()] = pipeline,
+            None => {
+                pipelines[object.material_index()] = pipeline_keys.len();
+                pipeline_keys.push(key);
+            }
+        }
+    }
+
+    // Squared distances are never negative, so their bits order them
+    objects.sort_by_cached_key(|object| {
+        let material_index = object.material_index();
+        let (_, bound) = materials[material_index];
+
+        (
+            pipelines[material_index],
+            bound.then_some(material_index),
+            object.mesh().first_index,
+            camera_position.distance2(object.world_position()).to_bits(),
+        )
+    });
+
+    objects
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, Vector3};
+
+    use crate::{
+        entity::{Aabb, BoundingSphere},
+        render_world::extracted::ExtractedMesh,
+    };
+
+    use super::*;
+
+    fn object(material_index: usize, first_index: u32, z: f32) -> RenderObject {
+        let mesh = ExtractedMesh {
+            first_index,
+            index_count: 36,
+            base_vertex: 0,
+            aabb: Aabb::new((-1.0, -1.0, -1.0), (1.0, 1.0, 1.0)),
+            bounding_sphere: BoundingSphere {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 1.0,
+            },
+        };
+
+        RenderObject::new(
+            mesh,
+            material_index,
+            0,
+            &[Matrix4::from_translation(Vector3::new(0.0, 0.0, z))],
+        )
+    }
+
+    #[test]
+    fn sorts_by_pipeline_material_mesh_and_distance() {
+        let opaque = PipelineKey {
+            cull_mode: None,
+            blend: None,
+            depth_write: true,
+            alpha_mask: false,
+            normal_map: false,
+            shadows: false,
+        };
+        let masked = PipelineKey {
+            alpha_mask: true,
+            ..opaque
+        };
+        let materials = [
+            (masked, true),
+            (opaque, true),
+            (masked, true),
+            (opaque, false),
+        ];
+
+        let objects = vec![
+            object(1, 0, -5.0),
+            object(0, 36, -1.0),
+            object(2, 0, -1.0),
+            object(0, 0, -9.0),
+            object(0, 36, -3.0),
+            object(0, 0, -2.0),
+            object(3, 72, -1.0),
+            object(3, 0, -4.0),
+        ];
+
+        let order = sort_objects(objects, &materials, Point3::new(0.0, 0.0, 0.0))
+            .iter()
+            .map(|object| {
+                (
+                    object.material_index(),
+                    object.mesh().first_index,
+                    -object.world_position().z,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            order,
+            [
+                // The pipeline of the first object, the material without a
+                // bind group first
+                (3, 0, 4.0),
+                (3, 72, 1.0),
+                (1, 0, 5.0),
+                // The other pipeline, by material, then mesh, then distance
+                (0, 0, 2.0),
+                (0, 0, 9.0),
+                (0, 36, 1.0),
+                (0, 36, 3.0),
+                (2, 0, 1.0),
+            ]
+        );
+    }
+}
@@ -1,33 +1,33 @@
 use std::ops::Deref;
 
-use cgmath::{Matrix4, SquareMatrix};
+use cgmath::{EuclideanSpace, Matrix4, SquareMatrix, Vector3};
+use encase::ShaderType;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, Device,
 };
 
-use crate::camera::Camera;
+use crate::{
+    camera::{Camera, Frustum},
+    shader_types::uniform_bytes,
+};
 
 #[allow(dead_code)]
 pub struct ExtractedCamera {
     buffer: Buffer,
     bind_group: BindGroup,
+    pub frustum: Frustum,
+    pub view_proj: Matrix4<f32>,
 }
 
 impl ExtractedCamera {
     pub fn new(device: &Device, layout: &BindGroupLayout, camera: &Camera) -> ExtractedCamera {
-        let uniform = CameraUniform::from(camera);
-        let uniform = unsafe {
-            std::slice::from_raw_parts(
-                &uniform as *const CameraUniform as *const u8,
-                std::mem::size_of::<CameraUniform>(),
-            )
-        };
+        let uniform = uniform_bytes(&CameraUniform::from(camera));
 
         let buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Model camera buffer"),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            contents: uniform,
+            contents: &uniform,
         });
 
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
@@ -39,7 +39,12 @@ impl ExtractedCamera {
             }],
         });
 
-        ExtractedCamera { buffer, bind_group }
+        ExtractedCamera {
+            buffer,
+            bind_group,
+            frustum: camera.get_frustum(),
+            view_proj: camera.get_projection() * camera.get_view(),
+        }
     }
 }
 
@@ -51,11 +56,9 @@ impl Deref for ExtractedCamera {
     }
 }
 
-#[allow(dead_code)]
-#[repr(C)]
+#[derive(ShaderType)]
 pub struct CameraUniform {
-    position: [f32; 3],
-    _padding: f32,
+    position: Vector3<f32>,
     view: Matrix4<f32>,
     inv_view: Matrix4<f32>,
     proj: Matrix4<f32>,
@@ -68,8 +71,7 @@ impl From<&Camera> for CameraUniform {
         let view = camera.get_view();
 
         CameraUniform {
-            position: camera.position.into(),
-            _padding: 0.0,
+            position: camera.position.to_vec(),
             view,
             proj,
             inv_proj: proj.invert().unwrap(),
@@ -77,3 +79,15 @@ impl From<&Camera> for CameraUniform {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn camera_matches_wgsl() {
+        assert_layout::<CameraUniform>("shaders/include/camera.wgsl", &[], "Camera");
+    }
+}
@@ -1,133 +1,171 @@
-use std::ops::Deref;
-
+use cgmath::{Vector3, Vector4};
+use encase::ShaderType;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BufferUsages, Device,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BlendState, BufferUsages, Device, Face,
 };
 
-use crate::{material::Material, texture::Texture};
+use crate::{
+    layouts::Layouts,
+    material::{AlphaMode, Material},
+    render_pass::PipelineKey,
+    settings::RenderSettings,
+    shader_types::uniform_bytes,
+    texture::Texture,
+};
 
+/// A material of the world. It only has its own bind group when the bindless
+/// material table is not used.
 pub struct ExtractedMaterial {
-    bind_group: BindGroup,
+    bind_group: Option<BindGroup>,
+    pub alpha_mode: AlphaMode,
+    pub pipeline_key: PipelineKey,
 }
 
 impl ExtractedMaterial {
     pub fn new(
         device: &Device,
-        layout: &BindGroupLayout,
+        layouts: &Layouts,
         material: &Material,
+        settings: &RenderSettings,
     ) -> ExtractedMaterial {
-        let uniform = MaterialUniform {
-            base_color: material.base_color,
-            metallic_factor: material.metallic_factor,
-            roughness_factor: material.roughness_factor,
-            _padding: 0.0,
-            _padding2: 0.0,
+        let blended = material.alpha_mode == AlphaMode::Blend;
+
+        let pipeline_key = PipelineKey {
+            cull_mode: (!material.double_sided).then_some(Face::Back),
+            blend: blended.then_some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            depth_write: !blended,
+            alpha_mask: matches!(material.alpha_mode, AlphaMode::Mask(_)),
+            normal_map: material.normal_texture.is_some(),
+            shadows: settings.shadows,
         };
 
-        let uniform_data = unsafe {
-            std::slice::from_raw_parts(
-                &uniform as *const MaterialUniform as *const u8,
-                std::mem::size_of::<MaterialUniform>(),
-            )
-        };
-
-        let material_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Material buffer"),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            contents: uniform_data,
-        });
-
-        let empty_texture = Texture::new(
-            device,
-            1,
-            1,
-            None,
-            Texture::SRGBA_UNORM,
-            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-        );
-
-        let base_texture = match &material.base_texture {
-            Some(texture) => texture,
-            None => &empty_texture,
-        };
-
-        let normal_texture = match &material.normal_texture {
-            Some(texture) => texture,
-            None => &empty_texture,
-        };
-
-        let metallic_roughness_texture = match &material.metallic_roughness_texture {
-            Some(texture) => texture,
-            None => &empty_texture,
-        };
+        let bind_group = (!layouts.bindless).then(|| create_bind_group(device, layouts, material));
 
-        let ambient_occlussion_texture = match &material.ambient_occlussion_texture {
-            Some(texture) => texture,
-            None => &empty_texture,
-        };
+        ExtractedMaterial {
+            bind_group,
+            alpha_mode: material.alpha_mode,
+            pipeline_key,
+        }
+    }
 
-        let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Material bind group"),
-            layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: material_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&base_texture.sampler),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&base_texture.view),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-                BindGroupEntry {
-                    binding: 4,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::Sampler(&metallic_roughness_texture.sampler),
-                },
-                BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.view),
-                },
-                BindGroupEntry {
-                    binding: 7,
-                    resource: wgpu::BindingResource::Sampler(&ambient_occlussion_texture.sampler),
-                },
-                BindGroupEntry {
-                    binding: 8,
-                    resource: wgpu::BindingResource::TextureView(&ambient_occlussion_texture.view),
-                },
-            ],
-        });
-
-        ExtractedMaterial { bind_group }
+    pub fn bind_group(&self) -> Option<&BindGroup> {
+        self.bind_group.as_ref()
     }
 }
 
-impl Deref for ExtractedMaterial {
-    type Target = BindGroup;
-
-    fn deref(&self) -> &Self::Target {
-        &self.bind_group
-    }
+fn create_bind_group(device: &Device, layouts: &Layouts, material: &Material) -> BindGroup {
+    let uniform = MaterialUniform {
+        base_color: material.base_color.into(),
+        metallic_factor: material.metallic_factor,
+        roughness_factor: material.roughness_factor,
+        alpha_cutoff: material.alpha_mode.cutoff(),
+        emissive_factor: material.emissive_factor.into(),
+    };
+
+    let material_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        label: Some("Material buffer"),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        contents: &uniform_bytes(&uniform),
+    });
+
+    let empty_texture = Texture::new(
+        device,
+        1,
+        1,
+        None,
+        Texture::SRGBA_UNORM,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    );
+
+    let base_texture = material.base_texture.as_deref().unwrap_or(&empty_texture);
+
+    let normal_texture = material.normal_texture.as_deref().unwrap_or(&empty_texture);
+
+    let metallic_roughness_texture = material
+        .metallic_roughness_texture
+        .as_deref()
+        .unwrap_or(&empty_texture);
+
+    let ambient_occlussion_texture = material
+        .ambient_occlussion_texture
+        .as_deref()
+        .unwrap_or(&empty_texture);
+
+    let emissive_texture = material
+        .emissive_texture
+        .as_deref()
+        .unwrap_or(&empty_texture);
+
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Material bind group"),
+        layout: &layouts.material,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: material_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&base_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&base_texture.view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::Sampler(&metallic_roughness_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::TextureView(&metallic_roughness_texture.view),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Sampler(&ambient_occlussion_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&ambient_occlussion_texture.view),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::Sampler(&emissive_texture.sampler),
+            },
+            BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+            },
+        ],
+    })
 }
 
-#[allow(dead_code)]
-#[repr(C)]
+#[derive(ShaderType)]
 struct MaterialUniform {
-    base_color: [f32; 4],
+    base_color: Vector4<f32>,
     metallic_factor: f32,
-    _padding: f32,
     roughness_factor: f32,
-    _padding2: f32,
+    alpha_cutoff: f32,
+    emissive_factor: Vector3<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn material_matches_wgsl() {
+        assert_layout::<MaterialUniform>("shaders/material.wgsl", &[], "MaterialProperties");
+    }
 }
@@ -0,0 +1,205 @@
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use cgmath::{Vector3, Vector4};
+use encase::ShaderType;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferUsages, Device, Sampler,
+    TextureUsages, TextureView,
+};
+
+use crate::{layouts::Layouts, material::Material, shader_types::storage_bytes, texture::Texture};
+
+/// Every material of the world bound at once: the parameters in a storage
+/// buffer indexed by material id and the textures in a binding array. Objects
+/// with different materials can then be drawn without switching bind groups.
+pub struct ExtractedMaterialTable {
+    bind_group: BindGroup,
+}
+
+impl ExtractedMaterialTable {
+    pub fn new(
+        device: &Device,
+        layouts: &Layouts,
+        materials: &[&Material],
+    ) -> ExtractedMaterialTable {
+        // Slot 0 stands in for missing textures and pads the arrays
+        let placeholder = Texture::new(
+            device,
+            1,
+            1,
+            Some("Material placeholder texture"),
+            Texture::SRGBA_UNORM,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        );
+
+        let capacity = layouts.material_texture_capacity as usize;
+        let mut slots = TextureSlots::new(capacity);
+        let entries = materials
+            .iter()
+            .map(|material| MaterialEntry {
+                base_color: material.base_color.into(),
+                metallic_factor: material.metallic_factor,
+                roughness_factor: material.roughness_factor,
+                alpha_cutoff: material.alpha_mode.cutoff(),
+                base_color_texture: slots.slot(&material.base_texture),
+                normal_texture: slots.slot(&material.normal_texture),
+                metallic_roughness_texture: slots.slot(&material.metallic_roughness_texture),
+                ambient_occlussion_texture: slots.slot(&material.ambient_occlussion_texture),
+                emissive_factor: material.emissive_factor.into(),
+                emissive_texture: slots.slot(&material.emissive_texture),
+            })
+            .collect::<Vec<_>>();
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Material table buffer"),
+            // Storage buffers can not be empty, an empty table gets a zeroed entry
+            contents: &storage_bytes(&entries),
+            usage: BufferUsages::STORAGE,
+        });
+
+        // Binding arrays have to be filled up to the size of the layout
+        let mut textures = std::iter::once(&placeholder)
+            .chain(slots.textures.iter().map(|texture| texture.as_ref()))
+            .collect::<Vec<_>>();
+        textures.resize(capacity, &placeholder);
+
+        let views = textures
+            .iter()
+            .map(|texture| &texture.view)
+            .collect::<Vec<&TextureView>>();
+        let samplers = textures
+            .iter()
+            .map(|texture| texture.sampler.as_ref())
+            .collect::<Vec<&Sampler>>();
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Material table bind group"),
+            layout: &layouts.material,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::SamplerArray(&samplers),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureViewArray(&views),
+                },
+            ],
+        });
+
+        ExtractedMaterialTable { bind_group }
+    }
+
+    /// Whether the distinct textures of the `materials` fit in the arrays of
+    /// a table of the `capacity`, next to the placeholder.
+    pub fn fits<'a>(materials: impl IntoIterator<Item = &'a Material>, capacity: u32) -> bool {
+        let mut slots = TextureSlots::new(capacity as usize);
+        for material in materials {
+            slots.slot(&material.base_texture);
+            slots.slot(&material.normal_texture);
+            slots.slot(&material.metallic_roughness_texture);
+            slots.slot(&material.ambient_occlussion_texture);
+            slots.slot(&material.emissive_texture);
+        }
+
+        !slots.overflow
+    }
+}
+
+/// The slots of the textures in the binding arrays. Slot 0 is the placeholder
+/// standing in for missing textures, shared textures take a single slot.
+struct TextureSlots<'a, T> {
+    capacity: usize,
+    /// From slot 1 on.
+    textures: Vec<&'a Arc<T>>,
+    slots: HashMap<*const T, u32>,
+    /// Whether textures past the capacity were left out, drawn as missing.
+    overflow: bool,
+}
+
+impl<'a, T> TextureSlots<'a, T> {
+    fn new(capacity: usize) -> TextureSlots<'a, T> {
+        TextureSlots {
+            capacity,
+            textures: Vec::new(),
+            slots: HashMap::new(),
+            overflow: false,
+        }
+    }
+
+    fn slot(&mut self, texture: &'a Option<Arc<T>>) -> u32 {
+        let Some(texture) = texture else {
+            return 0;
+        };
+
+        if let Some(slot) = self.slots.get(&Arc::as_ptr(texture)) {
+            return *slot;
+        }
+        if self.textures.len() + 1 >= self.capacity {
+            self.overflow = true;
+            return 0;
+        }
+
+        self.textures.push(texture);
+        let slot = self.textures.len() as u32;
+        self.slots.insert(Arc::as_ptr(texture), slot);
+        slot
+    }
+}
+
+impl Deref for ExtractedMaterialTable {
+    type Target = BindGroup;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bind_group
+    }
+}
+
+#[derive(ShaderType)]
+struct MaterialEntry {
+    base_color: Vector4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    alpha_cutoff: f32,
+    base_color_texture: u32,
+    normal_texture: u32,
+    metallic_roughness_texture: u32,
+    ambient_occlussion_texture: u32,
+    emissive_factor: Vector3<f32>,
+    emissive_texture: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn shared_textures_take_one_slot() {
+        let (first, second, third) = (Some(Arc::new(1)), Some(Arc::new(2)), Some(Arc::new(3)));
+        let shared = first.clone();
+
+        let mut slots = TextureSlots::new(3);
+        assert_eq!(slots.slot(&None), 0);
+        assert_eq!(slots.slot(&first), 1);
+        assert_eq!(slots.slot(&second), 2);
+        assert_eq!(slots.slot(&shared), 1);
+        assert!(!slots.overflow);
+
+        // Past the capacity the texture is drawn as missing
+        assert_eq!(slots.slot(&third), 0);
+        assert!(slots.overflow);
+        assert_eq!(slots.slot(&second), 2);
+    }
+
+    #[test]
+    fn material_entry_matches_wgsl() {
+        assert_layout::<MaterialEntry>("shaders/material_bindless.wgsl", &[], "MaterialProperties");
+    }
+}
@@ -1,58 +1,75 @@
+use std::ops::Range;
+
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
-    Buffer, BufferUsages, Device, IndexFormat, RenderPass,
+    Buffer, BufferUsages, Device,
 };
 
-use crate::entity::{Geometry, Vertex};
+use crate::entity::{Aabb, BoundingSphere, Geometry, Vertex};
 
+/// A mesh primitive inside the shared [ExtractedGeometry] buffers.
+#[derive(Debug, Clone, Copy)]
 pub struct ExtractedMesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl ExtractedMesh {
+    pub fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+/// Vertices and indices of every mesh in the world, packed into a single
+/// vertex and a single index buffer so they are only bound once per pass.
+pub struct ExtractedGeometry {
     pub vertex_buffer: Buffer,
     pub index_buffer: Buffer,
-    pub indices_len: usize,
 }
 
-impl ExtractedMesh {
-    pub fn new(device: &Device, geometry: &Geometry) -> ExtractedMesh {
-        let vertices_data: &[Vertex] = &geometry.vertices;
-        let vertex_buffer_data = as_u8_slice(vertices_data);
+/// Accumulates the geometry of the world before uploading it.
+#[derive(Default)]
+pub struct GeometryPacker {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+
+impl GeometryPacker {
+    /// Appends the geometry, its indices stay relative to its first vertex.
+    pub fn push(&mut self, geometry: &Geometry) -> ExtractedMesh {
+        let mesh = ExtractedMesh {
+            first_index: self.indices.len() as u32,
+            index_count: geometry.indices.len() as u32,
+            base_vertex: self.vertices.len() as i32,
+            aabb: geometry.aabb,
+            bounding_sphere: geometry.bounding_sphere,
+        };
 
-        let indices_data: &[u16] = &geometry.indices;
-        let index_buffer_data = as_u8_slice(indices_data);
+        self.vertices.extend_from_slice(&geometry.vertices);
+        self.indices.extend_from_slice(&geometry.indices);
 
+        mesh
+    }
+
+    pub fn finish(self, device: &Device) -> ExtractedGeometry {
         let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Vertex buffer"),
-            contents: vertex_buffer_data,
+            contents: bytemuck::cast_slice(&self.vertices),
             usage: BufferUsages::VERTEX,
         });
 
         let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Index buffer"),
-            contents: index_buffer_data,
+            contents: bytemuck::cast_slice(&self.indices),
             usage: BufferUsages::INDEX,
         });
 
-        ExtractedMesh {
+        ExtractedGeometry {
             vertex_buffer,
             index_buffer,
-            indices_len: geometry.indices.len(),
         }
     }
 }
-
-fn as_u8_slice<T: Sized>(data: &[T]) -> &[u8] {
-    let size = std::mem::size_of_val(data);
-    unsafe { std::slice::from_raw_parts(data as *const [T] as *const u8, size) }
-}
-
-pub trait DrawMesh<'a> {
-    fn draw_mesh(&mut self, object: &'a ExtractedMesh);
-}
-
-impl<'a> DrawMesh<'a> for RenderPass<'a> {
-    fn draw_mesh(&mut self, mesh: &'a ExtractedMesh) {
-        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-        self.set_index_buffer(mesh.index_buffer.slice(..), IndexFormat::Uint16);
-
-        self.draw_indexed(0..mesh.indices_len as u32, 0, 0..1);
-    }
-}
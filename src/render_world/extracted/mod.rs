@@ -1,5 +1,6 @@
 mod camera;
 mod material;
+mod material_table;
 mod mesh;
 mod point_light;
 mod skybox;
@@ -7,7 +8,8 @@ mod transform;
 
 pub use camera::ExtractedCamera;
 pub use material::ExtractedMaterial;
-pub use mesh::{DrawMesh, ExtractedMesh};
-pub use point_light::{ExtractedPointLight, PointLightUniform};
+pub use material_table::ExtractedMaterialTable;
+pub use mesh::{ExtractedGeometry, ExtractedMesh, GeometryPacker};
+pub use point_light::{ExtractedPointLight, PointLightsUniform};
 pub use skybox::ExtractedSkybox;
-pub use transform::ExtractedTransform;
+pub use transform::ExtractedTransforms;
@@ -1,4 +1,5 @@
 use cgmath::Vector3;
+use encase::ShaderType;
 use wgpu::Device;
 
 use crate::{camera::Camera, layouts::Layouts, light::PointLight, texture::CubeMap};
@@ -56,21 +57,34 @@ impl ExtractedPointLight {
     }
 }
 
-#[repr(C)]
+/// The point lights of the world, as the shaders read them.
+#[derive(ShaderType)]
+pub struct PointLightsUniform {
+    count: u32,
+    #[size(runtime)]
+    lights: Vec<PointLightUniform>,
+}
+
+impl PointLightsUniform {
+    pub fn new(lights: &[ExtractedPointLight]) -> PointLightsUniform {
+        PointLightsUniform {
+            count: lights.len() as u32,
+            lights: lights.iter().map(|light| light.uniform).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ShaderType)]
 pub struct PointLightUniform {
-    position: (f32, f32, f32),
-    _padding1: f32,
-    color: (f32, f32, f32),
-    _padding2: f32,
+    position: Vector3<f32>,
+    color: Vector3<f32>,
 }
 
 impl From<&PointLight> for PointLightUniform {
     fn from(point_light: &PointLight) -> Self {
         PointLightUniform {
-            position: point_light.position,
-            _padding1: 0.0,
-            color: point_light.color,
-            _padding2: 0.0,
+            position: point_light.position.into(),
+            color: point_light.color.into(),
         }
     }
 }
@@ -147,3 +161,20 @@ impl From<&PointLight> for PointLightUniform {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn point_light_matches_wgsl() {
+        assert_layout::<PointLightUniform>("shaders/lighting.wgsl", &["SHADOWS"], "PointLight");
+    }
+
+    #[test]
+    fn point_lights_match_wgsl() {
+        assert_layout::<PointLightsUniform>("shaders/lighting.wgsl", &["SHADOWS"], "PointLights");
+    }
+}
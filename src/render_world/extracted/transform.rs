@@ -1,58 +1,75 @@
 use std::ops::Deref;
 
 use cgmath::{Matrix, Matrix4, SquareMatrix};
+use encase::ShaderType;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BufferUsages, Device,
 };
 
-use crate::transform::Transform;
+use crate::shader_types::storage_bytes;
 
-pub struct ExtractedTransform {
+/// The transforms and material ids of every instance in the world, in storage
+/// buffers the shaders index with the instance index.
+pub struct ExtractedTransforms {
     bind_group: BindGroup,
 }
 
-impl ExtractedTransform {
+impl ExtractedTransforms {
     pub fn new(
         device: &Device,
         layout: &BindGroupLayout,
-        transform: &Transform,
-        parent_model_matrix: Matrix4<f32>,
-    ) -> ExtractedTransform {
-        let model_matrix = parent_model_matrix * transform.model();
-        let normal_matrix = model_matrix.invert().unwrap().transpose();
+        model_matrices: &[Matrix4<f32>],
+        material_ids: &[u32],
+    ) -> ExtractedTransforms {
+        let mut uniforms = model_matrices
+            .iter()
+            .map(|&model_matrix| TransformUniform {
+                model_matrix,
+                normal_matrix: model_matrix.invert().unwrap().transpose(),
+            })
+            .collect::<Vec<_>>();
 
-        let uniform = TransformUniform {
-            model_matrix,
-            normal_matrix,
-        };
+        // Storage buffers can not be empty
+        if uniforms.is_empty() {
+            uniforms.push(TransformUniform {
+                model_matrix: Matrix4::identity(),
+                normal_matrix: Matrix4::identity(),
+            });
+        }
 
-        let buffer_data = unsafe {
-            std::slice::from_raw_parts(
-                &uniform as *const TransformUniform as *const u8,
-                std::mem::size_of::<TransformUniform>(),
-            )
-        };
         let buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Transform buffer"),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            contents: buffer_data,
+            label: Some("Transforms buffer"),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            contents: &storage_bytes(&uniforms),
+        });
+
+        let material_ids_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Instance materials buffer"),
+            usage: BufferUsages::STORAGE,
+            contents: &storage_bytes(material_ids),
         });
 
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Transform bind group"),
+            label: Some("Transforms bind group"),
             layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: material_ids_buffer.as_entire_binding(),
+                },
+            ],
         });
 
-        ExtractedTransform { bind_group }
+        ExtractedTransforms { bind_group }
     }
 }
 
-impl Deref for ExtractedTransform {
+impl Deref for ExtractedTransforms {
     type Target = BindGroup;
 
     fn deref(&self) -> &Self::Target {
@@ -60,8 +77,20 @@ impl Deref for ExtractedTransform {
     }
 }
 
-#[allow(dead_code)]
+#[derive(ShaderType)]
 struct TransformUniform {
-    model_matrix: cgmath::Matrix4<f32>,
-    normal_matrix: cgmath::Matrix4<f32>,
+    model_matrix: Matrix4<f32>,
+    normal_matrix: Matrix4<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn transform_matches_wgsl() {
+        assert_layout::<TransformUniform>("shaders/include/transform.wgsl", &[], "Transform");
+    }
 }
@@ -0,0 +1,207 @@
+use std::ops::Deref;
+
+use cgmath::{EuclideanSpace, Vector3, Vector4};
+use encase::ShaderType;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt, DrawIndexedIndirectArgs},
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, Buffer, BufferUsages, Device,
+    Features,
+};
+
+use crate::{
+    camera::Frustum,
+    render_pass::PipelineKey,
+    shader_types::{storage_bytes, uniform_bytes},
+};
+
+use super::{extracted::ExtractedMaterial, render_object::RenderObject};
+
+/// Per object data of the GPU driven path. A compute pass culls the objects
+/// against the frustum and the depth pyramid and writes one indirect draw
+/// command for each of them, culled objects getting an instance count of zero.
+pub struct IndirectDraws {
+    bind_group: BindGroup,
+    pub commands: Buffer,
+    pub object_count: u32,
+    /// Consecutive commands sharing the pipeline and, unless the bindless
+    /// material table is used, the material, are drawn with a single multi
+    /// draw call. The objects come sorted by the draw list.
+    pub batches: Vec<IndirectBatch>,
+    pub multi_draw: bool,
+}
+
+pub struct IndirectBatch {
+    pub pipeline_key: PipelineKey,
+    /// The material to bind before drawing, `None` when it is in the table.
+    pub material_index: Option<usize>,
+    pub first_command: u32,
+    pub command_count: u32,
+}
+
+impl IndirectDraws {
+    pub const WORKGROUP_SIZE: u32 = 64;
+    pub const COMMAND_SIZE: u64 = std::mem::size_of::<DrawIndexedIndirectArgs>() as u64;
+
+    pub fn new(
+        device: &Device,
+        layout: &BindGroupLayout,
+        objects: &[RenderObject],
+        materials: &[ExtractedMaterial],
+        frustum: &Frustum,
+    ) -> IndirectDraws {
+        let mut object_data = vec![];
+        let mut batches: Vec<IndirectBatch> = vec![];
+
+        for object in objects {
+            let mesh = object.mesh();
+
+            for (instance, aabb, bounding_sphere) in object.instance_bounds() {
+                object_data.push(ObjectUniform {
+                    center: bounding_sphere.center.to_vec(),
+                    radius: bounding_sphere.radius,
+                    aabb_min: aabb.min.to_vec(),
+                    first_index: mesh.first_index,
+                    aabb_max: aabb.max.to_vec(),
+                    index_count: mesh.index_count,
+                    base_vertex: mesh.base_vertex,
+                    instance,
+                    material_index: object.material_index() as u32,
+                });
+            }
+
+            let material = &materials[object.material_index()];
+            let material_index = material
+                .bind_group()
+                .is_some()
+                .then_some(object.material_index());
+
+            match batches.last_mut() {
+                Some(batch)
+                    if batch.pipeline_key == material.pipeline_key
+                        && batch.material_index == material_index =>
+                {
+                    batch.command_count += object.instance_count();
+                }
+                _ => batches.push(IndirectBatch {
+                    pipeline_key: material.pipeline_key,
+                    material_index,
+                    first_command: object_data.len() as u32 - object.instance_count(),
+                    command_count: object.instance_count(),
+                }),
+            }
+        }
+
+        let object_count = object_data.len() as u32;
+
+        let cull_uniform = CullUniform {
+            planes: frustum.planes(),
+            object_count,
+        };
+
+        let cull_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Cull buffer"),
+            contents: &uniform_bytes(&cull_uniform),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let objects_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Objects buffer"),
+            contents: &storage_bytes(&object_data),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let commands = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect commands buffer"),
+            size: object_count as u64 * Self::COMMAND_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        // Objects the first cull phase found occluded, re-tested by the second
+        let occluded = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occluded objects buffer"),
+            size: object_count as u64 * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Cull bind group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: cull_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: objects_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: commands.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: occluded.as_entire_binding(),
+                },
+            ],
+        });
+
+        IndirectDraws {
+            bind_group,
+            commands,
+            object_count,
+            batches,
+            multi_draw: device.features().contains(Features::MULTI_DRAW_INDIRECT),
+        }
+    }
+
+    pub fn workgroup_count(&self) -> u32 {
+        self.object_count.div_ceil(Self::WORKGROUP_SIZE)
+    }
+}
+
+impl Deref for IndirectDraws {
+    type Target = BindGroup;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bind_group
+    }
+}
+
+#[derive(ShaderType)]
+struct CullUniform {
+    planes: [Vector4<f32>; 6],
+    object_count: u32,
+}
+
+#[derive(ShaderType)]
+struct ObjectUniform {
+    center: Vector3<f32>,
+    radius: f32,
+    aabb_min: Vector3<f32>,
+    first_index: u32,
+    aabb_max: Vector3<f32>,
+    index_count: u32,
+    base_vertex: i32,
+    instance: u32,
+    material_index: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shader_types::tests::assert_layout;
+
+    use super::*;
+
+    #[test]
+    fn cull_matches_wgsl() {
+        assert_layout::<CullUniform>("shaders/cull.wgsl", &[], "Cull");
+    }
+
+    #[test]
+    fn object_matches_wgsl() {
+        assert_layout::<ObjectUniform>("shaders/cull.wgsl", &[], "Object");
+    }
+}
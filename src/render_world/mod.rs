@@ -1,59 +1,179 @@
+mod draw_list;
 mod extracted;
+mod indirect;
 mod render_object;
+mod tracked_pass;
 
-use cgmath::Matrix4;
+use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+
+use cgmath::{Matrix4, MetricSpace};
+use draw_list::build_draw_list;
 use extracted::{
-    ExtractedMaterial, ExtractedMesh, ExtractedPointLight, ExtractedSkybox, ExtractedTransform,
-    PointLightUniform,
+    ExtractedGeometry, ExtractedMaterial, ExtractedMaterialTable, ExtractedPointLight,
+    ExtractedSkybox, ExtractedTransforms, GeometryPacker, PointLightsUniform,
 };
-use render_object::{DrawRenderObject, RenderObject};
+use render_object::RenderObject;
+use tracked_pass::TrackedRenderPass;
 use wgpu::{
-    BindGroup, BindGroupDescriptor, BindGroupEntry, BufferDescriptor, BufferUsages, Device, Queue,
-    RenderPass, Sampler, TextureView,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BufferDescriptor,
+    BufferUsages, Device, Features, Queue, RenderPass,
 };
 
 pub use extracted::ExtractedCamera;
+pub use indirect::IndirectDraws;
 
 use crate::{
     camera::Camera,
     entity::{Entity, Mesh, Node},
     layouts::Layouts,
-    light::PointLight,
-    skybox::Skybox,
+    material::{AlphaMode, Material},
+    render_pass::{PipelineCache, PipelineKey},
+    scene::Scene,
+    settings::RenderSettings,
+    shader_types::storage_bytes,
+    stats::DrawStats,
+    texture::CubeMap,
 };
 
 pub struct RenderWorld {
     objects: Vec<RenderObject>,
+    transparent_objects: Vec<RenderObject>,
+    geometry: ExtractedGeometry,
+    transforms: ExtractedTransforms,
+    indirect: Option<IndirectDraws>,
     pub camera: ExtractedCamera,
     materials: Vec<ExtractedMaterial>,
+    material_table: Option<ExtractedMaterialTable>,
     pub lights: Vec<ExtractedPointLight>,
     pub lights_bind_group: BindGroup,
     pub skybox: ExtractedSkybox, // TODO change to ExtractedSkybox
 }
 
 impl RenderWorld {
+    /// Whether the textures of the scene fit in the bindless material table
+    /// of the `layouts`.
+    pub fn material_table_fits(scene: &Scene, layouts: &Layouts) -> bool {
+        let materials = scene
+            .entities
+            .iter()
+            .flat_map(|entity| entity.get_materials().iter());
+
+        ExtractedMaterialTable::fits(materials, layouts.material_texture_capacity)
+    }
+
     pub fn extract(
         device: &Device,
         queue: &Queue,
         layouts: &Layouts,
-        entities: &[Entity],
+        scene: &Scene,
         camera: &Camera,
-        lights: &[PointLight],
-        skybox: &Skybox,
+        settings: &RenderSettings,
     ) -> RenderWorld {
+        let Scene {
+            entities,
+            lights,
+            skybox,
+        } = scene;
+
         let mut materials = vec![];
-        let mut objects = vec![];
+        let mut source_materials: Vec<&Material> = vec![];
+        let mut instances = vec![];
+
+        // Entities created with Entity::instantiate share their materials, so
+        // they are only extracted once
+        let mut material_offsets = HashMap::new();
 
         for entity in entities {
-            let mut entity_render_objects =
-                extract_entity_render_objects(device, layouts, entity, materials.len());
-            objects.append(&mut entity_render_objects);
+            let material_offset = *material_offsets
+                .entry(Arc::as_ptr(entity.get_materials()))
+                .or_insert_with(|| {
+                    let offset = materials.len();
+                    materials.append(&mut extract_entity_materials(
+                        device, layouts, entity, settings,
+                    ));
+                    source_materials.extend(entity.get_materials().iter());
+                    offset
+                });
+
+            for node in entity.get_nodes() {
+                collect_node_instances(
+                    node,
+                    material_offset,
+                    entity.transform.model(),
+                    &mut instances,
+                );
+            }
+        }
 
-            let mut entity_materials = extract_entity_materials(device, layouts, entity);
-            materials.append(&mut entity_materials);
+        let batches = batch_instances(instances, |index| {
+            materials[index].alpha_mode == AlphaMode::Blend
+        });
+
+        let mut model_matrices = vec![];
+        let mut material_ids = vec![];
+        let mut objects = vec![];
+        let mut transparent_objects = vec![];
+
+        // Primitives drawn with several materials are only packed once
+        let mut packer = GeometryPacker::default();
+        let mut packed_meshes = HashMap::new();
+
+        for batch in batches {
+            let first_instance = model_matrices.len() as u32;
+            let mesh = *packed_meshes
+                .entry((Arc::as_ptr(batch.mesh), batch.primitive))
+                .or_insert_with(|| {
+                    let (geometry, _) = &batch.mesh.primitives[batch.primitive];
+                    packer.push(geometry)
+                });
+
+            let object = RenderObject::new(
+                mesh,
+                batch.material_index,
+                first_instance,
+                &batch.model_matrices,
+            );
+            material_ids.extend(std::iter::repeat_n(
+                batch.material_index as u32,
+                batch.model_matrices.len(),
+            ));
+            model_matrices.extend(batch.model_matrices);
+
+            // Blended objects are drawn after the opaque ones, sorted back to front
+            match materials[batch.material_index].alpha_mode {
+                AlphaMode::Blend => transparent_objects.push(object),
+                _ => objects.push(object),
+            }
         }
 
+        transparent_objects.sort_by(|a, b| {
+            let a_distance = camera.position.distance2(a.world_position());
+            let b_distance = camera.position.distance2(b.world_position());
+            b_distance
+                .partial_cmp(&a_distance)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let objects = build_draw_list(objects, &materials, camera.position);
+
+        let geometry = packer.finish(device);
+        let transforms =
+            ExtractedTransforms::new(device, &layouts.transform, &model_matrices, &material_ids);
+        let material_table = layouts
+            .bindless
+            .then(|| ExtractedMaterialTable::new(device, layouts, &source_materials));
+
         let camera = ExtractedCamera::new(device, &layouts.camera, camera);
+
+        // Without a first instance in the indirect commands the instance
+        // transforms can not be found, so the CPU path is used instead
+        let indirect = (settings.gpu_culling
+            && device
+                .features()
+                .contains(Features::INDIRECT_FIRST_INSTANCE)
+            && !objects.is_empty())
+        .then(|| IndirectDraws::new(device, &layouts.cull, &objects, &materials, &camera.frustum));
+
         let lights = lights
             .iter()
             .map(|l| ExtractedPointLight::new(device, layouts, l))
@@ -64,37 +184,42 @@ impl RenderWorld {
         // -----------------------------------------------------------------------------------
 
         // Create lights buffer
-        let light_buffer_size = lights.len() * std::mem::size_of::<PointLightUniform>();
+        let light_data = storage_bytes(&PointLightsUniform::new(&lights));
 
         let lights_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("Model light buffer"),
-            size: light_buffer_size as u64,
+            size: light_data.len() as u64,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         // Put all lights data into lights buffer
-        let light_size = std::mem::size_of::<PointLightUniform>();
-        for (index, light) in lights.iter().enumerate() {
-            let light_data = unsafe {
-                std::slice::from_raw_parts(
-                    &light.uniform as *const PointLightUniform as *const u8,
-                    light_size,
-                )
-            };
-
-            queue.write_buffer(&lights_buffer, (light_size * index) as u64, light_data);
-        }
-
-        let view_array = lights
-            .iter()
-            .map(|light| &light.shadow_map.view)
-            .collect::<Vec<&TextureView>>();
+        queue.write_buffer(&lights_buffer, 0, &light_data);
+
+        // The shadow maps of the missing lights are bound to a placeholder,
+        // which the shaders never sample
+        let placeholder =
+            CubeMap::new_depth_cubemap(device, 1, 1, Some("Missing light shadow map"));
+        let shadow_maps = (0..Layouts::LIGHTS as usize)
+            .map(|light| {
+                lights
+                    .get(light)
+                    .map_or(&placeholder, |light| &light.shadow_map)
+            })
+            .collect::<Vec<_>>();
 
-        let sampler_array = lights
+        // The shadow maps are sampled the same way, with the sampler of the first
+        let shadow_sampler = BindGroupEntry {
+            binding: 1,
+            resource: BindingResource::Sampler(&shadow_maps[0].sampler),
+        };
+        let shadow_maps = shadow_maps
             .iter()
-            .map(|light| &light.shadow_map.sampler)
-            .collect::<Vec<&Sampler>>();
+            .zip(2..)
+            .map(|(shadow_map, binding)| BindGroupEntry {
+                binding,
+                resource: BindingResource::TextureView(&shadow_map.view),
+            });
 
         let lights_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Model light bind group"),
@@ -104,137 +229,355 @@ impl RenderWorld {
                     binding: 0,
                     resource: lights_buffer.as_entire_binding(),
                 },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureViewArray(&view_array),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::SamplerArray(&sampler_array),
-                },
-            ],
+                shadow_sampler,
+            ]
+            .into_iter()
+            .chain(shadow_maps)
+            .collect::<Vec<_>>(),
         });
         // -----------------------------------------------------------------------------------
 
         RenderWorld {
             objects,
+            transparent_objects,
+            geometry,
+            transforms,
+            indirect,
             camera,
             materials,
+            material_table,
             lights,
             lights_bind_group,
             skybox,
         }
     }
+
+    /// Opaque objects culled and drawn by the GPU, when enabled and supported.
+    pub fn indirect(&self) -> Option<&IndirectDraws> {
+        self.indirect.as_ref()
+    }
+
+    /// Pipeline variants needed to draw the opaque and alpha masked objects.
+    pub fn pipeline_keys(&self) -> impl Iterator<Item = PipelineKey> + '_ {
+        self.objects
+            .iter()
+            .map(|object| self.materials[object.material_index()].pipeline_key)
+    }
+
+    /// Pipeline variants needed to draw the blended objects.
+    pub fn transparent_pipeline_keys(&self) -> impl Iterator<Item = PipelineKey> + '_ {
+        self.transparent_objects
+            .iter()
+            .map(|object| self.materials[object.material_index()].pipeline_key)
+    }
 }
 
 fn extract_entity_materials(
     device: &Device,
     layouts: &Layouts,
     entity: &Entity,
+    settings: &RenderSettings,
 ) -> Vec<ExtractedMaterial> {
     let mut entity_materials = vec![];
-    for material in entity.get_materials() {
-        let extracted_material = ExtractedMaterial::new(device, &layouts.material, material);
+    for material in entity.get_materials().iter() {
+        let extracted_material = ExtractedMaterial::new(device, layouts, material, settings);
         entity_materials.push(extracted_material);
     }
 
     entity_materials
 }
 
-fn extract_entity_render_objects(
-    device: &Device,
-    layouts: &Layouts,
-    entity: &Entity,
-    current_material_index: usize,
-) -> Vec<RenderObject> {
-    let mut render_objects = vec![];
-    for node in entity.get_nodes() {
-        let mut node_render_objects = extract_node(
-            device,
-            layouts,
-            node,
-            current_material_index,
-            entity.transform.model(),
-        );
-        render_objects.append(&mut node_render_objects)
-    }
+/// A primitive of a mesh placed in the world with one of the world materials.
+struct MeshInstance<'a> {
+    mesh: &'a Arc<Mesh>,
+    primitive: usize,
+    material_index: usize,
+    model_matrix: Matrix4<f32>,
+}
 
-    render_objects
+/// Instances that can be drawn together with a single draw call.
+struct InstanceBatch<'a> {
+    mesh: &'a Arc<Mesh>,
+    primitive: usize,
+    material_index: usize,
+    model_matrices: Vec<Matrix4<f32>>,
 }
 
-fn extract_node(
-    device: &Device,
-    layouts: &Layouts,
-    node: &Node,
+fn collect_node_instances<'a>(
+    node: &'a Node,
     current_material_index: usize,
     parent_model_matrix: Matrix4<f32>,
-) -> Vec<RenderObject> {
-    let mut render_objects = vec![];
+    instances: &mut Vec<MeshInstance<'a>>,
+) {
+    let local_transform = parent_model_matrix * node.transform.model();
 
     if let Some(mesh) = &node.mesh {
-        let mut mesh_render_objects = extract_mesh(device, mesh, current_material_index)
-            .into_iter()
-            .map(|(extracted_mesh, material_index)| {
-                let transform = ExtractedTransform::new(
-                    device,
-                    &layouts.transform,
-                    &node.transform,
-                    parent_model_matrix,
-                );
-                RenderObject::new(extracted_mesh, transform, material_index)
-            })
-            .collect::<Vec<_>>();
-
-        render_objects.append(&mut mesh_render_objects);
+        for (primitive, (_, material_index)) in mesh.primitives.iter().enumerate() {
+            instances.push(MeshInstance {
+                mesh,
+                primitive,
+                material_index: material_index + current_material_index,
+                model_matrix: local_transform,
+            });
+        }
     }
 
-    let local_transform = parent_model_matrix * node.transform.model();
-
     for child in &node.children {
-        let mut child_render_objects = extract_node(
-            device,
-            layouts,
-            child,
-            current_material_index,
-            local_transform,
-        );
-        render_objects.append(&mut child_render_objects);
+        collect_node_instances(child, current_material_index, local_transform, instances);
     }
-
-    render_objects
 }
 
-fn extract_mesh(
-    device: &Device,
-    mesh: &Mesh,
-    current_material_index: usize,
-) -> Vec<(ExtractedMesh, usize)> {
-    let mut mesh_with_material_index = vec![];
-
-    for (geometry, material_index) in &mesh.primitives {
-        let mesh = ExtractedMesh::new(device, geometry);
+/// Groups the instances sharing a primitive and a material. The instances of
+/// `blended` materials are kept on their own so they can still be sorted back
+/// to front.
+fn batch_instances<'a>(
+    instances: Vec<MeshInstance<'a>>,
+    blended: impl Fn(usize) -> bool,
+) -> Vec<InstanceBatch<'a>> {
+    let mut batches: Vec<InstanceBatch> = vec![];
+    let mut batch_indices: HashMap<_, usize> = HashMap::new();
+
+    for instance in instances {
+        let key = (
+            Arc::as_ptr(instance.mesh),
+            instance.primitive,
+            instance.material_index,
+        );
 
-        mesh_with_material_index.push((mesh, *material_index + current_material_index));
+        let blended = blended(instance.material_index);
+
+        match batch_indices.get(&key) {
+            Some(&index) if !blended => batches[index].model_matrices.push(instance.model_matrix),
+            _ => {
+                batch_indices.insert(key, batches.len());
+                batches.push(InstanceBatch {
+                    mesh: instance.mesh,
+                    primitive: instance.primitive,
+                    material_index: instance.material_index,
+                    model_matrices: vec![instance.model_matrix],
+                });
+            }
+        }
     }
 
-    mesh_with_material_index
+    batches
 }
 
 pub trait DrawWorld<'a> {
-    fn draw_world(&mut self, world: &'a RenderWorld);
+    /// Draws the opaque and alpha masked objects inside the camera frustum.
+    fn draw_world(
+        &mut self,
+        world: &'a RenderWorld,
+        camera: &ExtractedCamera,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats;
+    /// Draws the opaque and alpha masked objects with the indirect commands
+    /// written by the cull pass.
+    fn draw_world_indirect(
+        &mut self,
+        world: &'a RenderWorld,
+        draws: &'a IndirectDraws,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats;
+    /// Draws the blended objects inside the camera frustum, back to front.
+    fn draw_transparent(
+        &mut self,
+        world: &'a RenderWorld,
+        camera: &ExtractedCamera,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats;
     fn draw_skybox(&mut self, world: &'a RenderWorld);
 }
 
 impl<'a> DrawWorld<'a> for RenderPass<'a> {
-    fn draw_world(&mut self, world: &'a RenderWorld) {
-        for render_object in &world.objects {
-            self.set_bind_group(4, &world.skybox.irr_map_bind_group, &[]);
-            self.draw_render_object(render_object, &world.materials)
+    fn draw_world(
+        &mut self,
+        world: &'a RenderWorld,
+        camera: &ExtractedCamera,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats {
+        draw_objects(self, &world.objects, world, camera, pipelines)
+    }
+
+    fn draw_world_indirect(
+        &mut self,
+        world: &'a RenderWorld,
+        draws: &'a IndirectDraws,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats {
+        let mut render_pass = TrackedRenderPass::new(self);
+
+        render_pass.bind_geometry(&world.geometry);
+        render_pass.set_bind_group(1, &world.transforms);
+        render_pass.set_bind_group(4, &world.skybox.irr_map_bind_group);
+        if let Some(material_table) = &world.material_table {
+            render_pass.set_bind_group(2, material_table);
+        }
+
+        for batch in &draws.batches {
+            render_pass.set_pipeline(batch.pipeline_key, pipelines);
+
+            if let Some(bind_group) = batch
+                .material_index
+                .and_then(|index| world.materials[index].bind_group())
+            {
+                render_pass.set_bind_group(2, bind_group);
+            }
+
+            render_pass.draw_indirect_batch(draws, batch);
+        }
+
+        // Culled objects are only known by the GPU, they are read back later
+        DrawStats {
+            submitted: draws.object_count,
+            ..render_pass.stats
         }
     }
 
+    fn draw_transparent(
+        &mut self,
+        world: &'a RenderWorld,
+        camera: &ExtractedCamera,
+        pipelines: &'a PipelineCache,
+    ) -> DrawStats {
+        draw_objects(self, &world.transparent_objects, world, camera, pipelines)
+    }
+
     fn draw_skybox(&mut self, world: &'a RenderWorld) {
         self.set_bind_group(1, &world.skybox.env_map_bind_group, &[]);
         self.draw(0..3, 0..1)
     }
 }
+
+fn draw_objects<'a>(
+    render_pass: &mut RenderPass<'a>,
+    objects: &'a [RenderObject],
+    world: &'a RenderWorld,
+    camera: &ExtractedCamera,
+    pipelines: &'a PipelineCache,
+) -> DrawStats {
+    let mut render_pass = TrackedRenderPass::new(render_pass);
+
+    render_pass.bind_geometry(&world.geometry);
+    render_pass.set_bind_group(1, &world.transforms);
+    render_pass.set_bind_group(4, &world.skybox.irr_map_bind_group);
+    if let Some(material_table) = &world.material_table {
+        render_pass.set_bind_group(2, material_table);
+    }
+
+    for render_object in objects {
+        let instances = render_object.visible_instances(&camera.frustum);
+        let visible = instances
+            .iter()
+            .map(|range| range.len() as u32)
+            .sum::<u32>();
+
+        render_pass.stats.culled += render_object.instance_count() - visible;
+        if instances.is_empty() {
+            continue;
+        }
+
+        let material = &world.materials[render_object.material_index()];
+        render_pass.set_pipeline(material.pipeline_key, pipelines);
+
+        // Without one the material is read from the bindless table
+        if let Some(bind_group) = material.bind_group() {
+            render_pass.set_bind_group(2, bind_group);
+        }
+
+        render_pass.draw_mesh(render_object.mesh(), &instances);
+        render_pass.stats.submitted += visible;
+    }
+
+    render_pass.stats
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use crate::entity::Geometry;
+
+    use super::*;
+
+    fn mesh() -> Arc<Mesh> {
+        Arc::new(Mesh {
+            primitives: vec![(Geometry::cube(), 0), (Geometry::plane(), 0)],
+        })
+    }
+
+    fn instance(
+        mesh: &Arc<Mesh>,
+        primitive: usize,
+        material_index: usize,
+        x: f32,
+    ) -> MeshInstance<'_> {
+        MeshInstance {
+            mesh,
+            primitive,
+            material_index,
+            model_matrix: Matrix4::from_translation(Vector3::new(x, 0.0, 0.0)),
+        }
+    }
+
+    #[test]
+    fn merges_instances_sharing_a_mesh_and_a_material() {
+        let (shared, other) = (mesh(), mesh());
+
+        let batches = batch_instances(
+            vec![
+                instance(&shared, 0, 0, 1.0),
+                instance(&shared, 0, 1, 2.0),
+                instance(&shared, 1, 0, 3.0),
+                instance(&other, 0, 0, 4.0),
+                instance(&shared, 0, 0, 5.0),
+            ],
+            |_| false,
+        );
+
+        // Only the first and the last instance share everything
+        let summary = batches
+            .iter()
+            .map(|batch| {
+                let offsets = batch.model_matrices.iter().map(|matrix| matrix.w.x);
+                (
+                    Arc::ptr_eq(batch.mesh, &shared),
+                    batch.primitive,
+                    batch.material_index,
+                    offsets.collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                (true, 0, 0, vec![1.0, 5.0]),
+                (true, 0, 1, vec![2.0]),
+                (true, 1, 0, vec![3.0]),
+                (false, 0, 0, vec![4.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_blended_instances_apart() {
+        let mesh = mesh();
+
+        let batches = batch_instances(
+            vec![
+                instance(&mesh, 0, 1, 1.0),
+                instance(&mesh, 0, 1, 2.0),
+                instance(&mesh, 0, 0, 3.0),
+                instance(&mesh, 0, 0, 4.0),
+            ],
+            |material_index| material_index == 1,
+        );
+
+        let sizes = batches
+            .iter()
+            .map(|batch| (batch.material_index, batch.model_matrices.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(1, 1), (1, 1), (0, 2)]);
+    }
+}
@@ -1,36 +1,107 @@
-use wgpu::RenderPass;
+use std::ops::Range;
 
-use super::extracted::{DrawMesh, ExtractedMaterial, ExtractedMesh, ExtractedTransform};
+use cgmath::{Matrix4, Point3, Vector4};
 
+use crate::{
+    camera::Frustum,
+    entity::{Aabb, BoundingSphere},
+};
+
+use super::extracted::ExtractedMesh;
+
+/// A mesh primitive drawn with the same material by one or more instances.
+/// The transforms of the instances live in the world transforms buffer,
+/// starting at `first_instance`.
 pub struct RenderObject {
     mesh: ExtractedMesh,
-    transform: ExtractedTransform,
     material_index: usize,
+    first_instance: u32,
+    instances: Vec<Instance>,
+}
+
+struct Instance {
+    world_position: Point3<f32>,
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
 }
 
 impl RenderObject {
     pub fn new(
         mesh: ExtractedMesh,
-        transform: ExtractedTransform,
         material_index: usize,
+        first_instance: u32,
+        model_matrices: &[Matrix4<f32>],
     ) -> RenderObject {
+        // World space bounds
+        let instances = model_matrices
+            .iter()
+            .map(|model_matrix| {
+                let origin = model_matrix * Vector4::unit_w();
+
+                Instance {
+                    world_position: Point3::new(origin.x, origin.y, origin.z),
+                    aabb: mesh.aabb.transform(model_matrix),
+                    bounding_sphere: mesh.bounding_sphere.transform(model_matrix),
+                }
+            })
+            .collect();
+
         RenderObject {
             mesh,
-            transform,
             material_index,
+            first_instance,
+            instances,
         }
     }
-}
 
-pub trait DrawRenderObject<'a> {
-    fn draw_render_object(&mut self, object: &'a RenderObject, materials: &'a [ExtractedMaterial]);
-}
+    pub fn material_index(&self) -> usize {
+        self.material_index
+    }
+
+    pub fn mesh(&self) -> &ExtractedMesh {
+        &self.mesh
+    }
+
+    /// Instance index and world space bounds of every instance.
+    pub fn instance_bounds(&self) -> impl Iterator<Item = (u32, &Aabb, &BoundingSphere)> {
+        self.instances.iter().enumerate().map(|(index, instance)| {
+            (
+                self.first_instance + index as u32,
+                &instance.aabb,
+                &instance.bounding_sphere,
+            )
+        })
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    /// World space origin of the node the first instance was extracted from.
+    pub fn world_position(&self) -> Point3<f32> {
+        self.instances[0].world_position
+    }
+
+    /// Ranges of consecutive instances inside the frustum. Tests the cheaper
+    /// bounding sphere first and the tighter box after.
+    pub fn visible_instances(&self, frustum: &Frustum) -> Vec<Range<u32>> {
+        let mut ranges: Vec<Range<u32>> = vec![];
+
+        for (index, instance) in self.instances.iter().enumerate() {
+            let visible = frustum.intersects_sphere(&instance.bounding_sphere)
+                && frustum.intersects_aabb(&instance.aabb);
+
+            if !visible {
+                continue;
+            }
+
+            let index = self.first_instance + index as u32;
+            match ranges.last_mut() {
+                Some(range) if range.end == index => range.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
 
-impl<'a> DrawRenderObject<'a> for RenderPass<'a> {
-    fn draw_render_object(&mut self, object: &'a RenderObject, materials: &'a [ExtractedMaterial]) {
-        let material = &materials[object.material_index];
-        self.set_bind_group(1, &object.transform, &[]);
-        self.set_bind_group(2, material, &[]);
-        self.draw_mesh(&object.mesh);
+        ranges
     }
 }
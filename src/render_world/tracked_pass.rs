@@ -0,0 +1,113 @@
+use std::ops::Range;
+
+use wgpu::{BindGroup, Buffer, Id, IndexFormat, RenderPass};
+
+use crate::{
+    render_pass::{PipelineCache, PipelineKey},
+    stats::DrawStats,
+};
+
+use super::{
+    extracted::{ExtractedGeometry, ExtractedMesh},
+    indirect::{IndirectBatch, IndirectDraws},
+};
+
+const MAX_BIND_GROUPS: usize = 8;
+
+/// Wraps a render pass and remembers the state bound on it, so the calls
+/// setting what is already bound are skipped. Counts the state changes and the
+/// skipped calls in its stats.
+pub struct TrackedRenderPass<'a, 'p> {
+    render_pass: &'p mut RenderPass<'a>,
+    pipeline: Option<PipelineKey>,
+    bind_groups: [Option<Id<BindGroup>>; MAX_BIND_GROUPS],
+    vertex_buffer: Option<Id<Buffer>>,
+    index_buffer: Option<Id<Buffer>>,
+    pub stats: DrawStats,
+}
+
+impl<'a, 'p> TrackedRenderPass<'a, 'p> {
+    pub fn new(render_pass: &'p mut RenderPass<'a>) -> TrackedRenderPass<'a, 'p> {
+        TrackedRenderPass {
+            render_pass,
+            pipeline: None,
+            bind_groups: [None; MAX_BIND_GROUPS],
+            vertex_buffer: None,
+            index_buffer: None,
+            stats: DrawStats::default(),
+        }
+    }
+
+    pub fn set_pipeline(&mut self, key: PipelineKey, pipelines: &'a PipelineCache) {
+        if self.pipeline == Some(key) {
+            self.stats.skipped_changes += 1;
+            return;
+        }
+
+        self.render_pass.set_pipeline(pipelines.get(&key));
+        self.pipeline = Some(key);
+        self.stats.pipeline_changes += 1;
+    }
+
+    pub fn set_bind_group(&mut self, index: u32, bind_group: &'a BindGroup) {
+        let bound = &mut self.bind_groups[index as usize];
+        if *bound == Some(bind_group.global_id()) {
+            self.stats.skipped_changes += 1;
+            return;
+        }
+
+        self.render_pass.set_bind_group(index, bind_group, &[]);
+        *bound = Some(bind_group.global_id());
+        self.stats.bind_group_changes += 1;
+    }
+
+    pub fn bind_geometry(&mut self, geometry: &'a ExtractedGeometry) {
+        if self.vertex_buffer == Some(geometry.vertex_buffer.global_id()) {
+            self.stats.skipped_changes += 1;
+        } else {
+            self.render_pass
+                .set_vertex_buffer(0, geometry.vertex_buffer.slice(..));
+            self.vertex_buffer = Some(geometry.vertex_buffer.global_id());
+            self.stats.buffer_changes += 1;
+        }
+
+        if self.index_buffer == Some(geometry.index_buffer.global_id()) {
+            self.stats.skipped_changes += 1;
+        } else {
+            self.render_pass
+                .set_index_buffer(geometry.index_buffer.slice(..), IndexFormat::Uint16);
+            self.index_buffer = Some(geometry.index_buffer.global_id());
+            self.stats.buffer_changes += 1;
+        }
+    }
+
+    /// Draws every range of instances of a mesh in the bound geometry.
+    pub fn draw_mesh(&mut self, mesh: &ExtractedMesh, instances: &[Range<u32>]) {
+        for instances in instances {
+            self.render_pass
+                .draw_indexed(mesh.indices(), mesh.base_vertex, instances.clone());
+        }
+        self.stats.draw_calls += instances.len() as u32;
+    }
+
+    /// Draws the commands of a batch, with a single call when multi draw is
+    /// supported.
+    pub fn draw_indirect_batch(&mut self, draws: &'a IndirectDraws, batch: &IndirectBatch) {
+        let offset = batch.first_command as u64 * IndirectDraws::COMMAND_SIZE;
+        if draws.multi_draw {
+            self.render_pass.multi_draw_indexed_indirect(
+                &draws.commands,
+                offset,
+                batch.command_count,
+            );
+            self.stats.draw_calls += 1;
+        } else {
+            for command in 0..batch.command_count as u64 {
+                let offset = offset + command * IndirectDraws::COMMAND_SIZE;
+                self.render_pass
+                    .draw_indexed_indirect(&draws.commands, offset);
+            }
+            self.stats.draw_calls += batch.command_count;
+        }
+    }
+}
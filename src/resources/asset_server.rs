@@ -0,0 +1,273 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use wgpu::{Device, Queue};
+
+use crate::{
+    entity::Entity, file_watcher::FileWatcher, shader_library::ShaderLibrary, skybox::Skybox,
+};
+
+use super::{GltfData, HdrImage, Resources, SkyboxLoader, TextureLoader};
+
+/// Identifies a requested asset until it is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AssetId(u64);
+
+pub enum Asset {
+    Model(Entity),
+    Skybox(Box<Skybox>),
+}
+
+pub enum LoadEvent {
+    /// Sent again with the same id when the asset is reloaded.
+    Loaded(AssetId, Asset),
+    /// A texture file changed and was rewritten in place.
+    TextureReloaded(PathBuf),
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+/// Counts of the requested assets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub requested: usize,
+}
+
+impl LoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.loaded + self.failed == self.requested
+    }
+}
+
+impl Display for LoadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{} assets loaded", self.loaded, self.requested)?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Job {
+    Gltf,
+    Skybox { size: u32 },
+}
+
+/// The part of a job done on a worker thread.
+enum Read {
+    Gltf(Box<GltfData>),
+    Skybox { image: HdrImage, size: u32 },
+}
+
+struct Request {
+    id: AssetId,
+    path: PathBuf,
+    job: Job,
+}
+
+type Response = (AssetId, PathBuf, Result<Read, String>);
+
+/// Loads assets in the background. Files are read and decoded on worker
+/// threads, and the GPU resources are created in [AssetServer::update], on the
+/// thread owning the queue. The loaded files are watched, and the assets
+/// reloaded when they change.
+pub struct AssetServer {
+    requests: Sender<Request>,
+    responses: Receiver<Response>,
+    skybox_loader: SkyboxLoader,
+    next_id: u64,
+    /// Whether each asset loaded the last time it finished. Reloads keep the
+    /// previous outcome until they finish, so they are not counted again.
+    finished: HashMap<AssetId, bool>,
+    /// What each asset was requested with, to load it again.
+    assets: HashMap<AssetId, (PathBuf, Job)>,
+    /// The assets read from each watched file.
+    dependents: HashMap<PathBuf, HashSet<AssetId>>,
+    watcher: Arc<FileWatcher>,
+}
+
+impl AssetServer {
+    pub fn new(device: &Device, shaders: &ShaderLibrary, watcher: Arc<FileWatcher>) -> AssetServer {
+        let (requests, request_receiver) = mpsc::channel::<Request>();
+        let (response_sender, responses) = mpsc::channel();
+
+        // The workers share the requests, each one takes the next when it is free
+        let request_receiver = Arc::new(Mutex::new(request_receiver));
+        let worker_count = thread::available_parallelism().map_or(2, |count| count.get().min(4));
+
+        for _ in 0..worker_count {
+            let requests = request_receiver.clone();
+            let responses = response_sender.clone();
+
+            thread::spawn(move || loop {
+                let Ok(Request { id, path, job }) = requests.lock().unwrap().recv() else {
+                    return;
+                };
+
+                let read = match job {
+                    Job::Gltf => Resources::read_gltf(&path).map(|data| Read::Gltf(Box::new(data))),
+                    Job::Skybox { size } => {
+                        Resources::read_hdr(&path).map(|image| Read::Skybox { image, size })
+                    }
+                };
+
+                if responses.send((id, path, read)).is_err() {
+                    return;
+                }
+            });
+        }
+
+        AssetServer {
+            requests,
+            responses,
+            skybox_loader: SkyboxLoader::new(device, shaders),
+            next_id: 0,
+            finished: HashMap::new(),
+            assets: HashMap::new(),
+            dependents: HashMap::new(),
+            watcher,
+        }
+    }
+
+    pub fn load_gltf(&mut self, path: &Path) -> AssetId {
+        let id = self.next_id();
+        self.request(id, path, Job::Gltf)
+    }
+
+    /// The environment map is projected on cube faces of `size`.
+    pub fn load_skybox(&mut self, path: &Path, size: u32) -> AssetId {
+        let id = self.next_id();
+        self.request(id, path, Job::Skybox { size })
+    }
+
+    /// Shown until a skybox is loaded.
+    pub fn placeholder_skybox(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+    ) -> Skybox {
+        self.skybox_loader
+            .placeholder(device, queue, &loader.mip_generator)
+    }
+
+    /// Loads the skyboxes again with the `skybox_loader`, made from changed
+    /// shaders.
+    pub fn reload_skyboxes(&mut self, skybox_loader: SkyboxLoader) {
+        self.skybox_loader = skybox_loader;
+
+        let skyboxes = self
+            .assets
+            .iter()
+            .filter(|(_, (_, job))| matches!(job, Job::Skybox { .. }))
+            .map(|(id, (path, job))| (*id, path.clone(), *job))
+            .collect::<Vec<_>>();
+        for (id, path, job) in skyboxes {
+            self.request(id, &path, job);
+        }
+    }
+
+    /// Polled rather than sent with the events, so it is known before any
+    /// asset finished.
+    pub fn progress(&self) -> LoadProgress {
+        let loaded = self.finished.values().filter(|loaded| **loaded).count();
+
+        LoadProgress {
+            loaded,
+            failed: self.finished.len() - loaded,
+            requested: self.assets.len(),
+        }
+    }
+
+    /// Creates the GPU resources of the assets read since the last update, and
+    /// reloads the ones whose files changed.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+    ) -> Vec<LoadEvent> {
+        let mut events = Vec::new();
+
+        for path in self.watcher.changed() {
+            if loader.reload(device, queue, &path) {
+                events.push(LoadEvent::TextureReloaded(path.clone()));
+            }
+
+            // The glTF file or one of its buffers changed, so the images
+            // embedded in it may have too
+            for id in self.dependents.get(&path).cloned().unwrap_or_default() {
+                let (path, job) = self.assets[&id].clone();
+                if let Job::Gltf = job {
+                    loader.evict_buffer_views(&path);
+                }
+                self.request(id, &path, job);
+            }
+        }
+
+        for (id, path, read) in self.responses.try_iter() {
+            if let Ok(read) = &read {
+                let files = match read {
+                    Read::Gltf(data) => data.files.clone(),
+                    Read::Skybox { .. } => vec![path.clone()],
+                };
+                for file in files {
+                    let file = self.watcher.watch(&file);
+                    self.dependents.entry(file).or_default().insert(id);
+                }
+            }
+
+            let asset = read.and_then(|read| match read {
+                Read::Gltf(data) => {
+                    Resources::upload_gltf(device, queue, loader, *data).map(Asset::Model)
+                }
+                Read::Skybox { image, size } => Ok(Asset::Skybox(Box::new(
+                    self.skybox_loader
+                        .create(device, queue, &loader.mip_generator, &image, size),
+                ))),
+            });
+
+            self.finished.insert(id, asset.is_ok());
+            match asset {
+                Ok(asset) => events.push(LoadEvent::Loaded(id, asset)),
+                Err(error) => events.push(LoadEvent::Failed { path, error }),
+            }
+        }
+
+        events
+    }
+
+    fn next_id(&mut self) -> AssetId {
+        self.next_id += 1;
+        AssetId(self.next_id - 1)
+    }
+
+    fn request(&mut self, id: AssetId, path: &Path, job: Job) -> AssetId {
+        self.assets.insert(id, (path.to_path_buf(), job));
+
+        self.requests
+            .send(Request {
+                id,
+                path: path.to_path_buf(),
+                job,
+            })
+            .unwrap();
+
+        id
+    }
+}
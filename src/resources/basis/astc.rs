@@ -0,0 +1,523 @@
+use super::{principal_endpoints, Pixels};
+
+/// The bits, trits and quints of each range values are quantized to, from 2
+/// to 256 levels. The weight ranges are the first 12.
+pub const RANGES: [(u32, u32, u32); 21] = [
+    (1, 0, 0),
+    (0, 1, 0),
+    (2, 0, 0),
+    (0, 0, 1),
+    (1, 1, 0),
+    (3, 0, 0),
+    (1, 0, 1),
+    (2, 1, 0),
+    (4, 0, 0),
+    (2, 0, 1),
+    (3, 1, 0),
+    (5, 0, 0),
+    (3, 0, 1),
+    (4, 1, 0),
+    (6, 0, 0),
+    (4, 0, 1),
+    (5, 1, 0),
+    (7, 0, 0),
+    (5, 0, 1),
+    (6, 1, 0),
+    (8, 0, 0),
+];
+
+/// The encoding of each group of 5 trits, and of 3 quints.
+const TRIT_PACKING: [u8; 243] = trit_packing();
+const QUINT_PACKING: [u8; 125] = quint_packing();
+
+/// A 4x4 block with a 4x4 grid of weights, before its bits are packed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AstcBlock {
+    /// The color endpoint mode shared by the partitions: 4 for luminance
+    /// and alpha, 8 for RGB and 12 for RGBA, all direct.
+    pub cem: u32,
+    pub partitions: u32,
+    pub partition_seed: u32,
+    /// The component weighted by the second plane, if there is one.
+    pub ccs: Option<u32>,
+    pub endpoint_range: usize,
+    pub weight_range: usize,
+    /// The quantized values of each partition in turn.
+    pub endpoints: [u8; 24],
+    /// The quantized weights of each texel, with the ones of the second
+    /// plane interleaved.
+    pub weights: [u8; 32],
+}
+
+impl AstcBlock {
+    pub fn endpoint_count(&self) -> usize {
+        ((self.cem / 4 + 1) * 2 * self.partitions) as usize
+    }
+
+    pub fn planes(&self) -> usize {
+        1 + self.ccs.is_some() as usize
+    }
+
+    /// The colors of the texels, from the 16 bit interpolation of the LDR
+    /// profile. The sRGB endpoints are expanded as the hardware does.
+    pub fn decode(&self, srgb: bool) -> Pixels {
+        let values = self
+            .endpoints
+            .map(|value| unquantize(self.endpoint_range, value) as u32);
+        let count = self.endpoint_count() / self.partitions as usize;
+        let expand = |value: u32| match srgb {
+            true => (value << 8) | 0x80,
+            false => value * 257,
+        };
+
+        let mut pixels = [[0; 4]; 16];
+        for (texel, pixel) in pixels.iter_mut().enumerate() {
+            let partition = partition(self.partition_seed, self.partitions, texel);
+            let (low, high) = endpoint_pair(self.cem, &values[partition * count..]);
+            for component in 0..4 {
+                let plane = (self.ccs == Some(component as u32)) as usize;
+                let weight = self.weights[texel * self.planes() + plane];
+                let weight = unquantize_weight(self.weight_range, weight);
+                let color = (expand(low[component]) * (64 - weight)
+                    + expand(high[component]) * weight
+                    + 32)
+                    >> 6;
+                pixel[component] = (color >> 8) as u8;
+            }
+        }
+
+        pixels
+    }
+
+    pub fn pack(&self) -> [u8; 16] {
+        let mut writer = BlockWriter::default();
+        let (weight_bits, trits, quints) = RANGES[self.weight_range];
+        let high_precision = self.weight_range >= 6;
+        let range = self.weight_range as u32 % 6 + 2;
+        // A 4x4 grid, with the range bits spread around the layout bits
+        let mode = ((range >> 1) & 1)
+            | ((range >> 2) & 1) << 1
+            | (range & 1) << 4
+            | 2 << 5
+            | (high_precision as u32) << 9
+            | (self.ccs.is_some() as u32) << 10;
+        writer.bits(mode, 11);
+        writer.bits(self.partitions - 1, 2);
+        if self.partitions > 1 {
+            writer.bits(self.partition_seed, 10);
+            writer.bits(0, 2);
+        }
+        writer.bits(self.cem, 4);
+        writer.bise(
+            self.endpoint_range,
+            &self.endpoints[..self.endpoint_count()],
+        );
+
+        // The weights go from the last bit down, the plane component right
+        // below them
+        let weight_count = 16 * self.planes();
+        let mut weights = BlockWriter::default();
+        weights.bise(self.weight_range, &self.weights[..weight_count]);
+        if let Some(ccs) = self.ccs {
+            writer.offset = 128 - weights.offset - 2;
+            writer.bits(ccs, 2);
+        }
+        debug_assert_eq!(
+            weights.offset,
+            bise_bits(weight_bits, trits, quints, weight_count as u32)
+        );
+
+        (writer.block | weights.block.reverse_bits()).to_le_bytes()
+    }
+
+    /// The range the endpoints are quantized to, the largest one that fits
+    /// next to the weights.
+    pub fn implied_endpoint_range(&self) -> usize {
+        let (bits, trits, quints) = RANGES[self.weight_range];
+        let weight_bits = bise_bits(bits, trits, quints, 16 * self.planes() as u32);
+        let header_bits = if self.partitions > 1 { 29 } else { 17 };
+        let available = 128 - header_bits - weight_bits - 2 * self.ccs.is_some() as u32;
+
+        (0..RANGES.len())
+            .rev()
+            .find(|&range| {
+                let (bits, trits, quints) = RANGES[range];
+                bise_bits(bits, trits, quints, self.endpoint_count() as u32) <= available
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// A block of a single color.
+pub fn void_extent(color: [u8; 4]) -> [u8; 16] {
+    let mut block = 0xFFFF_FFFF_FFFF_FDFC_u128;
+    for (component, &value) in color.iter().enumerate() {
+        block |= (value as u128 * 257) << (64 + 16 * component);
+    }
+
+    block.to_le_bytes()
+}
+
+/// Fits the texels to a line of RGB, or RGBA when some are translucent.
+pub fn encode(pixels: &Pixels) -> [u8; 16] {
+    if pixels.iter().all(|pixel| *pixel == pixels[0]) {
+        return void_extent(pixels[0]);
+    }
+
+    let opaque = pixels.iter().all(|pixel| pixel[3] == 255);
+    let mut block = AstcBlock {
+        cem: if opaque { 8 } else { 12 },
+        partitions: 1,
+        partition_seed: 0,
+        ccs: None,
+        endpoint_range: 0,
+        weight_range: if opaque { 5 } else { 2 },
+        endpoints: [0; 24],
+        weights: [0; 32],
+    };
+    block.endpoint_range = block.implied_endpoint_range();
+
+    let (low, high) = principal_endpoints(pixels);
+    let quantize = |value: f32| quantize(block.endpoint_range, value.round() as u8);
+    let components = if opaque { 3 } else { 4 };
+    for component in 0..components {
+        block.endpoints[2 * component] = quantize(low[component]);
+        block.endpoints[2 * component + 1] = quantize(high[component]);
+    }
+
+    // Without blue contraction the second endpoint is the brightest
+    let values = block
+        .endpoints
+        .map(|value| unquantize(block.endpoint_range, value) as u32);
+    if values[1] + values[3] + values[5] < values[0] + values[2] + values[4] {
+        for component in 0..components {
+            block.endpoints.swap(2 * component, 2 * component + 1);
+        }
+    }
+
+    let levels = levels(block.weight_range);
+    let palette: Vec<_> = (0..levels)
+        .map(|weight| {
+            block.weights = [weight as u8; 32];
+            block.decode(false)[0]
+        })
+        .collect();
+    for (texel, pixel) in pixels.iter().enumerate() {
+        block.weights[texel] = super::nearest(&palette, pixel) as u8;
+    }
+
+    block.pack()
+}
+
+/// The partition of a texel, from the hash of the seed.
+pub fn partition(seed: u32, partitions: u32, texel: usize) -> usize {
+    if partitions == 1 {
+        return 0;
+    }
+
+    // Blocks of less than 31 texels double their coordinates
+    let (x, y) = ((texel as u32 % 4) << 1, (texel as u32 / 4) << 1);
+    let seed = seed + (partitions - 1) * 1024;
+    let random = hash52(seed);
+
+    let mut seeds = [0, 4, 8, 12, 16, 20, 24, 28, 18, 22, 26, 30].map(|shift| {
+        let seed = random.rotate_right(shift) & 0xF;
+        seed * seed
+    });
+
+    let (sh1, sh2) = match seed & 1 {
+        1 => (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partitions == 3 { 6 } else { 5 },
+        ),
+        _ => (
+            if partitions == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        ),
+    };
+    let sh3 = if seed & 0x10 != 0 { sh1 } else { sh2 };
+    for (index, seed) in seeds.iter_mut().enumerate() {
+        *seed >>= match index {
+            8.. => sh3,
+            _ if index % 2 == 0 => sh1,
+            _ => sh2,
+        };
+    }
+
+    let a = (seeds[0] * x + seeds[1] * y + (random >> 14)) & 0x3F;
+    let b = (seeds[2] * x + seeds[3] * y + (random >> 10)) & 0x3F;
+    let c = match partitions {
+        3.. => (seeds[4] * x + seeds[5] * y + (random >> 6)) & 0x3F,
+        _ => 0,
+    };
+    let d = match partitions {
+        4 => (seeds[6] * x + seeds[7] * y + (random >> 2)) & 0x3F,
+        _ => 0,
+    };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+fn hash52(mut value: u32) -> u32 {
+    value ^= value >> 15;
+    value = value.wrapping_sub(value << 17);
+    value = value.wrapping_add(value << 7);
+    value = value.wrapping_add(value << 4);
+    value ^= value >> 5;
+    value = value.wrapping_add(value << 16);
+    value ^= value >> 7;
+    value ^= value >> 3;
+    value ^= value << 6;
+    value ^= value >> 17;
+    value
+}
+
+/// The low and high colors of the direct endpoint modes.
+fn endpoint_pair(cem: u32, values: &[u32]) -> ([u32; 4], [u32; 4]) {
+    let v = values;
+    if cem == 4 {
+        return ([v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]);
+    }
+
+    let (alpha_low, alpha_high) = match cem {
+        12 => (v[6], v[7]),
+        _ => (255, 255),
+    };
+    let blue_contract = |r: u32, g: u32, b: u32, a| [(r + b) >> 1, (g + b) >> 1, b, a];
+    if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+        (
+            [v[0], v[2], v[4], alpha_low],
+            [v[1], v[3], v[5], alpha_high],
+        )
+    } else {
+        (
+            blue_contract(v[1], v[3], v[5], alpha_high),
+            blue_contract(v[0], v[2], v[4], alpha_low),
+        )
+    }
+}
+
+pub fn levels(range: usize) -> u32 {
+    let (bits, trits, quints) = RANGES[range];
+    (1 << bits) * if trits == 1 { 3 } else { 1 } * if quints == 1 { 5 } else { 1 }
+}
+
+/// An endpoint value from 0 to 255.
+pub fn unquantize(range: usize, value: u8) -> u8 {
+    let (bits, trits, quints) = RANGES[range];
+    let value = value as u32;
+    if trits == 0 && quints == 0 {
+        return replicate(value, bits, 8) as u8;
+    }
+
+    let (digit, low) = (value >> bits, value & ((1 << bits) - 1));
+    let higher = low >> 1;
+    let (b, c) = match (trits, bits) {
+        (_, 0) => return (value * 255 / (levels(range) - 1)) as u8,
+        (1, 1) => (0, 204),
+        (1, 2) => (
+            (higher << 8) | (higher << 4) | (higher << 2) | (higher << 1),
+            93,
+        ),
+        (1, 3) => ((higher << 7) | (higher << 2) | higher, 44),
+        (1, 4) => ((higher << 6) | higher, 22),
+        (1, 5) => ((higher << 5) | (higher >> 2), 11),
+        (1, _) => ((higher << 4) | (higher >> 4), 5),
+        (_, 1) => (0, 113),
+        (_, 2) => ((higher << 8) | (higher << 3) | (higher << 2), 54),
+        (_, 3) => ((higher << 7) | (higher << 1) | (higher >> 1), 26),
+        (_, 4) => ((higher << 6) | (higher >> 1), 13),
+        (_, _) => ((higher << 5) | (higher >> 3), 6),
+    };
+    let a = if low & 1 == 1 { 0x1FF } else { 0 };
+    let t = (digit * c + b) ^ a;
+
+    ((a & 0x80) | (t >> 2)) as u8
+}
+
+/// The value of a quantized endpoint nearest to `value`.
+pub fn quantize(range: usize, value: u8) -> u8 {
+    (0..levels(range))
+        .map(|level| level as u8)
+        .min_by_key(|&level| unquantize(range, level).abs_diff(value))
+        .unwrap()
+}
+
+/// A weight from 0 to 64.
+fn unquantize_weight(range: usize, value: u8) -> u32 {
+    let (bits, trits, quints) = RANGES[range];
+    let value = value as u32;
+    let weight = if trits == 0 && quints == 0 {
+        replicate(value, bits, 6)
+    } else {
+        let (digit, low) = (value >> bits, value & ((1 << bits) - 1));
+        let higher = low >> 1;
+        let (b, c) = match (trits, bits) {
+            (1, 0) => return [0, 32, 64][digit as usize],
+            (_, 0) => return [0, 16, 32, 48, 64][digit as usize],
+            (1, 1) => (0, 50),
+            (1, 2) => ((higher << 6) | (higher << 2) | higher, 23),
+            (1, _) => ((higher << 5) | higher, 11),
+            (_, 1) => (0, 28),
+            (_, _) => ((higher << 6) | (higher << 1), 13),
+        };
+        let a = if low & 1 == 1 { 0x7F } else { 0 };
+        let t = (digit * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+
+    if weight > 32 {
+        weight + 1
+    } else {
+        weight
+    }
+}
+
+/// Repeats the bits of a value to fill `target` bits.
+fn replicate(value: u32, bits: u32, target: u32) -> u32 {
+    let mut result = 0;
+    let mut shift = target as i32 - bits as i32;
+    while shift > -(bits as i32) {
+        result |= match shift {
+            0.. => value << shift,
+            _ => value >> -shift,
+        };
+        shift -= bits as i32;
+    }
+
+    result & ((1 << target) - 1)
+}
+
+fn bise_bits(bits: u32, trits: u32, quints: u32, count: u32) -> u32 {
+    bits * count + trits * (8 * count).div_ceil(5) + quints * (7 * count).div_ceil(3)
+}
+
+const fn trits(packed: u32) -> [u32; 5] {
+    let (c, t4, t3) = if (packed >> 2) & 7 == 7 {
+        (((packed >> 5) & 7) << 2 | (packed & 3), 2, 2)
+    } else if (packed >> 5) & 3 == 3 {
+        (packed & 0x1F, 2, bit(packed, 7))
+    } else {
+        (packed & 0x1F, bit(packed, 7), (packed >> 5) & 3)
+    };
+
+    let (t2, t1, t0) = if c & 3 == 3 {
+        (2, bit(c, 4), bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3) & 1))
+    } else if (c >> 2) & 3 == 3 {
+        (2, 2, c & 3)
+    } else {
+        (
+            bit(c, 4),
+            (c >> 2) & 3,
+            bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1) & 1),
+        )
+    };
+
+    [t0, t1, t2, t3, t4]
+}
+
+const fn quints(packed: u32) -> [u32; 3] {
+    if (packed >> 1) & 3 == 3 && (packed >> 5) & 3 == 0 {
+        let q2 = bit(packed, 0) << 2
+            | (bit(packed, 4) & !bit(packed, 0) & 1) << 1
+            | (bit(packed, 3) & !bit(packed, 0) & 1);
+        return [4, 4, q2];
+    }
+
+    let (q2, c) = if (packed >> 1) & 3 == 3 {
+        (
+            4,
+            ((packed >> 3) & 3) << 3 | (!(packed >> 5) & 3) << 1 | (packed & 1),
+        )
+    } else {
+        ((packed >> 5) & 3, packed & 0x1F)
+    };
+    let (q1, q0) = if c & 7 == 5 {
+        (4, (c >> 3) & 3)
+    } else {
+        ((c >> 3) & 3, c & 7)
+    };
+
+    [q0, q1, q2]
+}
+
+const fn bit(value: u32, index: u32) -> u32 {
+    (value >> index) & 1
+}
+
+/// The first of the encodings of each group of trits, some have several.
+const fn trit_packing() -> [u8; 243] {
+    let mut packing = [0; 243];
+    let mut packed = 256;
+    while packed > 0 {
+        packed -= 1;
+        let t = trits(packed);
+        packing[(t[0] + 3 * t[1] + 9 * t[2] + 27 * t[3] + 81 * t[4]) as usize] = packed as u8;
+    }
+
+    packing
+}
+
+const fn quint_packing() -> [u8; 125] {
+    let mut packing = [0; 125];
+    let mut packed = 128;
+    while packed > 0 {
+        packed -= 1;
+        let q = quints(packed);
+        packing[(q[0] + 5 * q[1] + 25 * q[2]) as usize] = packed as u8;
+    }
+
+    packing
+}
+
+#[derive(Default)]
+struct BlockWriter {
+    block: u128,
+    offset: u32,
+}
+
+impl BlockWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        let mask = (1u128 << count) - 1;
+        self.block |= (value as u128 & mask) << self.offset;
+        self.offset += count;
+    }
+
+    /// Writes the values with their trits or quints packed in groups and
+    /// spread between the bits of each value.
+    fn bise(&mut self, range: usize, values: &[u8]) {
+        let (bits, trits, quints) = RANGES[range];
+        let (group, radix, splits): (usize, u32, &[u32]) = match (trits, quints) {
+            (1, _) => (5, 3, &[2, 2, 1, 2, 1]),
+            (_, 1) => (3, 5, &[3, 2, 2]),
+            _ => (1, 1, &[0]),
+        };
+
+        for values in values.chunks(group) {
+            let digits = values
+                .iter()
+                .rev()
+                .fold(0, |digits, &value| digits * radix + (value as u32 >> bits));
+            let packed = match (trits, quints) {
+                (1, _) => TRIT_PACKING[digits as usize] as u32,
+                (_, 1) => QUINT_PACKING[digits as usize] as u32,
+                _ => 0,
+            };
+
+            let mut shift = 0;
+            for (&value, &split) in values.iter().zip(splits) {
+                self.bits(value as u32, bits);
+                self.bits(packed >> shift, split);
+                shift += split;
+            }
+        }
+    }
+}
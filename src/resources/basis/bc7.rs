@@ -0,0 +1,66 @@
+use super::{nearest, principal_endpoints, Pixels};
+
+/// The weights of the 4 bit indices, out of 64.
+const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Fits the texels to a line of RGBA, in the mode 6 of a single subset with
+/// 16 colors.
+pub fn encode(pixels: &Pixels) -> [u8; 16] {
+    let (low, high) = principal_endpoints(pixels);
+    let mut endpoints = [quantize(low), quantize(high)];
+
+    let color = |endpoints: &[([u8; 4], u32); 2], index: usize| {
+        let [(low, low_bit), (high, high_bit)] = endpoints;
+        [0, 1, 2, 3].map(|component| {
+            let low = ((low[component] as u32) << 1) | low_bit;
+            let high = ((high[component] as u32) << 1) | high_bit;
+            (((64 - WEIGHTS[index]) * low + WEIGHTS[index] * high + 32) >> 6) as u8
+        })
+    };
+    let palette: Vec<_> = (0..16).map(|index| color(&endpoints, index)).collect();
+    let mut indices = pixels.map(|pixel| nearest(&palette, &pixel) as u32);
+
+    // The index of the first texel leaves out its highest bit
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|index| 15 - index);
+    }
+
+    let mut block = 1u128 << 6;
+    let mut offset = 7;
+    let mut write = |value: u32, bits: u32| {
+        block |= (value as u128) << offset;
+        offset += bits;
+    };
+    for component in 0..4 {
+        for (endpoint, _) in &endpoints {
+            write(endpoint[component] as u32, 7);
+        }
+    }
+    for (_, bit) in &endpoints {
+        write(*bit, 1);
+    }
+    for (texel, &index) in indices.iter().enumerate() {
+        write(index, if texel == 0 { 3 } else { 4 });
+    }
+
+    block.to_le_bytes()
+}
+
+/// The 7 bits of each component and the lowest bit they share.
+fn quantize(color: [f32; 4]) -> ([u8; 4], u32) {
+    (0..2)
+        .map(|bit| {
+            let components =
+                color.map(|value| ((value - bit as f32) / 2.0).round().clamp(0.0, 127.0));
+            let error: f32 = (0..4)
+                .map(|component| {
+                    (components[component] * 2.0 + bit as f32 - color[component]).powi(2)
+                })
+                .sum();
+            (components.map(|value| value as u8), bit, error)
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(components, bit, _)| (components, bit))
+        .unwrap()
+}
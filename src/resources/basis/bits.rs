@@ -0,0 +1,177 @@
+/// Reads a stream from the least significant bit of each byte. Reading past
+/// the end gives zeros, the streams are checked by what they decode to.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, offset: 0 }
+    }
+
+    pub fn bits(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for bit in 0..count {
+            let byte = self.data.get(self.offset / 8).copied().unwrap_or(0);
+            value |= (((byte >> (self.offset % 8)) & 1) as u32) << bit;
+            self.offset += 1;
+        }
+
+        value
+    }
+
+    /// A number split in chunks of `chunk_bits`, each followed by a bit
+    /// telling if another chunk follows.
+    pub fn vlc(&mut self, chunk_bits: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for shift in (0..32).step_by(chunk_bits as usize) {
+            let chunk = self.bits(chunk_bits + 1);
+            value |= (chunk & ((1 << chunk_bits) - 1)) << shift;
+            if chunk & (1 << chunk_bits) == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err("variable length number overflows".to_string())
+    }
+
+    pub fn huffman(&mut self, table: &Huffman) -> Result<u32, String> {
+        // Canonical codes, from their most significant bit
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &table.counts[1..] {
+            code |= self.bits(1) as i32;
+            if code - count < first {
+                return Ok(table.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err("invalid Huffman code".to_string())
+    }
+
+    /// A table stored as the lengths of its codes, themselves Huffman coded
+    /// with runs of zeros and repeats.
+    pub fn huffman_table(&mut self) -> Result<Huffman, String> {
+        // The lengths of the code length codes, in this order
+        const ORDER: [usize; 21] = [
+            17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16,
+        ];
+
+        let symbol_count = self.bits(14) as usize;
+        if symbol_count == 0 {
+            return Ok(Huffman::default());
+        }
+
+        let code_count = self.bits(5) as usize;
+        if !(1..=ORDER.len()).contains(&code_count) {
+            return Err("invalid Huffman code length count".to_string());
+        }
+        let mut code_lengths = [0; 21];
+        for &code in &ORDER[..code_count] {
+            code_lengths[code] = self.bits(3) as u8;
+        }
+        let code_table = Huffman::new(&code_lengths)?;
+
+        let mut lengths = Vec::with_capacity(symbol_count);
+        while lengths.len() < symbol_count {
+            let code = self.huffman(&code_table)?;
+            let (length, run) = match code {
+                0..=16 => (code as u8, 1),
+                17 => (0, self.bits(3) + 3),
+                18 => (0, self.bits(7) + 11),
+                19 | 20 => {
+                    let run = match code {
+                        19 => self.bits(2) + 3,
+                        _ => self.bits(7) + 7,
+                    };
+                    match lengths.last() {
+                        Some(&length) if length != 0 => (length, run),
+                        _ => return Err("Huffman repeat without a length".to_string()),
+                    }
+                }
+                _ => return Err("invalid Huffman code length".to_string()),
+            };
+            if lengths.len() + run as usize > symbol_count {
+                return Err("Huffman code lengths overflow".to_string());
+            }
+            lengths.resize(lengths.len() + run as usize, length);
+        }
+
+        Huffman::new(&lengths)
+    }
+}
+
+/// The canonical Huffman codes of a table, shorter codes first and the
+/// codes of the same length by symbol.
+#[derive(Default)]
+pub struct Huffman {
+    /// The number of codes of each length.
+    counts: [i32; 17],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    pub fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0; 17];
+        for &length in lengths {
+            let count = counts
+                .get_mut(length as usize)
+                .ok_or("Huffman code too long")?;
+            *count += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for length in 1..counts.len() {
+            symbols.extend(
+                (0..lengths.len() as u16)
+                    .filter(|&symbol| lengths[symbol as usize] == length as u8),
+            );
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+/// Writes a stream from the least significant bit of each byte.
+#[cfg(test)]
+#[derive(Default)]
+pub struct BitWriter {
+    pub data: Vec<u8>,
+    offset: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    pub fn bits(&mut self, value: u32, count: u32) {
+        for bit in 0..count {
+            if self.offset.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            *self.data.last_mut().unwrap() |= (((value >> bit) & 1) as u8) << (self.offset % 8);
+            self.offset += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_canonical_codes() {
+        // A: 0, B: 10, C: 110, D: 111, from the first bit of each code
+        let table = Huffman::new(&[1, 2, 3, 3]).unwrap();
+        let mut writer = BitWriter::default();
+        for (code, length) in [(0b0, 1), (0b01, 2), (0b011, 3), (0b111, 3), (0b0, 1)] {
+            writer.bits(code, length);
+        }
+
+        let mut reader = BitReader::new(&writer.data);
+        let symbols: Vec<_> = (0..5).map(|_| reader.huffman(&table).unwrap()).collect();
+        assert_eq!(symbols, [0, 1, 2, 3, 0]);
+    }
+}
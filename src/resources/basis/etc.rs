@@ -0,0 +1,236 @@
+use super::Pixels;
+
+/// The small and large modifiers of the ETC1 intensity tables.
+pub const INTENSITIES: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+/// The modifiers of the EAC alpha tables, multiplied by the block's.
+const ALPHA_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// The modifier of a selector, from the most negative one to the most
+/// positive one.
+pub fn modifier(intensity: usize, selector: u8) -> i32 {
+    let [small, large] = INTENSITIES[intensity];
+    [-large, -small, small, large][selector as usize]
+}
+
+/// A block of the two halves of a block, each with its base color and
+/// intensity table. The selectors go from the most negative modifier.
+struct Etc1Block {
+    /// The colors of 4 bits, or of 5 bits when they are differential.
+    colors: [[i32; 3]; 2],
+    differential: bool,
+    flip: bool,
+    intensities: [usize; 2],
+    selectors: [u8; 16],
+}
+
+impl Etc1Block {
+    fn pack(&self) -> [u8; 8] {
+        let mut block = 0u64;
+        for component in 0..3 {
+            let shift = 59 - 8 * component;
+            block |= match self.differential {
+                true => {
+                    let delta = self.colors[1][component] - self.colors[0][component];
+                    ((self.colors[0][component] as u64) << shift)
+                        | ((delta as u64 & 7) << (shift - 3))
+                }
+                false => {
+                    ((self.colors[0][component] as u64) << (shift + 1))
+                        | ((self.colors[1][component] as u64) << (shift - 3))
+                }
+            };
+        }
+        block |= (self.intensities[0] as u64) << 37 | (self.intensities[1] as u64) << 34;
+        block |= (self.differential as u64) << 33 | (self.flip as u64) << 32;
+
+        // The texels go down the columns, the selectors map to the
+        // codes of +small, +large, -small and -large
+        for (texel, &selector) in self.selectors.iter().enumerate() {
+            let code = [3, 2, 0, 1][selector as usize];
+            let index = (texel % 4) * 4 + texel / 4;
+            block |= ((code >> 1) as u64) << (16 + index) | ((code & 1) as u64) << index;
+        }
+
+        block.to_be_bytes()
+    }
+}
+
+/// An ETC1S block, where both halves share a color and a table.
+pub fn etc1s_block(color5: [u8; 3], intensity: usize, selectors: &[u8; 16]) -> [u8; 8] {
+    let color = color5.map(|component| component as i32);
+    Etc1Block {
+        colors: [color; 2],
+        differential: true,
+        flip: false,
+        intensities: [intensity; 2],
+        selectors: *selectors,
+    }
+    .pack()
+}
+
+/// Fits each half of the block to a base color and the best intensity
+/// table, trying both splits and both color precisions.
+pub fn encode_etc1(pixels: &Pixels) -> [u8; 8] {
+    let mut best: Option<(i32, Etc1Block)> = None;
+    for flip in [false, true] {
+        let half = |texel: usize| match flip {
+            false => (texel % 4 >= 2) as usize,
+            true => (texel / 4 >= 2) as usize,
+        };
+        let averages = [0, 1].map(|index| {
+            let mut sum = [0; 3];
+            for texel in (0..16).filter(|&texel| half(texel) == index) {
+                for (component, sum) in sum.iter_mut().enumerate() {
+                    *sum += pixels[texel][component] as i32;
+                }
+            }
+            sum.map(|sum| sum as f32 / 8.0)
+        });
+
+        let quantize = |bits: u32| {
+            averages.map(|color| {
+                color.map(|value| (value * ((1 << bits) - 1) as f32 / 255.0).round() as i32)
+            })
+        };
+        let colors5 = quantize(5);
+        let fits_differential = (0..3)
+            .all(|component| (-4..=3).contains(&(colors5[1][component] - colors5[0][component])));
+
+        let mut candidates = vec![(false, quantize(4))];
+        if fits_differential {
+            candidates.push((true, colors5));
+        }
+        for (differential, colors) in candidates {
+            let expand = |value: i32| match differential {
+                true => (value << 3) | (value >> 2),
+                false => (value << 4) | value,
+            };
+            let mut block = Etc1Block {
+                colors,
+                differential,
+                flip,
+                intensities: [0; 2],
+                selectors: [0; 16],
+            };
+
+            let mut error = 0;
+            for (index, color) in colors.iter().enumerate() {
+                let base = color.map(expand);
+                let texels: Vec<_> = (0..16).filter(|&texel| half(texel) == index).collect();
+                let (intensity_error, intensity, selectors) = (0..8)
+                    .map(|intensity| {
+                        let selectors: Vec<_> = texels
+                            .iter()
+                            .map(|&texel| {
+                                (0..4)
+                                    .map(|selector| {
+                                        let error = color_error(
+                                            &base,
+                                            modifier(intensity, selector),
+                                            &pixels[texel],
+                                        );
+                                        (error, selector)
+                                    })
+                                    .min()
+                                    .unwrap()
+                            })
+                            .collect();
+                        let error = selectors.iter().map(|(error, _)| error).sum::<i32>();
+                        (error, intensity, selectors)
+                    })
+                    .min_by_key(|(error, ..)| *error)
+                    .unwrap();
+
+                error += intensity_error;
+                block.intensities[index] = intensity;
+                for (&texel, (_, selector)) in texels.iter().zip(selectors) {
+                    block.selectors[texel] = selector;
+                }
+            }
+
+            if best.as_ref().is_none_or(|(best, _)| error < *best) {
+                best = Some((error, block));
+            }
+        }
+    }
+
+    best.unwrap().1.pack()
+}
+
+fn color_error(base: &[i32; 3], modifier: i32, pixel: &[u8; 4]) -> i32 {
+    (0..3)
+        .map(|component| {
+            let value = (base[component] + modifier).clamp(0, 255);
+            (value - pixel[component] as i32).pow(2)
+        })
+        .sum()
+}
+
+/// The alpha of an ETC2 RGBA block, from a base value, one of the modifier
+/// tables and a multiplier.
+pub fn encode_alpha(alphas: &[u8; 16]) -> [u8; 8] {
+    let (min, max) = (
+        *alphas.iter().min().unwrap() as i32,
+        *alphas.iter().max().unwrap() as i32,
+    );
+
+    let mut best = (i32::MAX, 0u64);
+    for (table, modifiers) in ALPHA_MODIFIERS.iter().enumerate() {
+        let span = modifiers[7] - modifiers[3];
+        let multiplier = ((max - min) as f32 / span as f32).ceil().clamp(1.0, 15.0) as i32;
+        for multiplier in (multiplier - 1).max(1)..=(multiplier + 1).min(15) {
+            let center = (min + max) / 2 - (modifiers[7] + modifiers[3]) * multiplier / 2;
+            for base in (center - 1).max(0)..=(center + 1).min(255) {
+                let mut error = 0;
+                let mut indices = 0u64;
+                for (texel, &alpha) in alphas.iter().enumerate() {
+                    let (texel_error, index) = (0..8)
+                        .map(|index| {
+                            let value = (base + modifiers[index] * multiplier).clamp(0, 255);
+                            ((value - alpha as i32).pow(2), index)
+                        })
+                        .min()
+                        .unwrap();
+                    error += texel_error;
+                    let position = (texel % 4) * 4 + texel / 4;
+                    indices |= (index as u64) << (45 - 3 * position);
+                }
+
+                if error < best.0 {
+                    let header =
+                        (base as u64) << 56 | (multiplier as u64) << 52 | (table as u64) << 48;
+                    best = (error, header | indices);
+                }
+            }
+        }
+    }
+
+    best.1.to_be_bytes()
+}
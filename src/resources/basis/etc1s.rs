@@ -0,0 +1,337 @@
+use super::{
+    bits::{BitReader, Huffman},
+    etc::modifier,
+    Pixels,
+};
+
+/// The symbol of the endpoint predictions repeating the last ones.
+const REPEAT_PREDICTIONS: u32 = 256;
+/// The palette deltas of the color components depend on the range of the
+/// previous component, split at these values.
+const COLOR_DELTA_SPLITS: [u8; 2] = [9, 21];
+
+/// The shared color and intensity table of an ETC1S block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Endpoint {
+    pub color5: [u8; 3],
+    pub intensity: usize,
+}
+
+/// A block of an ETC1S slice, indices into the palettes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Etc1sBlock {
+    pub endpoint: u16,
+    pub selector: u16,
+}
+
+/// The byte ranges of the slices of a level, relative to its data.
+pub struct ImageDesc {
+    pub rgb: std::ops::Range<usize>,
+    pub alpha: Option<std::ops::Range<usize>>,
+}
+
+/// The palettes and Huffman tables a BasisLZ container shares between its
+/// slices, from its supercompression global data.
+pub struct BasisLz {
+    pub endpoints: Vec<Endpoint>,
+    /// The selectors of each texel, going down the rows.
+    pub selectors: Vec<[u8; 16]>,
+    pub images: Vec<ImageDesc>,
+    endpoint_predictions: Huffman,
+    endpoint_deltas: Huffman,
+    selector_symbols: Huffman,
+    selector_runs: Huffman,
+    selector_history_size: usize,
+}
+
+impl BasisLz {
+    pub fn new(global_data: &[u8], image_count: usize) -> Result<BasisLz, String> {
+        let mut reader = ByteReader(global_data);
+        let endpoint_count = reader.u16()? as usize;
+        let selector_count = reader.u16()? as usize;
+        let endpoints_length = reader.u32()? as usize;
+        let selectors_length = reader.u32()? as usize;
+        let tables_length = reader.u32()? as usize;
+        let extended_length = reader.u32()? as usize;
+
+        let images = (0..image_count)
+            .map(|_| {
+                let _flags = reader.u32()?;
+                let rgb_offset = reader.u32()? as usize;
+                let rgb_length = reader.u32()? as usize;
+                let alpha_offset = reader.u32()? as usize;
+                let alpha_length = reader.u32()? as usize;
+                Ok(ImageDesc {
+                    rgb: rgb_offset..rgb_offset + rgb_length,
+                    alpha: (alpha_length > 0).then_some(alpha_offset..alpha_offset + alpha_length),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+
+        let endpoints_data = reader.bytes(endpoints_length)?;
+        let selectors_data = reader.bytes(selectors_length)?;
+        let tables_data = reader.bytes(tables_length)?;
+        reader.bytes(extended_length)?;
+
+        let mut tables = BitReader::new(tables_data);
+        let endpoint_predictions = tables.huffman_table()?;
+        let endpoint_deltas = tables.huffman_table()?;
+        let selector_symbols = tables.huffman_table()?;
+        let selector_runs = tables.huffman_table()?;
+        let selector_history_size = tables.bits(13) as usize;
+
+        Ok(BasisLz {
+            endpoints: decode_endpoints(endpoints_data, endpoint_count)?,
+            selectors: decode_selectors(selectors_data, selector_count)?,
+            images,
+            endpoint_predictions,
+            endpoint_deltas,
+            selector_symbols,
+            selector_runs,
+            selector_history_size,
+        })
+    }
+
+    /// The blocks of a slice, going down the rows. Each block predicts its
+    /// endpoint from its neighbors, or codes it as a delta to the previous
+    /// one, and reuses the recent selectors.
+    pub fn decode_slice(
+        &self,
+        data: &[u8],
+        blocks_x: usize,
+        blocks_y: usize,
+    ) -> Result<Vec<Etc1sBlock>, String> {
+        let mut reader = BitReader::new(data);
+        let endpoint_count = self.endpoints.len();
+        let selector_count = self.selectors.len();
+        let history_symbol = selector_count as u32 + self.selector_history_size as u32;
+
+        let mut blocks: Vec<Etc1sBlock> = Vec::with_capacity(blocks_x * blocks_y);
+        let mut history = SelectorHistory::new(self.selector_history_size);
+        let mut selector_run = 0;
+        // The predictions of each 2x2 group of blocks, the lower half
+        // is kept for the next row
+        let mut predictions = 0;
+        let mut lower_predictions = vec![0; blocks_x];
+        let mut last_predictions = 0;
+        let mut prediction_repeats = 0;
+        let mut previous_endpoint = 0;
+
+        for y in 0..blocks_y {
+            for (x, lower) in lower_predictions.iter_mut().enumerate() {
+                if x % 2 == 0 {
+                    if y % 2 == 0 {
+                        if prediction_repeats > 0 {
+                            prediction_repeats -= 1;
+                            predictions = last_predictions;
+                        } else {
+                            predictions = reader.huffman(&self.endpoint_predictions)?;
+                            if predictions == REPEAT_PREDICTIONS {
+                                prediction_repeats = reader.vlc(4)? + 3 - 1;
+                                predictions = last_predictions;
+                            } else {
+                                last_predictions = predictions;
+                            }
+                        }
+                        *lower = predictions >> 4;
+                    } else {
+                        predictions = *lower;
+                    }
+                }
+
+                let above = |x: usize| match y {
+                    0 => Err("endpoint predicted from above the first row".to_string()),
+                    _ => Ok(blocks[(y - 1) * blocks_x + x].endpoint),
+                };
+                let endpoint = match predictions & 3 {
+                    0 if x > 0 => previous_endpoint,
+                    1 => above(x)?,
+                    2 if x > 0 => above(x - 1)?,
+                    3 => {
+                        let delta = reader.huffman(&self.endpoint_deltas)? as usize;
+                        let endpoint = previous_endpoint as usize + delta;
+                        match endpoint >= endpoint_count {
+                            true => (endpoint - endpoint_count) as u16,
+                            false => endpoint as u16,
+                        }
+                    }
+                    _ => return Err("endpoint predicted from outside the slice".to_string()),
+                };
+                predictions >>= 2;
+                previous_endpoint = endpoint;
+
+                let symbol = if selector_run > 0 {
+                    selector_run -= 1;
+                    selector_count as u32
+                } else {
+                    let symbol = reader.huffman(&self.selector_symbols)?;
+                    if symbol == history_symbol {
+                        let run = reader.huffman(&self.selector_runs)?;
+                        selector_run = match run {
+                            63 => reader.vlc(7)? + 3,
+                            run => run + 3,
+                        } - 1;
+                        selector_count as u32
+                    } else {
+                        symbol
+                    }
+                };
+                let selector = match symbol as usize {
+                    symbol if symbol < selector_count => {
+                        history.add(symbol as u16);
+                        symbol as u16
+                    }
+                    symbol => history.take(symbol - selector_count)?,
+                };
+
+                if endpoint as usize >= endpoint_count || selector as usize >= selector_count {
+                    return Err("block palette index out of range".to_string());
+                }
+                blocks.push(Etc1sBlock { endpoint, selector });
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    pub fn pixels(&self, block: Etc1sBlock) -> Pixels {
+        let Endpoint { color5, intensity } = self.endpoints[block.endpoint as usize];
+        let selectors = &self.selectors[block.selector as usize];
+        let base = color5.map(|value| ((value << 3) | (value >> 2)) as i32);
+
+        let mut pixels = [[255; 4]; 16];
+        for (pixel, &selector) in pixels.iter_mut().zip(selectors) {
+            let modifier = modifier(intensity, selector);
+            for component in 0..3 {
+                pixel[component] = (base[component] + modifier).clamp(0, 255) as u8;
+            }
+        }
+
+        pixels
+    }
+}
+
+/// The endpoint palette, each color and intensity a delta to the previous.
+fn decode_endpoints(data: &[u8], count: usize) -> Result<Vec<Endpoint>, String> {
+    let mut reader = BitReader::new(data);
+    let color_deltas = [(); 3].map(|_| reader.huffman_table());
+    let color_deltas: Vec<_> = color_deltas.into_iter().collect::<Result<_, _>>()?;
+    let intensity_deltas = reader.huffman_table()?;
+    let grayscale = reader.bits(1) == 1;
+
+    let mut color = [16u8; 3];
+    let mut intensity = 0;
+    (0..count)
+        .map(|_| {
+            intensity = (intensity + reader.huffman(&intensity_deltas)? as usize) & 7;
+            for value in color.iter_mut().take(if grayscale { 1 } else { 3 }) {
+                let table = COLOR_DELTA_SPLITS
+                    .iter()
+                    .take_while(|&&split| *value > split)
+                    .count();
+                let delta = reader.huffman(&color_deltas[table])?;
+                *value = ((*value as u32 + delta) & 31) as u8;
+            }
+            if grayscale {
+                color = [color[0]; 3];
+            }
+
+            Ok(Endpoint {
+                color5: color,
+                intensity,
+            })
+        })
+        .collect()
+}
+
+/// The selector palette, each row of 4 selectors a byte, raw or as a delta
+/// to the same row of the previous selectors.
+fn decode_selectors(data: &[u8], count: usize) -> Result<Vec<[u8; 16]>, String> {
+    let mut reader = BitReader::new(data);
+    if reader.bits(1) == 1 || reader.bits(1) == 1 {
+        return Err("global selector palettes are not supported".to_string());
+    }
+    let raw = reader.bits(1) == 1;
+    let deltas = match raw {
+        true => Huffman::default(),
+        false => reader.huffman_table()?,
+    };
+
+    let mut rows = [0u8; 4];
+    (0..count)
+        .map(|index| {
+            for row in &mut rows {
+                *row = match raw || index == 0 {
+                    true => reader.bits(8) as u8,
+                    false => reader.huffman(&deltas)? as u8 ^ *row,
+                };
+            }
+
+            Ok(std::array::from_fn(|texel| {
+                (rows[texel / 4] >> (2 * (texel % 4))) & 3
+            }))
+        })
+        .collect()
+}
+
+/// The recently used selectors, roughly moved to the front as they are
+/// used again.
+struct SelectorHistory {
+    selectors: Vec<u16>,
+    next: usize,
+}
+
+impl SelectorHistory {
+    fn new(size: usize) -> SelectorHistory {
+        SelectorHistory {
+            selectors: vec![0; size],
+            next: size / 2,
+        }
+    }
+
+    fn add(&mut self, selector: u16) {
+        if self.selectors.is_empty() {
+            return;
+        }
+
+        self.selectors[self.next] = selector;
+        self.next += 1;
+        if self.next == self.selectors.len() {
+            self.next = self.selectors.len() / 2;
+        }
+    }
+
+    fn take(&mut self, index: usize) -> Result<u16, String> {
+        let selector = *self
+            .selectors
+            .get(index)
+            .ok_or("selector history index out of range")?;
+        if index > 0 {
+            self.selectors.swap(index / 2, index);
+        }
+
+        Ok(selector)
+    }
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if length > self.0.len() {
+            return Err("truncated BasisLZ global data".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
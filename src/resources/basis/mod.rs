@@ -0,0 +1,320 @@
+mod astc;
+mod bc7;
+mod bits;
+mod etc;
+mod etc1s;
+mod uastc;
+
+use ktx2::{ColorModel, DfdBlockBasic, Reader, TransferFunction};
+use wgpu::{AstcBlock, AstcChannel, Features, TextureFormat};
+
+use etc1s::{BasisLz, Etc1sBlock};
+use uastc::UastcBlock;
+
+/// The texels of a 4x4 block, going down the rows.
+type Pixels = [[u8; 4]; 16];
+
+/// The format Basis Universal textures are transcoded to, the best one the
+/// device can sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscodeTarget {
+    Bc7,
+    Astc,
+    Etc2,
+    Rgba8,
+}
+
+impl TranscodeTarget {
+    /// The block compressed formats need a size in whole blocks, other
+    /// sizes are left uncompressed.
+    pub fn new(features: Features, width: u32, height: u32) -> TranscodeTarget {
+        if !width.is_multiple_of(4) || !height.is_multiple_of(4) {
+            TranscodeTarget::Rgba8
+        } else if features.contains(Features::TEXTURE_COMPRESSION_BC) {
+            TranscodeTarget::Bc7
+        } else if features.contains(Features::TEXTURE_COMPRESSION_ASTC) {
+            TranscodeTarget::Astc
+        } else if features.contains(Features::TEXTURE_COMPRESSION_ETC2) {
+            TranscodeTarget::Etc2
+        } else {
+            TranscodeTarget::Rgba8
+        }
+    }
+
+    pub fn format(self, srgb: bool) -> TextureFormat {
+        match (self, srgb) {
+            (TranscodeTarget::Bc7, false) => TextureFormat::Bc7RgbaUnorm,
+            (TranscodeTarget::Bc7, true) => TextureFormat::Bc7RgbaUnormSrgb,
+            (TranscodeTarget::Astc, srgb) => TextureFormat::Astc {
+                block: AstcBlock::B4x4,
+                channel: match srgb {
+                    true => AstcChannel::UnormSrgb,
+                    false => AstcChannel::Unorm,
+                },
+            },
+            (TranscodeTarget::Etc2, false) => TextureFormat::Etc2Rgba8Unorm,
+            (TranscodeTarget::Etc2, true) => TextureFormat::Etc2Rgba8UnormSrgb,
+            (TranscodeTarget::Rgba8, false) => TextureFormat::Rgba8Unorm,
+            (TranscodeTarget::Rgba8, true) => TextureFormat::Rgba8UnormSrgb,
+        }
+    }
+}
+
+/// A block in one of the two Basis Universal formats.
+enum Block<'a> {
+    Etc1s {
+        palettes: &'a BasisLz,
+        rgb: Etc1sBlock,
+        alpha: Option<Etc1sBlock>,
+    },
+    Uastc(UastcBlock),
+}
+
+impl Block<'_> {
+    fn pixels(&self, srgb: bool) -> Pixels {
+        match self {
+            Block::Etc1s {
+                palettes,
+                rgb,
+                alpha,
+            } => {
+                let mut pixels = palettes.pixels(*rgb);
+                if let Some(alpha) = alpha {
+                    // The alpha slices are gray
+                    for (pixel, alpha) in pixels.iter_mut().zip(palettes.pixels(*alpha)) {
+                        pixel[3] = alpha[1];
+                    }
+                }
+                pixels
+            }
+            Block::Uastc(UastcBlock::Solid(color)) => [*color; 16],
+            Block::Uastc(UastcBlock::Astc(block)) => block.decode(srgb),
+        }
+    }
+
+    /// Keeps the ETC1S colors as ETC1 blocks and the UASTC ones as ASTC
+    /// blocks, the rest is encoded again.
+    fn transcode(&self, target: TranscodeTarget, srgb: bool, output: &mut Vec<u8>) {
+        let pixels = || self.pixels(srgb);
+        match (target, self) {
+            (TranscodeTarget::Bc7, _) => output.extend(bc7::encode(&pixels())),
+            (TranscodeTarget::Astc, Block::Uastc(UastcBlock::Solid(color))) => {
+                output.extend(astc::void_extent(*color))
+            }
+            (TranscodeTarget::Astc, Block::Uastc(UastcBlock::Astc(block))) => {
+                output.extend(block.pack())
+            }
+            (TranscodeTarget::Astc, Block::Etc1s { .. }) => output.extend(astc::encode(&pixels())),
+            (TranscodeTarget::Etc2, _) => {
+                let pixels = pixels();
+                output.extend(etc::encode_alpha(&pixels.map(|pixel| pixel[3])));
+                match self {
+                    Block::Etc1s { palettes, rgb, .. } => {
+                        let endpoint = palettes.endpoints[rgb.endpoint as usize];
+                        output.extend(etc::etc1s_block(
+                            endpoint.color5,
+                            endpoint.intensity,
+                            &palettes.selectors[rgb.selector as usize],
+                        ))
+                    }
+                    Block::Uastc(_) => output.extend(etc::encode_etc1(&pixels)),
+                }
+            }
+            (TranscodeTarget::Rgba8, _) => unreachable!("the texels are copied by their rows"),
+        }
+    }
+}
+
+/// Transcodes the levels of a container without a format, already without
+/// their Zstandard supercompression, to the best format of the `features`.
+/// Returns the format and the levels, from the largest one.
+pub fn transcode(
+    reader: &Reader<&[u8]>,
+    levels: &[Vec<u8>],
+    features: Features,
+) -> Result<(TextureFormat, Vec<Vec<u8>>), String> {
+    let header = reader.header();
+    let descriptor = reader
+        .dfd_blocks()
+        .next()
+        .ok_or("missing data format descriptor")?;
+    let descriptor = DfdBlockBasic::parse(descriptor.data).map_err(|error| error.to_string())?;
+    let srgb = descriptor.header.transfer_function == Some(TransferFunction::SRGB);
+    let target = TranscodeTarget::new(features, header.pixel_width, header.pixel_height);
+
+    let palettes = match descriptor.header.color_model {
+        Some(ColorModel::ETC1S) => Some(BasisLz::new(
+            reader.supercompression_global_data(),
+            levels.len(),
+        )?),
+        Some(ColorModel::UASTC) => None,
+        model => return Err(format!("unsupported color model {model:?}")),
+    };
+
+    let levels = levels
+        .iter()
+        .enumerate()
+        .map(|(level, data)| {
+            let width = (header.pixel_width >> level).max(1) as usize;
+            let height = (header.pixel_height >> level).max(1) as usize;
+            let (blocks_x, blocks_y) = (width.div_ceil(4), height.div_ceil(4));
+
+            let blocks = match &palettes {
+                Some(palettes) => etc1s_blocks(palettes, level, data, blocks_x, blocks_y)?,
+                None => uastc_blocks(data, blocks_x * blocks_y)?,
+            };
+
+            let mut output = Vec::new();
+            if target == TranscodeTarget::Rgba8 {
+                output.resize(width * height * 4, 0);
+                for (index, block) in blocks.iter().enumerate() {
+                    let (block_x, block_y) = (index % blocks_x * 4, index / blocks_x * 4);
+                    for (texel, pixel) in block.pixels(srgb).iter().enumerate() {
+                        let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+                        if x < width && y < height {
+                            let offset = (y * width + x) * 4;
+                            output[offset..offset + 4].copy_from_slice(pixel);
+                        }
+                    }
+                }
+            } else {
+                for block in &blocks {
+                    block.transcode(target, srgb, &mut output);
+                }
+            }
+
+            Ok(output)
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok((target.format(srgb), levels))
+}
+
+fn etc1s_blocks<'a>(
+    palettes: &'a BasisLz,
+    level: usize,
+    data: &[u8],
+    blocks_x: usize,
+    blocks_y: usize,
+) -> Result<Vec<Block<'a>>, String> {
+    let image = &palettes.images[level];
+    let slice = |range: &std::ops::Range<usize>| {
+        let data = data
+            .get(range.clone())
+            .ok_or("ETC1S slice out of its level")?;
+        palettes.decode_slice(data, blocks_x, blocks_y)
+    };
+
+    let rgb = slice(&image.rgb)?;
+    let alpha = image.alpha.as_ref().map(slice).transpose()?;
+
+    Ok(rgb
+        .into_iter()
+        .enumerate()
+        .map(|(index, rgb)| Block::Etc1s {
+            palettes,
+            rgb,
+            alpha: alpha.as_ref().map(|alpha| alpha[index]),
+        })
+        .collect())
+}
+
+fn uastc_blocks(data: &[u8], count: usize) -> Result<Vec<Block<'static>>, String> {
+    if data.len() != count * 16 {
+        return Err("UASTC level of the wrong size".to_string());
+    }
+
+    data.chunks_exact(16)
+        .map(|block| uastc::unpack(block.try_into().unwrap()).map(Block::Uastc))
+        .collect()
+}
+
+/// The ends of the line through the colors of the texels, along the
+/// direction they vary the most.
+fn principal_endpoints(pixels: &Pixels) -> ([f32; 4], [f32; 4]) {
+    let colors = pixels.map(|pixel| pixel.map(|component| component as f32));
+    let mean = [0, 1, 2, 3]
+        .map(|component| colors.iter().map(|color| color[component]).sum::<f32>() / 16.0);
+
+    let mut covariance = [[0.0; 4]; 4];
+    for color in &colors {
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+
+    // Power iterations, from the component that varies the most
+    let start = (0..4)
+        .max_by(|&a, &b| covariance[a][a].total_cmp(&covariance[b][b]))
+        .unwrap();
+    let mut axis = covariance[start];
+    for _ in 0..8 {
+        let next = covariance.map(|row| (0..4).map(|j| row[j] * axis[j]).sum::<f32>());
+        let length = next.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            return (mean, mean);
+        }
+        axis = next.map(|value| value / length);
+    }
+
+    let offsets = colors.map(|color| (0..4).map(|i| (color[i] - mean[i]) * axis[i]).sum::<f32>());
+    let min = offsets.iter().copied().fold(f32::MAX, f32::min);
+    let max = offsets.iter().copied().fold(f32::MIN, f32::max);
+    let point = |offset: f32| [0, 1, 2, 3].map(|i| (mean[i] + axis[i] * offset).clamp(0.0, 255.0));
+
+    (point(min), point(max))
+}
+
+/// The index of the color of the palette nearest to the pixel.
+fn nearest(palette: &[[u8; 4]], pixel: &[u8; 4]) -> usize {
+    let distance = |color: &[u8; 4]| {
+        (0..4)
+            .map(|i| (color[i] as i32 - pixel[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+
+    (0..palette.len())
+        .min_by_key(|&index| distance(&palette[index]))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_the_best_sampled_format() {
+        let all = Features::TEXTURE_COMPRESSION_BC
+            | Features::TEXTURE_COMPRESSION_ASTC
+            | Features::TEXTURE_COMPRESSION_ETC2;
+        let target = |features| TranscodeTarget::new(features, 256, 128);
+
+        assert_eq!(target(all), TranscodeTarget::Bc7);
+        assert_eq!(
+            target(all - Features::TEXTURE_COMPRESSION_BC),
+            TranscodeTarget::Astc
+        );
+        assert_eq!(
+            target(Features::TEXTURE_COMPRESSION_ETC2),
+            TranscodeTarget::Etc2
+        );
+        assert_eq!(target(Features::empty()), TranscodeTarget::Rgba8);
+        assert_eq!(TranscodeTarget::new(all, 250, 128), TranscodeTarget::Rgba8);
+    }
+
+    #[test]
+    fn targets_have_srgb_formats() {
+        for target in [
+            TranscodeTarget::Bc7,
+            TranscodeTarget::Astc,
+            TranscodeTarget::Etc2,
+            TranscodeTarget::Rgba8,
+        ] {
+            let format = target.format(true);
+            assert!(format.is_srgb(), "{target:?}");
+            assert_eq!(format.remove_srgb_suffix(), target.format(false));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::{
+    astc::{self, AstcBlock},
+    bits::BitReader,
+};
+
+/// The prefix code of each mode, from the first bit of the block, and its
+/// length.
+const MODE_CODES: [(u8, u32); 19] = [
+    (0x1, 4),
+    (0x35, 6),
+    (0x1D, 5),
+    (0x3, 5),
+    (0x13, 5),
+    (0xB, 5),
+    (0x1B, 5),
+    (0x7, 5),
+    (0x17, 5),
+    (0xF, 5),
+    (0x2, 3),
+    (0x0, 2),
+    (0x6, 3),
+    (0x1F, 5),
+    (0xD, 5),
+    (0x5, 7),
+    (0x15, 6),
+    (0x25, 6),
+    (0x9, 4),
+];
+const SOLID_MODE: usize = 8;
+
+/// The bits of the hints for transcoding to BC1, ETC1 and ETC2, which
+/// follow the mode and are not needed to decode the block.
+const MODE_HINT_BITS: [u32; 19] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 0, 23, 17, 17, 17, 23, 23, 23, 23, 23, 15,
+];
+/// The ASTC endpoint mode of each mode: RGB, RGBA or luminance and alpha.
+const MODE_CEMS: [u32; 19] = [8, 8, 8, 8, 8, 8, 8, 8, 0, 12, 12, 12, 12, 12, 4, 4, 4, 4, 8];
+const MODE_ENDPOINT_RANGES: [usize; 19] = [
+    19, 20, 8, 7, 12, 20, 18, 12, 0, 8, 13, 13, 19, 20, 20, 20, 20, 20, 11,
+];
+const MODE_WEIGHT_RANGES: [usize; 19] = [8, 2, 5, 2, 2, 5, 2, 2, 0, 2, 8, 2, 5, 0, 2, 8, 2, 2, 11];
+
+/// The ASTC seeds of the 2 and 3 subset patterns shared with BC7.
+const PARTITIONS_2: [u32; 30] = [
+    28, 20, 16, 29, 91, 9, 107, 72, 149, 204, 50, 114, 496, 17, 78, 39, 252, 828, 43, 156, 116,
+    210, 476, 273, 684, 359, 246, 195, 694, 524,
+];
+const PARTITIONS_3: [u32; 11] = [260, 74, 32, 156, 183, 15, 745, 0, 335, 902, 254];
+/// The ASTC seeds of the 2 subset patterns merging two of the subsets of a
+/// BC7 3 subset pattern.
+const PARTITIONS_3_AS_2: [u32; 19] = [
+    36, 48, 61, 137, 161, 183, 226, 281, 302, 307, 479, 495, 593, 594, 605, 799, 812, 988, 993,
+];
+
+pub enum UastcBlock {
+    Solid([u8; 4]),
+    Astc(AstcBlock),
+}
+
+/// Unpacks a block into the ASTC block it is a subset of. The trits and
+/// quints of the endpoints are packed ahead of their bits, and the first
+/// weight of each subset leaves out its highest bit.
+pub fn unpack(block: &[u8; 16]) -> Result<UastcBlock, String> {
+    let mode = MODE_CODES
+        .iter()
+        .position(|&(code, length)| block[0] & ((1 << length) - 1) == code)
+        .ok_or("reserved UASTC mode")?;
+    let mut reader = BitReader::new(block);
+    reader.bits(MODE_CODES[mode].1);
+
+    if mode == SOLID_MODE {
+        return Ok(UastcBlock::Solid([(); 4].map(|_| reader.bits(8) as u8)));
+    }
+    reader.bits(MODE_HINT_BITS[mode]);
+
+    let (partitions, seeds): (u32, &[u32]) = match mode {
+        2 | 4 | 9 | 16 => (2, &PARTITIONS_2),
+        3 => (3, &PARTITIONS_3),
+        7 => (2, &PARTITIONS_3_AS_2),
+        _ => (1, &[0]),
+    };
+    let partition_seed = match partitions {
+        1 => 0,
+        _ => {
+            let pattern = reader.bits(if mode == 3 { 4 } else { 5 });
+            *seeds
+                .get(pattern as usize)
+                .ok_or("invalid UASTC partition pattern")?
+        }
+    };
+    let ccs = match mode {
+        6 | 11 | 13 => Some(reader.bits(2)),
+        17 => Some(3),
+        _ => None,
+    };
+
+    let mut astc = AstcBlock {
+        cem: MODE_CEMS[mode],
+        partitions,
+        partition_seed,
+        ccs,
+        endpoint_range: MODE_ENDPOINT_RANGES[mode],
+        weight_range: MODE_WEIGHT_RANGES[mode],
+        endpoints: [0; 24],
+        weights: [0; 32],
+    };
+
+    let count = astc.endpoint_count();
+    let (bits, trits, quints) = astc::RANGES[astc.endpoint_range];
+    let (group, radix, group_bits): (usize, u32, &[u32]) = match (trits, quints) {
+        (1, _) => (5, 3, &[0, 2, 4, 5, 7, 8]),
+        (_, 1) => (3, 5, &[0, 3, 5, 7]),
+        _ => (1, 1, &[0, 0]),
+    };
+    let digits: Vec<_> = (0..count.div_ceil(group))
+        .map(|index| reader.bits(group_bits[(count - index * group).min(group)]))
+        .collect();
+    for (index, endpoint) in astc.endpoints[..count].iter_mut().enumerate() {
+        let digit = (digits[index / group] / radix.pow((index % group) as u32)) % radix;
+        *endpoint = ((digit << bits) | reader.bits(bits)) as u8;
+    }
+
+    let weight_bits = astc::RANGES[astc.weight_range].0;
+    let planes = astc.planes();
+    let partition = |texel| astc::partition(partition_seed, partitions, texel);
+    for texel in 0..16 {
+        let anchor = (0..texel).all(|other| partition(other) != partition(texel));
+        for plane in 0..planes {
+            astc.weights[texel * planes + plane] = reader.bits(weight_bits - anchor as u32) as u8;
+        }
+    }
+
+    Ok(UastcBlock::Astc(astc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_fit_in_an_astc_block() {
+        for mode in (0..MODE_CODES.len()).filter(|&mode| mode != SOLID_MODE) {
+            let mut block = [0; 16];
+            block[0] = MODE_CODES[mode].0;
+            let Ok(UastcBlock::Astc(astc)) = unpack(&block) else {
+                panic!("mode {mode} did not unpack");
+            };
+
+            assert_eq!(
+                astc.implied_endpoint_range(),
+                astc.endpoint_range,
+                "mode {mode}"
+            );
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use wgpu::{
-    include_wgsl, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, ComputePassDescriptor, ComputePipeline,
     ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderStages,
     StorageTextureAccess, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
     TextureViewDimension,
 };
 
-use crate::texture::{CubeMap, Texture};
+use crate::{
+    shader_library::ShaderLibrary,
+    texture::{CubeMap, MipFilter, MipGenerator, Texture},
+};
 
 pub struct HdrLoader {
     texture_format: TextureFormat,
@@ -15,8 +18,8 @@ pub struct HdrLoader {
 }
 
 impl HdrLoader {
-    pub fn new(device: &Device) -> HdrLoader {
-        let module = device.create_shader_module(include_wgsl!("equirectangular.wgsl"));
+    pub fn new(device: &Device, shaders: &ShaderLibrary) -> HdrLoader {
+        let module = shaders.module(device, "resources/hdr_loader/equirectangular.wgsl", &[]);
 
         let texture_format = CubeMap::RGBA_32_FLOAT;
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -67,10 +70,13 @@ impl HdrLoader {
         }
     }
 
+    /// Projects the equirectangular `hdr_texture` onto the faces of a cube map
+    /// with a full mip chain.
     pub fn generate(
         &self,
         device: &Device,
         queue: &Queue,
+        mip_generator: &MipGenerator,
         hdr_texture: &Texture,
         dst_size: u32,
     ) -> CubeMap {
@@ -79,13 +85,18 @@ impl HdrLoader {
             dst_size,
             dst_size,
             self.texture_format,
-            TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::RENDER_ATTACHMENT,
+            Texture::mip_level_count(dst_size, dst_size),
             Some("Environment map"),
         );
 
+        // Only the first level is projected, the others are downsampled from it
         let env_map_view = env_map.texture.create_view(&TextureViewDescriptor {
             label: Some("Environment map view"),
             dimension: Some(TextureViewDimension::D2Array),
+            mip_level_count: Some(1),
             ..Default::default()
         });
 
@@ -118,6 +129,8 @@ impl HdrLoader {
 
         queue.submit([encoder.finish()]);
 
+        mip_generator.generate(device, queue, &env_map.texture, MipFilter::Color);
+
         env_map
     }
 }
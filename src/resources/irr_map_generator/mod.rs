@@ -1,12 +1,12 @@
 use wgpu::{
-    include_wgsl, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, CommandEncoderDescriptor,
     ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device,
     PipelineLayoutDescriptor, PushConstantRange, Queue, ShaderStages, StorageTextureAccess,
     TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
 };
 
-use crate::texture::CubeMap;
+use crate::{shader_library::ShaderLibrary, texture::CubeMap};
 
 pub struct IrrMapGenerator {
     texture_format: TextureFormat,
@@ -15,8 +15,8 @@ pub struct IrrMapGenerator {
 }
 // TODO we could store the generated irradiance map into a texture so we don't have to compute it each time, only when we haven't done it before
 impl IrrMapGenerator {
-    pub fn new(device: &Device) -> IrrMapGenerator {
-        let shader = device.create_shader_module(include_wgsl!("convolution.wgsl"));
+    pub fn new(device: &Device, shaders: &ShaderLibrary) -> IrrMapGenerator {
+        let shader = shaders.module(device, "resources/irr_map_generator/convolution.wgsl", &[]);
 
         let texture_format = CubeMap::RGBA_32_FLOAT;
 
@@ -90,6 +90,7 @@ impl IrrMapGenerator {
             dst_size,
             self.texture_format,
             TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            1,
             Some("Irradiance map"),
         );
 
@@ -1,56 +1,161 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use cgmath::{InnerSpace, Vector2, Vector3};
 use gltf::{Gltf, Mesh as GltfMesh, Node as GltfNode, Scene as GltfScene};
+use image::{io::Reader, DynamicImage};
 use wgpu::{Device, Queue, TextureFormat};
 
 use crate::{
-    entity::{Entity, Geometry, Mesh, Node, Vertex},
-    material::Material,
-    texture::Texture,
+    entity::{Aabb, Entity, Geometry, Mesh, Node, Vertex},
+    material::{AlphaMode, Material},
+    texture::{MipFilter, SamplerKey, Texture},
     transform::Transform,
 };
 
-use super::Resources;
+use super::{load_ktx2::Ktx2Error, Resources, TextureKey, TextureLoader, TextureSource};
+
+/// A glTF file read with its buffers and decoded images, the part of the
+/// loading that does not need the GPU, so it can be done on any thread.
+pub struct GltfData {
+    /// The glTF file and its external buffers, the images are the textures'.
+    pub files: Vec<PathBuf>,
+    gltf: Gltf,
+    buffers: Vec<Vec<u8>>,
+    /// Indexed like the images of the glTF file.
+    images: Vec<(TextureSource, ImageData)>,
+}
+
+enum ImageData {
+    Decoded(DynamicImage),
+    /// Transcoded or uploaded as it is once the adapter is known.
+    Ktx2(Vec<u8>),
+}
 
 impl Resources {
-    //TODO move texture loading to extract world stage?
-    pub fn load_gltf(device: &Device, queue: &Queue, path: &Path) -> Entity {
-        let current_directory = path.parent().unwrap();
+    /// Fails when a file can not be read, or an image decoded.
+    pub fn read_gltf(path: &Path) -> Result<GltfData, String> {
+        let current_directory = path.parent().unwrap_or(Path::new(""));
 
-        let file = std::fs::File::open(path).unwrap();
+        let file = std::fs::File::open(path).map_err(|error| error.to_string())?;
         let reader = std::io::BufReader::new(file);
-        let gltf = gltf::Gltf::from_reader(reader).unwrap();
+        let gltf = gltf::Gltf::from_reader(reader).map_err(|error| error.to_string())?;
 
         // Load buffers
-        let buffers = Resources::load_buffers(&gltf, current_directory);
+        let buffers = Resources::load_buffers(&gltf, current_directory)?;
+
+        // Decode images
+        let images = gltf
+            .images()
+            .map(|image| match image.source() {
+                gltf::image::Source::View { view, mime_type } => {
+                    let buffer = &buffers[view.buffer().index()];
+                    let data = &buffer[view.offset()..view.offset() + view.length()];
+                    let source = TextureSource::buffer_view(path, view.index());
+
+                    if mime_type == "image/ktx2" {
+                        return Ok((source, ImageData::Ktx2(data.to_vec())));
+                    }
+
+                    let image = image::load_from_memory(data)
+                        .map_err(|error| format!("image {}: {error}", image.index()))?;
+                    Ok((source, ImageData::Decoded(image)))
+                }
+                gltf::image::Source::Uri { uri, mime_type } => {
+                    let path = current_directory.join(uri);
+                    let source = TextureSource::file(&path);
+                    let with_uri = |error: &dyn std::fmt::Display| format!("{uri}: {error}");
+
+                    if mime_type == Some("image/ktx2") || uri.ends_with(".ktx2") {
+                        let data = std::fs::read(&path).map_err(|error| with_uri(&error))?;
+                        return Ok((source, ImageData::Ktx2(data)));
+                    }
+
+                    let image = Reader::open(&path)
+                        .map_err(|error| with_uri(&error))?
+                        .decode()
+                        .map_err(|error| with_uri(&error))?;
+                    Ok((source, ImageData::Decoded(image)))
+                }
+            })
+            .collect::<Result<_, String>>()?;
+
+        let mut files = vec![path.to_path_buf()];
+        files.extend(gltf.buffers().filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => Some(current_directory.join(uri)),
+            gltf::buffer::Source::Bin => None,
+        }));
+
+        Ok(GltfData {
+            files,
+            gltf,
+            buffers,
+            images,
+        })
+    }
 
+    /// Creates the textures of a read glTF file, on the thread owning the
+    /// queue. Fails when a texture or a mesh can not be loaded.
+    pub fn upload_gltf(
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+        data: GltfData,
+    ) -> Result<Entity, String> {
         // Load materials
-        let mut materials = Resources::load_materials(device, queue, &gltf, current_directory);
-
-        let default_material =
-            Material::new([0.4, 0.4, 0.2, 1.0], None, None, 0.0, 0.0, None, None);
+        let mut materials = Resources::load_materials(device, queue, loader, &data)?;
+
+        let default_material = Material::new(
+            [0.4, 0.4, 0.2, 1.0],
+            None,
+            None,
+            0.0,
+            0.0,
+            None,
+            None,
+            [0.0, 0.0, 0.0],
+            None,
+            AlphaMode::Opaque,
+            false,
+        );
 
         materials.push(default_material); // Put default material at the end of the array
 
+        let GltfData { gltf, buffers, .. } = data;
+
         // Load default scene
-        let default_scene = gltf.default_scene().expect("Default scene not provided!");
+        let default_scene = gltf
+            .default_scene()
+            .ok_or("the file has no default scene")?;
 
-        Resources::load_scene(default_scene, materials, buffers)
+        Resources::load_scene(&gltf, default_scene, materials, buffers)
     }
 
-    fn load_scene(scene: GltfScene, materials: Vec<Material>, buffers: Vec<Vec<u8>>) -> Entity {
+    fn load_scene(
+        gltf: &Gltf,
+        scene: GltfScene,
+        materials: Vec<Material>,
+        buffers: Vec<Vec<u8>>,
+    ) -> Result<Entity, String> {
+        // Nodes referencing the same mesh share it, so they can be instanced
+        let meshes = gltf
+            .meshes()
+            .map(|mesh| Resources::load_mesh(&mesh, &materials, &buffers).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let mut nodes = vec![];
 
         for node in scene.nodes() {
-            let node = Resources::load_node(node, &materials, &buffers);
+            let node = Resources::load_node(node, &meshes);
             nodes.push(node);
         }
 
-        Entity::new(nodes, materials, Transform::zero())
+        Ok(Entity::new(nodes, materials, Transform::zero()))
     }
 
-    fn load_node(node: GltfNode, materials: &Vec<Material>, buffers: &Vec<Vec<u8>>) -> Node {
+    fn load_node(node: GltfNode, meshes: &[Arc<Mesh>]) -> Node {
         let transform = match node.transform() {
             gltf::scene::Transform::Matrix { .. } => {
                 let t = node.transform().decomposed();
@@ -63,13 +168,11 @@ impl Resources {
             } => Transform::new(translation.into(), rotation.into(), scale.into()),
         };
 
-        let mesh = node
-            .mesh()
-            .map(|m| Resources::load_mesh(&m, materials, buffers));
+        let mesh = node.mesh().map(|m| meshes[m.index()].clone());
 
         let children = node
             .children()
-            .map(|c| Resources::load_node(c, materials, buffers))
+            .map(|c| Resources::load_node(c, meshes))
             .collect();
 
         Node {
@@ -79,8 +182,14 @@ impl Resources {
         }
     }
 
-    fn load_mesh(mesh: &GltfMesh, materials: &[Material], buffers: &[Vec<u8>]) -> Mesh {
+    /// Fails when a primitive lacks the indices or an attribute of [Vertex].
+    fn load_mesh(
+        mesh: &GltfMesh,
+        materials: &[Material],
+        buffers: &[Vec<u8>],
+    ) -> Result<Mesh, String> {
         let mut primitives = vec![];
+        let missing = |what| format!("mesh {}: a primitive has no {what}", mesh.index());
 
         for primitive in mesh.primitives() {
             let material_index = match primitive.material().index() {
@@ -94,19 +203,25 @@ impl Resources {
             // Read vertex attributes
             let indices: Vec<u16> = reader
                 .read_indices()
-                .unwrap()
+                .ok_or_else(|| missing("indices"))?
                 .into_u32()
                 .map(|i| i as u16)
                 .collect();
 
-            let positions = reader.read_positions().unwrap().collect::<Vec<_>>();
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| missing("positions"))?
+                .collect::<Vec<_>>();
             let uvs = reader
                 .read_tex_coords(0)
                 .map(|v| v.into_f32())
-                .unwrap()
+                .ok_or_else(|| missing("texture coordinates"))?
                 .collect::<Vec<_>>();
 
-            let normals = reader.read_normals().unwrap().collect::<Vec<_>>();
+            let normals = reader
+                .read_normals()
+                .ok_or_else(|| missing("normals"))?
+                .collect::<Vec<_>>();
 
             let tangents = reader.read_tangents().map(|iter| iter.collect());
 
@@ -173,45 +288,108 @@ impl Resources {
                 })
                 .collect::<Vec<Vertex>>();
 
-            let geometry = Geometry::new(vertices, indices);
+            let geometry = match Resources::read_bounds(&primitive) {
+                Some(aabb) => Geometry::with_aabb(vertices, indices, aabb),
+                None => Geometry::new(vertices, indices),
+            };
 
             primitives.push((geometry, material_index));
         }
 
-        Mesh { primitives }
+        Ok(Mesh { primitives })
+    }
+
+    /// Reads the bounds from the min/max of the position accessor, when present.
+    fn read_bounds(primitive: &gltf::Primitive) -> Option<Aabb> {
+        let positions = primitive.get(&gltf::Semantic::Positions)?;
+
+        let min: [f32; 3] = gltf::json::deserialize::from_value(positions.min()?).ok()?;
+        let max: [f32; 3] = gltf::json::deserialize::from_value(positions.max()?).ok()?;
+
+        Some(Aabb::new(min, max))
     }
 
     fn load_materials(
         device: &Device,
         queue: &Queue,
-        gltf: &Gltf,
-        current_directory: &Path,
-    ) -> Vec<Material> {
-        let load_texture =
-            |texture: &gltf::Texture, format: TextureFormat| match texture.source().source() {
-                gltf::image::Source::View { .. } => {
-                    todo!()
+        loader: &TextureLoader,
+        data: &GltfData,
+    ) -> Result<Vec<Material>, String> {
+        let gltf = &data.gltf;
+
+        let load_image = |image: &gltf::Image,
+                          format: TextureFormat,
+                          filter: MipFilter,
+                          sampler: SamplerKey|
+         -> Result<Arc<Texture>, Ktx2Error> {
+            let (source, image_data) = &data.images[image.index()];
+            let key = TextureKey {
+                source: source.clone(),
+                format,
+                filter,
+                sampler,
+            };
+
+            if let Some(texture) = loader.get(&key) {
+                return Ok(texture);
+            }
+
+            let texture = match image_data {
+                ImageData::Decoded(image) => {
+                    Resources::image_texture(device, queue, loader, image, &key)
                 }
-                gltf::image::Source::Uri { uri, .. } => {
-                    let path = current_directory.join(uri);
-                    Resources::load_texture(device, queue, &path, format)
+                ImageData::Ktx2(bytes) => {
+                    Resources::ktx2_texture(device, queue, loader, bytes, &key)?
                 }
             };
 
+            Ok(loader.insert(key, texture))
+        };
+
+        let load_texture = |texture: &gltf::Texture, format: TextureFormat, filter: MipFilter| {
+            let sampler = Resources::sampler_key(&texture.sampler());
+
+            // KHR_texture_basisu points to a KTX2 image, the source is then
+            // the fallback for when it can not be loaded
+            let basisu = texture
+                .extension_value("KHR_texture_basisu")
+                .and_then(|basisu| basisu.get("source")?.as_u64())
+                .and_then(|index| gltf.images().nth(index as usize));
+
+            if let Some(image) = basisu {
+                match load_image(&image, format, filter, sampler) {
+                    Ok(texture) => return Ok(texture),
+                    Err(error) => eprintln!(
+                        "Falling back to the source of texture {}: {error}",
+                        texture.index()
+                    ),
+                }
+            }
+
+            load_image(&texture.source(), format, filter, sampler)
+                .map_err(|error| format!("texture {}: {error}", texture.index()))
+        };
+
         let mut materials = Vec::new();
 
-        let load_material = |material: gltf::Material| {
+        let load_material = |material: gltf::Material| -> Result<Material, String> {
             let pbr_metallic_roughness = material.pbr_metallic_roughness();
 
             let base_color = pbr_metallic_roughness.base_color_factor();
 
             let diffuse_texture = pbr_metallic_roughness
                 .base_color_texture()
-                .map(|diffuse| load_texture(&diffuse.texture(), Texture::SRGBA_UNORM));
+                .map(|diffuse| {
+                    load_texture(&diffuse.texture(), Texture::SRGBA_UNORM, MipFilter::Color)
+                })
+                .transpose()?;
 
             let normal_texture = material
                 .normal_texture()
-                .map(|normal| load_texture(&normal.texture(), Texture::RGBA_UNORM));
+                .map(|normal| {
+                    load_texture(&normal.texture(), Texture::RGBA_UNORM, MipFilter::NormalMap)
+                })
+                .transpose()?;
 
             let metallic_factor = pbr_metallic_roughness.metallic_factor();
             let roughness_factor = pbr_metallic_roughness.roughness_factor();
@@ -219,13 +397,34 @@ impl Resources {
             let metallic_roughness_texture = material
                 .pbr_metallic_roughness()
                 .metallic_roughness_texture()
-                .map(|texture| load_texture(&texture.texture(), Texture::RGBA_UNORM));
+                .map(|texture| {
+                    load_texture(&texture.texture(), Texture::RGBA_UNORM, MipFilter::Color)
+                })
+                .transpose()?;
 
             let ambient_occlusion_texture = material
                 .occlusion_texture()
-                .map(|texture| load_texture(&texture.texture(), Texture::RGBA_UNORM));
+                .map(|texture| {
+                    load_texture(&texture.texture(), Texture::RGBA_UNORM, MipFilter::Color)
+                })
+                .transpose()?;
 
-            Material::new(
+            let emissive_texture = material
+                .emissive_texture()
+                .map(|texture| {
+                    load_texture(&texture.texture(), Texture::SRGBA_UNORM, MipFilter::Color)
+                })
+                .transpose()?;
+
+            let alpha_mode = match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => {
+                    AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5))
+                }
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            };
+
+            Ok(Material::new(
                 base_color,
                 diffuse_texture,
                 normal_texture,
@@ -233,31 +432,147 @@ impl Resources {
                 roughness_factor,
                 metallic_roughness_texture,
                 ambient_occlusion_texture,
-            )
+                material.emissive_factor(),
+                emissive_texture,
+                alpha_mode,
+                material.double_sided(),
+            ))
         };
 
         for material in gltf.materials() {
-            let material = load_material(material);
+            let material = load_material(material)?;
             materials.push(material);
         }
 
-        materials
+        Ok(materials)
     }
 
-    fn load_buffers(gltf: &gltf::Gltf, current_directory: &Path) -> Vec<Vec<u8>> {
+    /// The filters left undefined by the glTF sampler are linear.
+    fn sampler_key(sampler: &gltf::texture::Sampler) -> SamplerKey {
+        use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+        use wgpu::{AddressMode, FilterMode};
+
+        let address_mode = |wrap| match wrap {
+            WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+            WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+            WrappingMode::Repeat => AddressMode::Repeat,
+        };
+
+        let mag_filter = match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => FilterMode::Linear,
+        };
+
+        let (min_filter, mipmap_filter) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (FilterMode::Nearest, None),
+            Some(MinFilter::Linear) => (FilterMode::Linear, None),
+            Some(MinFilter::NearestMipmapNearest) => {
+                (FilterMode::Nearest, Some(FilterMode::Nearest))
+            }
+            Some(MinFilter::LinearMipmapNearest) => (FilterMode::Linear, Some(FilterMode::Nearest)),
+            Some(MinFilter::NearestMipmapLinear) => (FilterMode::Nearest, Some(FilterMode::Linear)),
+            Some(MinFilter::LinearMipmapLinear) | None => {
+                (FilterMode::Linear, Some(FilterMode::Linear))
+            }
+        };
+
+        SamplerKey {
+            address_mode_u: address_mode(sampler.wrap_s()),
+            address_mode_v: address_mode(sampler.wrap_t()),
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+        }
+    }
+
+    fn load_buffers(gltf: &gltf::Gltf, current_directory: &Path) -> Result<Vec<Vec<u8>>, String> {
         let mut buffers = Vec::new();
         for buffer in gltf.buffers() {
             let buffer_data: Vec<u8> = match buffer.source() {
-                gltf::buffer::Source::Uri(uri) => {
-                    std::fs::read(&current_directory.join(uri)).expect("Failed to load binary")
-                }
-                gltf::buffer::Source::Bin => {
-                    gltf.blob.as_deref().expect("Missing binary blob").into()
-                }
+                gltf::buffer::Source::Uri(uri) => std::fs::read(current_directory.join(uri))
+                    .map_err(|error| format!("{uri}: {error}"))?,
+                gltf::buffer::Source::Bin => gltf
+                    .blob
+                    .as_deref()
+                    .ok_or("the binary chunk is missing")?
+                    .into(),
             };
             buffers.push(buffer_data);
         }
 
-        buffers
+        Ok(buffers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_unreadable_files() {
+        let error = Resources::read_gltf(Path::new("missing.gltf")).err();
+        assert!(error.is_some());
+
+        let path = std::env::temp_dir().join("wgpu-renderer-invalid.gltf");
+        std::fs::write(&path, "{").unwrap();
+        let error = Resources::read_gltf(&path).err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.is_some());
+
+        let error = Resources::read_hdr(Path::new("missing.hdr")).err();
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn maps_the_gltf_samplers() {
+        use wgpu::{AddressMode, FilterMode};
+
+        // The wrap modes, the mag filters and then each min filter
+        let gltf = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "samplers": [
+                    { "wrapS": 33648, "wrapT": 33071, "magFilter": 9728 },
+                    { "wrapT": 10497, "magFilter": 9729 },
+                    { "minFilter": 9728 },
+                    { "minFilter": 9729 },
+                    { "minFilter": 9984 },
+                    { "minFilter": 9985 },
+                    { "minFilter": 9986 },
+                    { "minFilter": 9987 },
+                    {}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let keys = gltf
+            .samplers()
+            .map(|sampler| Resources::sampler_key(&sampler))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            (keys[0].address_mode_u, keys[0].address_mode_v),
+            (AddressMode::MirrorRepeat, AddressMode::ClampToEdge)
+        );
+        assert_eq!(keys[0].mag_filter, FilterMode::Nearest);
+        assert_eq!(
+            (keys[1].address_mode_u, keys[1].address_mode_v),
+            (AddressMode::Repeat, AddressMode::Repeat)
+        );
+        assert_eq!(keys[1].mag_filter, FilterMode::Linear);
+
+        let min_filters = [
+            (FilterMode::Nearest, None),
+            (FilterMode::Linear, None),
+            (FilterMode::Nearest, Some(FilterMode::Nearest)),
+            (FilterMode::Linear, Some(FilterMode::Nearest)),
+            (FilterMode::Nearest, Some(FilterMode::Linear)),
+            (FilterMode::Linear, Some(FilterMode::Linear)),
+            (FilterMode::Linear, Some(FilterMode::Linear)),
+        ];
+        for (key, expected) in keys[2..].iter().zip(min_filters) {
+            assert_eq!((key.min_filter, key.mipmap_filter), expected);
+        }
+        assert_eq!(keys[8], SamplerKey::default());
     }
 }
@@ -0,0 +1,188 @@
+use std::{fmt, io::Read};
+
+use ktx2::{Format, SupercompressionScheme};
+use wgpu::{AstcBlock, AstcChannel, Device, Queue, TextureFormat};
+
+use crate::texture::Texture;
+
+use super::{basis, Resources, TextureKey, TextureLoader};
+
+/// Why a KTX2 texture could not be loaded.
+#[derive(Debug)]
+pub enum Ktx2Error {
+    Parse(ktx2::ParseError),
+    /// Only single 2D images are loaded, without layers or cube faces.
+    NotA2dTexture,
+    UnsupportedSupercompression(SupercompressionScheme),
+    Decompression(String),
+    /// The container format has no wgpu equivalent.
+    UnsupportedFormat(Format),
+    /// The adapter lacks the features to sample the precompressed format.
+    MissingFeatures(TextureFormat),
+    /// The ETC1S or UASTC payload could not be transcoded.
+    Transcode(String),
+}
+
+impl fmt::Display for Ktx2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ktx2Error::Parse(error) => write!(f, "invalid KTX2 container: {error}"),
+            Ktx2Error::NotA2dTexture => write!(f, "only 2D textures are supported"),
+            Ktx2Error::UnsupportedSupercompression(scheme) => {
+                write!(f, "unsupported supercompression {scheme:?}")
+            }
+            Ktx2Error::Decompression(error) => write!(f, "Zstandard decompression: {error}"),
+            Ktx2Error::UnsupportedFormat(format) => write!(f, "unsupported format {format:?}"),
+            Ktx2Error::MissingFeatures(format) => {
+                write!(f, "the adapter can not sample {format:?}")
+            }
+            Ktx2Error::Transcode(error) => write!(f, "Basis Universal transcoding: {error}"),
+        }
+    }
+}
+
+impl Resources {
+    /// Uploads a KTX2 container as the texture of the `key`, without caching
+    /// it. Its precompressed mip levels are uploaded as they are, uncompressed
+    /// images with a single level get their mip chain generated. The format of
+    /// the container is used instead of the one of the `key`. Basis Universal
+    /// payloads are transcoded to the best format of the device, see
+    /// [basis::TranscodeTarget].
+    pub fn ktx2_texture(
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+        data: &[u8],
+        key: &TextureKey,
+    ) -> Result<Texture, Ktx2Error> {
+        let reader = ktx2::Reader::new(data).map_err(Ktx2Error::Parse)?;
+        let header = reader.header();
+
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+            return Err(Ktx2Error::NotA2dTexture);
+        }
+
+        let levels = reader
+            .levels()
+            .map(|level| match header.supercompression_scheme {
+                None | Some(SupercompressionScheme::BasisLZ) => Ok(level.data.to_vec()),
+                Some(SupercompressionScheme::Zstandard) => {
+                    let mut data = Vec::with_capacity(level.uncompressed_byte_length as usize);
+                    ruzstd::StreamingDecoder::new(level.data)
+                        .map_err(|error| Ktx2Error::Decompression(error.to_string()))?
+                        .read_to_end(&mut data)
+                        .map_err(|error| Ktx2Error::Decompression(error.to_string()))?;
+                    Ok(data)
+                }
+                Some(scheme) => Err(Ktx2Error::UnsupportedSupercompression(scheme)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Basis Universal payloads, ETC1S or UASTC, have no container format
+        let (texture_format, levels) = match header.format {
+            Some(container_format) => {
+                let texture_format = Resources::ktx2_format(container_format)?;
+                if !device
+                    .features()
+                    .contains(texture_format.required_features())
+                {
+                    return Err(Ktx2Error::MissingFeatures(texture_format));
+                }
+                (texture_format, levels)
+            }
+            None => basis::transcode(&reader, &levels, device.features())
+                .map_err(Ktx2Error::Transcode)?,
+        };
+
+        let label = key.source.to_string();
+        let sampler = loader.samplers.get(device, key.sampler);
+
+        if levels.len() == 1 && !texture_format.is_compressed() {
+            let texture = Texture::init(
+                device,
+                queue,
+                header.pixel_width,
+                header.pixel_height,
+                &levels[0],
+                Some(label.as_str()),
+                texture_format,
+                sampler,
+            );
+            texture.generate_mips(device, queue, &loader.mip_generator, key.filter);
+
+            return Ok(texture);
+        }
+
+        Ok(Texture::init_levels(
+            device,
+            queue,
+            header.pixel_width,
+            header.pixel_height,
+            &levels,
+            Some(label.as_str()),
+            texture_format,
+            sampler,
+        ))
+    }
+
+    fn ktx2_format(format: Format) -> Result<TextureFormat, Ktx2Error> {
+        let astc_4x4 = |channel| TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel,
+        };
+
+        Ok(match format {
+            Format::R8G8B8A8_UNORM => Texture::RGBA_UNORM,
+            Format::R8G8B8A8_SRGB => Texture::SRGBA_UNORM,
+            Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+            Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+            Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+            Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+            Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+            Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+            Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+            Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+            Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+            Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+            Format::ASTC_4x4_UNORM_BLOCK => astc_4x4(AstcChannel::Unorm),
+            Format::ASTC_4x4_SRGB_BLOCK => astc_4x4(AstcChannel::UnormSrgb),
+            format => return Err(Ktx2Error::UnsupportedFormat(format)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_container_formats() {
+        assert_eq!(
+            Resources::ktx2_format(Format::R8G8B8A8_SRGB).unwrap(),
+            TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(
+            Resources::ktx2_format(Format::BC7_UNORM_BLOCK).unwrap(),
+            TextureFormat::Bc7RgbaUnorm
+        );
+        assert_eq!(
+            Resources::ktx2_format(Format::ETC2_R8G8B8A8_SRGB_BLOCK).unwrap(),
+            TextureFormat::Etc2Rgba8UnormSrgb
+        );
+        assert_eq!(
+            Resources::ktx2_format(Format::ASTC_4x4_UNORM_BLOCK).unwrap(),
+            TextureFormat::Astc {
+                block: AstcBlock::B4x4,
+                channel: AstcChannel::Unorm,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_formats_without_an_equivalent() {
+        assert!(matches!(
+            Resources::ktx2_format(Format::R8_UNORM),
+            Err(Ktx2Error::UnsupportedFormat(Format::R8_UNORM))
+        ));
+    }
+}
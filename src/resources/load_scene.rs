@@ -0,0 +1,574 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use cgmath::{Deg, Euler, Quaternion};
+use ron::extensions::Extensions;
+use serde::Deserialize;
+use wgpu::{Device, Queue, TextureFormat};
+
+use crate::{
+    camera::Camera,
+    entity::{Entity, Geometry, Mesh, Node},
+    layouts::Layouts,
+    light::PointLight,
+    material::{AlphaMode, Material, MaterialOverrides},
+    scene::Scene,
+    texture::{MipFilter, SamplerKey, Texture},
+    transform::Transform,
+};
+
+use super::{AssetId, AssetServer, Resources, TextureLoader};
+
+/// A scene described in a RON file: its entities, lights, skybox and initial
+/// camera. The paths are relative to the file. See `scenes/default.ron`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    #[serde(default)]
+    camera: CameraDescription,
+    /// Only the placeholder environment is shown without it.
+    skybox: Option<SkyboxDescription>,
+    #[serde(default)]
+    entities: Vec<EntityDescription>,
+    #[serde(default)]
+    lights: Vec<PointLight>,
+    /// Frames the camera on the first model once it is loaded, for the models
+    /// shown without a scene file.
+    #[serde(skip)]
+    frame: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDescription {
+    position: (f32, f32, f32),
+    /// In degrees, -90 looks towards -z.
+    yaw: f32,
+    /// In degrees.
+    pitch: f32,
+    /// Vertical field of view in degrees.
+    fovy: f32,
+    near: f32,
+    far: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        CameraDescription {
+            position: (0.0, 2.0, 3.0),
+            yaw: -90.0,
+            pitch: 0.0,
+            fovy: 45.0,
+            near: 0.01,
+            far: 100.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyboxDescription {
+    /// An equirectangular HDR image.
+    path: PathBuf,
+    /// Size of the faces of the environment cube map.
+    #[serde(default = "SkyboxDescription::default_size")]
+    size: u32,
+}
+
+impl SkyboxDescription {
+    fn default_size() -> u32 {
+        512
+    }
+}
+
+/// A model placed at each of its transforms, the instances sharing its meshes
+/// and materials.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntityDescription {
+    model: ModelDescription,
+    /// Replaces parameters of every material of a glTF model, or describes the
+    /// material of a built in geometry, a white dielectric by default.
+    material: Option<MaterialDescription>,
+    #[serde(default = "EntityDescription::default_transforms")]
+    transforms: Vec<TransformDescription>,
+}
+
+impl EntityDescription {
+    fn default_transforms() -> Vec<TransformDescription> {
+        vec![TransformDescription::default()]
+    }
+}
+
+#[derive(Deserialize)]
+enum ModelDescription {
+    /// A glTF or GLB file, loaded in the background.
+    Gltf(PathBuf),
+    Cube,
+    Plane,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TransformDescription {
+    translation: (f32, f32, f32),
+    /// Euler angles in degrees, rotating around x, then y, then z.
+    rotation: (f32, f32, f32),
+    scale: (f32, f32, f32),
+}
+
+impl Default for TransformDescription {
+    fn default() -> Self {
+        TransformDescription {
+            translation: (0.0, 0.0, 0.0),
+            rotation: (0.0, 0.0, 0.0),
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl From<&TransformDescription> for Transform {
+    fn from(description: &TransformDescription) -> Self {
+        let (x, y, z) = description.rotation;
+        let rotation = Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)));
+
+        Transform::new(description.translation, rotation, description.scale)
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDescription {
+    base_color: Option<[f32; 4]>,
+    base_texture: Option<PathBuf>,
+    normal_texture: Option<PathBuf>,
+    metallic: Option<f32>,
+    roughness: Option<f32>,
+    metallic_roughness_texture: Option<PathBuf>,
+    ambient_occlusion_texture: Option<PathBuf>,
+    emissive: Option<[f32; 3]>,
+    emissive_texture: Option<PathBuf>,
+    alpha_mode: Option<AlphaMode>,
+    double_sided: Option<bool>,
+}
+
+impl MaterialDescription {
+    fn overrides(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+    ) -> Result<MaterialOverrides, String> {
+        let texture = |path: &Option<PathBuf>, format: TextureFormat, filter: MipFilter| {
+            path.as_ref()
+                .map(|path| {
+                    Resources::load_texture(
+                        device,
+                        queue,
+                        loader,
+                        path,
+                        format,
+                        filter,
+                        SamplerKey::default(),
+                    )
+                })
+                .transpose()
+        };
+
+        Ok(MaterialOverrides {
+            base_color: self.base_color,
+            base_texture: texture(&self.base_texture, Texture::SRGBA_UNORM, MipFilter::Color)?,
+            normal_texture: texture(
+                &self.normal_texture,
+                Texture::RGBA_UNORM,
+                MipFilter::NormalMap,
+            )?,
+            metallic_factor: self.metallic,
+            roughness_factor: self.roughness,
+            metallic_roughness_texture: texture(
+                &self.metallic_roughness_texture,
+                Texture::RGBA_UNORM,
+                MipFilter::Color,
+            )?,
+            ambient_occlussion_texture: texture(
+                &self.ambient_occlusion_texture,
+                Texture::RGBA_UNORM,
+                MipFilter::Color,
+            )?,
+            emissive_factor: self.emissive,
+            emissive_texture: texture(
+                &self.emissive_texture,
+                Texture::SRGBA_UNORM,
+                MipFilter::Color,
+            )?,
+            alpha_mode: self.alpha_mode,
+            double_sided: self.double_sided,
+        })
+    }
+}
+
+/// The content of a scene file once loaded. The glTF models are requested
+/// from the asset server, to be placed in the scene when they are loaded.
+pub struct LoadedScene {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub models: Vec<ModelRequest>,
+    /// The model to frame the camera on once loaded.
+    pub framed: Option<AssetId>,
+}
+
+pub struct ModelRequest {
+    pub id: AssetId,
+    pub transforms: Vec<Transform>,
+    pub materials: Option<MaterialOverrides>,
+}
+
+impl Resources {
+    /// Reads a scene file, or a glTF or GLB model to show on its own, with
+    /// the `environment` replacing its skybox. Checks the files it refers to
+    /// exist, so the errors are reported before anything is loaded.
+    pub fn read_scene(path: &Path, environment: Option<&Path>) -> Result<SceneFile, String> {
+        let is_model = path.extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+        });
+
+        let scene = match is_model {
+            true => SceneFile::model(path),
+            false => {
+                let source = fs::read_to_string(path)
+                    .map_err(|error| format!("Can not read {}: {error}", path.display()))?;
+                SceneFile::parse(&source, path)?
+            }
+        };
+
+        scene.check(path, environment)
+    }
+}
+
+impl SceneFile {
+    /// Parses the `source` of the scene file at `path`, with its paths made
+    /// relative to the working directory.
+    fn parse(source: &str, path: &Path) -> Result<SceneFile, String> {
+        let mut scene: SceneFile = ron::Options::default()
+            .with_default_extension(Extensions::IMPLICIT_SOME)
+            .from_str(source)
+            .map_err(|error| {
+                let position = error.position;
+                format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    position.line,
+                    position.col,
+                    error.code
+                )
+            })?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for (_, file) in scene.paths() {
+            *file = directory.join(&*file);
+        }
+
+        Ok(scene)
+    }
+
+    /// Replaces the skybox by the `environment`, and reports every missing
+    /// file and invalid field of the scene file at `path` at once.
+    fn check(mut self, path: &Path, environment: Option<&Path>) -> Result<SceneFile, String> {
+        let mut errors = Vec::new();
+
+        for (field, file) in self.paths() {
+            if !file.is_file() {
+                errors.push(format!("{field}: there is no file {}", file.display()));
+            }
+        }
+
+        if let Some(environment) = environment {
+            if !environment.is_file() {
+                errors.push(format!("--env: there is no file {}", environment.display()));
+            }
+
+            let size = self
+                .skybox
+                .as_ref()
+                .map_or(SkyboxDescription::default_size(), |skybox| skybox.size);
+            self.skybox = Some(SkyboxDescription {
+                path: environment.to_path_buf(),
+                size,
+            });
+        }
+
+        if self.lights.len() > Layouts::LIGHTS as usize {
+            errors.push(format!(
+                "lights: the renderer binds at most {} point lights, the scene has {}",
+                Layouts::LIGHTS,
+                self.lights.len()
+            ));
+        }
+
+        if self.skybox.as_ref().is_some_and(|skybox| skybox.size == 0) {
+            errors.push("skybox.size: the faces can not be empty".to_string());
+        }
+
+        match errors.is_empty() {
+            true => Ok(self),
+            false => Err(errors
+                .iter()
+                .map(|error| format!("{}: {error}", path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")),
+        }
+    }
+
+    /// The model at the origin, lit by white lights around it.
+    fn model(path: &Path) -> SceneFile {
+        let light = |position| PointLight {
+            position,
+            color: (150.0, 150.0, 150.0),
+        };
+
+        SceneFile {
+            camera: CameraDescription::default(),
+            skybox: None,
+            entities: vec![EntityDescription {
+                model: ModelDescription::Gltf(path.to_path_buf()),
+                material: None,
+                transforms: EntityDescription::default_transforms(),
+            }],
+            lights: vec![
+                light((5.0, 5.0, 5.0)),
+                light((-5.0, 4.0, 2.0)),
+                light((0.0, 5.0, -5.0)),
+            ],
+            frame: true,
+        }
+    }
+
+    /// Creates the entities of the built in geometry and the lights, and
+    /// requests the models and the skybox. Fails when a texture of a material
+    /// can not be loaded.
+    pub fn load(
+        self,
+        device: &Device,
+        queue: &Queue,
+        texture_loader: &TextureLoader,
+        asset_server: &mut AssetServer,
+        aspect: f32,
+    ) -> Result<LoadedScene, String> {
+        let CameraDescription {
+            position,
+            yaw,
+            pitch,
+            fovy,
+            near,
+            far,
+        } = self.camera;
+        let camera = Camera::new(position, Deg(yaw), Deg(pitch), fovy, aspect, near, far);
+
+        let mut entities = Vec::new();
+        let mut models = Vec::new();
+
+        for description in &self.entities {
+            let transforms = description.transforms.iter().map(Transform::from);
+            let materials = description
+                .material
+                .as_ref()
+                .map(|material| material.overrides(device, queue, texture_loader))
+                .transpose()?;
+
+            let geometry = match &description.model {
+                ModelDescription::Gltf(path) => {
+                    models.push(ModelRequest {
+                        id: asset_server.load_gltf(path),
+                        transforms: transforms.collect(),
+                        materials,
+                    });
+                    continue;
+                }
+                ModelDescription::Cube => Geometry::cube(),
+                ModelDescription::Plane => Geometry::plane(),
+            };
+
+            let material = Material::new(
+                [1.0, 1.0, 1.0, 1.0],
+                None,
+                None,
+                0.0,
+                1.0,
+                None,
+                None,
+                [0.0, 0.0, 0.0],
+                None,
+                AlphaMode::Opaque,
+                false,
+            );
+            let material = match materials {
+                Some(overrides) => overrides.apply(&material),
+                None => material,
+            };
+
+            let entity = Entity::new(
+                vec![Node {
+                    mesh: Some(Arc::new(Mesh {
+                        primitives: vec![(geometry, 0)],
+                    })),
+                    transform: Transform::zero(),
+                    children: Vec::new(),
+                }],
+                vec![material],
+                Transform::zero(),
+            );
+            entities.extend(transforms.map(|transform| entity.instantiate(transform)));
+        }
+
+        if let Some(skybox) = &self.skybox {
+            asset_server.load_skybox(&skybox.path, skybox.size);
+        }
+
+        let scene = Scene {
+            entities,
+            lights: self.lights,
+            skybox: asset_server.placeholder_skybox(device, queue, texture_loader),
+        };
+
+        let framed = models.first().filter(|_| self.frame).map(|model| model.id);
+
+        Ok(LoadedScene {
+            scene,
+            camera,
+            models,
+            framed,
+        })
+    }
+
+    /// Every path of the file, with the field it is in.
+    fn paths(&mut self) -> Vec<(String, &mut PathBuf)> {
+        let mut paths = Vec::new();
+
+        if let Some(skybox) = &mut self.skybox {
+            paths.push(("skybox.path".to_string(), &mut skybox.path));
+        }
+
+        for (index, entity) in self.entities.iter_mut().enumerate() {
+            if let ModelDescription::Gltf(path) = &mut entity.model {
+                paths.push((format!("entities[{index}].model"), path));
+            }
+
+            let Some(material) = &mut entity.material else {
+                continue;
+            };
+            let textures = [
+                ("base_texture", &mut material.base_texture),
+                ("normal_texture", &mut material.normal_texture),
+                (
+                    "metallic_roughness_texture",
+                    &mut material.metallic_roughness_texture,
+                ),
+                (
+                    "ambient_occlusion_texture",
+                    &mut material.ambient_occlusion_texture,
+                ),
+                ("emissive_texture", &mut material.emissive_texture),
+            ];
+            for (name, texture) in textures {
+                if let Some(path) = texture {
+                    paths.push((format!("entities[{index}].material.{name}"), path));
+                }
+            }
+        }
+
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the `source` as the scene file `scenes/test.ron`.
+    fn read(source: &str) -> Result<SceneFile, String> {
+        let path = Path::new("scenes/test.ron");
+        SceneFile::parse(source, path)?.check(path, None)
+    }
+
+    fn read_error(source: &str) -> String {
+        read(source).err().expect("The scene file is invalid")
+    }
+
+    #[test]
+    fn parses_the_default_scene() {
+        let path = Path::new("scenes/default.ron");
+        let source = fs::read_to_string(path).unwrap();
+        if let Err(error) = SceneFile::parse(&source, path) {
+            panic!("{error}");
+        }
+    }
+
+    #[test]
+    fn reports_where_the_syntax_is_invalid() {
+        let error = read_error("(\n    camera: (\n        yaw: -90.0,,\n    ),\n)");
+        assert_eq!(error, "scenes/test.ron:3:20: Expected identifier");
+    }
+
+    #[test]
+    fn reports_unknown_fields() {
+        let error = read_error("(camera: (zoom: 2.0))");
+        assert!(error.contains("zoom"), "{error}");
+    }
+
+    #[test]
+    fn reports_every_missing_file() {
+        let error = Resources::read_scene(Path::new("scenes/missing.ron"), None)
+            .err()
+            .expect("There is no scene file");
+        assert!(
+            error.starts_with("Can not read scenes/missing.ron"),
+            "{error}"
+        );
+
+        let error = read_error(
+            "(
+                entities: [
+                    (model: Gltf(\"missing.gltf\")),
+                    (model: Cube, material: (normal_texture: \"missing.png\")),
+                    (model: Plane, material: (emissive: (1.0, 0.5, 0.0), emissive_texture: \"glow.png\")),
+                ],
+            )",
+        );
+        assert_eq!(
+            error,
+            "scenes/test.ron: entities[0].model: there is no file scenes/missing.gltf\n\
+             scenes/test.ron: entities[1].material.normal_texture: there is no file scenes/missing.png\n\
+             scenes/test.ron: entities[2].material.emissive_texture: there is no file scenes/glow.png"
+        );
+    }
+
+    #[test]
+    fn binds_up_to_a_shadow_map_per_light() {
+        let light = "(position: (0.0, 5.0, 0.0), color: (1.0, 1.0, 1.0))";
+        for count in 0..=Layouts::LIGHTS as usize {
+            let lights = vec![light; count].join(", ");
+            let scene = read(&format!("(lights: [{lights}])")).unwrap();
+            assert_eq!(scene.lights.len(), count);
+        }
+
+        let lights = [light; 4].join(", ");
+        assert_eq!(
+            read_error(&format!("(lights: [{lights}])")),
+            "scenes/test.ron: lights: the renderer binds at most 3 point lights, the scene has 4"
+        );
+    }
+
+    #[test]
+    fn rejects_empty_skybox_faces() {
+        let error = read_error("(skybox: (path: \"../assets/skybox/studio_2k.hdr\", size: 0))");
+        assert!(
+            error
+                .lines()
+                .any(|line| line == "scenes/test.ron: skybox.size: the faces can not be empty"),
+            "{error}"
+        );
+    }
+}
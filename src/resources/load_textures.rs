@@ -1,62 +1,119 @@
-use std::path::Path;
+use std::{fmt::Display, path::Path, sync::Arc};
 
-use image::io::Reader;
+use image::{io::Reader, DynamicImage};
 use wgpu::{Device, Queue, TextureFormat};
 
-use crate::texture::Texture;
+use crate::texture::{MipFilter, SamplerKey, Texture};
 
-use super::Resources;
+use super::{Resources, TextureKey, TextureLoader, TextureSource};
+
+/// The RGBA texels of a decoded HDR image.
+pub struct HdrImage {
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
 
 impl Resources {
+    /// Loads an image with its full mip chain, downsampled with the `filter`,
+    /// and the cached sampler of the `sampler` state. KTX2 files keep their
+    /// own mip levels, see [Resources::ktx2_texture]. Returns the cached
+    /// texture when the same file was already loaded the same way, or why the
+    /// file could not be loaded.
     pub fn load_texture(
         device: &Device,
         queue: &Queue,
+        loader: &TextureLoader,
         path: &Path,
         format: TextureFormat,
-    ) -> Texture {
-        let image = Reader::open(path).unwrap().decode().unwrap();
+        filter: MipFilter,
+        sampler: SamplerKey,
+    ) -> Result<Arc<Texture>, String> {
+        let key = TextureKey {
+            source: TextureSource::file(path),
+            format,
+            filter,
+            sampler,
+        };
+
+        if let Some(texture) = loader.get(&key) {
+            return Ok(texture);
+        }
+
+        let with_path = |error: &dyn Display| format!("{}: {error}", path.display());
 
+        let texture = if path
+            .extension()
+            .is_some_and(|extension| extension == "ktx2")
+        {
+            let data = std::fs::read(path).map_err(|error| with_path(&error))?;
+            Resources::ktx2_texture(device, queue, loader, &data, &key)
+                .map_err(|error| with_path(&error))?
+        } else {
+            let image = Reader::open(path)
+                .map_err(|error| with_path(&error))?
+                .decode()
+                .map_err(|error| with_path(&error))?;
+            Resources::image_texture(device, queue, loader, &image, &key)
+        };
+
+        Ok(loader.insert(key, texture))
+    }
+
+    /// Uploads a decoded image as the texture of the `key`, without caching it.
+    pub fn image_texture(
+        device: &Device,
+        queue: &Queue,
+        loader: &TextureLoader,
+        image: &DynamicImage,
+        key: &TextureKey,
+    ) -> Texture {
         let width = image.width();
         let height = image.height();
 
         let data = image.to_rgba8();
 
-        let label = format!("{}", path.display());
+        let label = key.source.to_string();
 
-        Texture::init(
+        let texture = Texture::init(
             device,
             queue,
             width,
             height,
             &data,
             Some(label.as_str()),
-            format,
-        )
-    }
-
-    pub fn load_hdr_texture(device: &Device, queue: &Queue, path: &Path) -> Texture {
-        let image = Reader::open(path).unwrap().decode().unwrap();
-
-        let width = image.width();
-        let height = image.height();
+            key.format,
+            loader.samplers.get(device, key.sampler),
+        );
+        texture.generate_mips(device, queue, &loader.mip_generator, key.filter);
 
-        let data = image.to_rgba32f().to_vec();
+        texture
+    }
 
-        let data = unsafe {
-            let len = data.len() * std::mem::size_of::<f32>();
-            let ptr = data.as_ptr() as *const u8;
-            std::slice::from_raw_parts(ptr, len)
-        };
+    /// Decodes an HDR image, which can be done on any thread.
+    pub fn read_hdr(path: &Path) -> Result<HdrImage, String> {
+        let image = Reader::open(path)
+            .map_err(|error| error.to_string())?
+            .decode()
+            .map_err(|error| error.to_string())?;
 
-        let label = format!("{}", path.display());
+        Ok(HdrImage {
+            label: format!("{}", path.display()),
+            width: image.width(),
+            height: image.height(),
+            data: image.to_rgba32f().to_vec(),
+        })
+    }
 
+    pub fn hdr_texture(device: &Device, queue: &Queue, image: &HdrImage) -> Texture {
         Texture::init_hdr(
             device,
             queue,
-            width,
-            height,
-            data,
-            Some(label.as_str()),
+            image.width,
+            image.height,
+            bytemuck::cast_slice(&image.data),
+            Some(image.label.as_str()),
             Texture::RGBA_32_FLOAT,
         )
     }
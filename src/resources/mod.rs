@@ -1,9 +1,22 @@
+mod asset_server;
+mod basis;
 mod hdr_loader;
 mod irr_map_generator;
 mod load_gltf;
+mod load_ktx2;
+mod load_scene;
 mod load_textures;
 mod skybox_loader;
+mod texture_loader;
 
 pub struct Resources;
 
-pub use {hdr_loader::HdrLoader, skybox_loader::SkyboxLoader};
+pub use {
+    asset_server::{Asset, AssetId, AssetServer, LoadEvent, LoadProgress},
+    hdr_loader::HdrLoader,
+    load_gltf::GltfData,
+    load_scene::{LoadedScene, SceneFile},
+    load_textures::HdrImage,
+    skybox_loader::SkyboxLoader,
+    texture_loader::{TextureKey, TextureLoader, TextureSource},
+};
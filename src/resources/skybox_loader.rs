@@ -1,10 +1,8 @@
-use std::path::Path;
-
 use wgpu::{Device, Queue};
 
-use crate::skybox::Skybox;
+use crate::{shader_library::ShaderLibrary, skybox::Skybox, texture::MipGenerator};
 
-use super::{irr_map_generator::IrrMapGenerator, HdrLoader, Resources};
+use super::{irr_map_generator::IrrMapGenerator, HdrImage, HdrLoader, Resources};
 
 pub struct SkyboxLoader {
     hdr_loader: HdrLoader,
@@ -12,9 +10,9 @@ pub struct SkyboxLoader {
 }
 
 impl SkyboxLoader {
-    pub fn new(device: &Device) -> SkyboxLoader {
-        let hdr_loader = HdrLoader::new(device);
-        let irr_generator = IrrMapGenerator::new(device);
+    pub fn new(device: &Device, shaders: &ShaderLibrary) -> SkyboxLoader {
+        let hdr_loader = HdrLoader::new(device, shaders);
+        let irr_generator = IrrMapGenerator::new(device, shaders);
 
         SkyboxLoader {
             hdr_loader,
@@ -22,12 +20,36 @@ impl SkyboxLoader {
         }
     }
 
-    pub fn load(&self, device: &Device, queue: &Queue, path: &Path, dst_size: u32) -> Skybox {
-        let hdr_texture = Resources::load_hdr_texture(device, queue, path);
+    /// A uniformly lit skybox, shown until the actual one is loaded.
+    pub fn placeholder(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        mip_generator: &MipGenerator,
+    ) -> Skybox {
+        let image = HdrImage {
+            label: String::from("Placeholder skybox"),
+            width: 1,
+            height: 1,
+            data: vec![0.2, 0.2, 0.2, 1.0],
+        };
+        self.create(device, queue, mip_generator, &image, 16)
+    }
 
-        let env_map = self
-            .hdr_loader
-            .generate(device, queue, &hdr_texture, dst_size);
+    /// Creates the skybox of an already decoded image.
+    pub fn create(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        mip_generator: &MipGenerator,
+        image: &HdrImage,
+        dst_size: u32,
+    ) -> Skybox {
+        let hdr_texture = Resources::hdr_texture(device, queue, image);
+
+        let env_map =
+            self.hdr_loader
+                .generate(device, queue, mip_generator, &hdr_texture, dst_size);
         let irr_map = self
             .irr_generator
             .generate(device, queue, &env_map, dst_size);
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use wgpu::{Device, Queue, TextureFormat};
+
+use crate::{
+    file_watcher::{canonicalize, FileWatcher},
+    shader_library::ShaderLibrary,
+    stats::TextureMemory,
+    texture::{MipFilter, MipGenerator, SamplerCache, SamplerKey, Texture},
+};
+
+/// Where the data of a texture comes from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TextureSource {
+    /// Canonicalized, so different spellings of a path are the same file.
+    File(PathBuf),
+    /// An image embedded in a buffer view of a glTF file.
+    BufferView { gltf: PathBuf, view: usize },
+}
+
+impl TextureSource {
+    pub fn file(path: &Path) -> TextureSource {
+        TextureSource::File(canonicalize(path))
+    }
+
+    pub fn buffer_view(gltf: &Path, view: usize) -> TextureSource {
+        TextureSource::BufferView {
+            gltf: canonicalize(gltf),
+            view,
+        }
+    }
+
+    /// Whether this is an image embedded in the glTF file at `path`.
+    pub fn is_embedded_in(&self, path: &Path) -> bool {
+        match self {
+            TextureSource::BufferView { gltf, .. } => *gltf == canonicalize(path),
+            TextureSource::File(_) => false,
+        }
+    }
+}
+
+impl Display for TextureSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureSource::File(path) => write!(f, "{}", path.display()),
+            TextureSource::BufferView { gltf, view } => {
+                write!(f, "{} (buffer view {view})", gltf.display())
+            }
+        }
+    }
+}
+
+/// Identifies a loaded texture in the [TextureLoader]. Besides the source and
+/// the format, the mip filter changes the content and the sampler is owned by
+/// the texture, so they are part of it too.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureKey {
+    pub source: TextureSource,
+    pub format: TextureFormat,
+    pub filter: MipFilter,
+    pub sampler: SamplerKey,
+}
+
+/// What the loaded textures share: the generator of their mip chains, their
+/// samplers, and the textures already loaded so each source is only decoded
+/// and uploaded once. Their files are watched to reload them.
+pub struct TextureLoader {
+    pub mip_generator: MipGenerator,
+    pub samplers: SamplerCache,
+    textures: Mutex<HashMap<TextureKey, Arc<Texture>>>,
+    watcher: Arc<FileWatcher>,
+}
+
+impl TextureLoader {
+    /// See [SamplerCache::new] for the `anisotropy`.
+    pub fn new(
+        device: &Device,
+        shaders: &ShaderLibrary,
+        anisotropy: u16,
+        watcher: Arc<FileWatcher>,
+    ) -> TextureLoader {
+        TextureLoader {
+            mip_generator: MipGenerator::new(device, shaders),
+            samplers: SamplerCache::new(anisotropy),
+            textures: Mutex::new(HashMap::new()),
+            watcher,
+        }
+    }
+
+    pub fn get(&self, key: &TextureKey) -> Option<Arc<Texture>> {
+        self.textures.lock().unwrap().get(key).cloned()
+    }
+
+    /// Returns the texture already cached under the `key` instead, if it was
+    /// loaded in the meantime.
+    pub fn insert(&self, key: TextureKey, texture: Texture) -> Arc<Texture> {
+        if let TextureSource::File(path) = &key.source {
+            self.watcher.watch(path);
+        }
+
+        self.textures
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(texture))
+            .clone()
+    }
+
+    /// Rewrites the textures loaded from the file at `path` in place, so the
+    /// materials using them see the change. Returns whether any was.
+    pub fn reload(&self, device: &Device, queue: &Queue, path: &Path) -> bool {
+        let source = TextureSource::file(path);
+        let textures = self
+            .textures
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.source == source)
+            .map(|(key, texture)| (key.clone(), texture.clone()))
+            .collect::<Vec<_>>();
+
+        if textures.is_empty() {
+            return false;
+        }
+
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "ktx2")
+        {
+            eprintln!("{source}: KTX2 textures are not reloaded");
+            return true;
+        }
+
+        let image = match image::open(path) {
+            Ok(image) => image.to_rgba8(),
+            Err(error) => {
+                eprintln!("{source}: {error}");
+                return true;
+            }
+        };
+
+        for (key, texture) in textures {
+            let size = texture.size();
+            if (size.width, size.height) != image.dimensions() {
+                eprintln!("{source}: the size changed, restart to see it");
+                continue;
+            }
+
+            texture.write(queue, &image);
+            texture.generate_mips(device, queue, &self.mip_generator, key.filter);
+        }
+
+        true
+    }
+
+    /// Drops the textures of the images embedded in the glTF file at `path`,
+    /// so they are decoded again when the changed file is loaded.
+    pub fn evict_buffer_views(&self, path: &Path) {
+        self.textures
+            .lock()
+            .unwrap()
+            .retain(|key, _| !key.source.is_embedded_in(path));
+    }
+
+    /// The GPU memory taken by every cached texture.
+    pub fn memory_usage(&self) -> TextureMemory {
+        let textures = self.textures.lock().unwrap();
+
+        let mut usage = textures
+            .iter()
+            .map(|(key, texture)| {
+                (
+                    format!("{} {:?}", key.source, key.format),
+                    texture.memory_usage(),
+                )
+            })
+            .collect::<Vec<_>>();
+        usage.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        TextureMemory { textures: usage }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_views_are_embedded_in_their_gltf() {
+        let changed = Path::new("changed.gltf");
+
+        assert!(TextureSource::buffer_view(changed, 0).is_embedded_in(changed));
+        assert!(TextureSource::buffer_view(changed, 1).is_embedded_in(changed));
+        assert!(!TextureSource::buffer_view(Path::new("other.gltf"), 0).is_embedded_in(changed));
+        assert!(!TextureSource::file(changed).is_embedded_in(changed));
+    }
+}
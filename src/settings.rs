@@ -0,0 +1,390 @@
+use std::path::PathBuf;
+
+use wgpu::{Backends, PresentMode};
+
+/// How blended materials are composited over the opaque geometry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransparencyMode {
+    /// Objects are sorted back to front and blended in that order.
+    #[default]
+    Sorted,
+    /// Weighted blended order independent transparency, for scenes with many
+    /// overlapping translucent surfaces where per object sorting falls short.
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> TransparencyMode {
+        match self {
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+        }
+    }
+}
+
+/// How the opaque objects are shaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadingPath {
+    /// Every object is shaded while it is drawn, by every light.
+    #[default]
+    Forward,
+    /// The objects are drawn into a G-buffer first and shaded once per pixel,
+    /// for scenes with many lights.
+    Deferred,
+}
+
+/// Screen space ambient occlusion of the opaque geometry. Off by default, the
+/// forward path pays for a depth prepass to compute it.
+#[derive(Clone, Copy, Debug)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// World space radius of the sampled hemisphere.
+    pub radius: f32,
+    /// Exponent applied to the visibility, higher darkens the occlusion.
+    pub intensity: f32,
+    /// Samples per pixel, up to 64.
+    pub sample_count: u32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        SsaoSettings {
+            enabled: false,
+            radius: 0.5,
+            intensity: 1.5,
+            sample_count: 16,
+        }
+    }
+}
+
+/// Screen space reflections of the opaque geometry.
+#[derive(Clone, Copy, Debug)]
+pub struct SsrSettings {
+    pub enabled: bool,
+    /// Iterations of the traversal of the depth pyramid per ray.
+    pub max_steps: u32,
+    /// View space distance a hit can be behind the depth buffer.
+    pub thickness: f32,
+    /// View space length of the rays.
+    pub max_distance: f32,
+    /// Rougher surfaces only reflect the environment cube map.
+    pub max_roughness: f32,
+    /// Blur radius in pixels at the maximum roughness.
+    pub blur_radius: f32,
+}
+
+impl Default for SsrSettings {
+    fn default() -> Self {
+        SsrSettings {
+            enabled: true,
+            max_steps: 64,
+            thickness: 0.3,
+            max_distance: 20.0,
+            max_roughness: 0.6,
+            blur_radius: 12.0,
+        }
+    }
+}
+
+/// Renderer options chosen at startup.
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    /// Can not be changed once the renderer is created.
+    pub shading: ShadingPath,
+    pub transparency: TransparencyMode,
+    /// Culls the opaque objects in a compute pass and draws them with indirect
+    /// commands, when the adapter supports `INDIRECT_FIRST_INSTANCE`.
+    pub gpu_culling: bool,
+    /// Binds every material at once in a single table indexed by material id,
+    /// when the adapter can index texture arrays. Otherwise each material has
+    /// its own bind group.
+    pub bindless_materials: bool,
+    /// Writes the depth of the opaque objects before shading them, so only the
+    /// visible fragments are shaded. Only used by the forward path.
+    pub depth_prepass: bool,
+    /// The forward path needs the depth prepass for it, which is then always
+    /// drawn.
+    pub ssao: SsaoSettings,
+    pub ssr: SsrSettings,
+    /// Renders the shadow maps of the lights and shades with them.
+    pub shadows: bool,
+    /// Anisotropic filtering of the loaded textures, from 1 (off) to 16. Only
+    /// read at startup.
+    pub anisotropy: u16,
+    /// Reads the shaders from the source tree instead of the built in ones,
+    /// and rebuilds the pipelines when they change.
+    pub dev_shaders: bool,
+    /// The scene file, or glTF or GLB model, to show. Read at startup.
+    pub scene: PathBuf,
+    /// An HDR environment map replacing the skybox of the scene.
+    pub environment: Option<PathBuf>,
+    pub display: DisplaySettings,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            shading: ShadingPath::default(),
+            transparency: TransparencyMode::default(),
+            gpu_culling: true,
+            bindless_materials: true,
+            depth_prepass: false,
+            ssao: SsaoSettings::default(),
+            ssr: SsrSettings::default(),
+            shadows: true,
+            anisotropy: 16,
+            dev_shaders: false,
+            scene: PathBuf::from("./scenes/default.ron"),
+            environment: None,
+            display: DisplaySettings::default(),
+        }
+    }
+}
+
+/// The window and the GPU the renderer runs on, chosen at startup.
+#[derive(Clone, Debug)]
+pub struct DisplaySettings {
+    /// Inner size of the window, in physical pixels.
+    pub size: (u32, u32),
+    /// Borderless, on the current monitor.
+    pub fullscreen: bool,
+    /// Falls back to `Fifo` when the surface does not support it.
+    pub present_mode: PresentMode,
+    /// Samples per pixel of the forward shading path, from the skybox to the
+    /// transparent objects. They are resolved into the HDR texture, and the
+    /// depth into a single sampled one for the screen space passes.
+    pub msaa: u32,
+    pub backends: Backends,
+    /// The index of the adapter in the list printed by `--list-adapters`,
+    /// otherwise the high performance one is picked.
+    pub adapter: Option<usize>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            size: (1024, 768),
+            fullscreen: false,
+            present_mode: PresentMode::Fifo,
+            msaa: 1,
+            backends: Backends::all(),
+            adapter: None,
+        }
+    }
+}
+
+/// What the binary is asked to do by its command line arguments.
+pub enum Command {
+    View(RenderSettings),
+    ListAdapters(Backends),
+    Help,
+}
+
+impl Command {
+    pub const USAGE: &'static str = "\
+Usage: wgpu-renderer [OPTIONS] [FILE]
+
+Shows FILE, a RON scene file or a glTF or GLB model, ./scenes/default.ron by
+default.
+
+Options:
+  --env <HDR>              Equirectangular HDR environment replacing the skybox
+  --size <WIDTHxHEIGHT>    Window size in pixels [default: 1024x768]
+  --fullscreen             Borderless fullscreen window
+  --present-mode <MODE>    fifo, fifo-relaxed, mailbox, immediate, auto-vsync or
+                           auto-no-vsync [default: fifo]
+  --no-vsync               Same as --present-mode auto-no-vsync
+  --msaa <SAMPLES>         Samples per pixel, 1, 2, 4 or 8 [default: 1]
+  --backend <BACKEND>      vulkan, gl, metal, dx12 or all [default: all]
+  --adapter <INDEX>        Adapter to render with, see --list-adapters
+  --list-adapters          Prints the adapters of the backend and exits
+  --deferred               Deferred shading path
+  --dev-shaders            Reloads the shaders from the source tree on change
+  -h, --help               Prints this message and exits";
+
+    /// Parses the arguments, without the name of the binary.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+        let mut settings = RenderSettings::default();
+        let mut scene = None;
+        let mut list_adapters = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{arg} needs a value, see --help"))
+            };
+
+            let display = &mut settings.display;
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--list-adapters" => list_adapters = true,
+                "--deferred" => settings.shading = ShadingPath::Deferred,
+                "--dev-shaders" => settings.dev_shaders = true,
+                "--env" => settings.environment = Some(PathBuf::from(value()?)),
+                "--size" => display.size = parse_size(&value()?)?,
+                "--fullscreen" => display.fullscreen = true,
+                "--present-mode" => display.present_mode = parse_present_mode(&value()?)?,
+                "--no-vsync" => display.present_mode = PresentMode::AutoNoVsync,
+                "--msaa" => display.msaa = parse_msaa(&value()?)?,
+                "--backend" => display.backends = parse_backends(&value()?)?,
+                "--adapter" => {
+                    let index = value()?;
+                    let index = index
+                        .parse()
+                        .map_err(|_| format!("--adapter: {index} is not an adapter index"))?;
+                    display.adapter = Some(index);
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(format!("Unknown option {arg}, see --help"));
+                }
+                _ if scene.is_some() => {
+                    return Err(format!("Only one file can be shown, {arg} is one too many"));
+                }
+                _ => scene = Some(PathBuf::from(arg)),
+            }
+        }
+
+        if settings.display.msaa > 1 && settings.shading == ShadingPath::Deferred {
+            return Err("--msaa: the deferred shading path draws a single sample per pixel".into());
+        }
+
+        if list_adapters {
+            return Ok(Command::ListAdapters(settings.display.backends));
+        }
+
+        if let Some(scene) = scene {
+            settings.scene = scene;
+        }
+
+        Ok(Command::View(settings))
+    }
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|(width, height)| *width > 0 && *height > 0)
+        .ok_or_else(|| format!("--size: {size} is not a size like 1280x720"))
+}
+
+fn parse_present_mode(mode: &str) -> Result<PresentMode, String> {
+    match mode {
+        "fifo" => Ok(PresentMode::Fifo),
+        "fifo-relaxed" => Ok(PresentMode::FifoRelaxed),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        "auto-vsync" => Ok(PresentMode::AutoVsync),
+        "auto-no-vsync" => Ok(PresentMode::AutoNoVsync),
+        _ => Err(format!("--present-mode: unknown present mode {mode}")),
+    }
+}
+
+fn parse_msaa(samples: &str) -> Result<u32, String> {
+    match samples.parse() {
+        Ok(samples @ (1 | 2 | 4 | 8)) => Ok(samples),
+        _ => Err(format!("--msaa: {samples} samples, expected 1, 2, 4 or 8")),
+    }
+}
+
+fn parse_backends(backend: &str) -> Result<Backends, String> {
+    match backend {
+        "vulkan" => Ok(Backends::VULKAN),
+        "gl" => Ok(Backends::GL),
+        "metal" => Ok(Backends::METAL),
+        "dx12" => Ok(Backends::DX12),
+        "all" => Ok(Backends::all()),
+        _ => Err(format!("--backend: unknown backend {backend}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{args:?} are valid arguments"),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn parses_the_size() {
+        let Ok(Command::View(settings)) = parse(&["--size", "1280x720", "model.glb"]) else {
+            panic!("The viewer is not started");
+        };
+        assert_eq!(settings.display.size, (1280, 720));
+        assert_eq!(settings.scene, PathBuf::from("model.glb"));
+
+        for size in ["1280", "1280x", "x720", "0x720", "1280x-720", "1280 x 720"] {
+            assert_eq!(
+                parse_error(&["--size", size]),
+                format!("--size: {size} is not a size like 1280x720")
+            );
+        }
+    }
+
+    #[test]
+    fn options_need_their_value() {
+        assert_eq!(parse_error(&["--env"]), "--env needs a value, see --help");
+        assert_eq!(
+            parse_error(&["scene.ron", "--adapter"]),
+            "--adapter needs a value, see --help"
+        );
+    }
+
+    #[test]
+    fn parses_the_msaa() {
+        let Ok(Command::View(settings)) = parse(&["--msaa", "4"]) else {
+            panic!("The viewer is not started");
+        };
+        assert_eq!(settings.display.msaa, 4);
+
+        for samples in ["0", "3", "16", "four"] {
+            assert_eq!(
+                parse_error(&["--msaa", samples]),
+                format!("--msaa: {samples} samples, expected 1, 2, 4 or 8")
+            );
+        }
+        assert_eq!(
+            parse_error(&["--deferred", "--msaa", "2"]),
+            "--msaa: the deferred shading path draws a single sample per pixel"
+        );
+        assert!(parse(&["--deferred", "--msaa", "1"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        assert_eq!(
+            parse_error(&["--samples", "4"]),
+            "Unknown option --samples, see --help"
+        );
+    }
+
+    #[test]
+    fn shows_a_single_file() {
+        assert_eq!(
+            parse_error(&["scene.ron", "model.glb"]),
+            "Only one file can be shown, model.glb is one too many"
+        );
+    }
+
+    #[test]
+    fn lists_the_adapters_of_the_backend() {
+        let Ok(Command::ListAdapters(backends)) =
+            parse(&["--backend", "vulkan", "--list-adapters"])
+        else {
+            panic!("The adapters are not listed");
+        };
+        assert_eq!(backends, Backends::VULKAN);
+
+        let Ok(Command::ListAdapters(backends)) = parse(&["--list-adapters"]) else {
+            panic!("The adapters are not listed");
+        };
+        assert_eq!(backends, Backends::all());
+    }
+}
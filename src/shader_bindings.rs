@@ -0,0 +1,429 @@
+use std::num::NonZeroU32;
+
+use naga::{
+    valid::{Capabilities, ValidationFlags, Validator},
+    AddressSpace, ArraySize, GlobalVariable, ImageClass, ImageDimension, Module, ResourceBinding,
+    ScalarKind, ShaderStage, StorageAccess, TypeInner,
+};
+use wgpu::{
+    BindGroupLayoutEntry, BindingType, BufferBindingType, SamplerBindingType, StorageTextureAccess,
+    TextureSampleType, TextureViewDimension,
+};
+
+use crate::shader_library::ShaderLibrary;
+
+/// The bind group layouts of a pipeline, by group. `None` stands for a layout
+/// the pass creates itself, which is not checked.
+pub type Groups<'a> = [Option<&'a [BindGroupLayoutEntry]>];
+
+/// The differences between the bindings the entry points of the shader `file`
+/// use and the bind group layouts of its pipeline, one line each.
+pub fn mismatches(
+    file: &str,
+    defines: &[&str],
+    entry_points: &[&str],
+    groups: &Groups,
+) -> Vec<String> {
+    let module = ShaderLibrary::reflect(file, defines);
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .unwrap_or_else(|error| panic!("{file} is invalid: {error}"));
+
+    let shader = match defines.is_empty() {
+        true => format!("{file} [{}]", entry_points.join(", ")),
+        false => format!(
+            "{file} ({}) [{}]",
+            defines.join(", "),
+            entry_points.join(", ")
+        ),
+    };
+
+    let mut mismatches = Vec::new();
+    for name in entry_points {
+        let Some(index) = module.entry_points.iter().position(|ep| ep.name == *name) else {
+            mismatches.push(format!("{shader}: there is no entry point {name}"));
+            continue;
+        };
+        let stage = module.entry_points[index].stage;
+        let uses = info.get_entry_point(index);
+
+        for (handle, var) in module.global_variables.iter() {
+            let Some(ResourceBinding { group, binding }) = var.binding else {
+                continue;
+            };
+            if uses[handle].is_empty() {
+                continue;
+            }
+
+            let location = format!(
+                "{shader}: @group({group}) @binding({binding}) {}",
+                var.name.as_deref().unwrap_or_default()
+            );
+
+            let mismatch = match groups.get(group as usize) {
+                None => Some(format!(
+                    "is used by {name}, but the pipeline layout has {} groups",
+                    groups.len()
+                )),
+                Some(None) => None,
+                Some(Some(entries)) => match entries.iter().find(|e| e.binding == binding) {
+                    None => Some(format!(
+                        "is used by {name}, but the layout of the group has no binding {binding}"
+                    )),
+                    Some(entry) => check_entry(&module, var, stage, entry),
+                },
+            };
+
+            if let Some(mismatch) = mismatch {
+                let mismatch = format!("{location} {mismatch}");
+                if !mismatches.contains(&mismatch) {
+                    mismatches.push(mismatch);
+                }
+            }
+        }
+    }
+
+    mismatches
+}
+
+fn check_entry(
+    module: &Module,
+    var: &GlobalVariable,
+    stage: ShaderStage,
+    entry: &BindGroupLayoutEntry,
+) -> Option<String> {
+    let shader = describe_variable(module, var, entry.count);
+    let layout = describe_entry(entry);
+    if shader != layout {
+        return Some(format!(
+            "is a `{shader}` in the shader, a `{layout}` in the layout"
+        ));
+    }
+
+    let visibility = match stage {
+        ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    };
+    if !entry.visibility.contains(visibility) {
+        return Some(format!(
+            "is used by the {stage:?} stage, but the layout only makes it visible to {:?}",
+            entry.visibility
+        ));
+    }
+
+    None
+}
+
+/// Runtime sized binding arrays take the count of the layout.
+fn describe_variable(module: &Module, var: &GlobalVariable, count: Option<NonZeroU32>) -> String {
+    match var.space {
+        AddressSpace::Uniform => "uniform buffer".to_string(),
+        AddressSpace::Storage { access } => match access.contains(StorageAccess::STORE) {
+            true => "read write storage buffer".to_string(),
+            false => "read only storage buffer".to_string(),
+        },
+        AddressSpace::Handle => describe_type(module, &module.types[var.ty].inner, count),
+        space => format!("{space:?} variable"),
+    }
+}
+
+fn describe_type(module: &Module, ty: &TypeInner, count: Option<NonZeroU32>) -> String {
+    match ty {
+        TypeInner::Sampler { comparison: true } => "comparison sampler".to_string(),
+        TypeInner::Sampler { comparison: false } => "sampler".to_string(),
+        TypeInner::Image {
+            dim,
+            arrayed,
+            class,
+        } => {
+            let dimension = match (dim, arrayed) {
+                (ImageDimension::D1, _) => "1D",
+                (ImageDimension::D2, false) => "2D",
+                (ImageDimension::D2, true) => "2D array",
+                (ImageDimension::D3, _) => "3D",
+                (ImageDimension::Cube, false) => "cube",
+                (ImageDimension::Cube, true) => "cube array",
+            };
+
+            match class {
+                ImageClass::Sampled { kind, multi } => {
+                    let kind = match kind {
+                        ScalarKind::Sint => "int",
+                        ScalarKind::Uint => "uint",
+                        _ => "float",
+                    };
+                    texture(dimension, kind, *multi)
+                }
+                ImageClass::Depth { multi } => texture(dimension, "depth", *multi),
+                ImageClass::Storage { format, access } => {
+                    let access = match (
+                        access.contains(StorageAccess::LOAD),
+                        access.contains(StorageAccess::STORE),
+                    ) {
+                        (true, true) => "read write",
+                        (true, false) => "read only",
+                        _ => "write only",
+                    };
+                    format!("{dimension} {format:?} storage texture, {access}")
+                }
+            }
+        }
+        TypeInner::BindingArray { base, size } => {
+            let base = describe_type(module, &module.types[*base].inner, None);
+            match (size, count) {
+                (ArraySize::Constant(size), _) => format!("[{base}; {size}]"),
+                (ArraySize::Dynamic, Some(count)) => format!("[{base}; {count}]"),
+                (ArraySize::Dynamic, None) => format!("[{base}]"),
+            }
+        }
+        ty => format!("{ty:?}"),
+    }
+}
+
+fn describe_entry(entry: &BindGroupLayoutEntry) -> String {
+    let binding = match entry.ty {
+        BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            ..
+        } => "uniform buffer".to_string(),
+        BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            ..
+        } => "read only storage buffer".to_string(),
+        BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: false },
+            ..
+        } => "read write storage buffer".to_string(),
+        BindingType::Sampler(SamplerBindingType::Comparison) => "comparison sampler".to_string(),
+        BindingType::Sampler(_) => "sampler".to_string(),
+        BindingType::Texture {
+            sample_type,
+            view_dimension,
+            multisampled,
+        } => {
+            let kind = match sample_type {
+                TextureSampleType::Float { .. } => "float",
+                TextureSampleType::Sint => "int",
+                TextureSampleType::Uint => "uint",
+                TextureSampleType::Depth => "depth",
+            };
+            texture(dimension(view_dimension), kind, multisampled)
+        }
+        BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension,
+        } => {
+            let access = match access {
+                StorageTextureAccess::WriteOnly => "write only",
+                StorageTextureAccess::ReadOnly => "read only",
+                StorageTextureAccess::ReadWrite => "read write",
+            };
+            format!(
+                "{} {format:?} storage texture, {access}",
+                dimension(view_dimension)
+            )
+        }
+        ty => format!("{ty:?}"),
+    };
+
+    match entry.count {
+        Some(count) => format!("[{binding}; {count}]"),
+        None => binding,
+    }
+}
+
+fn dimension(view_dimension: TextureViewDimension) -> &'static str {
+    match view_dimension {
+        TextureViewDimension::D1 => "1D",
+        TextureViewDimension::D2 => "2D",
+        TextureViewDimension::D2Array => "2D array",
+        TextureViewDimension::Cube => "cube",
+        TextureViewDimension::CubeArray => "cube array",
+        TextureViewDimension::D3 => "3D",
+    }
+}
+
+fn texture(dimension: &str, kind: &str, multisampled: bool) -> String {
+    match multisampled {
+        true => format!("multisampled {dimension} {kind} texture"),
+        false => format!("{dimension} {kind} texture"),
+    }
+}
+
+mod tests {
+    use crate::{
+        layouts::{Group, LayoutEntries},
+        render_pass::{
+            CullPass, DeferredPass, DepthPrepass, DepthResolve, ErrorOverlay, HdrPipeline, OitPass,
+            PbrPass, ShadowPass, SkyboxPass, SsaoPass, SsrPass, TransparentPass,
+        },
+    };
+
+    use super::*;
+
+    /// The shader and the layouts of each pipeline built on the shared layouts.
+    fn report(bindless: bool) -> Vec<String> {
+        let layouts = LayoutEntries::new(bindless, 64);
+        let mut report = Vec::new();
+
+        // The variants of the material pipelines
+        for normal_map in [false, true] {
+            for shadows in [false, true] {
+                let defines = [
+                    (bindless, "BINDLESS"),
+                    (normal_map, "NORMAL_MAP"),
+                    (shadows, "SHADOWS"),
+                ]
+                .into_iter()
+                .filter_map(|(defined, define)| defined.then_some(define))
+                .collect::<Vec<_>>();
+
+                let mut check = |file, fragment, groups: &[Group]| {
+                    report.extend(mismatches(
+                        file,
+                        &defines,
+                        &["vs_main", fragment],
+                        &layouts.groups(groups),
+                    ));
+                };
+                check("shaders/pbr.wgsl", "fs_main", &PbrPass::BIND_GROUPS);
+                check("shaders/pbr.wgsl", "fs_prepass", &DepthPrepass::BIND_GROUPS);
+                check(
+                    "shaders/pbr.wgsl",
+                    "fs_gbuffer",
+                    &DeferredPass::GBUFFER_GROUPS,
+                );
+                check(
+                    "shaders/pbr.wgsl",
+                    "fs_transparent",
+                    &TransparentPass::BIND_GROUPS,
+                );
+                check("shaders/pbr.wgsl", "fs_oit", &OitPass::BIND_GROUPS);
+                check("shaders/shadow.wgsl", "fs_main", &ShadowPass::BIND_GROUPS);
+                check(
+                    "shaders/shadow.wgsl",
+                    "fs_blended",
+                    &ShadowPass::BIND_GROUPS,
+                );
+            }
+        }
+
+        let mut check = |file, defines: &[&str], entry_points: &[&str], groups: &[Group]| {
+            report.extend(mismatches(
+                file,
+                defines,
+                entry_points,
+                &layouts.groups(groups),
+            ));
+        };
+
+        let lighting = &DeferredPass::LIGHTING_GROUPS;
+        check(
+            "shaders/deferred.wgsl",
+            &[],
+            &["vs_main", "fs_main"],
+            lighting,
+        );
+        check(
+            "shaders/deferred.wgsl",
+            &["SHADOWS"],
+            &["vs_main", "fs_main"],
+            lighting,
+        );
+        check(
+            "shaders/skybox.wgsl",
+            &[],
+            &["vs_main", "fs_main"],
+            &SkyboxPass::BIND_GROUPS,
+        );
+        check(
+            "shaders/cull.wgsl",
+            &[],
+            &["cs_main"],
+            &CullPass::BIND_GROUPS,
+        );
+        check(
+            "shaders/hdr.wgsl",
+            &[],
+            &["vs_main", "fs_main"],
+            &HdrPipeline::BIND_GROUPS,
+        );
+        check(
+            "shaders/depth_resolve.wgsl",
+            &[],
+            &["vs_main", "fs_main"],
+            &DepthResolve::BIND_GROUPS,
+        );
+        check(
+            "shaders/error_overlay.wgsl",
+            &[],
+            &["vs_main", "fs_main"],
+            &ErrorOverlay::BIND_GROUPS,
+        );
+        check(
+            "shaders/ssao.wgsl",
+            &[],
+            &["vs_main", "fs_ssao"],
+            &SsaoPass::SSAO_GROUPS,
+        );
+        check(
+            "shaders/ssao.wgsl",
+            &[],
+            &["vs_main", "fs_blur"],
+            &SsaoPass::BLUR_GROUPS,
+        );
+        check(
+            "shaders/ssr.wgsl",
+            &[],
+            &["vs_main", "fs_trace"],
+            &SsrPass::TRACE_GROUPS,
+        );
+        check(
+            "shaders/ssr.wgsl",
+            &[],
+            &["vs_main", "fs_composite"],
+            &SsrPass::COMPOSITE_GROUPS,
+        );
+
+        report
+    }
+
+    #[test]
+    fn shaders_match_layouts() {
+        for bindless in [false, true] {
+            let report = report(bindless);
+            assert!(
+                report.is_empty(),
+                "The shaders do not match the bind group layouts{}:\n{}",
+                if bindless {
+                    " of the material table"
+                } else {
+                    ""
+                },
+                report.join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let layouts = LayoutEntries::new(false, 64);
+        let groups = [
+            Some(layouts.camera.as_slice()),
+            Some(layouts.transform.as_slice()),
+            Some(layouts.light.as_slice()),
+        ];
+
+        let report = mismatches("shaders/pbr.wgsl", &[], &["vs_main", "fs_prepass"], &groups);
+        assert!(
+            report.contains(
+                &"shaders/pbr.wgsl [vs_main, fs_prepass]: @group(2) @binding(0) material is a `uniform buffer` in the \
+                  shader, a `read only storage buffer` in the layout"
+                    .to_string()
+            ),
+            "{report:#?}"
+        );
+    }
+}
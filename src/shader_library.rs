@@ -0,0 +1,456 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use wgpu::{Device, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+use crate::file_watcher::FileWatcher;
+
+/// Pairs each shader file, relative to `src`, with its built in source.
+macro_rules! shader_files {
+    ($($path:literal),* $(,)?) => {
+        &[$(($path, include_str!($path))),*]
+    };
+}
+
+const SHADERS: &[(&str, &str)] = shader_files![
+    "shaders/cull.wgsl",
+    "shaders/deferred.wgsl",
+    "shaders/depth_pyramid.wgsl",
+    "shaders/depth_resolve.wgsl",
+    "shaders/error_overlay.wgsl",
+    "shaders/hdr.wgsl",
+    "shaders/include/camera.wgsl",
+    "shaders/include/fullscreen.wgsl",
+    "shaders/include/transform.wgsl",
+    "shaders/include/vertex.wgsl",
+    "shaders/lighting.wgsl",
+    "shaders/material.wgsl",
+    "shaders/material_bindless.wgsl",
+    "shaders/mipmap.wgsl",
+    "shaders/oit_composite.wgsl",
+    "shaders/pbr.wgsl",
+    "shaders/shadow.wgsl",
+    "shaders/skybox.wgsl",
+    "shaders/ssao.wgsl",
+    "shaders/ssr.wgsl",
+    "resources/hdr_loader/equirectangular.wgsl",
+    "resources/irr_map_generator/convolution.wgsl",
+];
+
+/// The WGSL sources of the pipelines. They are built in, or in development
+/// mode read from the source tree and watched, so the pipelines can be
+/// rebuilt when they change.
+///
+/// The sources are preprocessed: `#import "file"` pastes a file, relative to
+/// the importing one, the first time it is imported, and the lines between
+/// `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` are only kept when the
+/// define is given or not. Each variant is compiled once and shared.
+pub struct ShaderLibrary {
+    dev: Option<DevSources>,
+    modules: Mutex<HashMap<ShaderVariant, Arc<ShaderModule>>>,
+}
+
+/// A file compiled with a set of defines.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ShaderVariant {
+    file: String,
+    /// Sorted, so the order they are given in does not matter.
+    defines: Vec<String>,
+}
+
+impl Display for ShaderVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if !self.defines.is_empty() {
+            write!(f, " ({})", self.defines.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct DevSources {
+    directory: PathBuf,
+    watcher: FileWatcher,
+    /// The last sources of each variant that passed validation.
+    last_good: Mutex<HashMap<ShaderVariant, String>>,
+    /// The errors since the shaders last changed.
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+impl ShaderLibrary {
+    pub fn new(dev: bool) -> ShaderLibrary {
+        let dev = dev.then(|| {
+            let directory = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src"));
+            let watcher = FileWatcher::new();
+            for (file, _) in SHADERS {
+                watcher.watch(&directory.join(file));
+            }
+
+            DevSources {
+                directory,
+                watcher,
+                last_good: Mutex::new(HashMap::new()),
+                errors: Arc::new(Mutex::new(Vec::new())),
+            }
+        });
+
+        ShaderLibrary {
+            dev,
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The module of the `file`, relative to `src`, with the `defines`. In
+    /// development mode the source is validated first, and the last valid one
+    /// is used instead when it fails, or the built in one if there was none.
+    pub fn module(&self, device: &Device, file: &str, defines: &[&str]) -> Arc<ShaderModule> {
+        let mut defines = defines
+            .iter()
+            .map(|define| define.to_string())
+            .collect::<Vec<_>>();
+        defines.sort();
+        defines.dedup();
+
+        let variant = ShaderVariant {
+            file: file.to_string(),
+            defines,
+        };
+
+        if let Some(module) = self.modules.lock().unwrap().get(&variant) {
+            return module.clone();
+        }
+
+        let source = match &self.dev {
+            None => preprocess(&variant, &built_in).unwrap_or_else(|error| panic!("{error}")),
+            Some(dev) => dev.validated(&variant),
+        };
+
+        let module = Arc::new(device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(&variant.to_string()),
+            source: ShaderSource::Wgsl(source.into()),
+        }));
+
+        self.modules.lock().unwrap().insert(variant, module.clone());
+
+        module
+    }
+
+    /// Whether a shader file changed since the last call, always false outside
+    /// of development mode. Forgets the modules and the errors of the previous
+    /// sources.
+    pub fn changed(&self) -> bool {
+        let Some(dev) = &self.dev else {
+            return false;
+        };
+
+        let changed = !dev.watcher.changed().is_empty();
+        if changed {
+            self.modules.lock().unwrap().clear();
+            dev.errors.lock().unwrap().clear();
+        }
+
+        changed
+    }
+
+    /// Logs an error caused by the shaders, to show it until they change.
+    pub fn report_error(&self, error: String) {
+        eprintln!("{error}");
+        if let Some(dev) = &self.dev {
+            dev.errors.lock().unwrap().push(error);
+        }
+    }
+
+    /// Reports the errors wgpu would panic on in development mode instead, as
+    /// the pipelines of invalid shaders are only created when first drawn.
+    pub fn capture_device_errors(&self, device: &Device) {
+        let Some(dev) = &self.dev else {
+            return;
+        };
+
+        let errors = dev.errors.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            let error = error.to_string();
+            eprintln!("{error}");
+
+            let mut errors = errors.lock().unwrap();
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }));
+    }
+
+    /// The errors since the shaders last changed, with their whole
+    /// diagnostics.
+    pub fn error(&self) -> Option<String> {
+        let errors = self.dev.as_ref()?.errors.lock().unwrap();
+
+        (!errors.is_empty()).then(|| errors.join("\n\n"))
+    }
+
+    /// The naga module of the built in `file` with the `defines`.
+    #[cfg(test)]
+    pub fn reflect(file: &str, defines: &[&str]) -> naga::Module {
+        let variant = ShaderVariant {
+            file: file.to_string(),
+            defines: defines.iter().map(|define| define.to_string()).collect(),
+        };
+        let source = preprocess(&variant, &built_in).unwrap_or_else(|error| panic!("{error}"));
+
+        naga::front::wgsl::parse_str(&source)
+            .unwrap_or_else(|error| panic!("{}", error.emit_to_string_with_path(&source, file)))
+    }
+}
+
+impl DevSources {
+    fn validated(&self, variant: &ShaderVariant) -> String {
+        let read = |file: &str| {
+            let path = self.directory.join(file);
+            fs::read_to_string(&path)
+                .map_err(|error| format!("Can not read {}: {error}", path.display()))
+        };
+
+        let result = preprocess(variant, &read)
+            .and_then(|source| validate(&source, &variant.to_string()).map(|_| source));
+
+        let mut last_good = self.last_good.lock().unwrap();
+        match result {
+            Ok(source) => {
+                last_good.insert(variant.clone(), source.clone());
+                source
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                self.errors.lock().unwrap().push(error);
+
+                last_good.get(variant).cloned().unwrap_or_else(|| {
+                    preprocess(variant, &built_in).unwrap_or_else(|error| panic!("{error}"))
+                })
+            }
+        }
+    }
+}
+
+fn built_in(file: &str) -> Result<String, String> {
+    SHADERS
+        .iter()
+        .find(|(path, _)| *path == file)
+        .map(|(_, source)| source.to_string())
+        .ok_or_else(|| format!("Unknown shader {file}"))
+}
+
+/// The source of the variant, with its imports and conditions resolved.
+fn preprocess(
+    variant: &ShaderVariant,
+    read: &impl Fn(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut output = String::new();
+    let file = normalize(&variant.file);
+    let mut imported = HashSet::from([file.clone()]);
+    expand(&file, &variant.defines, read, &mut imported, &mut output)?;
+
+    Ok(output)
+}
+
+/// An `#ifdef` or `#ifndef` block being expanded.
+struct Branch {
+    /// Whether the lines of the current branch are kept.
+    kept: bool,
+    else_seen: bool,
+}
+
+/// Appends the expanded `file` to the `output`. The `imported` files are only
+/// expanded once, which also breaks import cycles.
+fn expand(
+    file: &str,
+    defines: &[String],
+    read: &impl Fn(&str) -> Result<String, String>,
+    imported: &mut HashSet<String>,
+    output: &mut String,
+) -> Result<(), String> {
+    let source = read(file)?;
+    let defined = |name: &str| defines.iter().any(|define| define == name.trim());
+
+    let mut branches: Vec<Branch> = Vec::new();
+    let branch = |kept| Branch {
+        kept,
+        else_seen: false,
+    };
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("{file}:{}: {message}", index + 1);
+        let directive = line.trim();
+
+        if let Some(name) = directive.strip_prefix("#ifdef ") {
+            branches.push(branch(defined(name)));
+        } else if let Some(name) = directive.strip_prefix("#ifndef ") {
+            branches.push(branch(!defined(name)));
+        } else if directive == "#else" {
+            let branch = branches
+                .last_mut()
+                .ok_or_else(|| error("#else without #ifdef"))?;
+            if branch.else_seen {
+                return Err(error("duplicate #else"));
+            }
+            branch.kept = !branch.kept;
+            branch.else_seen = true;
+        } else if directive == "#endif" {
+            branches
+                .pop()
+                .ok_or_else(|| error("#endif without #ifdef"))?;
+        } else if !branches.iter().all(|branch| branch.kept) {
+            continue;
+        } else if let Some(path) = directive.strip_prefix("#import ") {
+            let path = path.trim().trim_matches('"');
+            let import = match file.rsplit_once('/') {
+                Some((directory, _)) => normalize(&format!("{directory}/{path}")),
+                None => normalize(path),
+            };
+
+            if imported.insert(import.clone()) {
+                expand(&import, defines, read, imported, output)
+                    .map_err(|message| format!("{message}\n  imported by {file}:{}", index + 1))?;
+            }
+        } else {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    match branches.is_empty() {
+        true => Ok(()),
+        false => Err(format!("{file}: #ifdef without #endif")),
+    }
+}
+
+/// Resolves the `.` and `..` components of a relative path, so each file is
+/// imported under a single name.
+fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." if components.last().is_some_and(|last| *last != "..") => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+
+    components.join("/")
+}
+
+/// Parses and validates the source with naga, so errors are reported before
+/// wgpu gets to them.
+fn validate(source: &str, path: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source)
+        .map_err(|error| error.emit_to_string_with_path(source, path))?;
+
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|error| error.emit_to_string_with_path(source, path))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(file: &str, defines: &[&str], files: &[(&str, &str)]) -> Result<String, String> {
+        let read = |path: &str| {
+            files
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| format!("Unknown shader {path}"))
+        };
+        let variant = ShaderVariant {
+            file: file.to_string(),
+            defines: defines.iter().map(|define| define.to_string()).collect(),
+        };
+
+        preprocess(&variant, &read)
+    }
+
+    const NESTED: &str = "\
+#ifdef A
+a
+#ifdef B
+a b
+#else
+a not b
+#endif
+#else
+not a
+#endif
+";
+
+    #[test]
+    fn keeps_the_branches_of_the_defines() {
+        let files = [("main.wgsl", NESTED)];
+
+        assert_eq!(
+            expanded("main.wgsl", &["A", "B"], &files).unwrap(),
+            "a\na b\n"
+        );
+        assert_eq!(
+            expanded("main.wgsl", &["A"], &files).unwrap(),
+            "a\na not b\n"
+        );
+        assert_eq!(expanded("main.wgsl", &["B"], &files).unwrap(), "not a\n");
+    }
+
+    #[test]
+    fn reports_unbalanced_directives() {
+        let error = |source| expanded("main.wgsl", &[], &[("main.wgsl", source)]).unwrap_err();
+
+        assert_eq!(error("#ifdef A\n"), "main.wgsl: #ifdef without #endif");
+        assert_eq!(error("#endif\n"), "main.wgsl:1: #endif without #ifdef");
+        assert_eq!(error("#else\n"), "main.wgsl:1: #else without #ifdef");
+        assert_eq!(
+            error("#ifdef A\n#else\n#else\n#endif\n"),
+            "main.wgsl:3: duplicate #else"
+        );
+    }
+
+    #[test]
+    fn imports_each_file_once() {
+        let files = [
+            (
+                "shaders/main.wgsl",
+                "#import \"include/a.wgsl\"\n#import \"./include/a.wgsl\"\nmain\n",
+            ),
+            ("shaders/include/a.wgsl", "#import \"../main.wgsl\"\na\n"),
+        ];
+
+        assert_eq!(
+            expanded("shaders/main.wgsl", &[], &files).unwrap(),
+            "a\nmain\n"
+        );
+    }
+
+    #[test]
+    fn reports_where_a_missing_file_is_imported() {
+        let files = [("main.wgsl", "\n#import \"missing.wgsl\"\n")];
+
+        assert_eq!(
+            expanded("main.wgsl", &[], &files).unwrap_err(),
+            "Unknown shader missing.wgsl\n  imported by main.wgsl:2"
+        );
+    }
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize("./a.wgsl"), "a.wgsl");
+        assert_eq!(normalize("shaders/include/../a.wgsl"), "shaders/a.wgsl");
+        assert_eq!(normalize("../a.wgsl"), "../a.wgsl");
+    }
+}
@@ -0,0 +1,127 @@
+use encase::{internal::WriteInto, ShaderType, StorageBuffer, UniformBuffer};
+
+/// The bytes of a value read from a uniform buffer, laid out with the std140
+/// like rules of the uniform address space.
+pub fn uniform_bytes<T: ShaderType + WriteInto>(value: &T) -> Vec<u8> {
+    let mut buffer = UniformBuffer::new(Vec::new());
+    buffer.write(value).unwrap();
+    buffer.into_inner()
+}
+
+/// The bytes of a value read from a storage buffer, laid out with the std430
+/// like rules of the storage address space. Slices become runtime sized arrays.
+pub fn storage_bytes<T: ShaderType + WriteInto + ?Sized>(value: &T) -> Vec<u8> {
+    let mut buffer = StorageBuffer::new(Vec::new());
+    buffer.write(value).unwrap();
+    buffer.into_inner()
+}
+
+/// Checks the Rust types against the structs the shaders declare.
+#[cfg(test)]
+pub mod tests {
+    use encase::{
+        internal::{CreateFrom, WriteInto},
+        ShaderType, StorageBuffer,
+    };
+    use naga::{ArraySize, Handle, Module, Type, TypeInner};
+
+    use crate::shader_library::ShaderLibrary;
+
+    use super::storage_bytes;
+
+    /// Asserts the struct `name` of the shader `file`, with the `defines`, has
+    /// the size and member layout of `T`. Every word of the struct is given a
+    /// distinct value and read into `T`: written back, the members keep their
+    /// values and the padding is zeroed only when the layouts match.
+    pub fn assert_layout<T>(file: &str, defines: &[&str], name: &str)
+    where
+        T: ShaderType + CreateFrom + WriteInto,
+    {
+        let module = ShaderLibrary::reflect(file, defines);
+        let (_, ty) = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some(name))
+            .unwrap_or_else(|| panic!("{file} has no struct {name}"));
+
+        let TypeInner::Struct { members, span } = &ty.inner else {
+            panic!("{name} of {file} is not a struct");
+        };
+
+        assert_eq!(
+            *span as u64,
+            T::min_size().get(),
+            "{name} of {file} has a size of {span}, the Rust type {}",
+            T::min_size()
+        );
+
+        let words = (1..=*span / 4)
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+        let value: T = StorageBuffer::new(words.as_slice()).create().unwrap();
+        let written = storage_bytes(&value);
+
+        // The member holding each byte, the padding is zeroed
+        let mut expected = vec![0; words.len()];
+        let mut member_at = vec![None; words.len()];
+        for member in members {
+            let name = member.name.clone().unwrap_or_default();
+            let mut leaf = |name: String, range: std::ops::Range<usize>| {
+                expected[range.clone()].copy_from_slice(&words[range.clone()]);
+                member_at[range].fill(Some(name));
+            };
+            visit_leaves(&module, member.ty, member.offset as usize, name, &mut leaf);
+        }
+        if let Some(offset) = (0..written.len()).find(|&i| written[i] != expected[i]) {
+            match &member_at[offset] {
+                Some(member) => panic!(
+                    "{name}.{member} of {file}, at {offset}, is not a member of the Rust type"
+                ),
+                None => panic!("{name} of {file} has padding at {offset}, the Rust type a member"),
+            }
+        }
+    }
+
+    /// Calls `leaf` with the name and byte range of every member that is not
+    /// a struct or an array, so the padding inside nested structs is known.
+    /// A runtime sized array holds a single element.
+    fn visit_leaves(
+        module: &Module,
+        ty: Handle<Type>,
+        offset: usize,
+        name: String,
+        leaf: &mut impl FnMut(String, std::ops::Range<usize>),
+    ) {
+        let inner = &module.types[ty].inner;
+        match inner {
+            TypeInner::Struct { members, .. } => {
+                for member in members {
+                    let member_name = member.name.as_deref().unwrap_or_default();
+                    visit_leaves(
+                        module,
+                        member.ty,
+                        offset + member.offset as usize,
+                        format!("{name}.{member_name}"),
+                        leaf,
+                    );
+                }
+            }
+            TypeInner::Array { base, size, stride } => {
+                let count = match size {
+                    ArraySize::Constant(count) => count.get(),
+                    ArraySize::Dynamic => 1,
+                };
+                for index in 0..count as usize {
+                    visit_leaves(
+                        module,
+                        *base,
+                        offset + index * *stride as usize,
+                        format!("{name}[{index}]"),
+                        leaf,
+                    );
+                }
+            }
+            _ => leaf(name, offset..offset + inner.size(module.to_ctx()) as usize),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+struct Cull {
+    planes: array<vec4f, 6>,
+    object_count: u32,
+}
+
+struct Object {
+    center: vec3f,
+    radius: f32,
+    aabb_min: vec3f,
+    first_index: u32,
+    aabb_max: vec3f,
+    index_count: u32,
+    base_vertex: i32,
+    instance: u32,
+    material_index: u32,
+}
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0) var<uniform> cull: Cull;
+@group(0) @binding(1) var<storage, read> objects: array<Object>;
+@group(0) @binding(2) var<storage, read_write> commands: array<DrawIndexedIndirect>;
+@group(0) @binding(3) var<storage, read_write> occluded: array<u32>;
+
+struct DepthPyramid {
+    // Camera the pyramid was built from
+    view_proj: mat4x4f,
+    size: vec2f,
+    valid: u32,
+}
+
+@group(1) @binding(0) var<uniform> pyramid: DepthPyramid;
+@group(1) @binding(1) var pyramid_texture: texture_2d<f32>;
+
+// Objects each phase found visible and occluded, read back for the stats. The
+// others are outside the frustum, or were drawn by an earlier phase
+struct PhaseCounts {
+    visible: atomic<u32>,
+    occluded: atomic<u32>,
+}
+
+@group(2) @binding(0) var<storage, read_write> counts: array<PhaseCounts, 3>;
+
+// 0 culls against the pyramid of the previous frame, 1 re-tests the objects
+// that failed against the pyramid built from the first phase, 2 culls against
+// the pyramid of the complete depth prepass
+var<push_constant> phase: u32;
+
+// Same tests as Frustum::intersects_sphere and Frustum::intersects_aabb
+fn is_visible(object: Object) -> bool {
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+
+        if dot(plane.xyz, object.center) + plane.w < -object.radius {
+            return false;
+        }
+
+        // Corner of the box that is furthest along the plane normal
+        let corner = select(object.aabb_min, object.aabb_max, plane.xyz >= vec3f(0.0));
+        if dot(plane.xyz, corner) + plane.w < 0.0 {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+// Compares the nearest depth of the projected box with the farthest depth in
+// the pyramid level where the box covers at most 2x2 texels
+fn is_occluded(object: Object) -> bool {
+    var min_uv = vec2f(1.0);
+    var max_uv = vec2f(0.0);
+    var nearest = 1.0;
+
+    for (var i = 0u; i < 8u; i++) {
+        let corner = select(object.aabb_min, object.aabb_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = pyramid.view_proj * vec4f(corner, 1.0);
+
+        // The box crosses the camera plane
+        if clip.w <= 0.0 {
+            return false;
+        }
+
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+
+        min_uv = min(min_uv, uv);
+        max_uv = max(max_uv, uv);
+        nearest = min(nearest, ndc.z);
+    }
+
+    min_uv = clamp(min_uv, vec2f(0.0), vec2f(1.0));
+    max_uv = clamp(max_uv, vec2f(0.0), vec2f(1.0));
+
+    let extent = (max_uv - min_uv) * pyramid.size;
+    let max_level = f32(textureNumLevels(pyramid_texture) - 1u);
+    let level = i32(clamp(ceil(log2(max(extent.x, extent.y))), 0.0, max_level));
+
+    let level_size = textureDimensions(pyramid_texture, level);
+    let min_texel = min(vec2u(min_uv * vec2f(level_size)), level_size - 1u);
+    let max_texel = min(vec2u(max_uv * vec2f(level_size)), level_size - 1u);
+
+    let farthest = max(
+        max(textureLoad(pyramid_texture, min_texel, level).r, textureLoad(pyramid_texture, vec2u(max_texel.x, min_texel.y), level).r),
+        max(textureLoad(pyramid_texture, vec2u(min_texel.x, max_texel.y), level).r, textureLoad(pyramid_texture, max_texel, level).r),
+    );
+
+    return nearest > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3u) {
+    let index = id.x;
+    if index >= cull.object_count {
+        return;
+    }
+
+    let object = objects[index];
+
+    var visible = false;
+    var hidden = false;
+    if phase == 0u {
+        let in_frustum = is_visible(object);
+        hidden = in_frustum && pyramid.valid == 1u && is_occluded(object);
+
+        occluded[index] = select(0u, 1u, hidden);
+        visible = in_frustum && !hidden;
+    } else if phase == 1u {
+        // Objects drawn in the first phase are already in the depth buffer
+        let retested = occluded[index] == 1u;
+        hidden = retested && is_occluded(object);
+        visible = retested && !hidden;
+    } else {
+        let in_frustum = is_visible(object);
+        hidden = in_frustum && is_occluded(object);
+        visible = in_frustum && !hidden;
+    }
+
+    if visible {
+        atomicAdd(&counts[phase].visible, 1u);
+    }
+    if hidden {
+        atomicAdd(&counts[phase].occluded, 1u);
+    }
+
+    commands[index] = DrawIndexedIndirect(
+        object.index_count,
+        select(0u, 1u, visible),
+        object.first_index,
+        object.base_vertex,
+        object.instance,
+    );
+}
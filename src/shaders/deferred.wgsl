@@ -0,0 +1,50 @@
+// Lighting pass of the deferred path. Shades every pixel covered by the
+// G-buffer with the lighting it shares with the forward path.
+
+#import "lighting.wgsl"
+#import "include/camera.wgsl"
+#import "include/fullscreen.wgsl"
+
+@group(1) @binding(0) var gbuffer_albedo: texture_2d<f32>;
+@group(1) @binding(1) var gbuffer_normal: texture_2d<f32>;
+@group(1) @binding(2) var gbuffer_material: texture_2d<f32>;
+@group(1) @binding(3) var gbuffer_emissive: texture_2d<f32>;
+
+@group(2) @binding(0) var depth_texture: texture_depth_2d;
+
+@group(5) @binding(0) var ssao_texture: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4f {
+    return fullscreen_position(id, 0.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let texel = vec2u(position.xy);
+    let depth = textureLoad(depth_texture, texel, 0);
+
+    // Nothing was drawn, keep the skybox
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    // World position from the depth
+    let uv = position.xy / vec2f(textureDimensions(depth_texture));
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view_position = camera.inv_proj * ndc;
+    let world_position = camera.inv_view * (view_position / view_position.w);
+
+    let material = textureLoad(gbuffer_material, texel, 0);
+
+    var surface: Surface;
+    surface.position = world_position.xyz;
+    surface.normal = normalize(textureLoad(gbuffer_normal, texel, 0).xyz);
+    surface.albedo = textureLoad(gbuffer_albedo, texel, 0).rgb;
+    surface.metallic = material.r;
+    surface.roughness = material.g;
+    surface.ao = material.b * textureLoad(ssao_texture, texel, 0).r;
+
+    let emissive = textureLoad(gbuffer_emissive, texel, 0).rgb;
+    return vec4f(light_surface(surface, camera.position) + emissive, 1.0);
+}
@@ -0,0 +1,66 @@
+// Builds a hierarchical depth buffer where every texel holds the farthest, or
+// with cs_downsample_nearest the nearest, depth of the texels it covers in the
+// level below.
+
+@group(0) @binding(0) var depth: texture_depth_2d;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var destination: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn cs_copy(@builtin(global_invocation_id) id: vec3u) {
+    if any(id.xy >= textureDimensions(destination)) {
+        return;
+    }
+
+    textureStore(destination, id.xy, vec4f(textureLoad(depth, id.xy, 0)));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample(@builtin(global_invocation_id) id: vec3u) {
+    downsample(id, false);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_downsample_nearest(@builtin(global_invocation_id) id: vec3u) {
+    downsample(id, true);
+}
+
+fn reduce(a: f32, b: f32, nearest: bool) -> f32 {
+    return select(max(a, b), min(a, b), nearest);
+}
+
+fn downsample(id: vec3u, nearest: bool) {
+    let size = textureDimensions(destination);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    let source_size = textureDimensions(source);
+    let texel = id.xy * 2u;
+
+    var depth = reduce(
+        reduce(textureLoad(source, texel, 0).r, textureLoad(source, texel + vec2u(1u, 0u), 0).r, nearest),
+        reduce(textureLoad(source, texel + vec2u(0u, 1u), 0).r, textureLoad(source, texel + vec2u(1u, 1u), 0).r, nearest),
+        nearest,
+    );
+
+    // Odd sizes leave a last row or column that the level above would miss
+    let extra_column = (source_size.x & 1u) == 1u && id.x == size.x - 1u;
+    let extra_row = (source_size.y & 1u) == 1u && id.y == size.y - 1u;
+
+    if extra_column {
+        depth = reduce(depth, textureLoad(source, texel + vec2u(2u, 0u), 0).r, nearest);
+        depth = reduce(depth, textureLoad(source, texel + vec2u(2u, 1u), 0).r, nearest);
+    }
+
+    if extra_row {
+        depth = reduce(depth, textureLoad(source, texel + vec2u(0u, 2u), 0).r, nearest);
+        depth = reduce(depth, textureLoad(source, texel + vec2u(1u, 2u), 0).r, nearest);
+    }
+
+    if extra_column && extra_row {
+        depth = reduce(depth, textureLoad(source, texel + vec2u(2u, 2u), 0).r, nearest);
+    }
+
+    textureStore(destination, id.xy, vec4f(depth));
+}
@@ -0,0 +1,30 @@
+#import "include/fullscreen.wgsl"
+
+// Resolves the multisampled depth into a single sample per pixel, for the
+// screen space passes and the depth pyramid
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = fullscreen_position(vi, 0.0);
+    return out;
+}
+
+// Bound as a float texture, the GL backend can not load from a depth one
+@group(0) @binding(0) var depth: texture_multisampled_2d<f32>;
+
+// The farthest sample, so nothing is culled behind a partly covered pixel
+@fragment
+fn fs_main(vs: VertexOutput) -> @builtin(frag_depth) f32 {
+    let pixel = vec2i(vs.clip_position.xy);
+
+    var farthest = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(depth)); i++) {
+        farthest = max(farthest, textureLoad(depth, pixel, i).r);
+    }
+    return farthest;
+}
@@ -0,0 +1,28 @@
+#import "include/fullscreen.wgsl"
+
+// The shader errors of development mode, on a banner along the top of the frame
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4f,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vi: u32) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = fullscreen_position(vi, 0.0);
+    return out;
+}
+
+// Coverage of the message, as wide as the frame and as high as the banner
+@group(0) @binding(0) var message: texture_2d<f32>;
+
+@fragment
+fn fs_main(vs: VertexOutput) -> @location(0) vec4f {
+    let pixel = vec2u(vs.clip_position.xy);
+    if any(pixel >= textureDimensions(message)) {
+        discard;
+    }
+
+    let coverage = textureLoad(message, pixel, 0).r;
+    return mix(vec4f(0.45, 0.0, 0.0, 0.85), vec4f(1.0), coverage);
+}
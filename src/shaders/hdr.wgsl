@@ -1,3 +1,5 @@
+#import "include/fullscreen.wgsl"
+
 
 
 struct VertexOutput {
@@ -10,12 +12,8 @@ fn vs_main(
     @builtin(vertex_index) vi: u32
 ) -> VertexOutput {
     var out: VertexOutput;
-    out.uv = vec2<f32>(
-        f32((vi << 1u) & 2u),
-        f32(vi & 2u),
-    );
-    out.clip_position = vec4<f32>(out.uv * 2.0 - 1.0, 0.0, 1.0);
-    out.uv.y = 1.0 - out.uv.y;
+    out.uv = fullscreen_uv(vi);
+    out.clip_position = fullscreen_position(vi, 0.0);
     return out;
 }
 
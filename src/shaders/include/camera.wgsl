@@ -0,0 +1,11 @@
+// The camera of the pass, laid out like CameraUniform
+
+struct Camera {
+    position: vec3f,
+    view: mat4x4f,
+    inv_view: mat4x4f,
+    proj: mat4x4f,
+    inv_proj: mat4x4f,
+}
+
+@group(0) @binding(0) var<uniform> camera: Camera;
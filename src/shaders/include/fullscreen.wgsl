@@ -0,0 +1,13 @@
+// A triangle covering the screen, drawn with 3 vertices and no vertex buffer
+
+// Clip space position of the vertex, at the given depth
+fn fullscreen_position(id: u32, depth: f32) -> vec4f {
+    let uv = vec2f(vec2u(id & 1u, (id >> 1u) & 1u));
+    return vec4f(uv * 4.0 - 1.0, depth, 1.0);
+}
+
+// Texture coordinates of the vertex, with y pointing down
+fn fullscreen_uv(id: u32) -> vec2f {
+    let uv = vec2f(vec2u(id & 1u, (id >> 1u) & 1u)) * 2.0;
+    return vec2f(uv.x, 1.0 - uv.y);
+}
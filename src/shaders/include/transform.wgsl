@@ -0,0 +1,9 @@
+// The transforms and materials of the drawn instances, indexed by the instance
+
+struct Transform {
+    model: mat4x4f,
+    normal: mat4x4f,
+}
+
+@group(1) @binding(0) var<storage, read> transforms: array<Transform>;
+@group(1) @binding(1) var<storage, read> instance_materials: array<u32>;
@@ -0,0 +1,8 @@
+// The vertex attributes of the meshes, matching Vertex::desc
+
+struct Vertex {
+    @location(0) position: vec3f,
+    @location(1) uv: vec2f,
+    @location(2) normal: vec3f,
+    @location(3) tangent: vec4f,
+}
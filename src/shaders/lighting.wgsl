@@ -0,0 +1,184 @@
+// Lighting shared by the forward and the deferred paths: the point lights with
+// their shadow maps, the irradiance map and the Cook-Torrance BRDF.
+
+const PI: f32 = 3.14159265359;
+
+struct PointLight {
+    @location(0) position: vec3f,
+    @location(1) color: vec3f,
+}
+
+// The lights past the count pad the buffer, the scene may have none
+struct PointLights {
+    count: u32,
+    lights: array<PointLight>,
+}
+
+@group(3) @binding(0) var<storage, read> point_lights: PointLights;
+@group(3) @binding(1) var shadow_sampler: sampler;
+// One binding per light, binding arrays need a feature only the bindless
+// material table requires. The maps of missing lights are placeholders.
+@group(3) @binding(2) var shadow_map_0: texture_cube<f32>;
+@group(3) @binding(3) var shadow_map_1: texture_cube<f32>;
+@group(3) @binding(4) var shadow_map_2: texture_cube<f32>;
+
+@group(4) @binding(0) var irrSampler: sampler;
+@group(4) @binding(1) var irrMap: texture_cube<f32>;
+
+// What the lighting needs to know about a surface, in world space
+struct Surface {
+    position: vec3f,
+    normal: vec3f,
+    albedo: vec3f,
+    metallic: f32,
+    roughness: f32,
+    ao: f32,
+}
+
+fn light_surface(surface: Surface, view_position: vec3f) -> vec3f {
+    var normal = surface.normal;
+    var albedo = surface.albedo;
+    var metallic = surface.metallic;
+    var roughness = surface.roughness;
+    var world_position = surface.position;
+
+    var V = normalize(view_position - world_position);
+    var F0 = mix(vec3(0.04), albedo, metallic);
+
+    // Over all lights:
+    var Lo = vec3(0.0);
+
+    for (var i: u32 = 0; i < point_lights.count; i = i + 1 ) {
+        var light = point_lights.lights[i];
+
+        var L = normalize(light.position - world_position);
+        var H = normalize(V + L);
+
+        var light_distance = length(light.position - world_position);
+        var attenuation = 1.0 / (light_distance * light_distance);
+        var light_radiance = light.color * attenuation;
+
+        // Calculate Cook-Torrance specular BRDF: DFG / 4(ωo⋅n)(ωi⋅n)
+        var F = fresnel_schlick(max( dot(H, V), 0.0 ), F0);
+        var D = distribution_ggx(normal, H, roughness);
+        var G = geometry_smith(normal, V, L, roughness);
+
+        var numerator = D*F*G;
+        var denominator = 4.0 * max(dot(normal, V), 0.0) * max(dot(normal, L), 0.0) + 0.001;
+        var specular = numerator / denominator;
+
+        // Calculate ratio of reflected-refracted light.
+        var kS = F;
+        var kD = vec3f(1.0) - kS;
+
+        kD *= 1.0 - metallic;	
+
+        // Calculate output radiance.
+        var NdotL = max(dot(normal, L), 0.0);
+
+        var Loi = (kD * albedo / PI + specular) * light_radiance * NdotL;
+
+        // add to outgoing radiance Lo
+#ifdef SHADOWS
+        var shadow = shadow(world_position, i);
+        Lo += Loi * (1.0 - shadow);
+#else
+        Lo += Loi;
+#endif
+    }
+
+    // ambient lighting.
+    var F = fresnel_schlick_roughness(max(dot(normal, V), 0.0), F0, roughness);
+    var kS = F;
+    var kD = vec3f(1.0) - kS;
+    kD *= 1.0 - metallic;
+    
+    var irradiance = textureSample(irrMap, irrSampler, normal).rgb;
+    var diffuse = irradiance * albedo;
+    var ambient = (kD * diffuse) * surface.ao;
+    
+    return ambient + Lo;
+}
+
+fn calc_attenuation(world_position: vec3f, light: PointLight) -> f32 {
+    var d: f32 = length(light.position - world_position);
+
+    var constant = 1.0;
+    var linear = 0.027;
+    var quadratic = 0.0028;
+
+    var attenuation: f32 = 1.0 / (constant + linear * d + quadratic * (d * d));
+
+    return attenuation;
+}
+
+fn sample_shadow_map(i: u32, direction: vec3f) -> f32 {
+    switch i {
+        case 0u: {
+            return textureSample(shadow_map_0, shadow_sampler, direction).r;
+        }
+        case 1u: {
+            return textureSample(shadow_map_1, shadow_sampler, direction).r;
+        }
+        default: {
+            return textureSample(shadow_map_2, shadow_sampler, direction).r;
+        }
+    }
+}
+
+fn shadow(world_position: vec3f, i: u32) -> f32 {
+    let light = point_lights.lights[i];
+
+    var zFar = 25.0;
+    var zNear = 0.5;
+    var bias = 0.01;
+
+    var fragToLight: vec3f = world_position - light.position;
+
+    var currentDepth = length(fragToLight) / zFar;
+
+    var closestDepth = sample_shadow_map(i, fragToLight);
+
+    var shadow = select(0.0, 1.0, currentDepth - closestDepth > bias);
+    return shadow;
+}
+
+fn distribution_ggx(N: vec3f, H: vec3f, roughness: f32) -> f32 {
+    var a = roughness * roughness;
+    var a2 = a*a;
+    var NdotH  = max(dot(N, H), 0.0);
+    var NdotH2 = NdotH*NdotH;
+	
+    var nom = a2;
+    var denom  = (NdotH2 * (a2 - 1.0) + 1.0);
+    denom = PI * denom * denom;
+	
+    return nom / denom;    
+}
+
+fn geometry_schlick_ggx(NdotV: f32, roughness: f32) -> f32 {
+    var r = (roughness + 1.0);
+    var k = (r * r) / 8.0;
+
+    var nom   = NdotV;
+    var denom = NdotV * (1.0 - k) + k;
+	
+    return nom / denom;
+}
+  
+fn geometry_smith(N: vec3f, V: vec3f, L: vec3f, roughness: f32) -> f32 {
+    var NdotV = max(dot(N, V), 0.0);
+    var NdotL = max(dot(N, L), 0.0);
+    var ggx1 = geometry_schlick_ggx(NdotV, roughness);
+    var ggx2 = geometry_schlick_ggx(NdotL, roughness);
+	
+    return ggx1 * ggx2;
+}
+
+fn fresnel_schlick(cosTheta: f32, F0: vec3f) -> vec3f {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+fn fresnel_schlick_roughness(cosTheta: f32, F0: vec3f, roughness: f32) -> vec3f {
+    return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
@@ -0,0 +1,87 @@
+// Material bindings of a single material, the bind group is switched for every
+// material so the material id is not used.
+
+struct MaterialProperties {
+    base_color: vec4f,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    alpha_cutoff: f32,
+    emissive_factor: vec3f,
+}
+
+@group(2) @binding(0) var<uniform> material: MaterialProperties;
+@group(2) @binding(1) var baseColorSampler: sampler;
+@group(2) @binding(2) var baseColorTexture: texture_2d<f32>;
+@group(2) @binding(3) var normalSampler: sampler;
+@group(2) @binding(4) var normalTexture: texture_2d<f32>;
+@group(2) @binding(5) var metallicRoughnessSampler: sampler;
+@group(2) @binding(6) var metallicRoughnessTexture: texture_2d<f32>;
+@group(2) @binding(7) var ambientOcclussionSampler: sampler;
+@group(2) @binding(8) var ambientOcclussionTexture: texture_2d<f32>;
+@group(2) @binding(9) var emissiveSampler: sampler;
+@group(2) @binding(10) var emissiveTexture: texture_2d<f32>;
+
+fn get_alpha_cutoff(material_id: u32) -> f32 {
+    return material.alpha_cutoff;
+}
+
+fn get_base_color(material_id: u32, uv: vec2f) -> vec4f {
+    var textureColor: vec4f;
+    if (textureDimensions(baseColorTexture).x > 1) {
+        textureColor = textureSample(baseColorTexture, baseColorSampler, uv);
+    } else {
+        textureColor = vec4f(1.0, 1.0, 1.0, 1.0);
+    }
+    return material.base_color * textureColor;
+}
+
+// The pipeline variant tells whether the material has a normal map
+fn get_normal(material_id: u32, uv: vec2f, tbn_matrix: mat3x3f) -> vec3f {
+#ifdef NORMAL_MAP
+    var normal = textureSample(normalTexture, normalSampler, uv).xyz;
+    normal = normal * 2.0 - 1.0;
+    return normalize(tbn_matrix * normal);
+#else
+    return tbn_matrix[2];
+#endif
+}
+
+fn get_metalness(material_id: u32, uv: vec2f) -> f32 {
+    var metalness: f32;
+    if (textureDimensions(metallicRoughnessTexture).x > 1) {
+        metalness = textureSample(metallicRoughnessTexture, metallicRoughnessSampler, uv).b;
+    } else {
+        metalness = material.metallic_factor;
+    }
+    return metalness;
+}
+
+fn get_roughness(material_id: u32, uv: vec2f) -> f32 {
+    var roughness: f32;
+    if (textureDimensions(metallicRoughnessTexture).x > 1) {
+        roughness = textureSample(metallicRoughnessTexture, metallicRoughnessSampler, uv).g;
+    } else {
+        roughness = material.roughness_factor;
+    }
+    return roughness;
+}
+
+fn get_ambient_occlussion(material_id: u32, uv: vec2f) -> f32 {
+    var ao: f32;
+    if (textureDimensions(ambientOcclussionTexture).x > 1) {
+        ao = textureSample(ambientOcclussionTexture, ambientOcclussionSampler, uv).g;
+    } else {
+        ao = 1.0;
+    }
+    return ao;
+}
+
+fn get_emissive(material_id: u32, uv: vec2f) -> vec3f {
+    var emissive: vec3f;
+    if (textureDimensions(emissiveTexture).x > 1) {
+        emissive = textureSample(emissiveTexture, emissiveSampler, uv).rgb;
+    } else {
+        emissive = vec3f(1.0);
+    }
+    return material.emissive_factor * emissive;
+}
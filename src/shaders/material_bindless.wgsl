@@ -0,0 +1,76 @@
+// Material bindings of every material at once. The parameters are indexed by
+// the material id and hold the indices of their textures in the binding array,
+// the first texture being an empty placeholder for the missing ones.
+
+struct MaterialProperties {
+    base_color: vec4f,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    alpha_cutoff: f32,
+    base_color_texture: u32,
+    normal_texture: u32,
+    metallic_roughness_texture: u32,
+    ambient_occlussion_texture: u32,
+    emissive_factor: vec3f,
+    emissive_texture: u32,
+}
+
+@group(2) @binding(0) var<storage, read> materials: array<MaterialProperties>;
+@group(2) @binding(1) var material_samplers: binding_array<sampler>;
+@group(2) @binding(2) var material_textures: binding_array<texture_2d<f32>>;
+
+// The material id varies between fragments, so the texture is always sampled
+// and the result discarded when there is no texture
+fn sample_material_texture(index: u32, uv: vec2f) -> vec4f {
+    return textureSample(material_textures[index], material_samplers[index], uv);
+}
+
+fn has_material_texture(index: u32) -> bool {
+    return index != 0u;
+}
+
+fn get_alpha_cutoff(material_id: u32) -> f32 {
+    return materials[material_id].alpha_cutoff;
+}
+
+fn get_base_color(material_id: u32, uv: vec2f) -> vec4f {
+    let material = materials[material_id];
+    let sampled = sample_material_texture(material.base_color_texture, uv);
+    let textureColor = select(vec4f(1.0), sampled, has_material_texture(material.base_color_texture));
+    return material.base_color * textureColor;
+}
+
+// The pipeline variant tells whether the material has a normal map, so it is
+// only sampled when there is one
+fn get_normal(material_id: u32, uv: vec2f, tbn_matrix: mat3x3f) -> vec3f {
+#ifdef NORMAL_MAP
+    let sampled = sample_material_texture(materials[material_id].normal_texture, uv).xyz * 2.0 - 1.0;
+    return normalize(tbn_matrix * sampled);
+#else
+    return tbn_matrix[2];
+#endif
+}
+
+fn get_metalness(material_id: u32, uv: vec2f) -> f32 {
+    let material = materials[material_id];
+    let sampled = sample_material_texture(material.metallic_roughness_texture, uv).b;
+    return select(material.metallic_factor, sampled, has_material_texture(material.metallic_roughness_texture));
+}
+
+fn get_roughness(material_id: u32, uv: vec2f) -> f32 {
+    let material = materials[material_id];
+    let sampled = sample_material_texture(material.metallic_roughness_texture, uv).g;
+    return select(material.roughness_factor, sampled, has_material_texture(material.metallic_roughness_texture));
+}
+
+fn get_ambient_occlussion(material_id: u32, uv: vec2f) -> f32 {
+    let material = materials[material_id];
+    let sampled = sample_material_texture(material.ambient_occlussion_texture, uv).g;
+    return select(1.0, sampled, has_material_texture(material.ambient_occlussion_texture));
+}
+
+fn get_emissive(material_id: u32, uv: vec2f) -> vec3f {
+    let material = materials[material_id];
+    let sampled = sample_material_texture(material.emissive_texture, uv).rgb;
+    return material.emissive_factor * select(vec3f(1.0), sampled, has_material_texture(material.emissive_texture));
+}
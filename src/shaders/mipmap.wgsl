@@ -0,0 +1,39 @@
+// Downsamples a mip level into the next one with a 2x2 box filter. sRGB
+// textures are loaded and written through sRGB views, so the average is taken
+// in linear space.
+
+#import "include/fullscreen.wgsl"
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4f {
+    return fullscreen_position(id, 0.0);
+}
+
+// Loads instead of sampling, so it also works on unfilterable float textures.
+// Odd sizes drop the last row or column of the level above.
+fn average(position: vec4f) -> vec4f {
+    let max_texel = vec2i(textureDimensions(source)) - 1;
+    let texel = vec2i(position.xy) * 2;
+
+    return (
+        textureLoad(source, min(texel, max_texel), 0)
+        + textureLoad(source, min(texel + vec2i(1, 0), max_texel), 0)
+        + textureLoad(source, min(texel + vec2i(0, 1), max_texel), 0)
+        + textureLoad(source, min(texel + vec2i(1, 1), max_texel), 0)
+    ) * 0.25;
+}
+
+@fragment
+fn fs_color(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    return average(position);
+}
+
+// Averaged normals get shorter where they diverge
+@fragment
+fn fs_normal(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let color = average(position);
+    let normal = normalize(color.xyz * 2.0 - 1.0);
+    return vec4f(normal * 0.5 + 0.5, color.a);
+}
@@ -0,0 +1,40 @@
+#import "include/fullscreen.wgsl"
+
+struct VertexOutput {
+    @location(0) uv: vec2<f32>,
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vi: u32
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.uv = fullscreen_uv(vi);
+    out.clip_position = fullscreen_position(vi, 0.0);
+    return out;
+}
+
+@group(0)
+@binding(0)
+var accum_texture: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var revealage_texture: texture_2d<f32>;
+
+@fragment
+fn fs_main(vs: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(vs.clip_position.xy);
+
+    // Revealage is the product of (1 - alpha) of every transparent fragment
+    let revealage = textureLoad(revealage_texture, pixel, 0).r;
+    if (revealage >= 1.0) {
+        discard;
+    }
+
+    let accum = textureLoad(accum_texture, pixel, 0);
+    let average_color = accum.rgb / max(accum.a, 1e-5);
+
+    return vec4(average_color, 1.0 - revealage);
+}
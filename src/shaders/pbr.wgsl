@@ -1,44 +1,32 @@
-struct Vertex {
-    @location(0) position: vec3f,
-    @location(1) uv: vec2f,
-    @location(2) normal: vec3f,
-    @location(3) tangent: vec4f,
-}
+#ifdef BINDLESS
+#import "material_bindless.wgsl"
+#else
+#import "material.wgsl"
+#endif
+#import "lighting.wgsl"
+#import "include/camera.wgsl"
+#import "include/transform.wgsl"
+#import "include/vertex.wgsl"
 
 struct VSOut {
-    @builtin(position) position: vec4f, 
+    // Invariant so the depth prepass and the shading pass write the same depth
+    @builtin(position) @invariant position: vec4f,
     @location(0) uv: vec2f,
     @location(1) normal: vec3f,
     @location(2) world_position: vec4f,
     @location(3) tangent: vec3f,
     @location(4) bitangent: vec3f,
+    @location(5) @interpolate(flat) material: u32,
 }
 
-
-struct Camera {
-    position: vec3f,
-    view: mat4x4f,
-    inv_view: mat4x4f,
-    proj: mat4x4f,
-    inv_proj: mat4x4f
-}
-
-@group(0) @binding(0) var<uniform> camera: Camera;
-
-struct Transform {
-    model: mat4x4f,
-    normal: mat4x4f
-}
-
-@group(1) @binding(0) var<uniform> transform: Transform;
-
-const PI: f32 = 3.14159265359;
-
 @vertex 
 fn vs_main(
-    vertex: Vertex,    
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32,
 ) -> VSOut {
 
+    let transform = transforms[instance_index];
+
     var vsout: VSOut;
 
     var vertex_world_position = transform.model * vec4f(vertex.position, 1.0);
@@ -49,226 +37,139 @@ fn vs_main(
     vsout.normal = normalize((transform.normal * vec4f(vertex.normal, 1.0)).xyz);
     vsout.tangent = normalize((transform.normal * vertex.tangent).xyz);
     vsout.bitangent = cross(vsout.tangent, vsout.normal) * vertex.tangent.w; // Correct right-handeness
+    vsout.material = instance_materials[instance_index];
     
     return vsout;
 }
 
-struct MaterialProperties {
-    base_color: vec4f,
-    metallic_factor: f32,
-    roughness_factor: f32,
-}
-
-@group(2) @binding(0) var<uniform> material: MaterialProperties;
-@group(2) @binding(1) var baseColorSampler: sampler;
-@group(2) @binding(2) var baseColorTexture: texture_2d<f32>;
-@group(2) @binding(3) var normalSampler: sampler;
-@group(2) @binding(4) var normalTexture: texture_2d<f32>;
-@group(2) @binding(5) var metallicRoughnessSampler: sampler;
-@group(2) @binding(6) var metallicRoughnessTexture: texture_2d<f32>;
-@group(2) @binding(7) var ambientOcclussionSampler: sampler;
-@group(2) @binding(8) var ambientOcclussionTexture: texture_2d<f32>;
+// Screen space ambient occlusion of the opaque geometry, only used by fs_main
+@group(5) @binding(0) var ssao_texture: texture_2d<f32>;
 
-struct PointLight {
-    @location(0) position: vec3f,
-    @location(1) color: vec3f,
+struct ForwardOut {
+    @location(0) color: vec4f,
+    // Normal and roughness, albedo and metallic for the screen space reflections
+    @location(1) normal: vec4f,
+    @location(2) albedo: vec4f,
 }
 
-@group(3) @binding(0) var<storage, read> lights: array<PointLight>;
-@group(3) @binding(1) var shadow_maps: binding_array<texture_cube<f32>, 3>;
-@group(3) @binding(2) var shadow_maps_samplers: binding_array<sampler, 3>;
-
-@group(4) @binding(0) var irrSampler: sampler;
-@group(4) @binding(1) var irrMap: texture_cube<f32>;;
-
 @fragment 
-fn fs_main(vsout: VSOut) -> @location(0) vec4f {
-    
-    var albedo = get_albedo(vsout.uv);
-    var normal = get_normal(vsout);
-    var metallic = get_metalness(vsout.uv);
-    var roughness = get_roughness(vsout.uv);
-    var ao = get_ambient_occlussion(vsout.uv);
-    
-    var world_position = vsout.world_position.xyz;
-
-    var V = normalize(camera.position - world_position);
-    var F0 = mix(vec3(0.04), albedo, metallic);
-
-    // Over all lights:
-    var Lo = vec3(0.0);
-
-    for (var i: u32 = 0; i < arrayLength(&lights); i = i + 1 ) {
-        var light = lights[i];
-
-        var L = normalize(light.position - world_position);
-        var H = normalize(V + L);
-
-        var light_distance = length(light.position - world_position);
-        var attenuation = 1.0 / (light_distance * light_distance);
-        var light_radiance = light.color * attenuation;
-
-        // Calculate Cook-Torrance specular BRDF: DFG / 4(ωo⋅n)(ωi⋅n)
-        var F = fresnel_schlick(max( dot(H, V), 0.0 ), F0);
-        var D = distribution_ggx(normal, H, roughness);
-        var G = geometry_smith(normal, V, L, roughness);
-
-        var numerator = D*F*G;
-        var denominator = 4.0 * max(dot(normal, V), 0.0) * max(dot(normal, L), 0.0) + 0.001;
-        var specular = numerator / denominator;
+fn fs_main(vsout: VSOut, @builtin(front_facing) front_facing: bool) -> ForwardOut {
+    var base_color = get_base_color(vsout.material, vsout.uv);
 
-        // Calculate ratio of reflected-refracted light.
-        var kS = F;
-        var kD = vec3f(1.0) - kS;
-
-        kD *= 1.0 - metallic;	
-
-        // Calculate output radiance.
-        var NdotL = max(dot(normal, L), 0.0);
-
-        var Loi = (kD * albedo / PI + specular) * light_radiance * NdotL;
-
-        // add to outgoing radiance Lo
-        var shadow = shadow(vsout, i);
-        Lo += Loi * (1.0 - shadow);
+    if (base_color.a < get_alpha_cutoff(vsout.material)) {
+        discard;
     }
 
-    // ambient lighting.
-    var F = fresnel_schlick_roughness(max(dot(normal, V), 0.0), F0, roughness);
-    var kS = F;
-    var kD = vec3f(1.0) - kS;
-    kD *= 1.0 - metallic;
-    
-    var irradiance = textureSample(irrMap, irrSampler, normal).rgb;
-    var diffuse = irradiance * albedo;
-    var ambient = (kD * diffuse) * ao;
-    
-    var color = ambient + Lo;
-    return vec4f(color, 1.0);
+    var surface = face_forward(vsout, front_facing);
+    var lit_surface = get_surface(surface, base_color.rgb);
+    lit_surface.ao *= textureLoad(ssao_texture, vec2u(vsout.position.xy), 0).r;
 
+    var out: ForwardOut;
+    out.color = vec4f(light_surface(lit_surface, camera.position) + get_emissive(surface.material, surface.uv), 1.0);
+    out.normal = vec4f(lit_surface.normal, lit_surface.roughness);
+    out.albedo = vec4f(lit_surface.albedo, lit_surface.metallic);
+    return out;
 }
 
-fn calc_attenuation(vsout: VSOut, light: PointLight) -> f32 {
-    var d: f32 = length(light.position - vsout.world_position.xyz);
-
-    var constant = 1.0;
-    var linear = 0.027;
-    var quadratic = 0.0028;
-
-    var attenuation: f32 = 1.0 / (constant + linear * d + quadratic * (d * d));
+// Depth prepass, only runs for the alpha masked materials
+@fragment
+fn fs_prepass(vsout: VSOut) {
+    if (get_base_color(vsout.material, vsout.uv).a < get_alpha_cutoff(vsout.material)) {
+        discard;
+    }
+}
 
-    return attenuation;
+struct GBufferOut {
+    @location(0) albedo: vec4f,
+    @location(1) normal: vec4f,
+    @location(2) material: vec4f,
+    @location(3) emissive: vec4f,
 }
 
-fn shadow(vsout: VSOut, i: u32) -> f32 {
-    let light = lights[i];
-    let tex = shadow_maps[i];
-    let samp = shadow_maps_samplers[i];
+// Deferred path, stores the surface for the lighting pass to shade it
+@fragment
+fn fs_gbuffer(vsout: VSOut, @builtin(front_facing) front_facing: bool) -> GBufferOut {
+    var base_color = get_base_color(vsout.material, vsout.uv);
 
-    var zFar = 25.0;
-    var zNear = 0.5;
-    var bias = 0.01;
+    if (base_color.a < get_alpha_cutoff(vsout.material)) {
+        discard;
+    }
 
-    var fragToLight: vec3f = vsout.world_position.xyz - light.position;
+    var surface = face_forward(vsout, front_facing);
+    var tbn_matrix = mat3x3f(surface.tangent, surface.bitangent, surface.normal);
 
-    var currentDepth = length(fragToLight) / zFar;
+    var out: GBufferOut;
+    out.albedo = vec4f(base_color.rgb, get_metalness(surface.material, surface.uv));
+    out.normal = vec4f(
+        get_normal(surface.material, surface.uv, tbn_matrix),
+        get_roughness(surface.material, surface.uv),
+    );
+    out.material = vec4f(
+        get_metalness(surface.material, surface.uv),
+        get_roughness(surface.material, surface.uv),
+        get_ambient_occlussion(surface.material, surface.uv),
+        0.0,
+    );
+    out.emissive = vec4f(get_emissive(surface.material, surface.uv), 0.0);
+    return out;
+}
 
-    var closestDepth = textureSample(tex, samp, fragToLight).r;
+// Blended materials output premultiplied alpha
+@fragment
+fn fs_transparent(vsout: VSOut, @builtin(front_facing) front_facing: bool) -> @location(0) vec4f {
+    var base_color = get_base_color(vsout.material, vsout.uv);
 
-    var shadow = select(0.0, 1.0, currentDepth - closestDepth > bias);
-    return shadow;
+    var color = shade(face_forward(vsout, front_facing), base_color.rgb);
+    return vec4f(color * base_color.a, base_color.a);
 }
 
-fn distribution_ggx(N: vec3f, H: vec3f, roughness: f32) -> f32 {
-    var a = roughness * roughness;
-    var a2 = a*a;
-    var NdotH  = max(dot(N, H), 0.0);
-    var NdotH2 = NdotH*NdotH;
-	
-    var nom = a2;
-    var denom  = (NdotH2 * (a2 - 1.0) + 1.0);
-    denom = PI * denom * denom;
-	
-    return nom / denom;    
+struct OitOut {
+    @location(0) accum: vec4f,
+    @location(1) revealage: f32,
 }
 
-fn geometry_schlick_ggx(NdotV: f32, roughness: f32) -> f32 {
-    var r = (roughness + 1.0);
-    var k = (r * r) / 8.0;
+// Weighted blended order independent transparency (McGuire and Bavoil, 2013)
+@fragment
+fn fs_oit(vsout: VSOut, @builtin(front_facing) front_facing: bool) -> OitOut {
+    var base_color = get_base_color(vsout.material, vsout.uv);
 
-    var nom   = NdotV;
-    var denom = NdotV * (1.0 - k) + k;
-	
-    return nom / denom;
-}
-  
-fn geometry_smith(N: vec3f, V: vec3f, L: vec3f, roughness: f32) -> f32 {
-    var NdotV = max(dot(N, V), 0.0);
-    var NdotL = max(dot(N, L), 0.0);
-    var ggx1 = geometry_schlick_ggx(NdotV, roughness);
-    var ggx2 = geometry_schlick_ggx(NdotL, roughness);
-	
-    return ggx1 * ggx2;
-}
+    var color = shade(face_forward(vsout, front_facing), base_color.rgb);
+    var alpha = base_color.a;
 
-fn fresnel_schlick(cosTheta: f32, F0: vec3f) -> vec3f {
-    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
-}
+    // Closer and more opaque surfaces weigh more in the average
+    var depth = vsout.position.z;
+    var weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - depth * 0.9, 3.0), 1e-2, 3e3);
 
-fn fresnel_schlick_roughness(cosTheta: f32, F0: vec3f, roughness: f32) -> vec3f {
-    return F0 + (max(vec3(1.0 - roughness), F0) - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+    var out: OitOut;
+    out.accum = vec4f(color * alpha, alpha) * weight;
+    out.revealage = alpha;
+    return out;
 }
 
-
-fn get_albedo(uv: vec2f) -> vec3f {
-     var textureColor: vec4f;
-    if (textureDimensions(baseColorTexture).x > 1) {
-        textureColor = textureSample(baseColorTexture, baseColorSampler, uv);
-    } else {
-        textureColor = vec4f(1.0, 1.0, 1.0, 1.0);
+// Back faces are only rasterized for double sided materials, shade them with the flipped normal
+fn face_forward(vsout: VSOut, front_facing: bool) -> VSOut {
+    var out = vsout;
+    if (!front_facing) {
+        out.normal = -out.normal;
+        out.tangent = -out.tangent;
+        out.bitangent = -out.bitangent;
     }
-    return (material.base_color * textureColor).rgb;
+    return out;
 }
 
-fn get_normal(vsout: VSOut) -> vec3f {
-    var normal: vec3f;
-    if (textureDimensions(normalTexture).x > 1) {
-        var tbn_matrix = mat3x3f(vsout.tangent, vsout.bitangent, vsout.normal);
-        normal = textureSample(normalTexture, normalSampler, vsout.uv).xyz;
-        normal = normal * 2.0 - 1.0;
-        normal = normalize(tbn_matrix * normal);
-    } else {
-        normal = vsout.normal;
-    }
-    return normal;
+fn shade(vsout: VSOut, albedo: vec3f) -> vec3f {
+    return light_surface(get_surface(vsout, albedo), camera.position) + get_emissive(vsout.material, vsout.uv);
 }
 
-fn get_metalness(uv: vec2f) -> f32 {
-    var metalness: f32;
-    if (textureDimensions(metallicRoughnessTexture).x > 1) {
-        metalness = textureSample(metallicRoughnessTexture, metallicRoughnessSampler, uv).b;
-    } else {
-        metalness = material.metallic_factor;
-    }
-    return metalness;
-}
+fn get_surface(vsout: VSOut, albedo: vec3f) -> Surface {
+    var tbn_matrix = mat3x3f(vsout.tangent, vsout.bitangent, vsout.normal);
 
-fn get_roughness(uv: vec2f) -> f32 {
-    var roughness: f32;
-    if (textureDimensions(metallicRoughnessTexture).x > 1) {
-        roughness = textureSample(metallicRoughnessTexture, metallicRoughnessSampler, uv).g;
-    } else {
-        roughness = material.roughness_factor;
-    }
-    return roughness;
+    var surface: Surface;
+    surface.position = vsout.world_position.xyz;
+    surface.normal = get_normal(vsout.material, vsout.uv, tbn_matrix);
+    surface.albedo = albedo;
+    surface.metallic = get_metalness(vsout.material, vsout.uv);
+    surface.roughness = get_roughness(vsout.material, vsout.uv);
+    surface.ao = get_ambient_occlussion(vsout.material, vsout.uv);
+    return surface;
 }
-
-fn get_ambient_occlussion(uv: vec2f) -> f32 {
-    var ao: f32;
-    if (textureDimensions(ambientOcclussionTexture).x > 1) {
-        ao = textureSample(ambientOcclussionTexture, ambientOcclussionSampler, uv).g;
-    } else {
-        ao = 1.0;
-    }
-    return ao;
-}
\ No newline at end of file
@@ -1,33 +1,27 @@
-struct Vertex {
-    @location(0) position: vec3f,
-}
+#ifdef BINDLESS
+#import "material_bindless.wgsl"
+#else
+#import "material.wgsl"
+#endif
+#import "include/camera.wgsl"
+#import "include/transform.wgsl"
+#import "include/vertex.wgsl"
 
 struct VSOut {
     @builtin(position) position: vec4f,
+    @location(0) uv: vec2f,
     @location(1) distance: f32,
+    @location(2) @interpolate(flat) material: u32,
 }
 
-struct Camera {
-    position: vec3f,
-    view: mat4x4f,
-    inv_view: mat4x4f,
-    proj: mat4x4f,
-    inv_proj: mat4x4f
-}
-
-@group(0) @binding(0) var<uniform> camera: Camera;
-
-struct Transform {
-    model: mat4x4f
-}
-
-@group(1) @binding(0) var<uniform> transform: Transform;
-
 @vertex 
 fn vs_main(
-    vertex: Vertex,    
+    vertex: Vertex,
+    @builtin(instance_index) instance_index: u32,
 ) -> VSOut {
 
+    let transform = transforms[instance_index];
+
     let invert_x = mat4x4f(
         vec4f(-1.0, 0.0, 0.0, 0.0),
         vec4f( 0.0, 1.0, 0.0, 0.0),
@@ -38,6 +32,8 @@ fn vs_main(
     var vsout: VSOut;
     var camera_space_vertex_position = camera.view * transform.model * vec4f(vertex.position, 1.0);
     
+    vsout.uv = vertex.uv;
+    vsout.material = instance_materials[instance_index];
     vsout.distance = min(length(camera_space_vertex_position.xyz) / 25.0, 1.0); //TODO read zFar plane from a uniform?
     vsout.position = invert_x * camera.proj * camera_space_vertex_position;
 
@@ -46,5 +42,27 @@ fn vs_main(
 
 @fragment
 fn fs_main(vsout: VSOut) -> @location(0) vec4f {
+    // Alpha masked materials cut holes in the shadow too
+    var alpha = get_base_color(vsout.material, vsout.uv).a;
+
+    if (alpha < get_alpha_cutoff(vsout.material)) {
+        discard;
+    }
+
     return vec4f(vsout.distance, vsout.distance, vsout.distance, 1.0);
-}
\ No newline at end of file
+}
+
+// Blended materials have no cutoff, they cast a shadow where they are mostly
+// opaque
+const BLENDED_CUTOFF: f32 = 0.5;
+
+@fragment
+fn fs_blended(vsout: VSOut) -> @location(0) vec4f {
+    var alpha = get_base_color(vsout.material, vsout.uv).a;
+
+    if (alpha < BLENDED_CUTOFF) {
+        discard;
+    }
+
+    return vec4f(vsout.distance, vsout.distance, vsout.distance, 1.0);
+}
@@ -1,3 +1,6 @@
+#import "include/camera.wgsl"
+#import "include/fullscreen.wgsl"
+
 struct VSOut {
     @builtin(position) frag_position: vec4<f32>,
     @location(0) clip_position: vec4<f32>,
@@ -9,28 +12,13 @@ fn vs_main(
 ) -> VSOut {
 
     // Reference: https://sotrh.github.io/learn-wgpu/intermediate/tutorial13-hdr/#skybox
-    let uv = vec2<f32>(vec2<u32>(
-        id & 1u,
-        (id >> 1u) & 1u,
-    ));
-
     var vsout: VSOut;
 
-    vsout.clip_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
-    vsout.frag_position = vec4(uv * 4.0 - 1.0, 1.0, 1.0);
+    vsout.clip_position = fullscreen_position(id, 1.0);
+    vsout.frag_position = vsout.clip_position;
     return vsout;
 }
 
-struct Camera {
-    position: vec3f,
-    view: mat4x4f,
-    inv_view: mat4x4f,
-    proj: mat4x4f,
-    inv_proj: mat4x4f,
-}
-
-@group(0) @binding(0) var<uniform> camera: Camera;
-
 @group(1) @binding(0) var env_sampler: sampler;
 @group(1) @binding(1) var env_map: texture_cube<f32>;
 
@@ -0,0 +1,119 @@
+// Screen space ambient occlusion from the depth of the opaque geometry. The
+// occlusion is the share of the samples in a hemisphere around each surface
+// that end up behind the depth buffer, blurred without crossing edges.
+
+#import "include/camera.wgsl"
+#import "include/fullscreen.wgsl"
+
+@group(1) @binding(0) var depth_texture: texture_depth_2d;
+
+struct Ssao {
+    radius: f32,
+    intensity: f32,
+    sample_count: u32,
+    // Hemisphere samples around +z, denser close to the center
+    kernel: array<vec4f, 64>,
+}
+
+@group(2) @binding(0) var<uniform> ssao: Ssao;
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4f {
+    return fullscreen_position(id, 0.0);
+}
+
+fn view_position(texel: vec2i) -> vec3f {
+    let size = vec2i(textureDimensions(depth_texture));
+    let clamped = clamp(texel, vec2i(0), size - 1);
+    let depth = textureLoad(depth_texture, clamped, 0);
+
+    let uv = (vec2f(clamped) + 0.5) / vec2f(size);
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = camera.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// The normal from the neighbours closest in depth, so edges do not bend it
+fn view_normal(texel: vec2i, center: vec3f) -> vec3f {
+    let left = center - view_position(texel - vec2i(1, 0));
+    let right = view_position(texel + vec2i(1, 0)) - center;
+    let up = center - view_position(texel - vec2i(0, 1));
+    let down = view_position(texel + vec2i(0, 1)) - center;
+
+    let dx = select(right, left, abs(left.z) < abs(right.z));
+    let dy = select(down, up, abs(up.z) < abs(down.z));
+    return normalize(cross(dy, dx));
+}
+
+// Interleaved gradient noise (Jimenez, 2014), rotates the kernel per pixel
+fn noise(texel: vec2f) -> f32 {
+    return fract(52.9829189 * fract(dot(texel, vec2f(0.06711056, 0.00583715))));
+}
+
+@fragment
+fn fs_ssao(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let texel = vec2i(position.xy);
+    if (textureLoad(depth_texture, texel, 0) >= 1.0) {
+        return vec4f(1.0);
+    }
+
+    let center = view_position(texel);
+    let normal = view_normal(texel, center);
+
+    // Basis around the normal, rotated by the noise
+    let angle = noise(position.xy) * 2.0 * 3.14159265359;
+    let random = vec3f(cos(angle), sin(angle), 0.0);
+    let tangent = normalize(random - normal * dot(random, normal));
+    let tbn = mat3x3f(tangent, cross(normal, tangent), normal);
+
+    let size = vec2f(textureDimensions(depth_texture));
+    let sample_count = min(ssao.sample_count, 64u);
+    var occlusion = 0.0;
+
+    for (var i = 0u; i < sample_count; i++) {
+        let sample = center + tbn * ssao.kernel[i].xyz * ssao.radius;
+
+        let clip = camera.proj * vec4f(sample, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2f(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(uv < vec2f(0.0)) || any(uv > vec2f(1.0))) {
+            continue;
+        }
+
+        let scene = view_position(vec2i(uv * size));
+
+        // Geometry far in front of the surface does not occlude it
+        let range = smoothstep(0.0, 1.0, ssao.radius / abs(center.z - scene.z));
+        occlusion += select(0.0, 1.0, scene.z >= sample.z + 0.025) * range;
+    }
+
+    let visibility = 1.0 - occlusion / f32(max(sample_count, 1u));
+    return vec4f(pow(visibility, ssao.intensity));
+}
+
+@group(3) @binding(0) var raw_ssao: texture_2d<f32>;
+
+// 5x5 blur weighted down across depth discontinuities
+@fragment
+fn fs_blur(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let texel = vec2i(position.xy);
+    let size = vec2i(textureDimensions(raw_ssao));
+    let center = view_position(texel).z;
+
+    var total = 0.0;
+    var weights = 0.0;
+
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let neighbour = clamp(texel + vec2i(x, y), vec2i(0), size - 1);
+            let depth = view_position(neighbour).z;
+
+            // Neighbours more than 5% away in depth are on another surface
+            let weight = 1.0 - smoothstep(0.0, 0.05 * abs(center), abs(center - depth));
+            total += textureLoad(raw_ssao, neighbour, 0).r * weight;
+            weights += weight;
+        }
+    }
+
+    return vec4f(total / weights);
+}
@@ -0,0 +1,216 @@
+// Screen space reflections of the opaque geometry. The reflected rays march the
+// nearest depth pyramid, the hits are blurred more on rougher surfaces and the
+// environment cube map fills in where the rays miss.
+
+#import "include/camera.wgsl"
+#import "include/fullscreen.wgsl"
+
+// Normal and roughness, albedo and metallic
+@group(1) @binding(0) var surface_normal: texture_2d<f32>;
+@group(1) @binding(1) var surface_albedo: texture_2d<f32>;
+
+@group(2) @binding(0) var depth_texture: texture_depth_2d;
+
+struct Ssr {
+    max_steps: u32,
+    // View space distance a hit can be behind the depth buffer
+    thickness: f32,
+    max_distance: f32,
+    max_roughness: f32,
+    // In pixels, at the maximum roughness
+    blur_radius: f32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) id: u32) -> @builtin(position) vec4f {
+    return fullscreen_position(id, 0.0);
+}
+
+fn view_position(uv: vec2f, depth: f32) -> vec3f {
+    let ndc = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = camera.inv_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// TRACE
+
+// The nearest depth pyramid
+@group(3) @binding(1) var pyramid_texture: texture_2d<f32>;
+
+@group(4) @binding(0) var<uniform> ssr: Ssr;
+@group(4) @binding(1) var scene_color: texture_2d<f32>;
+
+// Hierarchical traversal (Uludag, 2014) of a ray in screen space, where the
+// depth is linear along it. Climbs a level while the ray passes over cells and
+// descends where it reaches their nearest depth, so it hits at the first level.
+fn trace(origin: vec3f, direction: vec3f) -> vec4f {
+    let max_level = i32(textureNumLevels(pyramid_texture)) - 1;
+
+    // Starts outside the texel of the surface itself
+    let size = vec2f(textureDimensions(pyramid_texture, 0));
+    var level = 0;
+    var t = cell_exit(origin, direction, floor(origin.xy * size), size);
+
+    for (var i = 0u; i < ssr.max_steps; i++) {
+        let position = origin + direction * t;
+        if (t > 1.0 || any(position < vec3f(0.0)) || any(position >= vec3f(1.0))) {
+            break;
+        }
+
+        let level_size = vec2f(textureDimensions(pyramid_texture, level));
+        let cell = floor(position.xy * level_size);
+        let nearest = textureLoad(pyramid_texture, vec2i(cell), level).r;
+
+        if (position.z < nearest) {
+            // Where the ray leaves the cell, or reaches its nearest depth
+            let t_boundary = cell_exit(origin, direction, cell, level_size);
+            let t_depth = select(1e10, (nearest - origin.z) / direction.z, direction.z > 0.0);
+
+            if (t_depth < t_boundary) {
+                t = t_depth;
+                level = max(level - 1, 0);
+            } else {
+                t = t_boundary;
+                level = min(level + 1, max_level);
+            }
+        } else if (level == 0) {
+            return vec4f(position, 1.0);
+        } else {
+            level -= 1;
+        }
+    }
+
+    return vec4f(0.0);
+}
+
+// Where the ray crosses into the next cell of a level
+fn cell_exit(origin: vec3f, direction: vec3f, cell: vec2f, level_size: vec2f) -> f32 {
+    let cell_offset = select(vec2f(-0.001), vec2f(1.001), direction.xy > vec2f(0.0));
+    let boundary = (cell + cell_offset) / level_size;
+    let t = select((boundary - origin.xy) / direction.xy, vec2f(1e10), abs(direction.xy) < vec2f(1e-8));
+    return min(t.x, t.y);
+}
+
+@fragment
+fn fs_trace(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let texel = vec2i(position.xy);
+    let size = vec2f(textureDimensions(depth_texture));
+    let depth = textureLoad(depth_texture, texel, 0);
+
+    let normal_roughness = textureLoad(surface_normal, texel, 0);
+    let roughness = normal_roughness.w;
+    if (depth >= 1.0 || roughness > ssr.max_roughness) {
+        return vec4f(0.0);
+    }
+
+    let uv = position.xy / size;
+    let view_origin = view_position(uv, depth);
+    let view_normal = normalize((camera.view * vec4f(normal_roughness.xyz, 0.0)).xyz);
+    let reflected = reflect(normalize(view_origin), view_normal);
+
+    // Stop before the ray gets behind the camera
+    var ray_length = ssr.max_distance;
+    if (reflected.z > 0.0) {
+        ray_length = min(ray_length, -0.99 * view_origin.z / reflected.z);
+    }
+
+    let clip = camera.proj * vec4f(view_origin + reflected * ray_length, 1.0);
+    let end = vec3f(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5, clip.z / clip.w);
+    let origin = vec3f(uv, depth);
+
+    let hit = trace(origin, end - origin);
+    if (hit.w == 0.0) {
+        return vec4f(0.0);
+    }
+
+    let hit_texel = vec2i(hit.xy * size);
+
+    // Rays that pass far behind thin geometry do not hit it
+    let hit_depth = view_position(hit.xy, textureLoad(depth_texture, hit_texel, 0)).z;
+    let ray_depth = view_position(hit.xy, hit.z).z;
+    if (hit_depth - ray_depth > ssr.thickness) {
+        return vec4f(0.0);
+    }
+
+    // Back faces of the hit geometry are not visible on screen
+    let hit_normal = (camera.view * vec4f(textureLoad(surface_normal, hit_texel, 0).xyz, 0.0)).xyz;
+    if (dot(hit_normal, reflected) > 0.0) {
+        return vec4f(0.0);
+    }
+
+    // Fades out towards the screen edges and the maximum roughness
+    let edge = min(min(hit.x, 1.0 - hit.x), min(hit.y, 1.0 - hit.y));
+    let confidence = smoothstep(0.0, 0.1, edge)
+        * (1.0 - smoothstep(ssr.max_roughness * 0.75, ssr.max_roughness, roughness));
+
+    return vec4f(textureLoad(scene_color, hit_texel, 0).rgb, confidence);
+}
+
+// COMPOSITE
+
+@group(3) @binding(0) var<uniform> composite: Ssr;
+@group(3) @binding(1) var reflections: texture_2d<f32>;
+@group(3) @binding(2) var reflections_sampler: sampler;
+
+@group(4) @binding(0) var env_sampler: sampler;
+@group(4) @binding(1) var env_map: texture_cube<f32>;
+
+// Analytic fit of the split sum environment BRDF (Karis, 2014)
+fn env_brdf(f0: vec3f, roughness: f32, n_dot_v: f32) -> vec3f {
+    let c0 = vec4f(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = vec4f(1.0, 0.0425, 1.04, -0.04);
+    let r = roughness * c0 + c1;
+    let a004 = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    let ab = vec2f(-1.04, 1.04) * a004 + r.zw;
+    return f0 * ab.x + ab.y;
+}
+
+// Adds the specular reflection of the environment on top of the shaded opaque
+// geometry, from the traced hits where there are some
+@fragment
+fn fs_composite(@builtin(position) position: vec4f) -> @location(0) vec4f {
+    let texel = vec2i(position.xy);
+    let size = vec2f(textureDimensions(depth_texture));
+    let depth = textureLoad(depth_texture, texel, 0);
+
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    let normal_roughness = textureLoad(surface_normal, texel, 0);
+    let albedo_metallic = textureLoad(surface_albedo, texel, 0);
+    let normal = normalize(normal_roughness.xyz);
+    let roughness = normal_roughness.w;
+
+    let uv = position.xy / size;
+    let world_position = camera.inv_view * vec4f(view_position(uv, depth), 1.0);
+    let v = normalize(camera.position - world_position.xyz);
+    let r = reflect(-v, normal);
+
+    // Rougher surfaces spread the hits over a wider area
+    let radius = roughness / composite.max_roughness * composite.blur_radius / size;
+    var hits = vec4f(0.0);
+    var weights = 0.0;
+
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let offset = vec2f(f32(x), f32(y));
+            let weight = exp(-dot(offset, offset) / 4.0);
+            let hit = textureSampleLevel(reflections, reflections_sampler, uv + offset * radius * 0.5, 0.0);
+            hits += vec4f(hit.rgb * hit.a, hit.a) * weight;
+            weights += weight;
+        }
+    }
+
+    let hit_color = hits.rgb / max(hits.a, 1e-4);
+    let confidence = hits.a / weights;
+
+    // Rougher surfaces reflect a blurrier level of the environment
+    let max_level = f32(textureNumLevels(env_map) - 1);
+    let environment = textureSampleLevel(env_map, env_sampler, r, roughness * max_level).rgb;
+    let reflected = mix(environment, hit_color, confidence);
+
+    let f0 = mix(vec3f(0.04), albedo_metallic.rgb, albedo_metallic.a);
+    let specular = reflected * env_brdf(f0, roughness, max(dot(normal, v), 0.0));
+    return vec4f(specular, 0.0);
+}
@@ -0,0 +1,187 @@
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign},
+};
+
+/// Objects submitted by a pass, objects skipped by frustum culling on the CPU
+/// and the draw calls the submitted objects took once instanced. The GPU
+/// driven path submits every object and culls them on the GPU, the objects it
+/// drew and culled are read back from the [CullStats] of an earlier frame,
+/// once there is one. The state changes are the
+/// pipelines, bind groups and buffers bound, and the ones skipped because they
+/// were already bound.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStats {
+    pub submitted: u32,
+    pub culled: u32,
+    pub draw_calls: u32,
+    pub pipeline_changes: u32,
+    pub bind_group_changes: u32,
+    pub buffer_changes: u32,
+    pub skipped_changes: u32,
+}
+
+impl DrawStats {
+    /// Adds the draw calls and state changes of another pass over the same
+    /// objects, which are already counted.
+    pub fn with_pass(self, other: DrawStats) -> DrawStats {
+        DrawStats {
+            submitted: self.submitted,
+            culled: self.culled,
+            ..self + other
+        }
+    }
+
+    pub fn state_changes(&self) -> u32 {
+        self.pipeline_changes + self.bind_group_changes + self.buffer_changes
+    }
+}
+
+impl Add for DrawStats {
+    type Output = DrawStats;
+
+    fn add(self, other: DrawStats) -> DrawStats {
+        DrawStats {
+            submitted: self.submitted + other.submitted,
+            culled: self.culled + other.culled,
+            draw_calls: self.draw_calls + other.draw_calls,
+            pipeline_changes: self.pipeline_changes + other.pipeline_changes,
+            bind_group_changes: self.bind_group_changes + other.bind_group_changes,
+            buffer_changes: self.buffer_changes + other.buffer_changes,
+            skipped_changes: self.skipped_changes + other.skipped_changes,
+        }
+    }
+}
+
+impl AddAssign for DrawStats {
+    fn add_assign(&mut self, other: DrawStats) {
+        *self = *self + other;
+    }
+}
+
+impl Display for DrawStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} submitted, {} culled, {} draws, {} state changes ({} skipped)",
+            self.submitted,
+            self.culled,
+            self.draw_calls,
+            self.state_changes(),
+            self.skipped_changes
+        )
+    }
+}
+
+/// Objects a GPU cull phase found visible and occluded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullCounts {
+    pub visible: u32,
+    pub occluded: u32,
+}
+
+/// What the GPU cull pass did with the objects of a frame, read back a frame
+/// or more late. The objects not counted by the first phase are outside the
+/// frustum.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats {
+    pub objects: u32,
+    /// Against the depth of the previous frame.
+    pub first: CullCounts,
+    /// The objects the first phase found occluded, re-tested.
+    pub second: CullCounts,
+    /// Every object against the complete depth prepass, none without it.
+    pub last: CullCounts,
+}
+
+impl CullStats {
+    /// The objects drawn by the first two phases, which make the depth.
+    pub fn drawn(&self) -> u32 {
+        self.first.visible + self.second.visible
+    }
+
+    pub fn culled(&self) -> u32 {
+        self.objects.saturating_sub(self.drawn())
+    }
+}
+
+impl Display for CullStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} objects, {} outside, {}/{} visible/occluded then {}/{}",
+            self.objects,
+            self.objects
+                .saturating_sub(self.first.visible + self.first.occluded),
+            self.first.visible,
+            self.first.occluded,
+            self.second.visible,
+            self.second.occluded
+        )?;
+        if self.last != CullCounts::default() {
+            write!(
+                f,
+                ", {}/{} on the prepass",
+                self.last.visible, self.last.occluded
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Per pass statistics of the last rendered frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    pub opaque: DrawStats,
+    pub transparent: DrawStats,
+    /// Summed over every face of every point light shadow map.
+    pub shadow: DrawStats,
+    /// Only with the GPU driven path.
+    pub gpu_cull: Option<CullStats>,
+}
+
+impl Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "opaque: {} | transparent: {} | shadow: {}",
+            self.opaque, self.transparent, self.shadow
+        )?;
+        if let Some(gpu_cull) = &self.gpu_cull {
+            write!(f, " | gpu cull: {gpu_cull}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// GPU memory of the loaded textures, in bytes, largest first.
+#[derive(Debug, Default, Clone)]
+pub struct TextureMemory {
+    pub textures: Vec<(String, u64)>,
+}
+
+impl TextureMemory {
+    pub fn total(&self) -> u64 {
+        self.textures.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+impl Display for TextureMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+        write!(
+            f,
+            "{:.2} MiB in {} textures",
+            mib(self.total()),
+            self.textures.len()
+        )?;
+        if let Some((texture, bytes)) = self.textures.first() {
+            write!(f, ", largest {:.2} MiB {texture}", mib(*bytes))?;
+        }
+
+        Ok(())
+    }
+}
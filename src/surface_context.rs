@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
-use wgpu::{CompositeAlphaMode, SurfaceConfiguration, TextureUsages};
-use winit::window::Window;
+use wgpu::{CompositeAlphaMode, Instance, PresentMode, SurfaceConfiguration, TextureUsages};
+use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::GpuContext;
 
@@ -17,27 +17,53 @@ impl SurfaceContext {
             config: None,
         }
     }
-    pub fn init(&mut self, context: &GpuContext, window: Arc<Window>) {
-        let size = window.inner_size();
 
-        let surface = context.instance.create_surface(window).unwrap();
+    /// Creates the surface of the window, before the adapter presenting to it
+    /// is picked.
+    pub fn create(&mut self, instance: &Instance, window: Arc<Window>) -> Result<(), String> {
+        let surface = instance
+            .create_surface(window)
+            .map_err(|error| format!("Can not create a surface for the window: {error}"))?;
+        self.surface = Some(surface);
+
+        Ok(())
+    }
+
+    /// Presents with the `present_mode` when the surface supports it, with
+    /// `Fifo` otherwise.
+    pub fn init(
+        &mut self,
+        context: &GpuContext,
+        size: PhysicalSize<u32>,
+        present_mode: PresentMode,
+    ) {
+        let surface = self.surface.as_ref().unwrap();
+
+        let supported = surface.get_capabilities(&context.adapter).present_modes;
+        let present_mode = match present_mode {
+            PresentMode::AutoVsync | PresentMode::AutoNoVsync => present_mode,
+            _ if supported.contains(&present_mode) => present_mode,
+            _ => {
+                eprintln!("The surface does not support {present_mode:?}, presenting with Fifo");
+                PresentMode::Fifo
+            }
+        };
 
         let config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
             format: wgpu::TextureFormat::Bgra8UnormSrgb,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode,
             desired_maximum_frame_latency: 2,
             alpha_mode: CompositeAlphaMode::Auto,
             view_formats: vec![],
         };
 
-        self.surface = Some(surface);
         self.config = Some(config);
     }
 
-    pub fn get(&self) -> Option<&wgpu::Surface> {
+    pub fn get(&self) -> Option<&wgpu::Surface<'_>> {
         self.surface.as_ref()
     }
 
@@ -19,6 +19,7 @@ impl CubeMap {
         height: u32,
         format: wgpu::TextureFormat,
         usage: TextureUsages,
+        mip_level_count: u32,
         label: Option<&str>,
     ) -> CubeMap {
         let texture_size = Extent3d {
@@ -30,7 +31,7 @@ impl CubeMap {
         let texture = device.create_texture(&TextureDescriptor {
             label,
             size: texture_size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
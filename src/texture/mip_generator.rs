@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, ColorTargetState, ColorWrites,
+    CommandEncoderDescriptor, Device, FragmentState, LoadOp, MultisampleState, Operations,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderStages, StoreOp,
+    TextureFormat, TextureSampleType, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::shader_library::ShaderLibrary;
+
+use super::{CubeMap, Texture};
+
+/// How the texels of a level are averaged into the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MipFilter {
+    Color,
+    /// Renormalizes the averaged tangent space normals.
+    NormalMap,
+}
+
+/// Fills the mip chain of a texture from its first level on the GPU, for
+/// every layer, so it also works for cube maps.
+pub struct MipGenerator {
+    layout: BindGroupLayout,
+    pipelines: HashMap<(TextureFormat, MipFilter), RenderPipeline>,
+}
+
+impl MipGenerator {
+    /// The formats the loaded textures and environment maps use.
+    const VARIANTS: [(TextureFormat, MipFilter); 4] = [
+        (Texture::SRGBA_UNORM, MipFilter::Color),
+        (Texture::RGBA_UNORM, MipFilter::Color),
+        (Texture::RGBA_UNORM, MipFilter::NormalMap),
+        (CubeMap::RGBA_32_FLOAT, MipFilter::Color),
+    ];
+
+    pub fn new(device: &Device, shaders: &ShaderLibrary) -> MipGenerator {
+        let shader = shaders.module(device, "shaders/mipmap.wgsl", &[]);
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Mip generator layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mip generator pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = Self::VARIANTS
+            .into_iter()
+            .map(|(format, filter)| {
+                let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some("Mip generator pipeline"),
+                    layout: Some(&pipeline_layout),
+                    vertex: VertexState {
+                        module: &shader,
+                        entry_point: "vs_main",
+                        compilation_options: Default::default(),
+                        buffers: &[],
+                    },
+                    fragment: Some(FragmentState {
+                        module: &shader,
+                        entry_point: match filter {
+                            MipFilter::Color => "fs_color",
+                            MipFilter::NormalMap => "fs_normal",
+                        },
+                        compilation_options: Default::default(),
+                        targets: &[Some(ColorTargetState {
+                            format,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    multiview: None,
+                    cache: None,
+                });
+
+                ((format, filter), pipeline)
+            })
+            .collect();
+
+        MipGenerator { layout, pipelines }
+    }
+
+    /// Downsamples each level of every layer into the next one. The texture
+    /// needs to be a render attachment that can be bound as a texture.
+    pub fn generate(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        texture: &wgpu::Texture,
+        filter: MipFilter,
+    ) {
+        let pipeline = self
+            .pipelines
+            .get(&(texture.format(), filter))
+            .expect("No mip generator pipeline for the texture format");
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Mip generator Encoder"),
+        });
+
+        for layer in 0..texture.depth_or_array_layers() {
+            let level_view = |level| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Mip level view"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            };
+
+            for level in 1..texture.mip_level_count() {
+                let source = level_view(level - 1);
+                let destination = level_view(level);
+
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Mip generator bind group"),
+                    layout: &self.layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&source),
+                    }],
+                });
+
+                let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("Mip generator Pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &destination,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Load,
+                            store: StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
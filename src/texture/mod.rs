@@ -1,17 +1,25 @@
 mod cubemap;
+mod mip_generator;
+mod sampler_cache;
+
+use std::sync::Arc;
 
 use wgpu::{
-    Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, Sampler,
+    CommandEncoder, Device, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, Queue, Sampler,
     TextureDescriptor, TextureFormat, TextureUsages, TextureView,
 };
 
 pub use cubemap::CubeMap;
+pub use mip_generator::{MipFilter, MipGenerator};
+pub use sampler_cache::{SamplerCache, SamplerKey};
 
 #[derive(Debug)]
 pub struct Texture {
     texture: wgpu::Texture,
     pub view: TextureView,
-    pub sampler: Sampler,
+    /// Possibly shared with other textures, see [SamplerCache].
+    pub sampler: Arc<Sampler>,
+    #[allow(dead_code)]
     pub format: wgpu::TextureFormat,
 }
 
@@ -21,6 +29,8 @@ impl Texture {
     pub const RGBA_UNORM: TextureFormat = TextureFormat::Rgba8Unorm;
     pub const DEPTH_32_FLOAT: TextureFormat = TextureFormat::Depth32Float;
     pub const RGBA_16_FLOAT: TextureFormat = TextureFormat::Rgba16Float;
+    pub const R_16_FLOAT: TextureFormat = TextureFormat::R16Float;
+    pub const R_32_FLOAT: TextureFormat = TextureFormat::R32Float;
     pub const RGBA_32_FLOAT: TextureFormat = TextureFormat::Rgba32Float;
 
     pub fn new(
@@ -30,6 +40,39 @@ impl Texture {
         label: Option<&str>,
         format: TextureFormat,
         usage: TextureUsages,
+    ) -> Texture {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Texture::with_mips(
+            device,
+            width,
+            height,
+            label,
+            format,
+            usage,
+            1,
+            Arc::new(sampler),
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_mips(
+        device: &Device,
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+        format: TextureFormat,
+        usage: TextureUsages,
+        mip_level_count: u32,
+        sampler: Arc<Sampler>,
     ) -> Texture {
         let size = Extent3d {
             width,
@@ -40,7 +83,7 @@ impl Texture {
         let texture = device.create_texture(&TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format,
@@ -49,15 +92,6 @@ impl Texture {
         });
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
 
         Texture {
             texture,
@@ -67,6 +101,52 @@ impl Texture {
         }
     }
 
+    /// A render target with `samples` per pixel, to be resolved into a single
+    /// sampled texture.
+    pub fn multisampled(
+        device: &Device,
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+        format: TextureFormat,
+        usage: TextureUsages,
+        samples: u32,
+    ) -> Texture {
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Texture {
+            texture,
+            view,
+            sampler: Arc::new(sampler),
+            format,
+        }
+    }
+
+    /// Levels of a full mip chain, down to a single texel.
+    pub fn mip_level_count(width: u32, height: u32) -> u32 {
+        u32::BITS - width.max(height).leading_zeros()
+    }
+
+    pub fn size(&self) -> Extent3d {
+        self.texture.size()
+    }
+
     pub fn write(&self, queue: &Queue, data: &[u8]) {
         queue.write_texture(
             ImageCopyTexture {
@@ -85,6 +165,18 @@ impl Texture {
         );
     }
 
+    /// Records a copy of the whole texture into another one of the same size.
+    pub fn copy_to(&self, encoder: &mut CommandEncoder, destination: &Texture) {
+        encoder.copy_texture_to_texture(
+            self.texture.as_image_copy(),
+            destination.texture.as_image_copy(),
+            self.texture.size(),
+        );
+    }
+
+    /// Allocates the full mip chain but only writes the first level, see
+    /// [Texture::generate_mips].
+    #[allow(clippy::too_many_arguments)]
     pub fn init(
         device: &Device,
         queue: &Queue,
@@ -93,20 +185,111 @@ impl Texture {
         data: &[u8],
         label: Option<&str>,
         format: TextureFormat,
+        sampler: Arc<Sampler>,
     ) -> Texture {
-        let texture = Texture::new(
+        let texture = Texture::with_mips(
             device,
             width,
             height,
             label,
             format,
-            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            Texture::mip_level_count(width, height),
+            sampler,
         );
         texture.write(queue, data);
 
         texture
     }
 
+    /// Uploads every level as it is, for the precompressed textures. The
+    /// levels go from the largest to the smallest.
+    #[allow(clippy::too_many_arguments)]
+    pub fn init_levels(
+        device: &Device,
+        queue: &Queue,
+        width: u32,
+        height: u32,
+        levels: &[Vec<u8>],
+        label: Option<&str>,
+        format: TextureFormat,
+        sampler: Arc<Sampler>,
+    ) -> Texture {
+        let texture = Texture::with_mips(
+            device,
+            width,
+            height,
+            label,
+            format,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            levels.len() as u32,
+            sampler,
+        );
+
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap();
+
+        for (level, data) in levels.iter().enumerate() {
+            let size = texture
+                .texture
+                .size()
+                .mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            let blocks_per_row = size.width.div_ceil(block_width);
+            let rows = size.height.div_ceil(block_height);
+
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture.texture,
+                    mip_level: level as u32,
+                    origin: Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(blocks_per_row * block_size),
+                    rows_per_image: Some(rows),
+                },
+                size.physical_size(format),
+            );
+        }
+
+        texture
+    }
+
+    /// Bytes taken by every mip level of every layer.
+    pub fn memory_usage(&self) -> u64 {
+        let format = self.texture.format();
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or_default() as u64;
+
+        (0..self.texture.mip_level_count())
+            .map(|level| {
+                let size = self
+                    .texture
+                    .size()
+                    .mip_level_size(level, self.texture.dimension())
+                    .physical_size(format);
+                let blocks = (size.width / block_width) * (size.height / block_height);
+
+                blocks as u64 * size.depth_or_array_layers as u64 * block_size
+            })
+            .sum()
+    }
+
+    /// Fills the mip levels below the first one.
+    pub fn generate_mips(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        mip_generator: &MipGenerator,
+        filter: MipFilter,
+    ) {
+        mip_generator.generate(device, queue, &self.texture, filter);
+    }
+
     pub fn write_hdr(&self, queue: &Queue, data: &[u8]) {
         queue.write_texture(
             ImageCopyTexture {
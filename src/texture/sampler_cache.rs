@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use wgpu::{AddressMode, Device, FilterMode, Sampler, SamplerDescriptor};
+
+/// The sampler state a texture asks for, which identifies the sampler in the
+/// [SamplerCache].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_mode_u: AddressMode,
+    pub address_mode_v: AddressMode,
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    /// Without it only the first mip level is sampled.
+    pub mipmap_filter: Option<FilterMode>,
+}
+
+impl Default for SamplerKey {
+    fn default() -> Self {
+        SamplerKey {
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: Some(FilterMode::Linear),
+        }
+    }
+}
+
+/// Creates each distinct sampler once, so the textures asking for the same
+/// state share it.
+pub struct SamplerCache {
+    anisotropy: u16,
+    samplers: Mutex<HashMap<SamplerKey, Arc<Sampler>>>,
+}
+
+impl SamplerCache {
+    /// The `anisotropy` is clamped between 1, which turns it off, and 16.
+    pub fn new(anisotropy: u16) -> SamplerCache {
+        SamplerCache {
+            anisotropy: anisotropy.clamp(1, 16),
+            samplers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, device: &Device, key: SamplerKey) -> Arc<Sampler> {
+        let mut samplers = self.samplers.lock().unwrap();
+
+        samplers
+            .entry(key)
+            .or_insert_with(|| Arc::new(device.create_sampler(&self.descriptor(key))))
+            .clone()
+    }
+
+    fn descriptor(&self, key: SamplerKey) -> SamplerDescriptor<'static> {
+        let mipmap_filter = key.mipmap_filter.unwrap_or(FilterMode::Nearest);
+
+        // Anisotropic filtering needs every filter to be linear
+        let linear = [key.mag_filter, key.min_filter, mipmap_filter]
+            .iter()
+            .all(|filter| *filter == FilterMode::Linear);
+
+        SamplerDescriptor {
+            label: Some("Texture sampler"),
+            address_mode_u: key.address_mode_u,
+            address_mode_v: key.address_mode_v,
+            mag_filter: key.mag_filter,
+            min_filter: key.min_filter,
+            mipmap_filter,
+            lod_max_clamp: match key.mipmap_filter {
+                Some(_) => 32.0,
+                None => 0.0,
+            },
+            anisotropy_clamp: if linear { self.anisotropy } else { 1 },
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_the_first_level_without_a_mipmap_filter() {
+        let cache = SamplerCache::new(8);
+
+        let descriptor = cache.descriptor(SamplerKey::default());
+        assert_eq!(descriptor.mipmap_filter, FilterMode::Linear);
+        assert_eq!(descriptor.lod_max_clamp, 32.0);
+        assert_eq!(descriptor.anisotropy_clamp, 8);
+
+        let descriptor = cache.descriptor(SamplerKey {
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: None,
+            ..Default::default()
+        });
+        assert_eq!(descriptor.mipmap_filter, FilterMode::Nearest);
+        assert_eq!(descriptor.lod_max_clamp, 0.0);
+        assert_eq!(descriptor.anisotropy_clamp, 1);
+    }
+}
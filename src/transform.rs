@@ -1,6 +1,6 @@
 use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, Zero};
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Transform {
     pub translation: (f32, f32, f32),
     pub rotation: Quaternion<f32>,
@@ -3,23 +3,27 @@ use std::sync::Arc;
 use winit::{
     dpi::PhysicalSize,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
+use crate::settings::DisplaySettings;
+
 pub struct WindowContext {
     pub event_loop: EventLoop<()>,
     pub window: Arc<Window>,
 }
 
 impl WindowContext {
-    pub fn new() -> WindowContext {
+    pub const TITLE: &'static str = "WGPU renderer";
+
+    pub fn new(settings: &DisplaySettings) -> WindowContext {
+        let (width, height) = settings.size;
+
         let event_loop = EventLoop::new().unwrap();
         let window = WindowBuilder::new()
-            .with_title("WGPU renderer")
-            .with_inner_size(PhysicalSize {
-                width: 1024,
-                height: 768,
-            })
+            .with_title(WindowContext::TITLE)
+            .with_inner_size(PhysicalSize { width, height })
+            .with_fullscreen(settings.fullscreen.then_some(Fullscreen::Borderless(None)))
             .build(&event_loop)
             .unwrap();
 